@@ -0,0 +1,88 @@
+// Throughput of lexing, parsing and checking on the example programs and on a large
+// generated one. Compare a change against the tree before it with
+//
+//   cargo bench --bench frontend -- --save-baseline before
+//   cargo bench --bench frontend -- --baseline before
+
+use std::{hint::black_box, path::Path, sync::{Arc, Mutex}};
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+use bcplpp::{
+    api::{self, Source},
+    ast::Program,
+    lint::LintConfig,
+    parser::Parser,
+    source_file::SourceFile,
+    token::lexer::Lexer
+};
+
+// routines of the generated program; each copy exercises `switchon`, recursion, loops and `writef`
+const GENERATED_ROUTINES: usize = 500;
+
+const ROUTINES: &str = "
+manifest Limit# :: Int32 = 100
+
+static total# = 0
+
+let classify#(c :: char) : int => valof {
+    switchon c into {
+        case 'a', 'e', 'i', 'o', 'u': resultis 1;
+        case '0' to '9': resultis 2;
+        default: resultis 0;
+    };
+}
+
+let fib#(n :: Int32) : Int32 => n < 2 -> n, fib#(n - 1) + fib#(n - 2)
+
+let report#(n :: Int32) be
+    for i = 1 to n do
+        if i mod 3 = 0 do writef(\"%n: %n*n\", i, fib#(i))
+";
+
+fn generated_program() -> String {
+    let mut program = String::from("section Bench\n");
+    for i in 0..GENERATED_ROUTINES {
+        program.push_str(&ROUTINES.replace('#', &i.to_string()));
+    }
+    program.push_str("\nlet start be report0(Limit0)\n");
+    program
+}
+
+// `(name, contents)` of every program benchmarked
+fn programs() -> Vec<(String, String)> {
+    let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus");
+    let mut programs = ["primes", "queens", "patterns"].into_iter()
+        .map(|name| (name.to_string(), std::fs::read_to_string(corpus.join(name).with_extension("bpp")).expect("error reading corpus program")))
+        .collect::<Vec<_>>();
+    programs.push(("generated".into(), generated_program()));
+    programs
+}
+
+fn lex(file: &SourceFile) -> usize {
+    Lexer::from(file).take_while(|token| !token.is_eof()).count()
+}
+
+fn parse(file: &SourceFile, lints: &LintConfig) -> Arc<Mutex<Program>> {
+    let ast = Arc::new(Mutex::new(Program::default()));
+    let mut parser = Parser::new(Lexer::from(file), ast.clone(), lints);
+    parser.parse().expect("benchmarked programs parse");
+    ast
+}
+
+fn frontend(c: &mut Criterion) {
+    let lints = LintConfig::default();
+    for (name, contents) in programs() {
+        let file = SourceFile::new(format!("{name}.bpp"), 0, contents.clone());
+
+        let mut group = c.benchmark_group(&name);
+        group.throughput(Throughput::Bytes(contents.len() as u64));
+        group.bench_function("lex", |b| b.iter(|| lex(black_box(&file))));
+        group.bench_function("parse", |b| b.iter(|| parse(black_box(&file), &lints)));
+        group.bench_function("check", |b| b.iter(|| api::check(vec![Source::new(format!("{name}.bpp"), black_box(contents.clone()))])));
+        group.finish();
+    }
+}
+
+criterion_group!(benches, frontend);
+criterion_main!(benches);
@@ -18,25 +18,25 @@ hi def link bcplppLiteralKeyword Boolean
 
 " Keywords
 
-syn keyword bcplppConditionalKeyword resultis return if unless switchon match every
+syn keyword bcplppConditionalKeyword resultis return if unless switchon match every defer try catch throw rethrow
 hi def link bcplppConditionalKeyword Conditional
 
 syn keyword bcplppLoopKeyword repeat while until for
 hi def link bcplppLoopKeyword Repeat
 
-syn keyword bcplppLabelKeyword case default break finish skip
+syn keyword bcplppLabelKeyword case default break finish skip goto
 hi def link bcplppLabelKeyword Label
 
-syn keyword bcplppOperatorKeyword valof do mod abs be of by to
+syn keyword bcplppOperatorKeyword valof do mod abs be of by to slct asm
 hi def link bcplppOperatorKeyword Operator
 
-syn keyword bcplppPreProcessorKeyword section require
+syn keyword bcplppPreProcessorKeyword section require needs
 hi def link bcplppPreProcessorKeyword Statement
 
 syn keyword bcplppPrimitiveTypes Int8 Int16 Int Int64 UInt8 Uint16 Uint Uint64 Char Bool
 hi def link bcplppPrimitiveTypes Type
 
-syn keyword bcplppStorageClass global manifest static let and
+syn keyword bcplppStorageClass global manifest static let and struct enum
 hi def link bcplppStorageClass StorageClass
 
 " Preprocessor
@@ -50,6 +50,7 @@ hi def link bcplppDefine Define
 " Symbols
 
 syn match bcplppParens "[\[\](){}]"
+syn match bcplppParens "\$[()][a-zA-Z0-9_]*"
 hi def link bcplppParens Delimiter
 
 syn match bcplppDelims "[.,;]"
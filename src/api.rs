@@ -0,0 +1,246 @@
+use std::collections::HashMap;
+
+use crate::{
+    ast::{Decl, Function, GlobalDecl, ManifestDecl, Storage},
+    context::{Context, CompileResult},
+    error::{self, CompilerError},
+    report::json_string,
+    source_file::{SourceFile, SourceFileId, Location, Located}
+};
+
+// The interface for tools built on bcplpp. Within a `VERSION` items are only added: the
+// structs and enums below are `#[non_exhaustive]`, so new fields, kinds and severities do not
+// break callers. Removing or changing anything increases `VERSION`.
+pub const VERSION: u32 = 1;
+
+// lines count from 1, columns from 0, like the compiler prints them
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Span {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub width: usize
+}
+
+impl Span {
+    fn new(loc: &Location, source_files: &HashMap<SourceFileId, SourceFile>) -> Self {
+        Self {
+            file: source_files.get(&loc.file_id()).map_or("<unknown>".into(), |file| file.path().clone()),
+            line: loc.line(),
+            column: loc.column(),
+            width: loc.width()
+        }
+    }
+
+    fn to_json(&self) -> String {
+        format!("{{ \"file\": {}, \"line\": {}, \"column\": {}, \"width\": {} }}", json_string(&self.file), self.line, self.column, self.width)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Severity {
+    Error,
+    Warning,
+    Hint
+}
+
+impl Severity {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Error => "error",
+            Self::Warning => "warning",
+            Self::Hint => "hint"
+        }
+    }
+}
+
+impl From<&error::Severity> for Severity {
+    fn from(severity: &error::Severity) -> Self {
+        match severity {
+            error::Severity::Error => Self::Error,
+            error::Severity::Warning => Self::Warning,
+            error::Severity::Hint => Self::Hint
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub hint: Option<String>,
+    pub span: Span,
+    // notes at other locations, like the previous definition of a redefined name
+    pub related: Vec<Diagnostic>
+}
+
+impl Diagnostic {
+    fn new(diag: &Located<CompilerError>, source_files: &HashMap<SourceFileId, SourceFile>) -> Self {
+        Self {
+            severity: diag.severity().into(),
+            message: diag.message().clone(),
+            hint: diag.hint().clone(),
+            span: Span::new(diag.location(), source_files),
+            related: diag.additional.iter().map(|related| Self::new(related, source_files)).collect()
+        }
+    }
+
+    // `{ "severity", "message", "hint", "span", "related" }`; `hint` is `null` if there is none
+    pub fn to_json(&self) -> String {
+        format!("{{ \"severity\": \"{}\", \"message\": {}, \"hint\": {}, \"span\": {}, \"related\": [{}] }}",
+            self.severity.name(),
+            json_string(&self.message),
+            self.hint.as_ref().map_or("null".into(), |hint| json_string(hint)),
+            self.span.to_json(),
+            self.related.iter().map(Self::to_json).collect::<Vec<_>>().join(", ")
+        )
+    }
+}
+
+// Statements and expressions are not part of the stable interface yet; their
+// representation still changes with most new language features.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum NodeKind {
+    Section,
+    Routine,
+    Global,
+    Static,
+    Manifest
+}
+
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct Node {
+    pub kind: NodeKind,
+    pub name: String,
+    pub span: Span,
+    pub public: bool,
+    // declarations of a section, in source order
+    pub children: Vec<Node>
+}
+
+impl Node {
+    fn from_decl(decl: &dyn Decl, source_files: &HashMap<SourceFileId, SourceFile>) -> Option<Self> {
+        let any = decl.as_any();
+        let kind = if any.is::<Function>() {
+            NodeKind::Routine
+        }
+        else if let Some(global) = any.downcast_ref::<GlobalDecl>() {
+            match global.storage() {
+                Storage::Global => NodeKind::Global,
+                Storage::Static => NodeKind::Static
+            }
+        }
+        else if any.is::<ManifestDecl>() {
+            NodeKind::Manifest
+        }
+        else {
+            return None
+        };
+
+        Some(Self {
+            kind,
+            name: decl.ident().clone(),
+            span: Span::new(decl.location(), source_files),
+            public: decl.is_public(),
+            children: vec![]
+        })
+    }
+}
+
+#[non_exhaustive]
+pub struct Source {
+    pub path: String,
+    pub contents: String
+}
+
+impl Source {
+    pub fn new(path: impl Into<String>, contents: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            contents: contents.into()
+        }
+    }
+
+    pub fn read(path: impl Into<String>) -> std::io::Result<Self> {
+        let path = path.into();
+        let contents = std::fs::read_to_string(&path)?;
+        Ok(Self::new(path, contents))
+    }
+}
+
+#[derive(Debug, Default)]
+#[non_exhaustive]
+pub struct Checked {
+    // grouped by file, in source order
+    pub diagnostics: Vec<Diagnostic>,
+    // the declarations parsed, also when there were errors; ordered by name
+    pub sections: Vec<Node>
+}
+
+impl Checked {
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics.iter().any(|diag| diag.severity == Severity::Error)
+    }
+}
+
+// Parses, type checks and lints `sources` like `bcplpp check` with the default lint levels,
+// without writing anything. Files included by `get` are read relative to the including file.
+pub fn check(sources: Vec<Source>) -> Checked {
+    if sources.is_empty() {
+        return Checked::default()
+    }
+
+    let mut ctx = Context::from_program_name("bcplpp".into());
+    ctx.set_quiet(true);
+    ctx.add_source_files(sources.into_iter()
+        .enumerate()
+        .map(|(id, source)| (id as SourceFileId, SourceFile::new(source.path, id as SourceFileId, source.contents)))
+        .collect()
+    );
+
+    let diagnostics = match ctx.check() {
+        CompileResult::Ok => vec![],
+        CompileResult::Warn(diagnostics) | CompileResult::Err(diagnostics) | CompileResult::Interrupted(diagnostics) => diagnostics
+    };
+
+    let ast = ctx.ast().lock().unwrap();
+    let mut sections = ast.sections()
+        .map(|section| {
+            let mut children = section.declarations()
+                .filter_map(|decl| Node::from_decl(&**decl, ctx.source_files()))
+                .collect::<Vec<_>>();
+            children.sort_by(|a, b| (&a.span.file, a.span.line, a.span.column).cmp(&(&b.span.file, b.span.line, b.span.column)));
+            Node {
+                kind: NodeKind::Section,
+                name: section.ident().clone(),
+                span: Span::new(section.location(), ctx.source_files()),
+                public: true,
+                children
+            }
+        })
+        .collect::<Vec<_>>();
+    sections.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Checked {
+        diagnostics: diagnostics.iter().map(|diag| Diagnostic::new(diag, ctx.source_files())).collect(),
+        sections
+    }
+}
+
+// The symbol native backends give the routine `ident` declared in the section `module`; the
+// scheme is described in `mangle.rs`. Hoisted routines, methods and generic instances are
+// named as in `Node::name`, like `outer.inner` or `routine<T1, T2>`.
+pub fn mangle(ident: &str, module: &str) -> String {
+    crate::mangle::mangle(ident, Some(module))
+}
+
+// The routine named by a mangled symbol, followed by its section in brackets if the symbol
+// names it, like `bcplpp demangle` prints it; `None` for other symbols.
+pub fn demangle(symbol: &str) -> Option<String> {
+    crate::mangle::demangle(symbol).map(|demangled| demangled.to_string())
+}
@@ -0,0 +1,78 @@
+use crate::source_file::WithLocation;
+
+// `[[name]]` or `[[name("argument", ...)]]` in front of a declaration
+#[derive(Clone, Debug)]
+pub struct Attribute {
+    name: String,
+    args: Vec<String>
+}
+
+impl Attribute {
+    pub fn new(name: String, args: Vec<String>) -> Self {
+        Self {
+            name,
+            args
+        }
+    }
+
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+
+    pub fn args(&self) -> &Vec<String> {
+        &self.args
+    }
+}
+
+impl WithLocation for Attribute {}
+
+// set by `[[deprecated]]` or `[[deprecated("message")]]`
+#[derive(Clone, Debug)]
+pub struct Deprecation {
+    message: Option<String>
+}
+
+impl Deprecation {
+    pub fn new(message: Option<String>) -> Self {
+        Self {
+            message
+        }
+    }
+
+    pub fn message(&self) -> Option<&String> {
+        self.message.as_ref()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum CallingConvention {
+    #[default]
+    Bcpl,
+    // `[[ccall]]`: callable from and calling into C code
+    C
+}
+
+// `[[constructor]]` and `[[destructor]]`: routines the runtime calls before the entry routine
+// and after `finish`. Lower priorities run first; destructors run in reverse.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Hook {
+    Constructor(u16),
+    Destructor(u16)
+}
+
+impl Hook {
+    pub const DEFAULT_PRIORITY: u16 = u16::MAX;
+
+    pub fn priority(&self) -> u16 {
+        match self {
+            Self::Constructor(priority) | Self::Destructor(priority) => *priority
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Constructor(_) => "constructor",
+            Self::Destructor(_) => "destructor"
+        }
+    }
+}
@@ -1,8 +1,32 @@
 use crate::source_file::{Location, Located};
 
-use super::{types::TypeIndex, stmt::Stmt, pattern::Pattern};
+use super::{types::{TypeIndex, TypeList, TypeKind, Coercion}, stmt::Stmt, pattern::Pattern};
 
 pub type AtomIndex = u32;
+pub type TempIndex = u32;
+pub type ConstIndex = u32;
+
+// what happens when integer arithmetic leaves the range of its type
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OverflowMode {
+    #[default]
+    Wrap,
+    Trap,
+    Saturate
+}
+
+impl TryFrom<&str> for OverflowMode {
+    type Error = ();
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "wrap" => Ok(Self::Wrap),
+            "trap" => Ok(Self::Trap),
+            "saturate" => Ok(Self::Saturate),
+            _ => Err(())
+        }
+    }
+}
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct Expr {
@@ -29,6 +53,38 @@ impl Expr {
         }
     }
 
+    // converts `self` to `typ`, inserting an implicit cast where needed.
+    // Fails with the `(from, to)` type pair if no implicit conversion exists.
+    pub fn coerce(self, typ: TypeIndex, types: &TypeList) -> Result<Self, (TypeIndex, TypeIndex)> {
+        if let ExprKind::Nil = self.kind {
+            return self.coerce_nil(typ, types)
+        }
+
+        match self.typ {
+            None => Ok(self.implicit_cast(typ)),
+            Some(from) => match types.coercion(from, typ) {
+                Coercion::Identity => Ok(self),
+                Coercion::Implicit => Ok(self.implicit_cast(typ)),
+                Coercion::Impossible => Err((from, typ))
+            }
+        }
+    }
+
+    // `nil` converts to every pointer type, routine pointers included, without a cast, but never to a plain word
+    fn coerce_nil(mut self, typ: TypeIndex, types: &TypeList) -> Result<Self, (TypeIndex, TypeIndex)> {
+        match types.get(types.resolve(typ)).map(|typ| typ.kind()) {
+            Some(TypeKind::Pointer(_) | TypeKind::Routine(..) | TypeKind::Alias(_, None)) => {
+                self.typ = Some(typ);
+                Ok(self)
+            }
+            _ => Err((self.typ.unwrap_or(typ), typ))
+        }
+    }
+
+    pub fn is_nil(&self) -> bool {
+        matches!(self.kind, ExprKind::Nil)
+    }
+
     pub fn location(&self) -> &Location {
         &self.loc
     }
@@ -43,13 +99,97 @@ impl Expr {
 
     pub fn has_sideeffect(&self) -> bool {
         match &self.kind {
-            ExprKind::Cast(expr) | ExprKind::ImplicitCast(expr) => expr.has_sideeffect(),
+            ExprKind::Cast(expr) | ExprKind::ImplicitCast(expr) | ExprKind::Overflow(_, expr) => expr.has_sideeffect(),
             ExprKind::ValOf(_) | ExprKind::FuncCall(..) => true,
             ExprKind::Conditional(a, b, c) => a.has_sideeffect() || b.has_sideeffect() || c.has_sideeffect(),
+            ExprKind::LogAnd(a, b) | ExprKind::LogOr(a, b) => a.has_sideeffect() || b.has_sideeffect(),
+            ExprKind::VarArgs(args) => args.iter().any(Expr::has_sideeffect),
+            ExprKind::NamedArg(_, arg) => arg.has_sideeffect(),
             _ => false
         }
     }
 
+    // whether the expression names storage an assignment can write to
+    pub fn is_lvalue(&self) -> bool {
+        matches!(self.kind, ExprKind::Ident(_) | ExprKind::Deref(_) | ExprKind::Index(..) | ExprKind::FieldSelect(..) | ExprKind::Member(..))
+    }
+
+    pub fn const_int(&self) -> Option<i64> {
+        match &self.kind {
+            ExprKind::IntLit(value) => i64::try_from(*value).ok(),
+            ExprKind::CharLit(ch) => Some(*ch as i64),
+            ExprKind::Cast(expr) | ExprKind::ImplicitCast(expr) | ExprKind::Overflow(_, expr) => expr.const_int(),
+            ExprKind::Abs(expr) => expr.const_int()?.checked_abs(),
+            ExprKind::Not(expr) => Some(!expr.const_int()?),
+            ExprKind::Add(lhs, rhs) => lhs.const_int()?.checked_add(rhs.const_int()?),
+            ExprKind::Sub(lhs, rhs) => lhs.const_int()?.checked_sub(rhs.const_int()?),
+            ExprKind::Mul(lhs, rhs) => lhs.const_int()?.checked_mul(rhs.const_int()?),
+            ExprKind::Div(lhs, rhs) => lhs.const_int()?.checked_div(rhs.const_int()?),
+            ExprKind::Mod(lhs, rhs) => lhs.const_int()?.checked_rem(rhs.const_int()?),
+            ExprKind::And(lhs, rhs) => Some(lhs.const_int()? & rhs.const_int()?),
+            ExprKind::Or(lhs, rhs) => Some(lhs.const_int()? | rhs.const_int()?),
+            ExprKind::XOr(lhs, rhs) => Some(lhs.const_int()? ^ rhs.const_int()?),
+            ExprKind::LShift(lhs, rhs) => lhs.const_int()?.checked_shl(rhs.const_int()?.try_into().ok()?),
+            ExprKind::RShift(lhs, rhs) => lhs.const_int()?.checked_shr(rhs.const_int()?.try_into().ok()?),
+            ExprKind::Selector(size, shift, offset) => pack_selector(size.const_int()?, shift.const_int()?, offset.const_int()?),
+            _ => None
+        }
+    }
+
+    // truth value of a constant condition; words are true unless zero
+    pub fn const_bool(&self) -> Option<bool> {
+        if let Some(value) = self.const_int() {
+            return Some(value != 0)
+        }
+
+        let compare = |lhs: &Expr, rhs: &Expr, op: fn(&i64, &i64) -> bool| Some(op(&lhs.const_int()?, &rhs.const_int()?));
+        match &self.kind {
+            ExprKind::True => Some(true),
+            ExprKind::False => Some(false),
+            ExprKind::Cast(expr) | ExprKind::ImplicitCast(expr) | ExprKind::Overflow(_, expr) => expr.const_bool(),
+            ExprKind::Not(expr) => Some(!expr.const_bool()?),
+            ExprKind::LogAnd(lhs, rhs) | ExprKind::And(lhs, rhs) => Some(lhs.const_bool()? && rhs.const_bool()?),
+            ExprKind::LogOr(lhs, rhs) | ExprKind::Or(lhs, rhs) => Some(lhs.const_bool()? || rhs.const_bool()?),
+            ExprKind::Eq(lhs, rhs) => compare(lhs, rhs, i64::eq),
+            ExprKind::Ne(lhs, rhs) => compare(lhs, rhs, i64::ne),
+            ExprKind::Gt(lhs, rhs) => compare(lhs, rhs, i64::gt),
+            ExprKind::Ge(lhs, rhs) => compare(lhs, rhs, i64::ge),
+            ExprKind::Lt(lhs, rhs) => compare(lhs, rhs, i64::lt),
+            ExprKind::Le(lhs, rhs) => compare(lhs, rhs, i64::le),
+            _ => None
+        }
+    }
+
+    // whether `pred` holds for `self` or any subexpression not nested in a statement
+    pub fn contains(&self, pred: fn(&ExprKind) -> bool) -> bool {
+        pred(&self.kind) || self.kind.children().into_iter().any(|child| child.contains(pred))
+    }
+
+    // all identifiers referenced by `self`, including those in `valof` bodies
+    pub fn referenced_idents<'a>(&'a self, idents: &mut Vec<&'a String>) {
+        match &self.kind {
+            ExprKind::Ident(ident) => idents.push(ident),
+            ExprKind::ValOf(body) => body.referenced_idents(idents),
+            kind => kind.children().into_iter().for_each(|child| child.referenced_idents(idents))
+        }
+    }
+
+    pub fn contains_valof(&self) -> bool {
+        self.contains(|kind| matches!(kind, ExprKind::ValOf(_)))
+    }
+
+    pub fn contains_deref(&self) -> bool {
+        self.contains(|kind| matches!(kind, ExprKind::Deref(_)))
+    }
+
+    pub fn contains_layout_query(&self) -> bool {
+        self.contains(|kind| matches!(kind, ExprKind::SizeOf(_) | ExprKind::OffsetOf(..)))
+    }
+
+    pub fn kind(&self) -> &ExprKind {
+        &self.kind
+    }
+
     pub fn kind_mut(&mut self) -> &mut ExprKind {
         &mut self.kind
     }
@@ -68,6 +208,9 @@ pub enum ExprKind {
     True,
     False,
 
+    // the null pointer, typed by the pointer it is compared with or assigned to
+    Nil,
+
     Add(Box<Expr>, Box<Expr>),
     Sub(Box<Expr>, Box<Expr>),
     Mul(Box<Expr>, Box<Expr>),
@@ -81,6 +224,11 @@ pub enum ExprKind {
     Or(Box<Expr>, Box<Expr>),
     XOr(Box<Expr>, Box<Expr>),
 
+    // short-circuiting `&` and `|` on booleans; the right operand is only
+    // evaluated if the left one does not decide the result
+    LogAnd(Box<Expr>, Box<Expr>),
+    LogOr(Box<Expr>, Box<Expr>),
+
     Eq(Box<Expr>, Box<Expr>),
     Ne(Box<Expr>, Box<Expr>),
     Gt(Box<Expr>, Box<Expr>),
@@ -98,11 +246,116 @@ pub enum ExprKind {
 
     Cast(Box<Expr>),
     ImplicitCast(Box<Expr>),
+
+    // `checked`, `wrapping` and `saturating` select the overflow behavior of all arithmetic in
+    // the operand. After lowering, each arithmetic operation that does not wrap is marked individually.
+    Overflow(OverflowMode, Box<Expr>),
+
+    // layout queries, folded to constants once the target is known
+    SizeOf(TypeIndex),
+    OffsetOf(TypeIndex, String),
+
+    // `slct size:shift:offset`, a constant describing a bit field; see `pack_selector`
+    Selector(Box<Expr>, Box<Expr>, Box<Expr>),
+    // `selector of pointer`: the bit field `selector` of the record at `pointer`
+    FieldSelect(Box<Expr>, Box<Expr>),
+    // `value.field` of a struct; on pointers to structs, the parser inserts the dereference
+    Member(Box<Expr>, String),
+
+    // `vec n`: words `0` to `n` in the stack frame of the enclosing routine.
+    // The size has to be constant and is folded to an integer literal during lowering.
+    Vec(Box<Expr>),
+
     ValOf(Box<Stmt>), 
     FuncCall(Box<Expr>, Vec<Expr>),
+    // `name := value` as argument of a call; the type checker moves the value to the position
+    // of the parameter `name`
+    NamedArg(String, Box<Expr>),
+    // introduced by the type checker as the last argument of calls to variadic routines: the
+    // arguments after the fixed ones, passed as a vector holding their count followed by them
+    VarArgs(Vec<Expr>),
 
     Conditional(Box<Expr>, Box<Expr>, Box<Expr>),
 
     Match(Vec<Expr>, Vec<(Vec<Located<Pattern>>, Box<Expr>)>),
     Every(Vec<Expr>, Vec<(Vec<Located<Pattern>>, Box<Expr>)>),
+
+    // introduced during lowering, holds the result of a hoisted `valof`
+    Temporary(TempIndex),
+    // introduced during lowering, refers to a deduplicated string in read-only data
+    Constant(ConstIndex),
+
+    // an unparseable region the parser recovered from, spanning the node's location
+    Error,
+}
+
+impl ExprKind {
+    // direct subexpressions, not including those nested in statements or patterns
+    pub fn children(&self) -> Vec<&Expr> {
+        match self {
+            Self::Ident(_) | Self::Atom(_)
+                | Self::IntLit(_) | Self::FloatLit(_)
+                | Self::CharLit(_) | Self::StringLit(_)
+                | Self::True | Self::False | Self::Nil
+                | Self::SizeOf(_) | Self::OffsetOf(..)
+                | Self::ValOf(_) | Self::Temporary(_) | Self::Constant(_) | Self::Error => vec![],
+            Self::Abs(expr) | Self::Not(expr)
+                | Self::Ref(expr) | Self::Deref(expr) | Self::Overflow(_, expr)
+                | Self::Cast(expr) | Self::ImplicitCast(expr) | Self::Vec(expr) | Self::Member(expr, _) | Self::NamedArg(_, expr) => vec![expr],
+            Self::Add(lhs, rhs) | Self::Sub(lhs, rhs)
+                | Self::Mul(lhs, rhs) | Self::Div(lhs, rhs) | Self::Mod(lhs, rhs)
+                | Self::And(lhs, rhs) | Self::Or(lhs, rhs) | Self::XOr(lhs, rhs)
+                | Self::LogAnd(lhs, rhs) | Self::LogOr(lhs, rhs)
+                | Self::Eq(lhs, rhs) | Self::Ne(lhs, rhs) | Self::Gt(lhs, rhs)
+                | Self::Ge(lhs, rhs) | Self::Lt(lhs, rhs) | Self::Le(lhs, rhs)
+                | Self::LShift(lhs, rhs) | Self::RShift(lhs, rhs)
+                | Self::Index(lhs, rhs) | Self::FieldSelect(lhs, rhs) => vec![lhs, rhs],
+            Self::Slice(lhs, mhs, rhs) | Self::Conditional(lhs, mhs, rhs) | Self::Selector(lhs, mhs, rhs) => vec![lhs, mhs, rhs],
+            Self::FuncCall(callee, args) => std::iter::once(&**callee).chain(args.iter()).collect(),
+            Self::VarArgs(args) => args.iter().collect(),
+            Self::Match(args, branches) | Self::Every(args, branches) => args.iter()
+                .chain(branches.iter().map(|(_, expr)| &**expr))
+                .collect()
+        }
+    }
+
+    pub fn children_mut(&mut self) -> Vec<&mut Expr> {
+        match self {
+            Self::Ident(_) | Self::Atom(_)
+                | Self::IntLit(_) | Self::FloatLit(_)
+                | Self::CharLit(_) | Self::StringLit(_)
+                | Self::True | Self::False | Self::Nil
+                | Self::SizeOf(_) | Self::OffsetOf(..)
+                | Self::ValOf(_) | Self::Temporary(_) | Self::Constant(_) | Self::Error => vec![],
+            Self::Abs(expr) | Self::Not(expr)
+                | Self::Ref(expr) | Self::Deref(expr) | Self::Overflow(_, expr)
+                | Self::Cast(expr) | Self::ImplicitCast(expr) | Self::Vec(expr) | Self::Member(expr, _) | Self::NamedArg(_, expr) => vec![expr],
+            Self::Add(lhs, rhs) | Self::Sub(lhs, rhs)
+                | Self::Mul(lhs, rhs) | Self::Div(lhs, rhs) | Self::Mod(lhs, rhs)
+                | Self::And(lhs, rhs) | Self::Or(lhs, rhs) | Self::XOr(lhs, rhs)
+                | Self::LogAnd(lhs, rhs) | Self::LogOr(lhs, rhs)
+                | Self::Eq(lhs, rhs) | Self::Ne(lhs, rhs) | Self::Gt(lhs, rhs)
+                | Self::Ge(lhs, rhs) | Self::Lt(lhs, rhs) | Self::Le(lhs, rhs)
+                | Self::LShift(lhs, rhs) | Self::RShift(lhs, rhs)
+                | Self::Index(lhs, rhs) | Self::FieldSelect(lhs, rhs) => vec![lhs, rhs],
+            Self::Slice(lhs, mhs, rhs) | Self::Conditional(lhs, mhs, rhs) | Self::Selector(lhs, mhs, rhs) => vec![lhs, mhs, rhs],
+            Self::FuncCall(callee, args) => std::iter::once(&mut **callee).chain(args.iter_mut()).collect(),
+            Self::VarArgs(args) => args.iter_mut().collect(),
+            Self::Match(args, branches) | Self::Every(args, branches) => args.iter_mut()
+                .chain(branches.iter_mut().map(|(_, expr)| &mut **expr))
+                .collect()
+        }
+    }
+}
+
+// Selectors pack the size and shift of a bit field in bits and its offset in words into one
+// word: `size << 24 | shift << 16 | offset`. A size of 0 extends the field to the end of the word.
+pub fn pack_selector(size: i64, shift: i64, offset: i64) -> Option<i64> {
+    ((0..=0xff).contains(&size) && (0..=0xff).contains(&shift) && (0..=0xffff).contains(&offset))
+        .then_some(size << 24 | shift << 16 | offset)
+}
+
+//                                            size shift offset
+pub fn unpack_selector(selector: i64) -> (u32, u32, u64) {
+    ((selector >> 24 & 0xff) as u32, (selector >> 16 & 0xff) as u32, (selector & 0xffff) as u64)
 }
@@ -0,0 +1,101 @@
+use crate::match_decl;
+
+use super::{Section, Decl, Function, GlobalDecl, ManifestDecl, Storage, types::TypeIndex};
+
+// The publicly visible declarations of a section, as seen by other compilation units
+#[derive(Debug, Default)]
+pub struct SectionInterface {
+    ident: String,
+    // the metadata file this interface was imported from
+    origin: Option<String>,
+
+    routines: Vec<RoutineInterface>,
+    globals: Vec<GlobalInterface>,
+    manifests: Vec<(String, Option<TypeIndex>, Option<i64>)>
+}
+
+#[derive(Debug)]
+pub struct GlobalInterface {
+    pub ident: String,
+    pub slot: Option<u32>,
+    pub typ: Option<TypeIndex>
+}
+
+#[derive(Debug)]
+pub struct RoutineInterface {
+    pub ident: String,
+    pub params: Vec<Option<TypeIndex>>,
+    // takes further arguments after `params`
+    pub variadic: bool,
+    pub return_type: Option<TypeIndex>
+}
+
+impl SectionInterface {
+    pub fn new(ident: String, origin: Option<String>) -> Self {
+        Self {
+            ident,
+            origin,
+            ..Default::default()
+        }
+    }
+
+    pub fn of(section: &Section) -> Self {
+        let mut interface = Self::new(section.ident().clone(), None);
+
+        let mut decls = section.declarations()
+            .filter(|decl| decl.is_public())
+            .collect::<Vec<_>>();
+        decls.sort_by(|a, b| a.ident().cmp(b.ident()));
+
+        for decl in decls {
+            match_decl!{
+                decl;
+                func as Function => interface.add_routine(RoutineInterface {
+                    ident: func.ident().clone(),
+                    params: func.params().iter().map(|param| param.typ()).collect(),
+                    variadic: func.variadic().is_some(),
+                    return_type: *func.return_type()
+                }),
+                global as GlobalDecl => if global.storage() == Storage::Global {
+                    interface.add_global(GlobalInterface { ident: global.ident().clone(), slot: global.slot(), typ: *global.typ() })
+                },
+                manifest as ManifestDecl => interface.add_manifest(manifest.ident().clone(), *manifest.value().typ(), manifest.value().const_int()),
+                _ => ()
+            }
+        }
+
+        interface
+    }
+
+    pub fn ident(&self) -> &String {
+        &self.ident
+    }
+
+    pub fn add_routine(&mut self, routine: RoutineInterface) {
+        self.routines.push(routine);
+    }
+
+    pub fn origin(&self) -> &Option<String> {
+        &self.origin
+    }
+
+    pub fn add_global(&mut self, global: GlobalInterface) {
+        self.globals.push(global);
+    }
+
+    pub fn add_manifest(&mut self, ident: String, typ: Option<TypeIndex>, value: Option<i64>) {
+        self.manifests.push((ident, typ, value));
+    }
+
+    pub fn routines(&self) -> &Vec<RoutineInterface> {
+        &self.routines
+    }
+
+    pub fn globals(&self) -> &Vec<GlobalInterface> {
+        &self.globals
+    }
+
+    pub fn manifests(&self) -> &Vec<(String, Option<TypeIndex>, Option<i64>)> {
+        &self.manifests
+    }
+}
@@ -0,0 +1,138 @@
+use super::types::{TypeList, TypeIndex, TypeKind, SumVariant};
+
+// Target-dependent parameters of the layout engine
+#[derive(Clone, Copy, Debug)]
+pub struct DataLayout {
+    word_size: u64
+}
+
+impl Default for DataLayout {
+    fn default() -> Self {
+        Self {
+            word_size: std::mem::size_of::<usize>() as u64
+        }
+    }
+}
+
+impl DataLayout {
+    pub fn from_bits(bits: u32) -> Option<Self> {
+        match bits {
+            32 | 64 => Some(Self { word_size: bits as u64 / 8 }),
+            _ => None
+        }
+    }
+
+    pub fn word_size(&self) -> u64 {
+        self.word_size
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Layout {
+    size: u64,
+    align: u64
+}
+
+impl Layout {
+    fn scalar(size: u64) -> Self {
+        Self {
+            size,
+            align: size.max(1)
+        }
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn align(&self) -> u64 {
+        self.align
+    }
+
+    // lays out `fields` in order, padding each to its alignment, like a C compiler does.
+    // `[[repr("C")]]` structs rely on this to be shared with C code.
+    fn record(fields: impl Iterator<Item=Layout>) -> (Self, Vec<u64>) {
+        let mut record = Self { size: 0, align: 1 };
+        let mut offsets = vec![];
+
+        for field in fields {
+            record.size = record.size.next_multiple_of(field.align);
+            offsets.push(record.size);
+            record.size += field.size;
+            record.align = record.align.max(field.align);
+        }
+
+        record.size = record.size.next_multiple_of(record.align);
+        (record, offsets)
+    }
+}
+
+impl TypeList {
+    // `None` for types without a statically known size, like slices of unknown types or undefined aliases
+    pub fn layout(&self, index: TypeIndex, target: &DataLayout) -> Option<Layout> {
+        let typ = self.get(self.resolve(index))?;
+
+        match typ.kind() {
+            TypeKind::Pointer(_) | TypeKind::Routine(..) => Some(Layout::scalar(target.word_size)),
+            TypeKind::Slice(_) => Some(Layout { size: 2 * target.word_size, align: target.word_size }),
+            TypeKind::Array(inner, len) => {
+                let inner = self.layout(*inner, target)?;
+                Some(Layout {
+                    size: inner.size.checked_mul(u64::try_from(len.const_int()?).ok()?)?,
+                    align: inner.align
+                })
+            }
+            TypeKind::Alias(..) => None,
+            TypeKind::Sum(variants) => {
+                // a tag word followed by the largest variant
+                let payloads = variants.iter()
+                    .map(|SumVariant::Basic(_, fields)| fields.iter()
+                        .map(|field| self.layout(*field, target))
+                        .collect::<Option<Vec<_>>>()
+                        .map(|fields| Layout::record(fields.into_iter()).0)
+                    )
+                    .collect::<Option<Vec<_>>>()?;
+                let payload = payloads.into_iter()
+                    .fold(Layout { size: 0, align: 1 }, |a, b| Layout { size: a.size.max(b.size), align: a.align.max(b.align) });
+                Some(Layout::record([Layout::scalar(target.word_size), payload].into_iter()).0)
+            }
+            TypeKind::Struct(fields) => self.struct_layout(fields, target).map(|(layout, _)| layout),
+            TypeKind::Enum(..) => Some(Layout::scalar(target.word_size)),
+            kind => kind.try_get_size().map(|size| Layout::scalar(size as u64))
+        }
+    }
+
+    // byte offset of a named field of a struct
+    pub fn field_offset(&self, index: TypeIndex, field: &str, target: &DataLayout) -> Option<u64> {
+        let TypeKind::Struct(fields) = self.get(self.resolve(index))?.kind() else {
+            return None
+        };
+
+        let position = fields.iter().position(|(ident, _)| ident == field)?;
+        self.struct_layout(fields, target).map(|(_, offsets)| offsets[position])
+    }
+
+    // the first type held by a value of type `index` that has no C equivalent, which makes it
+    // unfit for fields of `[[repr("C")]]` structs. Pointers, to data or routines, are fine whatever they point to.
+    pub fn non_c_type(&self, index: TypeIndex) -> Option<TypeIndex> {
+        let Some(typ) = self.get(self.resolve(index)) else {
+            return Some(index)
+        };
+
+        match typ.kind() {
+            kind if kind.is_integer() || kind.is_float() => None,
+            TypeKind::Bool | TypeKind::Char | TypeKind::Enum(..) | TypeKind::Pointer(_) | TypeKind::Routine(..) => None,
+            TypeKind::Array(inner, len) if len.const_int().is_some() => self.non_c_type(*inner),
+            // checked on their own
+            TypeKind::Struct(_) if typ.is_c_layout() => None,
+            _ => Some(index)
+        }
+    }
+
+    fn struct_layout(&self, fields: &[(String, TypeIndex)], target: &DataLayout) -> Option<(Layout, Vec<u64>)> {
+        let fields = fields.iter()
+            .map(|(_, typ)| self.layout(*typ, target))
+            .collect::<Option<Vec<_>>>()?;
+        Some(Layout::record(fields.into_iter()))
+    }
+}
@@ -2,10 +2,13 @@ use std::{collections::{HashMap, HashSet}, fmt::Debug, any::Any};
 
 use crate::source_file::{Location, Located};
 
-use self::{types::{TypeList, TypeIndex}, expr::{Expr, AtomIndex}, stmt::Stmt, pattern::Pattern, visitor::Traversable};
+use self::{attribute::{Deprecation, CallingConvention, Hook}, types::{TypeList, TypeIndex}, interface::SectionInterface, expr::{Expr, AtomIndex}, stmt::Stmt, pattern::Pattern, visitor::Traversable};
 
 pub(crate) mod types;
-pub(crate) mod expr;
+pub(crate) mod attribute;
+pub mod layout;
+pub(crate) mod interface;
+pub mod expr;
 pub(crate) mod stmt;
 pub(crate) mod pattern;
 pub(crate) mod visitor;
@@ -15,6 +18,9 @@ pub struct Program {
     sections: HashMap<String, Section>,
     types: TypeList,
 
+    // sections of other compilation units, imported from metadata files
+    interfaces: HashMap<String, SectionInterface>,
+
     next_atom_index: AtomIndex,
     atoms: HashMap<String, AtomIndex>
 }
@@ -40,13 +46,92 @@ impl Program {
         }
     }
 
+    pub fn atom_name(&self, index: AtomIndex) -> Option<&String> {
+        self.atoms.iter().find_map(|(name, i)| (*i == index).then_some(name))
+    }
+
+    pub fn sections(&self) -> impl Iterator<Item=&Section> {
+        self.sections.values()
+    }
+
+    pub fn section(&self, ident: &String) -> Option<&Section> {
+        self.sections.get(ident)
+    }
+
+    // the first declaration of `ident` in any section, which may come from another file
+    pub fn defines(&self, ident: &String) -> Option<&Box<dyn Decl>> {
+        self.sections.values()
+            .filter_map(|section| section.defines(ident))
+            .min_by(|a, b| a.location().cmp(b.location()))
+    }
+
+    pub fn sections_mut(&mut self) -> impl Iterator<Item=&mut Section> {
+        self.sections.values_mut()
+    }
+
+    pub fn section_mut(&mut self, ident: &String) -> Option<&mut Section> {
+        self.sections.get_mut(ident)
+    }
+
     pub fn types(&self) -> &TypeList {
         &self.types
     }
 
+    // the type list stays readable while declarations are changed
+    pub fn types_and_sections_mut(&mut self) -> (&TypeList, impl Iterator<Item=&mut Section>) {
+        (&self.types, self.sections.values_mut())
+    }
+
+    pub fn import(&mut self, interface: SectionInterface) {
+        self.interfaces.insert(interface.ident().clone(), interface);
+    }
+
+    pub fn interface(&self, section: &str) -> Option<&SectionInterface> {
+        self.interfaces.get(section)
+    }
+
+    pub fn interfaces(&self) -> impl Iterator<Item=&SectionInterface> {
+        self.interfaces.values()
+    }
+
     pub fn types_mut(&mut self) -> &mut TypeList {
         &mut self.types
     }
+
+    // stable textual form of the parsed program, independent of hash map ordering
+    pub fn dump(&self) -> String {
+        let mut out = String::new();
+        for (index, typ) in self.types.iter().enumerate() {
+            out.push_str(&format!("type {index} = {:?}\n", typ.kind()));
+        }
+
+        let mut sections: Vec<&Section> = self.sections.values().collect();
+        sections.sort_by(|a, b| a.ident.cmp(&b.ident));
+        for section in sections {
+            out.push_str(&format!("\nsection {}\n", section.ident));
+
+            let mut required: Vec<&String> = section.required.iter().map(|require| &**require).collect();
+            required.sort();
+            for require in required {
+                out.push_str(&format!("require {require}\n"));
+            }
+            for need in &section.needs {
+                out.push_str(&format!("needs {:?}\n", **need));
+            }
+
+            let mut declarations: Vec<&Box<dyn Decl>> = section.declarations.values().collect();
+            declarations.sort_by_key(|decl| decl.location().clone());
+            for decl in declarations {
+                out.push_str(&format!("{decl:#?}\n"));
+            }
+
+            for (condition, message) in &section.assertions {
+                out.push_str(&format!("assert {condition:#?}, {message:?}\n"));
+            }
+        }
+
+        out
+    }
 }
 
 #[derive(Debug)]
@@ -55,8 +140,12 @@ pub struct Section {
     ident: String,
 
     required: HashSet<Located<String>>,
+    // sections named by `needs "name"`, which have to be part of the program or imported
+    needs: Vec<Located<String>>,
 
-    declarations: HashMap<String, Box<dyn Decl>>
+    declarations: HashMap<String, Box<dyn Decl>>,
+    //               condition message
+    assertions: Vec<(Expr, String)>
 }
 
 impl Section {
@@ -65,10 +154,16 @@ impl Section {
             loc,
             ident,
             required: HashSet::new(),
-            declarations: HashMap::new()
+            needs: vec![],
+            declarations: HashMap::new(),
+            assertions: vec![]
         }
     }
 
+    pub fn location(&self) -> &Location {
+        &self.loc
+    }
+
     pub fn ident(&self) -> &String {
         &self.ident
     }
@@ -77,20 +172,58 @@ impl Section {
         self.declarations.get(ident)
     }
 
+    pub fn declarations(&self) -> impl Iterator<Item=&Box<dyn Decl>> {
+        self.declarations.values()
+    }
+
+    pub fn declarations_mut(&mut self) -> impl Iterator<Item=&mut Box<dyn Decl>> {
+        self.declarations.values_mut()
+    }
+
+    pub fn required(&self) -> impl Iterator<Item=&Located<String>> {
+        self.required.iter()
+    }
+
     pub fn add_require(&mut self, require: Located<String>) {
         self.required.insert(require);
     }
 
+    pub fn needs(&self) -> &Vec<Located<String>> {
+        &self.needs
+    }
+
+    pub fn add_need(&mut self, need: Located<String>) {
+        if !self.needs.contains(&need) {
+            self.needs.push(need);
+        }
+    }
+
+    pub fn remove(&mut self, ident: &String) -> Option<Box<dyn Decl>> {
+        self.declarations.remove(ident)
+    }
+
     pub fn declare(&mut self, decl: Box<dyn Decl>) {
         self.declarations.insert(decl.ident().clone(), decl);
     }
+
+    pub fn assertions(&self) -> &Vec<(Expr, String)> {
+        &self.assertions
+    }
+
+    pub fn add_assertion(&mut self, condition: Expr, message: String) {
+        self.assertions.push((condition, message));
+    }
 }
 
-pub trait Decl: Debug {
+// shared between the typechecker's worker threads
+pub trait Decl: Debug + Send + Sync {
     fn location(&self) -> &Location;
     fn ident(&self) -> &String;
     fn is_public(&self) -> bool;
 
+    fn deprecation(&self) -> Option<&Deprecation>;
+    fn set_deprecation(&mut self, deprecation: Deprecation);
+
     fn as_any(&self) -> &dyn Any;
     fn as_mut_any(&mut self) -> &mut dyn Any;
 }
@@ -147,7 +280,34 @@ pub struct ManifestDecl {
 
     ident: String,
 
-    value: Expr
+    value: Expr,
+    deprecation: Option<Deprecation>
+}
+
+impl ManifestDecl {
+    pub fn new(loc: Location, ident: String, value: Expr) -> Self {
+        Self {
+            loc,
+            is_public: true,
+            ident,
+            value,
+            deprecation: None
+        }
+    }
+
+    pub fn value(&self) -> &Expr {
+        &self.value
+    }
+
+    pub fn value_mut(&mut self) -> &mut Expr {
+        &mut self.value
+    }
+}
+
+impl IntoDecl for ManifestDecl {
+    fn into_decl(self) -> Box<dyn Decl> {
+        Box::new(self)
+    }
 }
 
 impl Decl for ManifestDecl {
@@ -163,6 +323,14 @@ impl Decl for ManifestDecl {
         self.is_public
     }
 
+    fn deprecation(&self) -> Option<&Deprecation> {
+        self.deprecation.as_ref()
+    }
+
+    fn set_deprecation(&mut self, deprecation: Deprecation) {
+        self.deprecation = Some(deprecation);
+    }
+
     fn as_any(&self) -> &dyn Any {
         self
     }
@@ -172,7 +340,111 @@ impl Decl for ManifestDecl {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Storage {
+    Global,
+    Static
+}
+
+// `global` and `static` variables, initialized once before `main` runs
 #[derive(Debug)]
+pub struct GlobalDecl {
+    loc: Location,
+    is_public: bool,
+    storage: Storage,
+
+    ident: String,
+    // index into the global vector, shared by all modules of a program
+    slot: Option<u32>,
+    typ: Option<TypeIndex>,
+
+    value: Option<Expr>,
+    // object file section set by `_section("name")`
+    link_section: Option<String>,
+    deprecation: Option<Deprecation>
+}
+
+impl GlobalDecl {
+    pub fn new(loc: Location, storage: Storage, ident: String, slot: Option<u32>, typ: Option<TypeIndex>, value: Option<Expr>) -> Self {
+        Self {
+            loc,
+            is_public: storage == Storage::Global,
+            storage,
+            ident,
+            slot,
+            typ,
+            value,
+            link_section: None,
+            deprecation: None
+        }
+    }
+
+    pub fn link_section(&self) -> Option<&String> {
+        self.link_section.as_ref()
+    }
+
+    pub fn set_link_section(&mut self, link_section: String) {
+        self.link_section = Some(link_section);
+    }
+
+    pub fn storage(&self) -> Storage {
+        self.storage
+    }
+
+    pub fn slot(&self) -> Option<u32> {
+        self.slot
+    }
+
+    pub fn typ(&self) -> &Option<TypeIndex> {
+        &self.typ
+    }
+
+    pub fn value(&self) -> &Option<Expr> {
+        &self.value
+    }
+
+    pub fn value_mut(&mut self) -> &mut Option<Expr> {
+        &mut self.value
+    }
+}
+
+impl IntoDecl for GlobalDecl {
+    fn into_decl(self) -> Box<dyn Decl> {
+        Box::new(self)
+    }
+}
+
+impl Decl for GlobalDecl {
+    fn location(&self) -> &Location {
+        &self.loc
+    }
+
+    fn ident(&self) -> &String {
+        &self.ident
+    }
+
+    fn is_public(&self) -> bool {
+        self.is_public
+    }
+
+    fn deprecation(&self) -> Option<&Deprecation> {
+        self.deprecation.as_ref()
+    }
+
+    fn set_deprecation(&mut self, deprecation: Deprecation) {
+        self.deprecation = Some(deprecation);
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_mut_any(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct Function {
     loc: Location,
     is_public: bool,
@@ -181,11 +453,24 @@ pub struct Function {
 
     params: Vec<Param>,
     required_params: u32,
+    // trailing `...` taking any number of further arguments
+    variadic: Option<Variadic>,
+    // locals of enclosing routines it refers to, through the environment record passed as its first parameter
+    captures: Vec<String>,
+    // `<T, ...>` of a generic routine, which is only compiled as the instances its calls need
+    type_params: Vec<TypeIndex>,
 
     return_type: Option<TypeIndex>,
     tailcall_recursive: bool, // recursiveness indicated by the `and` declaration
 
-    body: FunctionBody
+    body: FunctionBody,
+    // object file section set by `_section("name")`
+    link_section: Option<String>,
+    deprecation: Option<Deprecation>,
+    // hints for code generation, set by `[[inline]]` and `[[ccall]]`
+    inline: bool,
+    calling_convention: CallingConvention,
+    hook: Option<Hook>
 }
 
 impl Function {
@@ -196,16 +481,113 @@ impl Function {
             ident,
             required_params: required_params_of(&params),
             params,
+            variadic: None,
+            captures: vec![],
+            type_params: vec![],
             return_type,
             tailcall_recursive,
-            body
+            body,
+            link_section: None,
+            deprecation: None,
+            inline: false,
+            calling_convention: CallingConvention::default(),
+            hook: None
         }
     }
+
+    pub fn link_section(&self) -> Option<&String> {
+        self.link_section.as_ref()
+    }
+
+    pub fn set_link_section(&mut self, link_section: String) {
+        self.link_section = Some(link_section);
+    }
+
+    pub fn is_inline(&self) -> bool {
+        self.inline
+    }
+
+    pub fn set_inline(&mut self) {
+        self.inline = true;
+    }
+
+    pub fn calling_convention(&self) -> CallingConvention {
+        self.calling_convention
+    }
+
+    pub fn set_calling_convention(&mut self, calling_convention: CallingConvention) {
+        self.calling_convention = calling_convention;
+    }
+
+    pub fn hook(&self) -> Option<Hook> {
+        self.hook
+    }
+
+    pub fn set_hook(&mut self, hook: Hook) {
+        self.hook = Some(hook);
+    }
+
+    pub fn params(&self) -> &Vec<Param> {
+        &self.params
+    }
+
+    // parameters before the first one with a default value
+    pub fn required_params(&self) -> u32 {
+        self.required_params
+    }
+
+    pub fn variadic(&self) -> Option<&Variadic> {
+        self.variadic.as_ref()
+    }
+
+    pub fn set_variadic(&mut self, variadic: Variadic) {
+        self.variadic = Some(variadic);
+    }
+
+    pub fn captures(&self) -> &Vec<String> {
+        &self.captures
+    }
+
+    pub fn set_captures(&mut self, captures: Vec<String>) {
+        self.captures = captures;
+    }
+
+    pub fn type_params(&self) -> &Vec<TypeIndex> {
+        &self.type_params
+    }
+
+    pub fn set_type_params(&mut self, type_params: Vec<TypeIndex>) {
+        self.type_params = type_params;
+    }
+
+    pub fn is_generic(&self) -> bool {
+        !self.type_params.is_empty()
+    }
+
+    pub fn set_ident(&mut self, ident: String) {
+        self.ident = ident;
+    }
+
+    pub fn return_type(&self) -> &Option<TypeIndex> {
+        &self.return_type
+    }
+
+    pub fn set_return_type(&mut self, return_type: TypeIndex) {
+        self.return_type = Some(return_type);
+    }
+
+    pub fn is_tailcall_recursive(&self) -> bool {
+        self.tailcall_recursive
+    }
+
+    pub fn body(&self) -> &FunctionBody {
+        &self.body
+    }
 }
 
 fn required_params_of(params: &[Param]) -> u32 {
     if let Some((i, _)) = params.iter().enumerate().find(|(_, param)| param.default_value.is_some()) {
-        i as u32 - 1
+        i as u32
     }
     else {
         params.len() as u32
@@ -231,6 +613,14 @@ impl Decl for Function {
         self.is_public
     }
 
+    fn deprecation(&self) -> Option<&Deprecation> {
+        self.deprecation.as_ref()
+    }
+
+    fn set_deprecation(&mut self, deprecation: Deprecation) {
+        self.deprecation = Some(deprecation);
+    }
+
     fn as_any(&self) -> &dyn Any {
         self
     }
@@ -240,7 +630,7 @@ impl Decl for Function {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum FunctionBody {
     Expr(Expr),
     Stmt(Stmt),
@@ -263,7 +653,7 @@ pub enum BasicFunctionBody {
     Stmt(Stmt)
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Param {
     loc: Location,
     ident: Located<Pattern>,
@@ -280,5 +670,50 @@ impl Param {
             default_value
         }
     }
+
+    pub fn location(&self) -> &Location {
+        &self.loc
+    }
+
+    pub fn typ(&self) -> Option<TypeIndex> {
+        self.typ
+    }
+
+    pub fn set_typ(&mut self, typ: TypeIndex) {
+        self.typ = Some(typ);
+    }
+
+    pub fn pattern(&self) -> &Located<Pattern> {
+        &self.ident
+    }
+
+    pub fn default_value(&self) -> Option<&Expr> {
+        self.default_value.as_ref()
+    }
+}
+
+// `...` or `... rest` after the last parameter. Calls pass the further arguments as a vector
+// holding their count, followed by the arguments; `rest` names that vector in the body.
+#[derive(Clone, Debug)]
+pub struct Variadic {
+    loc: Location,
+    ident: Option<String>
+}
+
+impl Variadic {
+    pub fn new(loc: Location, ident: Option<String>) -> Self {
+        Self {
+            loc,
+            ident
+        }
+    }
+
+    pub fn location(&self) -> &Location {
+        &self.loc
+    }
+
+    pub fn ident(&self) -> Option<&String> {
+        self.ident.as_ref()
+    }
 }
 
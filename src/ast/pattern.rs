@@ -1,4 +1,4 @@
-use crate::source_file::{Located, WithLocation};
+use crate::source_file::{Location, Located, WithLocation};
 
 use super::expr::Expr;
 
@@ -18,6 +18,23 @@ pub enum Pattern {
 
 impl WithLocation for Pattern {}
 
+// identifiers bound by a pattern, each once; both sides of `A | B` bind the same names
+pub fn bound_idents(pattern: &Located<Pattern>) -> Vec<(&String, &Location)> {
+    let mut idents: Vec<(&String, &Location)> = vec![];
+    let mut walk = vec![pattern];
+    while let Some(pattern) = walk.pop() {
+        match &**pattern {
+            Pattern::Query(ident) => if !idents.iter().any(|(other, _)| *other == ident) {
+                idents.push((ident, pattern.location()));
+            }
+            Pattern::Or(lhs, rhs) | Pattern::And(lhs, rhs) => walk.extend([&**rhs, &**lhs]),
+            Pattern::Variant(_, args) | Pattern::List(args) => walk.extend(args.iter().rev()),
+            Pattern::Any | Pattern::Remaining | Pattern::Term(_) => ()
+        }
+    }
+    idents
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum PatternTerm {
     Basic(Expr),
@@ -17,9 +17,27 @@ impl Stmt {
         }
     }
 
+    pub fn location(&self) -> &Location {
+        &self.loc
+    }
+
+    pub fn kind(&self) -> &StmtKind {
+        &self.kind
+    }
+
     pub fn kind_mut(&mut self) -> &mut StmtKind {
         &mut self.kind
     }
+
+    // all identifiers referenced by expressions in this statement
+    pub fn referenced_idents<'a>(&'a self, idents: &mut Vec<&'a String>) {
+        for expr in self.kind.exprs() {
+            expr.referenced_idents(idents);
+        }
+        for stmt in self.kind.stmts() {
+            stmt.referenced_idents(idents);
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -38,18 +56,104 @@ pub enum StmtKind {
     While(Box<Expr>, Box<Stmt>),
     Until(Box<Expr>, Box<Stmt>),
 
+    // trailing-condition loops, the body runs at least once
+    Repeat(Box<Stmt>),
+    RepeatWhile(Box<Stmt>, Box<Expr>),
+    RepeatUntil(Box<Stmt>, Box<Expr>),
+
     //  iterator   init val   target val         stepsize           body
     For(Located<Pattern>, Box<Expr>, Option<Box<Expr>>, Option<Box<Expr>>, Box<Stmt>),
 
     SwitchOn(Box<Expr>, Box<Stmt>),
-    Case(Box<Expr>),
+    // the values of `case a, b, lo to hi`, each with the upper bound of a range
+    Case(Vec<(Expr, Option<Expr>)>),
     DefaultCase,
     
     Break,
     Next,
+    // continues the innermost loop, even from within `match` or `every`
+    Loop,
+    // `goto label` jumps to the statement `label: stmt` of the same routine
+    Goto(Located<String>),
+    Labelled(Located<String>, Box<Stmt>),
+
+    // `try body catch (e) handler`: a `throw` in the body, or in a routine called from it,
+    // unwinds to the handler with the thrown value bound to `e`
+    //  body       caught value       handler
+    Try(Box<Stmt>, Located<Pattern>, Box<Stmt>),
+    Throw(Box<Expr>),
+    // throws the value caught by the innermost enclosing handler again
+    Rethrow,
 
     Match(Vec<Expr>, Vec<(Vec<Located<Pattern>>, Box<Stmt>)>),
     Every(Vec<Expr>, Vec<(Vec<Located<Pattern>>, Box<Stmt>)>),
 
-    Binding(Vec<(Located<Pattern>, Expr)>)
+    Binding(Vec<(Located<Pattern>, Expr)>),
+    // `a, b := x, y`; all values are computed before the first target is written
+    //         target value
+    Assign(Vec<(Expr, Expr)>),
+
+    // checked at compile time; holds the message reported when the condition is false
+    Assert(Box<Expr>, String),
+
+    // `asm { ... }`: instructions passed to the assembler as they are, where `{n}` stands for
+    // the n-th operand, counting the outputs first. Operands are locals of the routine.
+    //        template outputs    inputs
+    InlineAsm(String, Vec<Expr>, Vec<Expr>),
+
+    // an unparseable region the parser recovered from, spanning the node's location
+    Error
+}
+
+impl StmtKind {
+    // expressions directly contained in this statement
+    pub fn exprs(&self) -> Vec<&Expr> {
+        match self {
+            Self::Nop | Self::Return | Self::DefaultCase
+                | Self::Break | Self::Next | Self::Loop | Self::Goto(_) | Self::Labelled(..)
+                | Self::Block(_) | Self::Repeat(_) | Self::Try(..) | Self::Rethrow | Self::Error => vec![],
+            Self::Expr(expr) | Self::ResultIs(expr) | Self::Assert(expr, _) | Self::Throw(expr)
+                | Self::If(expr, ..) | Self::Unless(expr, _) | Self::SwitchOn(expr, _)
+                | Self::While(expr, _) | Self::Until(expr, _)
+                | Self::RepeatWhile(_, expr) | Self::RepeatUntil(_, expr) => vec![expr],
+            Self::Case(values) => values.iter().flat_map(|(expr, upper)| std::iter::once(expr).chain(upper)).collect(),
+            Self::For(_, init, bound, step, _) => std::iter::once(&**init)
+                .chain(bound.as_deref())
+                .chain(step.as_deref())
+                .collect(),
+            Self::Match(args, _) | Self::Every(args, _) => args.iter().collect(),
+            Self::Binding(bindings) => bindings.iter().map(|(_, expr)| expr).collect(),
+            Self::Assign(pairs) => pairs.iter().flat_map(|(target, value)| [target, value]).collect(),
+            Self::InlineAsm(_, outputs, inputs) => outputs.iter().chain(inputs).collect()
+        }
+    }
+
+    // statements directly nested in this statement
+    pub fn stmts(&self) -> Vec<&Stmt> {
+        match self {
+            Self::Block(stmts) => stmts.iter().collect(),
+            Self::If(_, if_branch, else_branch) => std::iter::once(&**if_branch).chain(else_branch.as_deref()).collect(),
+            Self::Try(body, _, handler) => vec![body, handler],
+            Self::Unless(_, body) | Self::SwitchOn(_, body) | Self::Labelled(_, body)
+                | Self::While(_, body) | Self::Until(_, body)
+                | Self::Repeat(body) | Self::RepeatWhile(body, _) | Self::RepeatUntil(body, _)
+                | Self::For(.., body) => vec![body],
+            Self::Match(_, branches) | Self::Every(_, branches) => branches.iter().map(|(_, body)| &**body).collect(),
+            _ => vec![]
+        }
+    }
+
+    pub fn stmts_mut(&mut self) -> Vec<&mut Stmt> {
+        match self {
+            Self::Block(stmts) => stmts.iter_mut().collect(),
+            Self::If(_, if_branch, else_branch) => std::iter::once(&mut **if_branch).chain(else_branch.as_deref_mut()).collect(),
+            Self::Try(body, _, handler) => vec![body, handler],
+            Self::Unless(_, body) | Self::SwitchOn(_, body) | Self::Labelled(_, body)
+                | Self::While(_, body) | Self::Until(_, body)
+                | Self::Repeat(body) | Self::RepeatWhile(body, _) | Self::RepeatUntil(body, _)
+                | Self::For(.., body) => vec![body],
+            Self::Match(_, branches) | Self::Every(_, branches) => branches.iter_mut().map(|(_, body)| &mut **body).collect(),
+            _ => vec![]
+        }
+    }
 }
@@ -12,6 +12,8 @@ pub struct Type {
     size: u32,
 
     is_builtin: bool,
+    // set on structs by `[[repr("C")]]`; see `TypeList::non_c_type`
+    c_layout: bool,
     kind: TypeKind
 }
 
@@ -21,6 +23,7 @@ impl Type {
             loc,
             size: kind.try_get_size().unwrap_or(0),
             is_builtin: false,
+            c_layout: false,
             kind
         }
     }
@@ -30,6 +33,7 @@ impl Type {
             loc: None,
             size: kind.try_get_size().expect(&format!("internal error when initializing builtin type {kind:?}")),
             is_builtin: true,
+            c_layout: false,
             kind
         }
     }
@@ -49,6 +53,14 @@ impl Type {
     pub fn kind(&self) -> &TypeKind {
         &self.kind
     }
+
+    pub fn is_c_layout(&self) -> bool {
+        self.c_layout
+    }
+
+    pub fn set_c_layout(&mut self) {
+        self.c_layout = true;
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -78,15 +90,37 @@ pub enum TypeKind {
 
     Alias(String, Option<TypeIndex>),
     Sum(Vec<SumVariant>),
+    // fields in declaration order; every `struct` is a distinct type, even with the same fields
+    Struct(Vec<(String, TypeIndex)>),
+    // named constants numbered from zero; like structs, each declaration is a distinct type
+    Enum(String, Vec<String>),
+    // a type parameter of a generic routine, replaced in each of its instances
+    Param(String),
+    // the address of a routine taking parameters of the first types and returning the last,
+    // `Unit` for routines defined with `be`; calls through it are checked against them
+    Routine(Vec<TypeIndex>, TypeIndex),
 
     // Table
-    // Function
-    // Generic
-    // Record
     // ...
 }
 
 impl TypeKind {
+    pub fn is_integer(&self) -> bool {
+        matches!(self,
+            TypeKind::UInt8 | TypeKind::UInt16 | TypeKind::UInt32 | TypeKind::UInt64
+            | TypeKind::Int8 | TypeKind::Int16 | TypeKind::Int32 | TypeKind::Int64
+        )
+    }
+
+    pub fn is_float(&self) -> bool {
+        matches!(self, TypeKind::Float32 | TypeKind::Float64)
+    }
+
+    // anything that fits into a single BCPL word and behaves like an integer
+    pub fn is_word(&self) -> bool {
+        self.is_integer() || matches!(self, TypeKind::Char | TypeKind::Bool | TypeKind::Pointer(_) | TypeKind::Enum(..))
+    }
+
     pub fn try_get_size(&self) -> Option<u32> {
         match self {
             TypeKind::Unit => Some(0),
@@ -94,7 +128,7 @@ impl TypeKind {
             TypeKind::UInt16 | TypeKind::Int16 => Some(2),
             TypeKind::UInt32 | TypeKind::Int32 | TypeKind::Float32 | TypeKind::Atom => Some(4),
             TypeKind::UInt64 | TypeKind::Int64 | TypeKind::Float64 => Some(8),
-            TypeKind::Pointer(_) => Some(std::mem::size_of::<*const ()>() as u32), // TODO: handle crosscompilation
+            TypeKind::Pointer(_) | TypeKind::Routine(..) | TypeKind::Enum(..) => Some(std::mem::size_of::<*const ()>() as u32), // TODO: handle crosscompilation
             _ => None,
         } 
     }
@@ -124,6 +158,13 @@ impl TryFrom<&str> for TypeKind {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Coercion {
+    Identity,
+    Implicit,
+    Impossible
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum SumVariant {
     Basic(String, Vec<TypeIndex>)
@@ -173,11 +214,204 @@ impl TypeList {
             .map(|(i, _)| i as u32)
     }
 
+    // returns the existing type of `kind`, defining it if there is none yet
+    pub fn intern(&mut self, kind: TypeKind) -> TypeIndex {
+        self.by_kind(&kind).unwrap_or_else(|| self.define(Type::new(None, kind)))
+    }
+
     pub fn define(&mut self, typ: Type) -> TypeIndex {
         self.types.push(typ);
         self.types.len() as u32 - 1
     }
 
+    pub fn get(&self, index: TypeIndex) -> Option<&Type> {
+        self.types.get(index as usize)
+    }
+
+    // follows defined aliases to the type they stand for
+    pub fn resolve(&self, index: TypeIndex) -> TypeIndex {
+        match self.get(index).map(Type::kind) {
+            Some(TypeKind::Alias(_, Some(inner))) if *inner != index => self.resolve(*inner),
+            _ => index
+        }
+    }
+
+    // the type of `field` of a struct, or of the struct `index` points to
+    pub fn field_type(&self, index: TypeIndex, field: &str) -> Option<TypeIndex> {
+        match self.get(self.resolve(index))?.kind() {
+            TypeKind::Struct(fields) => fields.iter().find(|(ident, _)| ident == field).map(|(_, typ)| *typ),
+            TypeKind::Pointer(inner) => self.field_type(*inner, field),
+            _ => None
+        }
+    }
+
+    // whether a value of type `outer` holds one of type `inner` directly, not through a pointer or slice
+    pub fn contains(&self, outer: TypeIndex, inner: TypeIndex) -> bool {
+        let Some(typ) = self.get(outer) else {
+            return false
+        };
+
+        let contains = |typ: &TypeIndex| *typ == inner || self.contains(*typ, inner);
+        match typ.kind() {
+            TypeKind::Alias(_, Some(aliased)) => contains(aliased),
+            TypeKind::Array(element, _) => contains(element),
+            TypeKind::Struct(fields) => fields.iter().any(|(_, typ)| contains(typ)),
+            TypeKind::Sum(variants) => variants.iter().any(|SumVariant::Basic(_, fields)| fields.iter().any(contains)),
+            _ => false
+        }
+    }
+
+    // whether the type `outer` is `inner` or is built from it, even through pointers and slices
+    pub fn mentions(&self, outer: TypeIndex, inner: TypeIndex) -> bool {
+        if outer == inner {
+            return true
+        }
+        let Some(typ) = self.get(outer) else {
+            return false
+        };
+
+        let mentions = |typ: &TypeIndex| self.mentions(*typ, inner);
+        match typ.kind() {
+            TypeKind::Pointer(element) | TypeKind::Slice(element) | TypeKind::Array(element, _) => mentions(element),
+            TypeKind::Alias(_, Some(aliased)) if *aliased != outer => mentions(aliased),
+            TypeKind::Routine(params, result) => params.iter().any(mentions) || mentions(result),
+            TypeKind::Struct(fields) => fields.iter().any(|(_, typ)| mentions(typ)),
+            TypeKind::Sum(variants) => variants.iter().any(|SumVariant::Basic(_, fields)| fields.iter().any(mentions)),
+            _ => false
+        }
+    }
+
+    // `index` with the types in `substitution` replaced, mostly type parameters by their
+    // arguments. Types built from replaced ones are rebuilt and added to `substitution`, so that
+    // a struct mentioned twice is still the same type afterwards.
+    pub fn substitute(&mut self, index: TypeIndex, substitution: &mut HashMap<TypeIndex, TypeIndex>) -> TypeIndex {
+        if let Some(replaced) = substitution.get(&index) {
+            return *replaced
+        }
+        let Some(typ) = self.get(index) else {
+            return index
+        };
+
+        let loc = typ.location().clone();
+        let kind = match typ.kind().clone() {
+            TypeKind::Pointer(inner) => TypeKind::Pointer(self.substitute(inner, substitution)),
+            TypeKind::Slice(inner) => TypeKind::Slice(self.substitute(inner, substitution)),
+            TypeKind::Array(inner, len) => TypeKind::Array(self.substitute(inner, substitution), len),
+            TypeKind::Routine(params, result) => TypeKind::Routine(
+                params.into_iter().map(|param| self.substitute(param, substitution)).collect(),
+                self.substitute(result, substitution)
+            ),
+            TypeKind::Struct(fields) => TypeKind::Struct(fields.into_iter()
+                .map(|(ident, typ)| (ident, self.substitute(typ, substitution)))
+                .collect()
+            ),
+            TypeKind::Sum(variants) => TypeKind::Sum(variants.into_iter()
+                .map(|SumVariant::Basic(ident, fields)| SumVariant::Basic(ident, fields.into_iter().map(|field| self.substitute(field, substitution)).collect()))
+                .collect()
+            ),
+            _ => return index
+        };
+        if self.get(index).is_some_and(|typ| *typ.kind() == kind) {
+            return index
+        }
+
+        let replaced = match kind {
+            TypeKind::Struct(_) => {
+                let c_layout = self.get(index).is_some_and(Type::is_c_layout);
+                let replaced = self.define(Type::new(loc, kind));
+                if c_layout {
+                    self.get_mut(replaced).unwrap().set_c_layout();
+                }
+                replaced
+            }
+            kind => self.intern(kind)
+        };
+        substitution.insert(index, replaced);
+        replaced
+    }
+
+    // the enumeration declaring the constant `ident`, with its value
+    pub fn enum_constant(&self, ident: &str) -> Option<(TypeIndex, u64)> {
+        self.types.iter()
+            .enumerate()
+            .find_map(|(i, typ)| match &typ.kind {
+                TypeKind::Enum(_, constants) => constants.iter().position(|constant| constant == ident).map(|value| (i as u32, value as u64)),
+                _ => None
+            })
+    }
+
+    pub fn is_enum(&self, index: TypeIndex) -> bool {
+        matches!(self.get(self.resolve(index)).map(Type::kind), Some(TypeKind::Enum(..)))
+    }
+
+    pub fn coercion(&self, from: TypeIndex, to: TypeIndex) -> Coercion {
+        let (from, to) = (self.resolve(from), self.resolve(to));
+        if from == to {
+            return Coercion::Identity
+        }
+
+        let (Some(from_kind), Some(to_kind)) = (self.get(from).map(Type::kind), self.get(to).map(Type::kind)) else {
+            return Coercion::Impossible
+        };
+
+        use TypeKind as TK;
+        match (from_kind, to_kind) {
+            // undefined aliases cannot be checked yet, and type parameters only once they are substituted
+            (TK::Alias(_, None), _) | (_, TK::Alias(_, None)) => Coercion::Implicit,
+            (TK::Param(_), _) | (_, TK::Param(_)) => Coercion::Implicit,
+            (TK::Unit, _) | (_, TK::Unit) => Coercion::Impossible,
+            (TK::Pointer(a), TK::Pointer(b)) if self.coercion(*a, *b) == Coercion::Identity
+                || matches!(self.get(*a).map(Type::kind), Some(TK::Unit))
+                || matches!(self.get(*b).map(Type::kind), Some(TK::Unit)) => Coercion::Implicit,
+            (TK::Pointer(_), TK::Pointer(_)) => Coercion::Impossible,
+            // routine types are interned, so different indices only match through aliases
+            (TK::Routine(a, ra), TK::Routine(b, rb)) if a.len() == b.len()
+                && a.iter().zip(b).chain([(ra, rb)]).all(|(a, b)| self.coercion(*a, *b) == Coercion::Identity) => Coercion::Implicit,
+            // constants of an enumeration are words, but words are not constants
+            (TK::Enum(..), TK::Enum(..)) | (_, TK::Enum(..)) => Coercion::Impossible,
+            (TK::Array(a, _), TK::Slice(b)) | (TK::Array(a, _) | TK::Slice(a), TK::Pointer(b))
+                if self.coercion(*a, *b) == Coercion::Identity => Coercion::Implicit,
+            (a, b) if a.is_word() && b.is_word() => Coercion::Implicit,
+            (a, b) if (a.is_integer() || a.is_float()) && (b.is_integer() || b.is_float()) => Coercion::Implicit,
+            _ => Coercion::Impossible
+        }
+    }
+
+    pub fn type_name(&self, index: TypeIndex) -> String {
+        let Some(typ) = self.get(index) else {
+            return "<unknown>".into()
+        };
+
+        match typ.kind() {
+            TypeKind::Pointer(inner) => format!("&{}", self.type_name(*inner)),
+            TypeKind::Array(inner, _) => format!("[{}, ...]", self.type_name(*inner)),
+            TypeKind::Slice(inner) => format!("[{}]", self.type_name(*inner)),
+            TypeKind::Routine(params, result) => {
+                let params = params.iter().map(|param| self.type_name(*param)).collect::<Vec<_>>().join(", ");
+                match self.get(*result).map(Type::kind) {
+                    Some(TypeKind::Unit) => format!("let({params})"),
+                    _ => format!("let({params}) :: {}", self.type_name(*result))
+                }
+            }
+            TypeKind::Alias(ident, _) | TypeKind::Enum(ident, _) | TypeKind::Param(ident) => ident.clone(),
+            TypeKind::Sum(variants) => variants.iter()
+                .map(|SumVariant::Basic(ident, fields)| if fields.is_empty() {
+                    ident.clone()
+                }
+                else {
+                    format!("{ident}({})", fields.iter().map(|field| self.type_name(*field)).collect::<Vec<_>>().join(", "))
+                })
+                .collect::<Vec<_>>()
+                .join(" : "),
+            TypeKind::Struct(fields) => format!("struct {{ {} }}", fields.iter()
+                .map(|(ident, typ)| format!("{ident} :: {}", self.type_name(*typ)))
+                .collect::<Vec<_>>()
+                .join(", ")
+            ),
+            kind => format!("{kind:?}")
+        }
+    }
+
     pub fn get_mut(&mut self, index: TypeIndex) -> Option<&mut Type> {
         self.types.get_mut(index as usize)
     }
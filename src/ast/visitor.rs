@@ -1,6 +1,6 @@
-use crate::{match_decl, ast::{ManifestDecl, FunctionBody, pattern::PatternTerm, stmt::StmtKind, expr::ExprKind}};
+use crate::{match_decl, ast::{ManifestDecl, GlobalDecl, FunctionBody, pattern::PatternTerm, stmt::StmtKind, expr::ExprKind}};
 
-use super::{Program, Function, Section, Decl, Param, stmt::Stmt, expr::Expr, pattern::Pattern};
+use super::{Program, Function, Section, Param, stmt::Stmt, expr::Expr, pattern::Pattern};
 
 pub enum Action {
     Continue,
@@ -55,7 +55,12 @@ impl Traversable for Section {
                     act!(func.traverse(visitor)?)
                 },
                 manifest as ManifestDecl => {
-                    println!("manifest: {}", manifest.ident());
+                    act!(manifest.value_mut().traverse(visitor)?)
+                },
+                global as GlobalDecl => {
+                    if let Some(value) = global.value_mut() {
+                        act!(value.traverse(visitor)?)
+                    }
                 },
                 _ => ()
             }
@@ -117,9 +122,15 @@ impl Traversable for Stmt {
 
         match self.kind_mut() {
             StmtKind::Nop | StmtKind::Return | StmtKind::DefaultCase 
-                | StmtKind::Break | StmtKind::Next => (),
-            StmtKind::Expr(expr) | StmtKind::ResultIs(expr) 
-                | StmtKind::Case(expr) => act!(expr.traverse(visitor)?),
+                | StmtKind::Break | StmtKind::Next | StmtKind::Loop | StmtKind::Goto(_) | StmtKind::Rethrow | StmtKind::Error => (),
+            StmtKind::Labelled(_, body) => act!(body.traverse(visitor)?),
+            StmtKind::Expr(expr) | StmtKind::ResultIs(expr) | StmtKind::Assert(expr, _) | StmtKind::Throw(expr) => act!(expr.traverse(visitor)?),
+            StmtKind::Case(values) => for (expr, upper) in values {
+                act!(expr.traverse(visitor)?);
+                if let Some(upper) = upper {
+                    act!(upper.traverse(visitor)?);
+                }
+            }
             StmtKind::Block(stmts) => for stmt in stmts {
                 act!(stmt.traverse(visitor)?);
             }
@@ -135,6 +146,19 @@ impl Traversable for Stmt {
                 act!(cond.traverse(visitor)?);
                 act!(body.traverse(visitor)?);
             }
+            StmtKind::Repeat(body) => act!(body.traverse(visitor)?),
+            StmtKind::InlineAsm(_, outputs, inputs) => for operand in outputs.iter_mut().chain(inputs) {
+                act!(operand.traverse(visitor)?);
+            }
+            StmtKind::Try(body, caught, handler) => {
+                act!(body.traverse(visitor)?);
+                act!(caught.traverse(visitor)?);
+                act!(handler.traverse(visitor)?);
+            }
+            StmtKind::RepeatWhile(body, cond) | StmtKind::RepeatUntil(body, cond) => {
+                act!(body.traverse(visitor)?);
+                act!(cond.traverse(visitor)?);
+            }
             StmtKind::For(iter, init, bound, step, body) => {
                 act!(iter.traverse(visitor)?);
                 act!(init.traverse(visitor)?);
@@ -163,6 +187,12 @@ impl Traversable for Stmt {
                     act!(expr.traverse(visitor)?);
                 }
             }
+            StmtKind::Assign(pairs) => {
+                for (target, value) in pairs {
+                    act!(target.traverse(visitor)?);
+                    act!(value.traverse(visitor)?);
+                }
+            }
         }
 
         visitor.visit(self)
@@ -177,21 +207,25 @@ impl Traversable for Expr {
             ExprKind::Ident(_) | ExprKind::Atom(_)
                 | ExprKind::IntLit(_) | ExprKind::FloatLit(_)
                 | ExprKind::CharLit(_) | ExprKind::StringLit(_)
-                | ExprKind::True | ExprKind::False => (),
+                | ExprKind::True | ExprKind::False | ExprKind::Nil
+                | ExprKind::SizeOf(_) | ExprKind::OffsetOf(..)
+                | ExprKind::Temporary(_) | ExprKind::Constant(_) | ExprKind::Error => (),
             ExprKind::Abs(expr) | ExprKind::Not(expr)
-                | ExprKind::Ref(expr) | ExprKind::Deref(expr)
-                | ExprKind::Cast(expr) | ExprKind::ImplicitCast(expr) => act!(expr.traverse(visitor)?),
+                | ExprKind::Ref(expr) | ExprKind::Deref(expr) | ExprKind::Overflow(_, expr)
+                | ExprKind::Cast(expr) | ExprKind::ImplicitCast(expr) | ExprKind::Vec(expr)
+                | ExprKind::Member(expr, _) | ExprKind::NamedArg(_, expr) => act!(expr.traverse(visitor)?),
             ExprKind::Add(lhs, rhs) | ExprKind::Sub(lhs, rhs) 
                 | ExprKind::Mul(lhs, rhs) | ExprKind::Div(lhs, rhs) | ExprKind::Mod(lhs, rhs)
                 | ExprKind::And(lhs, rhs) | ExprKind::Or(lhs, rhs) | ExprKind::XOr(lhs, rhs)
+                | ExprKind::LogAnd(lhs, rhs) | ExprKind::LogOr(lhs, rhs)
                 | ExprKind::Eq(lhs, rhs) | ExprKind::Ne(lhs, rhs) | ExprKind::Gt(lhs, rhs)
                 | ExprKind::Ge(lhs, rhs) | ExprKind::Lt(lhs, rhs) | ExprKind::Le(lhs, rhs)
                 | ExprKind::LShift(lhs, rhs) | ExprKind::RShift(lhs, rhs)
-                | ExprKind::Index(lhs, rhs) => {
+                | ExprKind::Index(lhs, rhs) | ExprKind::FieldSelect(lhs, rhs) => {
                     act!(lhs.traverse(visitor)?);
                     act!(rhs.traverse(visitor)?);
             }
-            ExprKind::Slice(lhs, mhs, rhs) | ExprKind::Conditional(lhs, mhs, rhs) => {
+            ExprKind::Slice(lhs, mhs, rhs) | ExprKind::Conditional(lhs, mhs, rhs) | ExprKind::Selector(lhs, mhs, rhs) => {
                 act!(lhs.traverse(visitor)?);
                 act!(mhs.traverse(visitor)?);
                 act!(rhs.traverse(visitor)?);
@@ -203,6 +237,9 @@ impl Traversable for Expr {
                     act!(arg.traverse(visitor)?);
                 }
             }
+            ExprKind::VarArgs(args) => for arg in args {
+                act!(arg.traverse(visitor)?);
+            }
             ExprKind::Match(cond, branches) | ExprKind::Every(cond, branches) => {
                 for c in cond {
                     act!(c.traverse(visitor)?);
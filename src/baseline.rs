@@ -0,0 +1,87 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{
+    error::CompilerError,
+    source_file::{Located, SourceFile, SourceFileId}
+};
+
+const HEADER: &str = "# bcplpp warning baseline; delete this file to record it again";
+
+// warnings known before stricter lints were adopted, counted per fingerprint.
+// Fingerprints leave out line numbers, so unrelated edits keep them valid.
+#[derive(Default)]
+pub struct Baseline {
+    entries: BTreeMap<String, usize>
+}
+
+impl Baseline {
+    // `None` if there is no baseline at `path` yet
+    pub fn read(path: &str) -> Result<Option<Self>, String> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(format!("error reading warning baseline `{path}`: {err}."))
+        };
+
+        let mut baseline = Self::default();
+        for (i, line) in contents.lines().enumerate() {
+            if line.is_empty() || line.starts_with('#') {
+                continue
+            }
+
+            let Some((count, fingerprint)) = line.split_once('\t') else {
+                return Err(format!("{path}:{}: malformed warning baseline entry.", i + 1))
+            };
+            let Ok(count) = count.parse::<usize>() else {
+                return Err(format!("{path}:{}: invalid warning count `{count}`.", i + 1))
+            };
+            *baseline.entries.entry(fingerprint.to_string()).or_default() += count;
+        }
+        Ok(Some(baseline))
+    }
+
+    pub fn record(warnings: &[Located<CompilerError>], source_files: &HashMap<SourceFileId, SourceFile>) -> Self {
+        let mut baseline = Self::default();
+        for warning in warnings {
+            *baseline.entries.entry(fingerprint(warning, source_files)).or_default() += 1;
+        }
+        baseline
+    }
+
+    pub fn write(&self, path: &str) -> Result<(), String> {
+        let mut contents = format!("{HEADER}\n");
+        for (fingerprint, count) in &self.entries {
+            contents.push_str(&format!("{count}\t{fingerprint}\n"));
+        }
+        std::fs::write(path, contents).map_err(|err| format!("error writing warning baseline `{path}`: {err}."))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.values().sum()
+    }
+
+    // removes every warning recorded in the baseline, returning how many were suppressed
+    pub fn suppress(&self, warnings: &mut Vec<Located<CompilerError>>, source_files: &HashMap<SourceFileId, SourceFile>) -> usize {
+        let mut remaining = self.entries.clone();
+        let before = warnings.len();
+        warnings.retain(|warning| match remaining.get_mut(&fingerprint(warning, source_files)) {
+            Some(count) if *count > 0 => {
+                *count -= 1;
+                false
+            }
+            _ => true
+        });
+        before - warnings.len()
+    }
+}
+
+// `file <TAB> message <TAB> source line`
+fn fingerprint(warning: &Located<CompilerError>, source_files: &HashMap<SourceFileId, SourceFile>) -> String {
+    let loc = warning.location();
+    let file = source_files.get(&loc.file_id());
+    let path = file.map_or("<unknown>", |file| file.path());
+    let line = file.and_then(|file| file.line(loc.line()))
+        .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+        .unwrap_or_default();
+    format!("{path}\t{}\t{line}", warning.message().replace(['\t', '\n'], " "))
+}
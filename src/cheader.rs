@@ -0,0 +1,172 @@
+use std::{collections::HashSet, fmt::Write};
+
+use crate::ast::{
+    Program, Function, Decl,
+    attribute::CallingConvention,
+    layout::DataLayout,
+    types::{Type, TypeList, TypeIndex, TypeKind}
+};
+
+// A C header with the `[[repr("C")]]` structs and `[[ccall]]` routines of a program, written by
+// `--emit h`. Every struct is followed by static assertions of its size and field offsets on the
+// target, so that C code built with a disagreeing compiler or word size fails to compile:
+//
+//   typedef struct Point Point;
+//
+//   struct Point {
+//       int32_t x;
+//       int32_t y;
+//   };
+//   _Static_assert(sizeof(Point) == 8, "size of `Point` differs from bcplpp");
+//   _Static_assert(offsetof(Point, y) == 4, "offset of `Point.y` differs from bcplpp");
+//
+//   intptr_t norm(Point *);
+//
+// Untyped parameters and results are BCPL words, declared as `intptr_t`.
+pub fn write_c_header(ast: &Program, target: &DataLayout) -> String {
+    let types = ast.types();
+    let structs = c_structs(types);
+    let mut out = String::from("#pragma once\n\n#include <stdbool.h>\n#include <stddef.h>\n#include <stdint.h>\n");
+
+    if !structs.is_empty() {
+        out.push('\n');
+        for (_, name) in &structs {
+            writeln!(out, "typedef struct {name} {name};").unwrap();
+        }
+    }
+
+    let mut written = HashSet::new();
+    for (index, _) in &structs {
+        write_struct(&mut out, types, &structs, *index, target, &mut written);
+    }
+
+    let mut routines = ast.sections()
+        .flat_map(|section| section.declarations())
+        .filter_map(|decl| decl.as_any().downcast_ref::<Function>())
+        .filter(|func| func.calling_convention() == CallingConvention::C)
+        .map(|func| (func.location(), prototype(types, &structs, func)))
+        .collect::<Vec<_>>();
+    routines.sort_by(|(a, _), (b, _)| a.cmp(b));
+    if !routines.is_empty() {
+        out.push('\n');
+        for (_, prototype) in routines {
+            writeln!(out, "{prototype}").unwrap();
+        }
+    }
+    out
+}
+
+// `[[repr("C")]]` structs with the name of the alias declaring them, in declaration order
+fn c_structs(types: &TypeList) -> Vec<(TypeIndex, String)> {
+    types.iter()
+        .filter_map(|typ| match typ.kind() {
+            TypeKind::Alias(name, Some(inner)) if types.get(*inner).is_some_and(Type::is_c_layout) => Some((*inner, name.clone())),
+            _ => None
+        })
+        .collect()
+}
+
+// writes the structs `index` holds by value first, since C needs them to be complete
+fn write_struct(out: &mut String, types: &TypeList, structs: &[(TypeIndex, String)], index: TypeIndex, target: &DataLayout, written: &mut HashSet<TypeIndex>) {
+    if !written.insert(index) {
+        return
+    }
+    let (Some(TypeKind::Struct(fields)), Some((_, name))) = (types.get(index).map(Type::kind), structs.iter().find(|(typ, _)| *typ == index)) else {
+        return
+    };
+
+    for (_, field) in fields {
+        if let Some(inner) = held_struct(types, *field) {
+            write_struct(out, types, structs, inner, target, written);
+        }
+    }
+
+    writeln!(out, "\nstruct {name} {{").unwrap();
+    for (field, typ) in fields {
+        let declaration = c_declaration(types, structs, *typ, field.clone())
+            .expect("fields of `repr(\"C\")` structs are checked by the type checker");
+        writeln!(out, "    {declaration};").unwrap();
+    }
+    writeln!(out, "}};").unwrap();
+
+    if let Some(layout) = types.layout(index, target) {
+        writeln!(out, "_Static_assert(sizeof({name}) == {}, \"size of `{name}` differs from bcplpp\");", layout.size()).unwrap();
+        for (field, _) in fields.iter().skip(1) {
+            if let Some(offset) = types.field_offset(index, field, target) {
+                writeln!(out, "_Static_assert(offsetof({name}, {field}) == {offset}, \"offset of `{name}.{field}` differs from bcplpp\");").unwrap();
+            }
+        }
+    }
+}
+
+// the struct a field of type `index` holds by value, directly or as array elements
+fn held_struct(types: &TypeList, index: TypeIndex) -> Option<TypeIndex> {
+    let index = types.resolve(index);
+    match types.get(index)?.kind() {
+        TypeKind::Struct(_) => Some(index),
+        TypeKind::Array(inner, _) => held_struct(types, *inner),
+        _ => None
+    }
+}
+
+// `declarator` declared with type `index`, like `int32_t (*rows)[4]`; pointers to types
+// without C equivalent become `void *`
+fn c_declaration(types: &TypeList, structs: &[(TypeIndex, String)], index: TypeIndex, declarator: String) -> Option<String> {
+    let index = types.resolve(index);
+    let base = match types.get(index)?.kind() {
+        TypeKind::UInt8 => "uint8_t",
+        TypeKind::UInt16 => "uint16_t",
+        TypeKind::UInt32 => "uint32_t",
+        TypeKind::UInt64 => "uint64_t",
+        TypeKind::Int8 => "int8_t",
+        TypeKind::Int16 => "int16_t",
+        TypeKind::Int32 => "int32_t",
+        TypeKind::Int64 => "int64_t",
+        TypeKind::Float32 => "float",
+        TypeKind::Float64 => "double",
+        TypeKind::Bool => "bool",
+        TypeKind::Char => "char",
+        TypeKind::Unit => "void",
+        TypeKind::Enum(..) => "intptr_t",
+        TypeKind::Struct(_) => structs.iter().find(|(typ, _)| *typ == index).map(|(_, name)| name.as_str())?,
+        TypeKind::Pointer(inner) => return c_declaration(types, structs, *inner, format!("*{declarator}"))
+            .or_else(|| Some(format!("void *{declarator}"))),
+        TypeKind::Array(inner, len) => {
+            let declarator = if declarator.starts_with('*') { format!("({declarator})") } else { declarator };
+            return c_declaration(types, structs, *inner, format!("{declarator}[{}]", len.const_int()?))
+        }
+        // `int64_t (*declarator)(int64_t, bool)`
+        TypeKind::Routine(params, result) => {
+            let params = params.iter()
+                .map(|param| c_declaration(types, structs, *param, String::new()))
+                .collect::<Option<Vec<_>>>()?;
+            let params = if params.is_empty() { "void".to_string() } else { params.join(", ") };
+            return c_declaration(types, structs, *result, format!("(*{declarator})({params})"))
+        }
+        _ => return None
+    };
+    Some(format!("{base} {declarator}").trim_end().to_string())
+}
+
+fn prototype(types: &TypeList, structs: &[(TypeIndex, String)], func: &Function) -> String {
+    let word = |typ: Option<TypeIndex>, declarator: String| match typ {
+        Some(typ) => c_declaration(types, structs, typ, declarator),
+        None => Some(format!("intptr_t {declarator}").trim_end().to_string())
+    };
+
+    // further arguments arrive as one vector of their count followed by them
+    let params = func.params().iter()
+        .map(|param| word(param.typ(), String::new()))
+        .chain(func.variadic().map(|_| Some("intptr_t *".to_string())))
+        .collect::<Option<Vec<_>>>();
+    let params = match params {
+        Some(params) if params.is_empty() => "void".to_string(),
+        Some(params) => params.join(", "),
+        None => return format!("/* `{}` has parameters without C equivalent */", func.ident())
+    };
+
+    match word(*func.return_type(), format!("{}({params})", func.ident())) {
+        Some(declaration) => format!("{declaration};"),
+        None => format!("/* `{}` returns a type without C equivalent */", func.ident())
+    }
+}
@@ -0,0 +1,597 @@
+use crate::{
+    config::{Config, ConfigValue},
+    lint::Lint,
+    report::{json_list, json_string}
+};
+
+// The driver's subcommands; `build` is assumed when none is given
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Command {
+    #[default]
+    Build,
+    Check,
+    Run,
+    Fmt,
+    Lsp,
+    Test,
+    Symbolize,
+    Demangle,
+    Extract,
+    Reduce,
+    Modernize
+}
+
+impl Command {
+    pub const ALL: [Command; 11] = [
+        Command::Build,
+        Command::Check,
+        Command::Run,
+        Command::Fmt,
+        Command::Lsp,
+        Command::Test,
+        Command::Symbolize,
+        Command::Demangle,
+        Command::Extract,
+        Command::Reduce,
+        Command::Modernize
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Build => "build",
+            Self::Check => "check",
+            Self::Run => "run",
+            Self::Fmt => "fmt",
+            Self::Lsp => "lsp",
+            Self::Test => "test",
+            Self::Symbolize => "symbolize",
+            Self::Demangle => "demangle",
+            Self::Extract => "extract",
+            Self::Reduce => "reduce",
+            Self::Modernize => "modernize"
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|command| command.name() == name)
+    }
+
+    fn summary(&self) -> &'static str {
+        match self {
+            Self::Build => "Compile the input files to an executable, object files or a shared library.",
+            Self::Check => "Parse, type check and lint the input files without writing any output.",
+            Self::Run => "Build an executable and run it; arguments after `--` are passed to the program.",
+            Self::Fmt => "Format the input files (not implemented yet).",
+            Self::Lsp => "Serve the language server protocol on stdin and stdout (not implemented yet).",
+            Self::Test => "Run the input programs with an interpreter and natively and compare their output and exit codes.",
+            Self::Symbolize => "Print the routine and source line of each address given as input, or of the hexadecimal addresses in the lines read from stdin, using a symbol map written by `--emit map`.",
+            Self::Demangle => "Print the routine and section named by each mangled symbol given as input, or the lines read from stdin with the mangled symbols in them demangled.",
+            Self::Extract => "Print the declarations reachable from the routine given with `--entry` as a minimal program, e.g. to reproduce a bug with.",
+            Self::Reduce => "Remove declarations and statements from the input file as long as building it still prints the text given with `--check`, like an internal compiler error, and print what is left. Arguments after `--` are passed to each build.",
+            Self::Modernize => "Rewrite the input files from classic BCPL to this dialect: `$( $)` become braces, uppercase keywords lowercase, inferred result types are written out and simple `goto`s become loops and conditionals. Constructs which mean something else than in classic BCPL and `goto`s left as they are are reported."
+        }
+    }
+}
+
+const COMPILING: &[Command] = &[Command::Build, Command::Check, Command::Run];
+const LINKING: &[Command] = &[Command::Build, Command::Run];
+const ALL: &[Command] = &Command::ALL;
+
+pub struct OptionSpec {
+    pub long: &'static str,
+    pub short: Option<&'static str>,
+    // placeholder of the option's argument; flags take none
+    pub value: Option<&'static str>,
+    // may be given several times, the values accumulate
+    pub repeatable: bool,
+    pub help: &'static str,
+    pub commands: &'static [Command],
+    // fallbacks when the option is not on the command line: an environment
+    // variable, then a `[table]` key of `bcpl.toml`
+    pub env: Option<&'static str>,
+    pub config: Option<(&'static str, &'static str)>
+}
+
+impl OptionSpec {
+    const fn new(long: &'static str, commands: &'static [Command], help: &'static str) -> Self {
+        Self {
+            long,
+            short: None,
+            value: None,
+            repeatable: false,
+            help,
+            commands,
+            env: None,
+            config: None
+        }
+    }
+
+    const fn short(mut self, short: &'static str) -> Self {
+        self.short = Some(short);
+        self
+    }
+
+    const fn value(mut self, value: &'static str) -> Self {
+        self.value = Some(value);
+        self
+    }
+
+    const fn repeatable(mut self) -> Self {
+        self.repeatable = true;
+        self
+    }
+
+    const fn env(mut self, env: &'static str) -> Self {
+        self.env = Some(env);
+        self
+    }
+
+    const fn config(mut self, table: &'static str, key: &'static str) -> Self {
+        self.config = Some((table, key));
+        self
+    }
+
+    fn applies_to(&self, command: Command) -> bool {
+        self.commands.contains(&command)
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        self.long == name || self.short == Some(name)
+    }
+}
+
+pub const OPTIONS: &[OptionSpec] = &[
+    OptionSpec::new("--output", LINKING, "Set the output file. A directory places the output inside it, named after the first input file; `{name}` and `{target}` are substituted. Default: `a`, or `a.exe` on Windows.")
+        .short("-o").value("<file>").env("BCPL_OUTPUT").config("build", "output"),
+    OptionSpec::new("--define", &[Command::Build, Command::Check, Command::Run, Command::Extract, Command::Modernize], "Set a BCPL tag for conditional compilation: the text between `$<tag` and `$>tag` is compiled, that between `$~tag` and `$>tag` skipped.")
+        .short("-D").value("<tag>").repeatable().env("BCPL_TAGS").config("build", "tags"),
+    OptionSpec::new("--object", &[Command::Build], "Skip linking and emit `.o` and `.bmeta` files.")
+        .short("-c"),
+    OptionSpec::new("--shared", &[Command::Build], "Create a shared library."),
+    OptionSpec::new("--entry", &[Command::Build, Command::Check, Command::Run, Command::Extract], "Set the entry routine of executables, or the routine `extract` starts from. Default: `start`.")
+        .value("<routine>").env("BCPL_ENTRY").config("build", "entry"),
+    OptionSpec::new("--freestanding", COMPILING, "Link neither the runtime library nor the entry shim.")
+        .config("build", "freestanding"),
+    OptionSpec::new("--checked", COMPILING, "Trap on dereferencing `nil` at runtime.")
+        .config("build", "checked"),
+    OptionSpec::new("-m32", COMPILING, "Set the target word size to 32 bits. Default: host."),
+    OptionSpec::new("-m64", COMPILING, "Set the target word size to 64 bits. Default: host."),
+    OptionSpec::new("--linker-script", LINKING, "Link using a custom linker script.")
+        .short("-T").value("<script>").config("build", "linker-script"),
+    OptionSpec::new("--output-format", LINKING, "Convert the linked executable to `bin` (raw binary) or `ihex` (Intel HEX). Default: `elf`.")
+        .value("<format>").config("build", "output-format"),
+    OptionSpec::new("--target-cpu", COMPILING, "Generate code for a specific cpu.")
+        .value("<cpu>").env("BCPL_TARGET_CPU").config("build", "target-cpu"),
+    OptionSpec::new("--target-feature", COMPILING, "Enable or disable target features, e.g. `-fpu` or `+soft-float` for targets without floating point unit.")
+        .value("<+feature,-feature,...>").repeatable().config("build", "target-features"),
+    OptionSpec::new("--overflow", COMPILING, "Set the behavior of integer overflow: `wrap`, `trap` or `saturate`. Default: `wrap`.")
+        .value("<mode>").env("BCPL_OVERFLOW").config("build", "overflow"),
+    OptionSpec::new("--codegen-units", COMPILING, "Lower the routines in this many units of consecutive routines in parallel. The output does not depend on it. Default: one per core.")
+        .value("<n>").config("build", "codegen-units"),
+    OptionSpec::new("--allow", COMPILING, "Silence warnings of a lint.")
+        .value("<lint>").repeatable(),
+    OptionSpec::new("--warn", COMPILING, "Report a lint as warning.")
+        .value("<lint>").repeatable(),
+    OptionSpec::new("--deny", COMPILING, "Report a lint as error.")
+        .value("<lint>").repeatable(),
+    OptionSpec::new("--warning-baseline", COMPILING, "Report only warnings not recorded in this file; it is created from the current warnings if missing.")
+        .value("<file>").config("build", "warning-baseline"),
+    OptionSpec::new("--ignore-result", COMPILING, "Allow discarding the result of a routine.")
+        .value("<routine>").repeatable(),
+    OptionSpec::new("--provided-global", COMPILING, "Assume the runtime library initializes a global.")
+        .value("<global>").repeatable(),
+    OptionSpec::new("--diagnostic-width", COMPILING, "Cut source lines in diagnostics to this many columns, keeping the marked text in view. Default: no limit.")
+        .value("<columns>").env("BCPL_DIAGNOSTIC_WIDTH").config("diagnostics", "width"),
+    OptionSpec::new("--tab-width", COMPILING, "Set the width tabs in source lines are expanded to in diagnostics. Default: `4`.")
+        .value("<columns>").config("diagnostics", "tab-width"),
+    OptionSpec::new("--diagnostic-columns", COMPILING, "Count the column of diagnostics in `bytes` from 0, or as `display` column from 1 with tabs expanded, the way editors show it. Default: `bytes`.")
+        .value("<style>").config("diagnostics", "columns"),
+    OptionSpec::new("--dump-ast", &[Command::Build, Command::Check], "Print the parsed program and stop."),
+    OptionSpec::new("--emit", LINKING, "Write a listing of the lowered IR, not target assembly (`asm`, as `.ir`), a symbol map with sizes (`map`), the type checked program as typed ast (`tast`) or a C header declaring `repr(\"C\")` structs and `ccall` routines (`h`) next to the output file. Typed asts are accepted as input files in place of sources.")
+        .value("<kind>").repeatable().config("build", "emit"),
+    OptionSpec::new("--asm-source", LINKING, "Interleave the emitted listing with the source lines."),
+    OptionSpec::new("--report-data-size", LINKING, "Print the size of the static data of each module."),
+    OptionSpec::new("--report-switches", LINKING, "Print the case count, value range and density of each `switchon`, and whether it becomes a jump table, a binary search or a chain of compares."),
+    OptionSpec::new("-Zmemory-stats", COMPILING, "Print the peak memory use and the allocations of each phase. Unstable: the output may change."),
+    OptionSpec::new("--build-report", COMPILING, "Write a JSON summary of inputs, artifacts, diagnostics, timings and cache hits of the compilation.")
+        .value("<file>").env("BCPL_BUILD_REPORT").config("build", "build-report"),
+    OptionSpec::new("--explain-rebuild", LINKING, "Print why each input file is compiled again instead of reusing the artifacts of the last build."),
+    OptionSpec::new("--log", COMPILING, "Log what the compiler does to stderr, filtered by module, e.g. `parser=debug,ir=info`; levels are `off`, `error`, `warn`, `info`, `debug` and `trace`.")
+        .value("<module=level,...>").env("BCPL_LOG"),
+    OptionSpec::new("--log-file", COMPILING, "Write the log to a file instead of stderr.")
+        .value("<file>"),
+    OptionSpec::new("--check-artifacts", &[Command::Build], "Exit with 0 if the emitted artifacts are up to date with the inputs and options, and with 1 otherwise, without compiling."),
+    OptionSpec::new("--interpreter", &[Command::Test], "Command running a program with the interpreter.")
+        .value("<command>").env("BCPL_INTERPRETER").config("test", "interpreter"),
+    OptionSpec::new("--native", &[Command::Test], "Command compiling and running a program natively.")
+        .value("<command>").env("BCPL_NATIVE").config("test", "native"),
+    OptionSpec::new("--map", &[Command::Symbolize], "Symbol map to look the addresses up in.")
+        .value("<file>"),
+    OptionSpec::new("--dry-run", &[Command::Modernize], "Report what `modernize` would convert without changing the files."),
+    OptionSpec::new("--check", &[Command::Reduce], "Text the output of building the reduced program has to contain.")
+        .value("<text>"),
+    OptionSpec::new("--completions", ALL, "Print a completion script for `bash`, `zsh` or `fish` and exit.")
+        .value("<shell>"),
+    OptionSpec::new("--dump-cli-json", ALL, "Print all commands and options as JSON and exit."),
+    OptionSpec::new("--help", ALL, "Print this help text and exit.")
+        .short("-h")
+];
+
+const SHELLS: [&str; 3] = ["bash", "zsh", "fish"];
+
+// the values an option accepts, if there is a fixed set of them
+fn choices(spec: &OptionSpec) -> Vec<&'static str> {
+    match spec.long {
+        "--allow" | "--warn" | "--deny" => Lint::ALL.iter().map(Lint::name).collect(),
+        "--emit" => vec!["asm", "map", "tast", "h"],
+        "--overflow" => vec!["wrap", "trap", "saturate"],
+        "--diagnostic-columns" => vec!["bytes", "display"],
+        "--output-format" => vec!["elf", "bin", "ihex"],
+        "--completions" => SHELLS.to_vec(),
+        _ => vec![]
+    }
+}
+
+pub struct Invocation {
+    pub command: Command,
+    // whether a subcommand was named explicitly
+    pub explicit_command: bool,
+    pub help: bool,
+    // in the order they take effect: values from `bcpl.toml` and the environment,
+    // then the command line. Each option is taken from the first of command line,
+    // environment and `bcpl.toml` that sets it.
+    pub options: Vec<(&'static OptionSpec, Option<String>)>,
+    pub inputs: Vec<String>,
+    // everything after `--`, passed on to the program by `run` and to each build by `reduce`
+    pub program_args: Vec<String>
+}
+
+impl Invocation {
+    pub fn value(&self, long: &str) -> Option<&String> {
+        self.options.iter().rev()
+            .find(|(spec, _)| spec.long == long)
+            .and_then(|(_, value)| value.as_ref())
+    }
+
+    pub fn is_set(&self, long: &str) -> bool {
+        self.options.iter().any(|(spec, _)| spec.long == long)
+    }
+}
+
+pub fn parse_args(args: Vec<String>, config: Option<&Config>) -> Result<Invocation, String> {
+    let mut args = args.into_iter().peekable();
+
+    let mut invocation = Invocation {
+        command: Command::default(),
+        explicit_command: false,
+        help: false,
+        options: vec![],
+        inputs: vec![],
+        program_args: vec![]
+    };
+
+    if args.peek().is_some_and(|arg| arg == "help") {
+        args.next();
+        invocation.help = true;
+    }
+    if let Some(command) = args.peek().and_then(|arg| Command::from_name(arg)) {
+        args.next();
+        invocation.command = command;
+        invocation.explicit_command = true;
+    }
+    let command = invocation.command;
+
+    let mut cli = vec![];
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            invocation.program_args.extend(args.by_ref());
+            break;
+        }
+
+        if !arg.starts_with('-') || arg == "-" {
+            invocation.inputs.push(arg);
+            continue;
+        }
+
+        let (name, inline_value) = match arg.split_once('=') {
+            Some((name, value)) if name.starts_with("--") => (name, Some(value.to_string())),
+            _ => (arg.as_str(), None)
+        };
+        let spec = OPTIONS.iter()
+            .find(|spec| spec.matches(name))
+            .ok_or_else(|| format!("invalid option -- {arg}"))?;
+        if !spec.applies_to(command) {
+            return Err(format!("option `{name}` does not apply to `{}`", command.name()))
+        }
+
+        if spec.long == "--help" {
+            invocation.help = true;
+            continue;
+        }
+
+        let value = match (spec.value, inline_value) {
+            (None, None) => None,
+            (None, Some(_)) => return Err(format!("option `{name}` takes no argument")),
+            (Some(_), Some(value)) => Some(value),
+            (Some(_), None) => Some(args.next().ok_or_else(|| format!("option requires an argument -- '{name}'"))?)
+        };
+        cli.push((spec, value));
+    }
+
+    for spec in OPTIONS.iter().filter(|spec| spec.applies_to(command)) {
+        if cli.iter().any(|(given, _)| std::ptr::eq(*given, spec)) {
+            continue;
+        }
+
+        let values = match spec.env.and_then(|env| std::env::var(env).ok().map(|value| (env, value))) {
+            Some((env, value)) => from_env(spec, env, &value)?,
+            None => match (config, spec.config) {
+                (Some(config), Some((table, key))) => match config.table(table).and_then(|table| table.get(key)) {
+                    Some(value) => from_config(spec, config, table, key, value)?,
+                    None => vec![]
+                }
+                _ => vec![]
+            }
+        };
+        invocation.options.extend(values.into_iter().map(|value| (spec, value)));
+    }
+    invocation.options.extend(cli);
+
+    Ok(invocation)
+}
+
+fn from_env(spec: &OptionSpec, env: &str, value: &str) -> Result<Vec<Option<String>>, String> {
+    match spec.value {
+        None => match value {
+            "1" | "true" => Ok(vec![None]),
+            "" | "0" | "false" => Ok(vec![]),
+            _ => Err(format!("environment variable `{env}` has to be `1` or `0`"))
+        }
+        Some(_) if spec.repeatable => Ok(value.split(',')
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .map(|value| Some(value.to_string()))
+            .collect()),
+        Some(_) => Ok(vec![Some(value.to_string())])
+    }
+}
+
+fn from_config(spec: &OptionSpec, config: &Config, table: &str, key: &str, value: &ConfigValue) -> Result<Vec<Option<String>>, String> {
+    let scalar = |value: &ConfigValue| match value {
+        ConfigValue::String(value) => Some(value.clone()),
+        ConfigValue::Integer(value) => Some(value.to_string()),
+        _ => None
+    };
+
+    let err = |expected: &str| format!("{}: `{table}.{key}` has to be {expected}", config.path());
+    match (spec.value, value) {
+        (None, ConfigValue::Bool(set)) => Ok(if *set { vec![None] } else { vec![] }),
+        (None, _) => Err(err("a boolean")),
+        (Some(_), ConfigValue::Array(values)) if spec.repeatable => values.iter()
+            .map(|value| scalar(value).map(Some).ok_or_else(|| err("an array of strings")))
+            .collect(),
+        (Some(_), value) => scalar(value).map(|value| vec![Some(value)]).ok_or_else(|| err("a string"))
+    }
+}
+
+const HELP_INDENT: usize = 20;
+const HELP_WIDTH: usize = 80;
+
+// the usage line and options of `command`; without an explicit command, all commands are listed as well
+pub fn help_text(program_name: &str, command: Command, explicit_command: bool) -> String {
+    let mut text = if explicit_command {
+        format!("Usage: {program_name} {} [OPTIONS] <input files>\n\n{}\n", command.name(), command.summary())
+    }
+    else {
+        let mut text = format!("Usage: {program_name} [COMMAND] [OPTIONS] <input files>\n\nCommands:\n");
+        for command in Command::ALL {
+            text.push_str(&help_entry(command.name(), command.summary()));
+        }
+        text.push_str(&help_entry("daemon", "Keep running and compile requests read from stdin, one per line: the working directory and the arguments, separated by tabs. Source and metadata files stay cached until they change."));
+        text.push_str(&format!("\nWithout a command, `build` is assumed.\nRun `{program_name} help <command>` for the options of a command.\n"));
+        text
+    };
+
+    text.push_str("\nOptions:\n");
+    for spec in OPTIONS.iter().filter(|spec| spec.applies_to(command)) {
+        let names = match spec.short {
+            Some(short) => format!("{short}, {}", spec.long),
+            None => spec.long.to_string()
+        };
+        let names = match spec.value {
+            Some(value) => format!("{names} {value}"),
+            None => names
+        };
+
+        let mut help = spec.help.to_string();
+        let fallbacks = spec.env.map(|env| format!("`{env}`"))
+            .into_iter()
+            .chain(spec.config.map(|(table, key)| format!("`{key}` in `[{table}]`")))
+            .collect::<Vec<_>>();
+        if !fallbacks.is_empty() {
+            help.push_str(&format!(" Also set by {}.", fallbacks.join(" or ")));
+        }
+        text.push_str(&help_entry(&names, &help));
+    }
+
+    text
+}
+
+fn help_entry(names: &str, help: &str) -> String {
+    let mut lines: Vec<String> = vec![];
+    let mut line = String::new();
+    for word in help.split_whitespace() {
+        if !line.is_empty() && HELP_INDENT + line.len() + 1 + word.len() > HELP_WIDTH {
+            lines.push(std::mem::take(&mut line));
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(word);
+    }
+    lines.push(line);
+
+    let indent = " ".repeat(HELP_INDENT);
+    let first = if names.len() + 4 <= HELP_INDENT {
+        format!("  {names:<width$}{}\n", lines[0], width = HELP_INDENT - 2)
+    }
+    else {
+        format!("  {names}\n{indent}{}\n", lines[0])
+    };
+
+    let mut entry = first;
+    for line in &lines[1..] {
+        entry.push_str(&format!("{indent}{line}\n"));
+    }
+    entry
+}
+
+
+// everything `--help` shows, for editor plugins and wrapper scripts
+pub fn cli_json() -> String {
+    let commands = Command::ALL.iter()
+        .map(|command| format!("    {{ \"name\": {}, \"summary\": {} }}", json_string(command.name()), json_string(command.summary())))
+        .collect::<Vec<_>>()
+        .join(",\n");
+
+    let options = OPTIONS.iter()
+        .map(|spec| {
+            let optional = |value: Option<String>| value.map_or("null".into(), |value| json_string(&value));
+            format!(
+                "    {{ \"long\": {}, \"short\": {}, \"value\": {}, \"repeatable\": {}, \"choices\": {}, \"commands\": {}, \"env\": {}, \"config\": {}, \"help\": {} }}",
+                json_string(spec.long),
+                optional(spec.short.map(String::from)),
+                optional(spec.value.map(String::from)),
+                spec.repeatable,
+                json_list(&choices(spec).into_iter().map(String::from).collect::<Vec<_>>()),
+                json_list(&spec.commands.iter().map(|command| command.name().to_string()).collect::<Vec<_>>()),
+                optional(spec.env.map(String::from)),
+                optional(spec.config.map(|(table, key)| format!("{table}.{key}"))),
+                json_string(spec.help)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(",\n");
+
+    format!("{{\n  \"program\": \"bcplpp\",\n  \"version\": {},\n  \"default_command\": {},\n  \"commands\": [\n{commands}\n  ],\n  \"options\": [\n{options}\n  ]\n}}\n",
+        json_string(env!("CARGO_PKG_VERSION")), json_string(Command::default().name()))
+}
+
+pub fn completions(program_name: &str, shell: &str) -> Result<String, String> {
+    match shell {
+        "bash" => Ok(bash_completions(program_name)),
+        // zsh can run bash completion functions
+        "zsh" => Ok(format!("#compdef {program_name}\nautoload -U +X bashcompinit && bashcompinit\n{}", bash_completions(program_name))),
+        "fish" => Ok(fish_completions(program_name)),
+        _ => Err(format!("unknown shell `{shell}`; expected one of {}.", SHELLS.map(|shell| format!("`{shell}`")).join(", ")))
+    }
+}
+
+fn option_names(spec: &OptionSpec) -> impl Iterator<Item = &'static str> {
+    spec.short.into_iter().chain(std::iter::once(spec.long))
+}
+
+fn bash_completions(program_name: &str) -> String {
+    let function = format!("_{}", program_name.replace(|c: char| !c.is_ascii_alphanumeric(), "_"));
+    let command_names = Command::ALL.map(|command| command.name()).join(" ");
+
+    let per_command = Command::ALL.iter()
+        .map(|command| format!("        {}) opts=\"{}\" ;;",
+            command.name(),
+            OPTIONS.iter().filter(|spec| spec.applies_to(*command)).flat_map(option_names).collect::<Vec<_>>().join(" ")
+        ))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let values = OPTIONS.iter()
+        .filter(|spec| !choices(spec).is_empty())
+        .map(|spec| format!("        {}) COMPREPLY=( $(compgen -W \"{}\" -- \"$cur\") ); return ;;",
+            option_names(spec).collect::<Vec<_>>().join("|"),
+            choices(spec).join(" ")
+        ))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    format!(r#"{function}() {{
+    local cur="${{COMP_WORDS[COMP_CWORD]}}"
+    local prev="${{COMP_WORDS[COMP_CWORD-1]}}"
+    local command={default} opts word
+
+    for word in "${{COMP_WORDS[@]:1:COMP_CWORD-1}}"; do
+        case "$word" in
+            {alternatives}) command="$word"; break ;;
+        esac
+    done
+
+    case "$prev" in
+{values}
+    esac
+
+    case "$command" in
+{per_command}
+    esac
+    if [[ $COMP_CWORD -eq 1 ]]; then
+        opts="$opts {command_names} daemon help"
+    fi
+
+    if [[ "$cur" == -* || $COMP_CWORD -eq 1 ]]; then
+        COMPREPLY=( $(compgen -W "$opts" -- "$cur") )
+    fi
+    if [[ "$cur" != -* ]]; then
+        COMPREPLY+=( $(compgen -f -- "$cur") )
+    fi
+}}
+complete -F {function} {program_name}
+"#,
+        default = Command::default().name(),
+        alternatives = Command::ALL.map(|command| command.name()).join("|")
+    )
+}
+
+fn fish_completions(program_name: &str) -> String {
+    let command_names = Command::ALL.map(|command| command.name()).join(" ");
+    let escape = |text: &str| text.replace('\\', "\\\\").replace('\'', "\\'");
+    // the first sentence of the help text
+    let summary = |text: &str| escape(text.split(". ").next().unwrap_or(text).trim_end_matches('.'));
+
+    let mut script = format!("complete -c {program_name} -f\n");
+    for command in Command::ALL {
+        script.push_str(&format!("complete -c {program_name} -n '__fish_use_subcommand' -a {} -d '{}'\n", command.name(), summary(command.summary())));
+    }
+
+    for spec in OPTIONS {
+        let mut used_by = spec.commands.iter().map(|command| command.name()).collect::<Vec<_>>();
+        let condition = if used_by.len() == Command::ALL.len() {
+            String::new()
+        }
+        else {
+            // without a subcommand, `build` is run
+            let default = spec.applies_to(Command::default())
+                .then(|| format!("; or not __fish_seen_subcommand_from {command_names}"))
+                .unwrap_or_default();
+            used_by.sort();
+            format!(" -n '__fish_seen_subcommand_from {}{default}'", used_by.join(" "))
+        };
+
+        let mut flags = String::new();
+        if let Some(short) = spec.short {
+            flags.push_str(&format!(" -s {}", &short[1..]));
+        }
+        match spec.long.strip_prefix("--") {
+            Some(long) => flags.push_str(&format!(" -l {long}")),
+            None => flags.push_str(&format!(" -o {}", &spec.long[1..]))
+        }
+
+        let choices = choices(spec);
+        if !choices.is_empty() {
+            flags.push_str(&format!(" -x -a '{}'", choices.join(" ")));
+        }
+        else if spec.value.is_some() {
+            flags.push_str(" -r -F");
+        }
+
+        script.push_str(&format!("complete -c {program_name}{condition}{flags} -d '{}'\n", summary(spec.help)));
+    }
+    script.push_str(&format!("complete -c {program_name} -n 'not __fish_use_subcommand' -a '(__fish_complete_path)'\n"));
+    script
+}
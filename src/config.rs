@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+
+// Per-project configuration, read from a small subset of TOML:
+// `[table]` headers and `key = value` pairs holding strings, integers,
+// booleans or single-line arrays thereof.
+
+pub const CONFIG_FILENAME: &str = "bcpl.toml";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConfigValue {
+    String(String),
+    Integer(i64),
+    Bool(bool),
+    Array(Vec<ConfigValue>)
+}
+
+impl ConfigValue {
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(s) => Some(s),
+            _ => None
+        }
+    }
+
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            Self::Integer(value) => Some(*value),
+            _ => None
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&Vec<ConfigValue>> {
+        match self {
+            Self::Array(values) => Some(values),
+            _ => None
+        }
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct Config {
+    path: String,
+    tables: HashMap<String, HashMap<String, ConfigValue>>
+}
+
+impl Config {
+    pub fn read(path: &str) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|err| format!("could not read `{path}`: {err}"))?;
+
+        Self::parse(path, &contents)
+    }
+
+    // reads the project configuration in the working directory, if there is one
+    pub fn find() -> Result<Option<Self>, String> {
+        if std::path::Path::new(CONFIG_FILENAME).is_file() {
+            Self::read(CONFIG_FILENAME).map(Some)
+        }
+        else {
+            Ok(None)
+        }
+    }
+
+    pub fn parse(path: &str, contents: &str) -> Result<Self, String> {
+        let mut config = Self {
+            path: path.to_string(),
+            tables: HashMap::new()
+        };
+        let mut table = String::new();
+
+        for (i, line) in contents.lines().enumerate() {
+            let err = |msg: &str| format!("{path}:{}: {msg}", i + 1);
+
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            if let Some(header) = line.strip_prefix('[') {
+                table = header.strip_suffix(']')
+                    .ok_or_else(|| err("expected `]` after table name"))?
+                    .trim()
+                    .to_string();
+                continue;
+            }
+
+            let (key, value) = line.split_once('=')
+                .ok_or_else(|| err("expected `key = value`"))?;
+            let value = parse_value(value.trim())
+                .ok_or_else(|| err("invalid value"))?;
+
+            config.tables.entry(table.clone())
+                .or_default()
+                .insert(key.trim().to_string(), value);
+        }
+
+        Ok(config)
+    }
+
+    pub fn path(&self) -> &String {
+        &self.path
+    }
+
+    pub fn table(&self, name: &str) -> Option<&HashMap<String, ConfigValue>> {
+        self.tables.get(name)
+    }
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    for (i, ch) in line.char_indices() {
+        match ch {
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            _ => ()
+        }
+    }
+    line
+}
+
+fn parse_value(value: &str) -> Option<ConfigValue> {
+    if let Some(s) = value.strip_prefix('"') {
+        return s.strip_suffix('"').map(|s| ConfigValue::String(s.replace("\\\"", "\"")))
+    }
+
+    if let Some(inner) = value.strip_prefix('[') {
+        let inner = inner.strip_suffix(']')?.trim();
+        if inner.is_empty() {
+            return Some(ConfigValue::Array(vec![]))
+        }
+
+        return inner.split(',')
+            .map(str::trim)
+            .filter(|elem| !elem.is_empty())
+            .map(parse_value)
+            .collect::<Option<Vec<_>>>()
+            .map(ConfigValue::Array)
+    }
+
+    match value {
+        "true" => Some(ConfigValue::Bool(true)),
+        "false" => Some(ConfigValue::Bool(false)),
+        _ => value.parse().ok().map(ConfigValue::Integer)
+    }
+}
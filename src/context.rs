@@ -1,17 +1,42 @@
-use std::{collections::HashMap, sync::{Arc, Mutex}};
+use std::{collections::{HashMap, HashSet, VecDeque}, path::{Path, PathBuf}, sync::{Arc, Mutex}, time::Instant};
 
 use colorize::AnsiColor;
 
 use crate::{
-    terminate,
-    source_file::{SourceFile, SourceFileId, Located},
+    terminate, log,
+    source_file::{SourceFile, SourceFileId, SourceText, ReadLimits, Location, Located, WithLocation},
     token::lexer::Lexer,
-    ast,
+    ast::{self, layout::DataLayout, expr::OverflowMode},
     parser::{Parser, ParseError},
-    error::CompilerError, typechecker::typecheck_ast
+    error::{CompilerError, Severity, DiagnosticStyle}, typechecker::typecheck_ast,
+    ir::{lower_ast, write_asm, write_map, write_switch_report, LowerError, LowerOptions},
+    lint::{Lint, LintConfig, LintLevel},
+    config::Config,
+    entry::{check_entry, EntryError, DEFAULT_ENTRY},
+    metadata::{read_metadata, parse_metadata, write_metadata, METADATA_EXT},
+    tast::{read_typed_ast, write_typed_ast},
+    cheader::write_c_header,
+    link::{check_globals, check_uninitialized_globals, check_linker_script},
+    target::Target,
+    freestanding::check_freestanding,
+    needs::{self, check_needs},
+    deprecation::check_deprecated,
+    writef::check_formats,
+    metrics::check_metrics,
+    identifiers::check_identifiers,
+    image::{Image, OutputFormat},
+    daemon::FileCache,
+    report::BuildReport,
+    baseline::Baseline,
+    interrupt::interrupted,
+    fingerprint::Fingerprint,
+    memory::{self, format_bytes},
+    depgraph::{self, DepGraph, DEPS_EXT},
+    extract::extract,
+    modernize::{modernize, respell, Modernized}
 };
 
-#[derive(Default)]
+#[derive(Debug, Default)]
 pub enum BuildKind {
     #[default]
     Executable,
@@ -45,18 +70,135 @@ impl BuildKind {
     }
 }
 
-#[derive(Default)]
+// additional artifacts written next to the output
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Emit {
+    Asm,
+    Map,
+    Tast,
+    Header
+}
+
+impl Emit {
+    fn ext(&self) -> &'static str {
+        match self {
+            // the listing shows the compiler's IR, not assembly for the target
+            Self::Asm => ".ir",
+            Self::Map => ".map",
+            Self::Tast => ".tast",
+            Self::Header => ".h"
+        }
+    }
+
+    // starts the line holding the fingerprint stamp
+    fn comment(&self) -> &'static str {
+        match self {
+            Self::Asm | Self::Tast => ";",
+            Self::Map => "#",
+            Self::Header => "//"
+        }
+    }
+}
+
+impl TryFrom<&str> for Emit {
+    type Error = ();
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "asm" => Ok(Self::Asm),
+            "map" => Ok(Self::Map),
+            "tast" => Ok(Self::Tast),
+            "h" => Ok(Self::Header),
+            _ => Err(())
+        }
+    }
+}
+
+#[derive(Clone, Default)]
 pub enum OutputFile {
+    // may contain `{name}` and `{target}` placeholders until resolved
     Name(String),
+    // `-o build/` places the output in `build`, named after the first input file
+    Directory(String),
     #[default]
     Default
 }
 
 impl OutputFile {
+    pub fn from_arg(arg: String) -> Self {
+        if arg.ends_with(std::path::MAIN_SEPARATOR) || arg.ends_with('/') || Path::new(&arg).is_dir() {
+            Self::Directory(arg)
+        }
+        else {
+            Self::Name(arg)
+        }
+    }
+
+    // substitutes placeholders and directories, so that only `Name` and `Default` remain
+    fn resolve(self, name: &str, target: &str, build_kind: &BuildKind) -> Result<Self, String> {
+        match self {
+            Self::Directory(dir) => {
+                let ext = build_kind.ext(std::env::consts::OS).expect("invalid operating system");
+                Ok(Self::Name(Path::new(&dir).join(format!("{name}{ext}")).to_string_lossy().into_owned()))
+            }
+            Self::Name(template) => {
+                let mut filename = String::new();
+                let mut rest = template.as_str();
+                while let Some(start) = rest.find('{') {
+                    filename.push_str(&rest[..start]);
+                    let end = rest[start..].find('}')
+                        .ok_or_else(|| format!("unterminated placeholder in output file `{template}`."))?;
+                    match &rest[start + 1..start + end] {
+                        "name" => filename.push_str(name),
+                        "target" => filename.push_str(target),
+                        placeholder => return Err(format!("unknown placeholder `{{{placeholder}}}` in output file `{template}`; expected `{{name}}` or `{{target}}`."))
+                    }
+                    rest = &rest[start + end + 1..];
+                }
+                filename.push_str(rest);
+                Ok(Self::Name(filename))
+            }
+            Self::Default => Ok(Self::Default)
+        }
+    }
+
+    // the directory the output is written to has to exist and be writable
+    fn check_directory(&self) -> Result<(), String> {
+        let Self::Name(filename) = self else {
+            return Ok(())
+        };
+
+        let dir = Path::new(filename).parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        match std::fs::metadata(dir) {
+            Ok(metadata) if !metadata.is_dir() => Err(format!("output directory `{}` is not a directory.", dir.display())),
+            Ok(metadata) if metadata.permissions().readonly() => Err(format!("output directory `{}` is not writable.", dir.display())),
+            Ok(_) => Ok(()),
+            Err(err) => Err(format!("output directory `{}` cannot be used: {err}.", dir.display()))
+        }
+    }
+
+    // a file accompanying the output, like `foo.bmeta` for `foo.o`
+    fn with_extension(&self, ext: &str) -> String {
+        let stem = match self {
+            Self::Name(filename) => std::path::Path::new(filename).with_extension("").to_string_lossy().into_owned(),
+            Self::Default => "a".into(),
+            Self::Directory(_) => unreachable!("output directories are resolved before compiling")
+        };
+        format!("{stem}{ext}")
+    }
+
+    // the metadata file accompanying an object file
+    fn metadata_filename(&self) -> String {
+        self.with_extension(METADATA_EXT)
+    }
+
     pub fn to_filename(self, build_kind: &BuildKind) -> String {
         match self {
             Self::Name(filename) => filename,
-            Self::Default => format!("a{}", build_kind.ext(std::env::consts::OS).expect("invalid operating system"))
+            Self::Default => format!("a{}", build_kind.ext(std::env::consts::OS).expect("invalid operating system")),
+            Self::Directory(_) => unreachable!("output directories are resolved before compiling")
         }
     }
 }
@@ -70,6 +212,42 @@ pub struct Context {
     tags: Vec<String>,
 
     source_files: HashMap<SourceFileId, SourceFile>,
+    metadata_files: Vec<String>,
+    typed_ast_files: Vec<String>,
+    // the files locations in typed asts refer to; they are only read for diagnostics
+    typed_ast_sources: HashSet<SourceFileId>,
+    lints: LintConfig,
+    lower_options: LowerOptions,
+    // routines are lowered in this many units in parallel; one per core if unset
+    codegen_units: Option<usize>,
+    target: Target,
+    linker_script: Option<String>,
+    entry: Option<String>,
+    dump_ast: bool,
+    // no status lines on stdout, for embedding the compiler
+    quiet: bool,
+    diagnostic_style: DiagnosticStyle,
+    // no runtime library and entry shim are linked
+    freestanding: bool,
+    emit: Vec<Emit>,
+    // interleave source lines with the emitted assembly
+    asm_source: bool,
+    report_data_size: bool,
+    report_switches: bool,
+    memory_stats: bool,
+    output_format: OutputFormat,
+    // shared between the requests of a daemon
+    file_cache: Option<Arc<FileCache>>,
+    // cache statistics when this compilation started
+    cache_stats: (usize, usize),
+
+    build_report: Option<String>,
+    report: Mutex<BuildReport>,
+    // warnings recorded here are not reported again
+    warning_baseline: Option<String>,
+    // print why translation units are compiled again
+    explain_rebuild: bool,
+    started: Option<Instant>,
 
     ast: Arc<Mutex<ast::Program>>
 }
@@ -78,11 +256,35 @@ impl Context {
     pub fn from_program_name(program_name: String) -> Self {
         let mut ctx = Self::default();
         ctx.program_name = program_name;
+        ctx.started = Some(Instant::now());
         ctx
     }
     
     pub fn set_output_file(&mut self, output_file: String) {
-        self.output_file = OutputFile::Name(output_file);
+        self.output_file = OutputFile::from_arg(output_file);
+    }
+
+    // names the output after the first input file and fails before compiling if it cannot be written
+    fn resolve_output_file(&mut self) {
+        let name = self.source_files.iter()
+            .min_by_key(|(id, _)| **id)
+            .map(|(_, file)| file.path())
+            .or(self.typed_ast_files.first())
+            .map(|path| Path::new(path).file_stem().unwrap_or_default().to_string_lossy().into_owned())
+            .unwrap_or_else(|| "a".into());
+        let target = self.target.name().to_string();
+
+        let output_file = std::mem::take(&mut self.output_file).resolve(&name, &target, &self.build_kind)
+            .and_then(|output_file| output_file.check_directory().map(|_| output_file));
+        match output_file {
+            Ok(output_file) => self.output_file = output_file,
+            Err(err) => self.fatal_error(&err)
+        }
+    }
+
+    // where the executable, object or library is written to
+    pub fn output_path(&self) -> String {
+        self.output_file.clone().to_filename(&self.build_kind)
     }
 
     pub fn program_name(&self) -> &String {
@@ -97,10 +299,127 @@ impl Context {
         self.build_kind = build_kind;
     }
 
+    pub fn set_entry(&mut self, entry: String) {
+        self.entry = Some(entry);
+    }
+
+    fn entry(&self) -> &str {
+        self.entry.as_deref().unwrap_or(DEFAULT_ENTRY)
+    }
+
+    pub fn set_dump_ast(&mut self, dump_ast: bool) {
+        self.dump_ast = dump_ast;
+    }
+
+    pub fn set_quiet(&mut self, quiet: bool) {
+        self.quiet = quiet;
+    }
+
+    pub fn diagnostic_style(&self) -> &DiagnosticStyle {
+        &self.diagnostic_style
+    }
+
+    pub fn diagnostic_style_mut(&mut self) -> &mut DiagnosticStyle {
+        &mut self.diagnostic_style
+    }
+
+    pub fn add_emit(&mut self, emit: Emit) {
+        if !self.emit.contains(&emit) {
+            self.emit.push(emit);
+        }
+    }
+
+    pub fn set_asm_source(&mut self, asm_source: bool) {
+        self.asm_source = asm_source;
+    }
+
+    pub fn set_report_data_size(&mut self, report_data_size: bool) {
+        self.report_data_size = report_data_size;
+    }
+
+    pub fn set_report_switches(&mut self, report_switches: bool) {
+        self.report_switches = report_switches;
+    }
+
+    pub fn set_memory_stats(&mut self, memory_stats: bool) {
+        self.memory_stats = memory_stats;
+    }
+
+    pub fn set_output_format(&mut self, output_format: OutputFormat) {
+        self.output_format = output_format;
+    }
+
+    pub fn set_file_cache(&mut self, file_cache: Arc<FileCache>) {
+        self.cache_stats = file_cache.stats();
+        self.file_cache = Some(file_cache);
+    }
+
+    pub fn set_build_report(&mut self, build_report: String) {
+        self.build_report = Some(build_report);
+    }
+
+    pub fn set_explain_rebuild(&mut self, explain_rebuild: bool) {
+        self.explain_rebuild = explain_rebuild;
+    }
+
+    pub fn set_warning_baseline(&mut self, warning_baseline: String) {
+        self.warning_baseline = Some(warning_baseline);
+    }
+
+    pub fn set_freestanding(&mut self, freestanding: bool) {
+        self.freestanding = freestanding;
+    }
+
+    pub fn set_checked(&mut self, checked: bool) {
+        self.lower_options.checked = checked;
+    }
+
+    pub fn set_overflow_mode(&mut self, overflow: OverflowMode) {
+        self.lower_options.overflow = overflow;
+    }
+
+    pub fn set_codegen_units(&mut self, codegen_units: usize) {
+        self.codegen_units = Some(codegen_units);
+    }
+
+    pub fn set_linker_script(&mut self, linker_script: String) {
+        self.linker_script = Some(linker_script);
+    }
+
+    pub fn target_mut(&mut self) -> &mut Target {
+        &mut self.target
+    }
+
+    pub fn set_data_layout(&mut self, data_layout: DataLayout) {
+        self.lower_options.data_layout = data_layout;
+    }
+
+    pub fn lints_mut(&mut self) -> &mut LintConfig {
+        &mut self.lints
+    }
+
+    pub fn apply_config(&mut self, config: &Config) {
+        if let Err(err) = self.lints.apply(config) {
+            self.fatal_error(&err);
+        }
+    }
+
     pub fn add_source_files(&mut self, source_files: HashMap<SourceFileId, SourceFile>) {
         self.source_files.extend(source_files);
     }
 
+    pub fn add_metadata_file(&mut self, path: String) {
+        self.metadata_files.push(path);
+    }
+
+    pub fn add_typed_ast_file(&mut self, path: String) {
+        self.typed_ast_files.push(path);
+    }
+
+    pub fn ast(&self) -> &Arc<Mutex<ast::Program>> {
+        &self.ast
+    }
+
     pub fn source_files(&self) -> &HashMap<SourceFileId, SourceFile> {
         &self.source_files
     }
@@ -110,54 +429,707 @@ impl Context {
             format!("{}:", self.program_name()).bold(),
             format!("fatal error:").bold().red()
         );
-        
+
+        if self.build_report.is_some() {
+            self.report.lock().unwrap().set_fatal();
+            self.write_build_report(None);
+        }
+
         terminate();
     }
 
+    // drops allowed warnings and turns denied ones into errors
+    fn apply_lint_level(&self, lint: Option<Lint>, warning: Located<CompilerError>) -> Option<Located<CompilerError>> {
+        match lint.map(|lint| self.lints.level(lint)) {
+            Some(LintLevel::Allow) => None,
+            Some(LintLevel::Deny) => Some(warning.map(|warning| warning.with_severity(Severity::Error))),
+            _ => Some(warning)
+        }
+    }
+
+    // the start of the first input file, for diagnostics without a declaration to point at
+    fn start_location(&self) -> Location {
+        let (_, file) = self.source_files.iter().min_by_key(|(id, _)| **id).unwrap();
+        Location::new(file, 1, 0, 1)
+    }
+
+    // records all warnings if the baseline does not exist yet, otherwise drops the recorded ones
+    fn apply_warning_baseline(&self, warnings: &mut Vec<Located<CompilerError>>) {
+        let Some(path) = &self.warning_baseline else {
+            return
+        };
+
+        let Some(baseline) = Baseline::read(path).unwrap_or_else(|err| self.fatal_error(&err)) else {
+            let baseline = Baseline::record(warnings, &self.source_files);
+            if let Err(err) = baseline.write(path) {
+                self.fatal_error(&err);
+            }
+            println!("{} {} warning(s) in `{path}`", "Recorded:".bold().magenta(), baseline.len());
+            warnings.clear();
+            return
+        };
+
+        let suppressed = baseline.suppress(warnings, &self.source_files);
+        if suppressed > 0 {
+            println!("{} {suppressed} warning(s) from baseline `{path}`", "Suppressed:".bold().magenta());
+        }
+    }
+
     fn print_compiling_status(&self, filepath: &String) {
+        // keep stdout clean for the dumped ast
+        if self.dump_ast || self.quiet {
+            return
+        }
         println!("{} {filepath}", "Compiling:".bold().magenta());
     }
 
-    //                              Warnings            Errors
-    pub fn compile(&self) -> CompileResult {
-        if self.source_files.is_empty() {
+    // converts the linked executable into a flat image next to it
+    fn write_image(&self) {
+        let elf_path = self.output_file.clone().to_filename(&self.build_kind);
+        let elf = std::fs::read(&elf_path)
+            .unwrap_or_else(|err| self.fatal_error(&format!("error reading `{elf_path}`: {err}.")));
+        let image = Image::from_elf(&elf)
+            .unwrap_or_else(|err| self.fatal_error(&format!("error reading `{elf_path}`: {err}.")));
+
+        let contents = match self.output_format {
+            OutputFormat::Elf => return,
+            OutputFormat::Binary => image.to_binary(),
+            OutputFormat::IntelHex => image.to_intel_hex()
+                .unwrap_or_else(|err| self.fatal_error(&format!("{err}.")))
+                .into_bytes()
+        };
+
+        let path = self.output_file.with_extension(self.output_format.ext());
+        if let Err(err) = std::fs::write(&path, contents) {
+            self.fatal_error(&format!("error writing `{path}`: {err}."));
+        }
+        self.add_artifact(path);
+    }
+
+    // does nothing unless `-Zmemory-stats` was given
+    pub fn print_memory_stats(&self) {
+        if !self.memory_stats {
+            return
+        }
+        if !memory::is_counting() {
+            println!("{} allocations are not counted by this build", "Memory:".bold().magenta());
+            return
+        }
+
+        let report = self.report.lock().unwrap();
+        let source_text = self.source_files.values().map(|file| file.contents().len()).sum();
+        let peak = report.memory().iter().map(|(_, usage)| usage.peak).max().unwrap_or_default();
+        println!("{} {} of source text, peak {}", "Memory:".bold().magenta(), format_bytes(source_text), format_bytes(peak));
+        for (phase, usage) in report.memory() {
+            println!("  {phase:<10} {:>10} in {:>8} allocation(s), peak {:>10}, {:>10} in use after",
+                format_bytes(usage.allocated), usage.allocations, format_bytes(usage.peak), format_bytes(usage.live));
+        }
+    }
+
+    // does nothing unless `--build-report` was given; `None` marks a fatal error
+    pub fn write_build_report(&self, result: Option<&CompileResult>) {
+        let Some(path) = &self.build_report else {
+            return
+        };
+
+        let mut report = self.report.lock().unwrap();
+        if let Some(CompileResult::Warn(diagnostics) | CompileResult::Err(diagnostics) | CompileResult::Interrupted(diagnostics)) = result {
+            let errors = diagnostics.iter().filter(|diag| matches!(diag.severity(), Severity::Error)).count();
+            report.set_diagnostics(errors, diagnostics.len() - errors);
+        }
+        if let Some(cache) = &self.file_cache {
+            let (hits, misses) = cache.stats();
+            report.set_cache_stats(hits - self.cache_stats.0, misses - self.cache_stats.1);
+        }
+
+        let total = self.started.map(|started| started.elapsed()).unwrap_or_default();
+        // not through `fatal_error`, which writes the report itself
+        if let Err(err) = std::fs::write(path, report.to_json(total)) {
+            eprintln!("{} error writing build report `{path}`: {err}.", format!("{}:", self.program_name()).bold());
+        }
+    }
+
+    // hashes the sources, including the ones read by `get`, the imported metadata and the options
+    fn fingerprint(&self) -> Fingerprint {
+        let mut fingerprint = Fingerprint::default();
+
+        let mut files = self.source_files.values()
+            .filter(|file| !self.typed_ast_sources.contains(&file.id()))
+            .collect::<Vec<_>>();
+        files.sort_by(|a, b| a.path().cmp(b.path()));
+        for file in files {
+            fingerprint.add(file.path().as_bytes());
+            fingerprint.add(file.contents().as_bytes());
+        }
+
+        for path in self.metadata_files.iter().chain(&self.typed_ast_files) {
+            let text = self.read_text(path).unwrap_or_else(|err| self.fatal_error(&format!("could not read `{path}`: {err}")));
+            fingerprint.add(path.as_bytes());
+            fingerprint.add(text.contents().as_bytes());
+        }
+
+        fingerprint.add(self.options_string().as_bytes());
+        fingerprint
+    }
+
+    // through the daemon's cache, if there is one
+    fn read_text(&self, path: &str) -> std::io::Result<Arc<SourceText>> {
+        match &self.file_cache {
+            Some(cache) => cache.read(path),
+            None => SourceText::read(path, ReadLimits::default()).map(Arc::new)
+        }
+    }
+
+    // everything besides the inputs that changes the result of a compilation
+    fn options_string(&self) -> String {
+        format!(
+            "{:?} {:?} {:?} {:?} {:?} {} {} {} {} {:?}",
+            self.build_kind, self.tags, self.target, self.emit, self.lower_options, self.entry(), self.freestanding, self.asm_source,
+            self.lints.describe(), self.warning_baseline
+        )
+    }
+
+    fn artifact_paths(&self) -> Vec<String> {
+        let mut artifacts = self.emit.iter().map(|emit| self.output_file.with_extension(emit.ext())).collect::<Vec<_>>();
+        if let BuildKind::Object = self.build_kind {
+            artifacts.push(self.output_file.metadata_filename());
+        }
+        artifacts
+    }
+
+    // checks that the artifacts of this compilation match the inputs, without compiling;
+    // returns why they do not
+    pub fn check_artifacts(&mut self) -> Result<(), Vec<String>> {
+        if self.source_files.is_empty() && self.typed_ast_files.is_empty() {
             self.fatal_error("no input files.");
         }
-            
+        self.resolve_output_file();
+
+        let artifacts = self.artifact_paths();
+        if artifacts.is_empty() {
+            self.fatal_error("there are no artifacts to check; use `--emit` or `--object`.");
+        }
+
+        // `get` directives are found by the lexer alone
+        let mut pending: VecDeque<SourceFileId> = self.source_files.keys().copied().collect();
+        let mut parsed: HashSet<PathBuf> = self.source_files.values()
+            .filter_map(|file| std::fs::canonicalize(file.path()).ok())
+            .collect();
+        while let Some(id) = pending.pop_front() {
+            let file = &self.source_files[&id];
+            let dir = Path::new(file.path()).parent().map(Path::to_path_buf).unwrap_or_default();
+            for include in depgraph::includes(file, &self.tags) {
+                match self.include_file(&dir, &include, &mut parsed) {
+                    Ok(Some(id)) => pending.push_back(id),
+                    Ok(None) => (),
+                    // a missing include fails the real compilation, so the artifacts are not current
+                    Err(err) => return Err(vec![err.message().clone()])
+                }
+            }
+        }
+
+        let fingerprint = self.fingerprint();
+        let stale = artifacts.iter()
+            .filter_map(|path| fingerprint.check(path).err())
+            .collect::<Vec<_>>();
+        if stale.is_empty() { Ok(()) } else { Err(stale) }
+    }
+
+    // removes everything written by this compilation, since it is incomplete
+    fn interrupt(&self, diagnostics: Vec<Located<CompilerError>>) -> CompileResult {
+        let mut report = self.report.lock().unwrap();
+        report.set_interrupted();
+        for path in report.take_artifacts() {
+            let _ = std::fs::remove_file(path);
+        }
+        CompileResult::Interrupted(diagnostics)
+    }
+
+    fn record_phase(&self, phase: &'static str, start: Instant) {
+        log!(Info, "{phase} took {:?}", start.elapsed());
+        self.report.lock().unwrap().record_phase(phase, start);
+    }
+
+    fn add_artifact(&self, path: String) {
+        self.report.lock().unwrap().add_artifact(path);
+    }
+
+    // reads a file named by a `get` directive, unless it was parsed already
+    fn include_file(&mut self, dir: &Path, include: &Located<String>, parsed: &mut HashSet<PathBuf>) -> Result<Option<SourceFileId>, Located<CompilerError>> {
+        let error = |msg: String, hint: Option<String>| CompilerError::new(Severity::Error, msg, hint, vec![])
+            .with_location(include.location().clone());
+
+        let Some(path) = depgraph::resolve_include(dir, include) else {
+            return Err(error(
+                format!("Cannot find included file `{}`.", **include),
+                Some(format!("Paths are relative to the directory of the including file, `{}`.", dir.display()))
+            ))
+        };
+        if !parsed.insert(std::fs::canonicalize(&path).unwrap_or(path.clone())) {
+            log!(Debug, "`{}` was parsed already, skipping", path.display());
+            return Ok(None)
+        }
+        log!(Debug, "`get \"{}\"` resolved to `{}`", **include, path.display());
+
+        let path = path.to_string_lossy().into_owned();
+        let text = self.read_text(&path).map_err(|err| error(format!("Cannot read included file `{path}`: {err}."), None))?;
+
+        let id = self.source_files.keys().max().map_or(0, |id| id + 1);
+        let mut file = SourceFile::with_text(path.clone(), id, text);
+        file.set_included_from(include.location().clone());
+        self.source_files.insert(id, file);
+        self.report.lock().unwrap().add_input(path);
+        Ok(Some(id))
+    }
+
+    // sections named by `needs` which no file parsed so far defines or imports are read from
+    // `name` or `name.bpp` next to the file needing them; the ones not found there are left to
+    // `check_needs`
+    fn needed_files(&mut self, parsed: &mut HashSet<PathBuf>, errors: &mut Vec<Located<CompilerError>>) -> Vec<SourceFileId> {
+        let missing = {
+            let ast = self.ast.lock().unwrap();
+            ast.sections()
+                .flat_map(|section| section.needs().iter().map(move |need| (section.location().file_id(), need)))
+                .filter(|(_, need)| !needs::is_available(&ast, need))
+                .map(|(file, need)| (file, need.clone()))
+                .collect::<Vec<_>>()
+        };
+
+        let mut ids = vec![];
+        for (file, need) in missing {
+            let Some(file) = self.source_files.get(&file) else {
+                continue
+            };
+            let dir = Path::new(file.path()).parent().map(Path::to_path_buf).unwrap_or_default();
+            if depgraph::resolve_include(&dir, &need).is_none() {
+                continue
+            }
+            match self.include_file(&dir, &need, parsed) {
+                Ok(Some(id)) => ids.push(id),
+                Ok(None) => (),
+                Err(err) => errors.push(err)
+            }
+        }
+        ids
+    }
+
+    pub fn compile(&mut self) -> CompileResult {
+        let units = self.scan_units();
+        if let Some(units) = &units && self.reuse_artifacts(units) {
+            return CompileResult::Ok
+        }
+
+        let mut result = self.compile_phases();
+        if let CompileResult::Warn(diagnostics) | CompileResult::Err(diagnostics) | CompileResult::Interrupted(diagnostics) = &mut result {
+            self.sort_diagnostics(diagnostics);
+        }
+
+        // builds with diagnostics are never reused, so the next one reports them again
+        let deps_path = self.output_file.with_extension(DEPS_EXT);
+        match (&result, units) {
+            (CompileResult::Ok, Some(units)) => {
+                if let Err(err) = units.write(&deps_path) {
+                    self.fatal_error(&err);
+                }
+                self.add_artifact(deps_path);
+            }
+            _ => { let _ = std::fs::remove_file(&deps_path); }
+        }
+        result
+    }
+
+    // the translation units of a build writing artifacts that a later build could reuse
+    fn scan_units(&mut self) -> Option<DepGraph> {
+        if self.source_files.is_empty() || self.dump_ast {
+            return None
+        }
+        self.resolve_output_file();
+        if self.artifact_paths().is_empty() {
+            return None
+        }
+
+        let read = |path: &str| self.read_text(path).map(|text| SourceFile::with_text(path.to_string(), 0, text));
+        let mut inputs = self.source_files.values().collect::<Vec<_>>();
+        inputs.sort_by(|a, b| a.path().cmp(b.path()));
+        DepGraph::scan(inputs.into_iter(), &self.options_string(), &self.tags, read).ok()
+    }
+
+    // whether no unit changed since the last build and its artifacts are still current
+    fn reuse_artifacts(&mut self, units: &DepGraph) -> bool {
+        let previous = DepGraph::read(&self.output_file.with_extension(DEPS_EXT));
+        let dirty = units.dirty_units(previous.as_ref());
+        if self.explain_rebuild {
+            for (unit, reason) in &dirty {
+                println!("{} {unit}: {reason}", "Rebuilding:".bold().magenta());
+            }
+        }
+        if !dirty.is_empty() {
+            return false
+        }
+
+        // metadata inputs and removed artifacts are only noticed by the fingerprints
+        if let Err(stale) = self.check_artifacts() {
+            if self.explain_rebuild {
+                stale.iter().for_each(|reason| println!("{} {reason}", "Rebuilding:".bold().magenta()));
+            }
+            return false
+        }
+
+        units.units().for_each(|unit| println!("{} {unit}", "Fresh:".bold().magenta()));
+        true
+    }
+
+    // reports diagnostics grouped by file and in source order, however the phases produced them
+    fn sort_diagnostics(&self, diagnostics: &mut [Located<CompilerError>]) {
+        diagnostics.sort_by_cached_key(|diag| {
+            let loc = diag.location();
+            (self.file_order(loc.file_id()), loc.line(), loc.column())
+        });
+    }
+
+    // input files in the order they were given, each file included by `get` right after the
+    // file including it, ordered by the position of the `get`; independent of the ids assigned
+    fn file_order(&self, id: SourceFileId) -> Vec<(SourceFileId, usize, usize)> {
+        match self.source_files.get(&id).and_then(SourceFile::included_from) {
+            Some(loc) => {
+                let mut order = self.file_order(loc.file_id());
+                order.push((0, loc.line(), loc.column()));
+                order
+            }
+            None => vec![(id, 0, 0)]
+        }
+    }
+
+    // parses, type checks and lints the input files without lowering or writing anything
+    pub fn check(&mut self) -> CompileResult {
+        let mut warnings = vec![];
+        let mut result = match self.check_phases(&mut warnings) {
+            Ok(()) => CompileResult::from(warnings),
+            Err(result) => result
+        };
+        if let CompileResult::Warn(diagnostics) | CompileResult::Err(diagnostics) | CompileResult::Interrupted(diagnostics) = &mut result {
+            self.sort_diagnostics(diagnostics);
+        }
+        result
+    }
+
+    // the source of the declarations reachable from the entry routine; the program has to check without errors
+    pub fn extract(&mut self) -> Result<String, CompileResult> {
+        let mut warnings = vec![];
+        self.check_phases(&mut warnings).map_err(|mut result| {
+            if let CompileResult::Err(diagnostics) | CompileResult::Interrupted(diagnostics) = &mut result {
+                self.sort_diagnostics(diagnostics);
+            }
+            result
+        })?;
+
+        let ast = self.ast.lock().unwrap();
+        extract(&ast, self.entry(), &self.source_files, &self.tags).map_err(|err| self.fatal_error(&err))
+    }
+
+    // the files of the program rewritten in this dialect, for `bcplpp modernize`; classic
+    // spellings are replaced before parsing, so diagnostics refer to the respelled sources
+    pub fn modernize(&mut self) -> Result<Modernized, CompileResult> {
+        let mut respelled = HashMap::new();
+        for (id, file) in self.source_files.iter_mut().filter(|(id, _)| !self.typed_ast_sources.contains(id)) {
+            let (contents, changes) = respell(file, &self.tags);
+            *file = SourceFile::new(file.path().clone(), *id, contents);
+            respelled.insert(*id, changes);
+        }
+
         let mut warnings = vec![];
-        let errors = self.source_files.values()
-            .map(|file| {
-                self.print_compiling_status(file.path());
-                Parser::new(Lexer::from(file), self.ast.clone())
-            })
-            .filter_map(|mut parser| {
-                let err = parser.parse();
-                warnings.extend(parser.warnings().iter().map(|warn| warn.clone().map(ParseError::into)));
-                err.err()
-            })
-            .map(|err| err.map(ParseError::into))
+        self.check_phases(&mut warnings).map_err(|mut result| {
+            if let CompileResult::Err(diagnostics) | CompileResult::Interrupted(diagnostics) = &mut result {
+                self.sort_diagnostics(diagnostics);
+            }
+            result
+        })?;
+
+        let ast = self.ast.lock().unwrap();
+        Ok(modernize(&ast, &self.source_files, &self.typed_ast_sources, &respelled, &self.tags))
+    }
+
+    // the phases `check` and `compile` share; `Err` ends the compilation early with its result
+    fn check_phases(&mut self, warnings: &mut Vec<Located<CompilerError>>) -> Result<(), CompileResult> {
+        {
+            let mut report = self.report.lock().unwrap();
+            report.mark_memory();
+            self.source_files.values().for_each(|file| report.add_input(file.path().clone()));
+            self.metadata_files.iter().chain(&self.typed_ast_files).for_each(|path| report.add_input(path.clone()));
+        }
+
+        let start = Instant::now();
+        for path in &self.metadata_files {
+            let result = match &self.file_cache {
+                Some(cache) => cache.read(path)
+                    .map_err(|err| format!("could not read `{path}`: {err}"))
+                    .and_then(|text| parse_metadata(path, text.contents(), &mut self.ast.lock().unwrap())),
+                None => read_metadata(path, &mut self.ast.lock().unwrap())
+            };
+            if let Err(err) = result {
+                self.fatal_error(&err);
+            }
+        }
+        self.record_phase("metadata", start);
+
+        let start = Instant::now();
+        for path in self.typed_ast_files.clone() {
+            self.print_compiling_status(&path);
+            let text = self.read_text(&path).map_err(|err| format!("could not read `{path}`: {err}"));
+
+            let first_id = self.source_files.keys().max().map_or(0, |id| id + 1);
+            match text.and_then(|text| read_typed_ast(&path, text.contents(), &mut self.ast.lock().unwrap(), first_id)) {
+                Ok(files) => {
+                    self.typed_ast_sources.extend(files.keys());
+                    self.source_files.extend(files);
+                }
+                Err(err) => self.fatal_error(&err)
+            }
+        }
+        self.record_phase("typed ast", start);
+
+        let start = Instant::now();
+        let mut errors = vec![];
+        // input files are parsed in the order they were given, so that definitions of a name in
+        // several files are reported at all but the first one; files included by `get` are
+        // parsed after the file including them
+        let mut pending = self.source_files.keys()
+            .copied()
+            .filter(|id| !self.typed_ast_sources.contains(id))
             .collect::<Vec<_>>();
+        pending.sort();
+        let mut pending = VecDeque::from(pending);
+        let mut parsed: HashSet<PathBuf> = self.source_files.values()
+            .filter_map(|file| std::fs::canonicalize(file.path()).ok())
+            .collect();
+        while let Some(id) = pending.pop_front() {
+            if interrupted() {
+                return Err(self.interrupt(std::mem::take(warnings)))
+            }
+
+            let file = &self.source_files[&id];
+            self.print_compiling_status(file.path());
+
+            let mut parser = Parser::new(Lexer::from(file).with_tags(&self.tags), self.ast.clone(), &self.lints);
+            let err = parser.parse();
+            warnings.extend(parser.warnings().iter().filter_map(|warn| self.apply_lint_level(warn.lint(), warn.clone().map(ParseError::into))));
+            errors.extend(parser.errors().iter().cloned().chain(err.err()).map(|err| err.map(ParseError::into)));
 
+            let includes = parser.includes().clone();
+            let dir = Path::new(file.path()).parent().map(Path::to_path_buf).unwrap_or_default();
+            for include in includes {
+                match self.include_file(&dir, &include, &mut parsed) {
+                    Ok(Some(id)) => pending.push_back(id),
+                    Ok(None) => (),
+                    Err(err) => errors.push(err)
+                }
+            }
+
+            if pending.is_empty() {
+                pending.extend(self.needed_files(&mut parsed, &mut errors));
+            }
+        }
+        self.record_phase("parse", start);
+
+        if errors.is_empty() {
+            errors.extend(check_needs(&self.ast.lock().unwrap()).into_iter().map(|err| err.map(|err| err.into())));
+        }
         if !errors.is_empty() {
-            return CompileResult::Err(errors)
+            return Err(CompileResult::Err(errors))
         }
 
-        if let Err(err) = typecheck_ast(self.ast.clone()) {
-            println!("typechecker error...");
+        if self.dump_ast {
+            print!("{}", self.ast.lock().unwrap().dump());
+            return Err(CompileResult::Ok)
         }
 
-//        println!("generated ast: {:#?}", self.ast);
-        if !warnings.is_empty() {
-            CompileResult::Warn(warnings)
+        if interrupted() {
+            return Err(self.interrupt(std::mem::take(warnings)))
         }
-        else {
-            CompileResult::Ok
+
+        let start = Instant::now();
+        let typechecked = typecheck_ast(self.ast.clone());
+        self.record_phase("typecheck", start);
+        if let Err(err) = typechecked {
+            return Err(CompileResult::Err(vec![err.map(|err| err.into())]))
         }
+
+        let deprecations = check_deprecated(&self.ast.lock().unwrap());
+        warnings.extend(deprecations.into_iter().filter_map(|warn| self.apply_lint_level(warn.lint(), warn.map(|warn| warn.into()))));
+
+        let formats = check_formats(&self.ast.lock().unwrap());
+        warnings.extend(formats.into_iter().filter_map(|warn| self.apply_lint_level(warn.lint(), warn.map(|warn| warn.into()))));
+
+        let metrics = check_metrics(&self.ast.lock().unwrap(), &self.lints);
+        warnings.extend(metrics.into_iter().filter_map(|warn| self.apply_lint_level(warn.lint(), warn.map(|warn| warn.into()))));
+
+        let identifiers = check_identifiers(&self.ast.lock().unwrap(), &self.lints);
+        warnings.extend(identifiers.into_iter().filter_map(|warn| self.apply_lint_level(warn.lint(), warn.map(|warn| warn.into()))));
+
+        if self.freestanding {
+            let errors = check_freestanding(&self.ast.lock().unwrap());
+            if !errors.is_empty() {
+                return Err(CompileResult::Err(errors.into_iter().map(|err| err.map(|err| err.into())).collect()))
+            }
+        }
+        Ok(())
+    }
+
+    //                              Warnings            Errors
+    fn compile_phases(&mut self) -> CompileResult {
+        if self.source_files.is_empty() && self.typed_ast_files.is_empty() {
+            self.fatal_error("no input files.");
+        }
+        self.resolve_output_file();
+
+        let mut warnings = vec![];
+        if let Err(result) = self.check_phases(&mut warnings) {
+            return result
+        }
+        let stamp = self.fingerprint().stamp();
+
+        if interrupted() {
+            return self.interrupt(warnings)
+        }
+
+        let start = Instant::now();
+        let mut lower_warnings = vec![];
+        let lowered = lower_ast(self.ast.clone(), self.lower_options, self.codegen_units, &mut lower_warnings);
+        warnings.extend(lower_warnings.into_iter().filter_map(|warn| self.apply_lint_level(warn.lint(), warn.map(LowerError::into))));
+        let module = match lowered {
+            Ok(module) => module,
+            Err(errors) => return CompileResult::Err(errors.into_iter().map(|err| err.map(LowerError::into)).collect())
+        };
+        self.record_phase("lower", start);
+
+        if self.report_data_size {
+            println!("{}", "Static data:".bold().magenta());
+            for (section, size) in module.data_size(&self.ast.lock().unwrap(), &self.lower_options.data_layout) {
+                println!("  {section}: {}", size.to_string());
+            }
+        }
+        if self.report_switches {
+            println!("{}", "Switches:".bold().magenta());
+            print!("{}", write_switch_report(&module, &self.source_files));
+        }
+
+        if interrupted() {
+            return self.interrupt(warnings)
+        }
+
+        let start = Instant::now();
+        let link_errors = check_globals(&self.ast.lock().unwrap());
+        if !link_errors.is_empty() {
+            // conflicts between imported modules have no location in this compilation
+            if let Some(err) = link_errors.iter().find(|err| err.location().is_none()) {
+                self.fatal_error(&err.to_string());
+            }
+
+            return CompileResult::Err(link_errors.into_iter()
+                .map(|err| {
+                    let loc = err.location().unwrap().clone();
+                    let err: CompilerError = err.into();
+                    err.with_location(loc)
+                })
+                .collect())
+        }
+
+        let mut link_warnings = check_uninitialized_globals(&self.ast.lock().unwrap(), &self.lints);
+        if let Some(path) = &self.linker_script {
+            let script = std::fs::read_to_string(path)
+                .unwrap_or_else(|err| self.fatal_error(&format!("error reading linker script `{path}`: {err}.")));
+            link_warnings.extend(check_linker_script(&self.ast.lock().unwrap(), path, &script));
+        }
+
+        warnings.extend(link_warnings.into_iter()
+            .filter_map(|warn| {
+                let (lint, loc) = (warn.lint(), warn.location().unwrap().clone());
+                let warn: CompilerError = warn.into();
+                self.apply_lint_level(lint, warn.with_location(loc))
+            }));
+
+        self.record_phase("link", start);
+
+        // before the entry check, whose errors are never recorded in the baseline
+        self.apply_warning_baseline(&mut warnings);
+
+        if let BuildKind::Executable = self.build_kind
+            && let Err(err) = check_entry(&self.ast.lock().unwrap(), self.entry(), self.freestanding) {
+            let loc = match &err {
+                EntryError::Missing(..) => self.start_location(),
+                EntryError::IncompatibleSignature(_, loc, _) => loc.clone()
+            };
+            let err: CompilerError = err.into();
+            warnings.push(err.with_location(loc));
+        }
+
+        if warnings.iter().any(|warn| matches!(warn.severity(), Severity::Error)) {
+            return CompileResult::Err(warnings)
+        }
+
+        // artifacts are only written once all checks passed, so failed builds leave none behind
+        let start = Instant::now();
+        for emit in &self.emit {
+            if interrupted() {
+                return self.interrupt(warnings)
+            }
+
+            let contents = match emit {
+                Emit::Asm => write_asm(&module, self.ast.lock().unwrap().types(), &self.target, self.asm_source.then_some(&self.source_files)),
+                Emit::Map => write_map(&module, &self.ast.lock().unwrap(), &self.lower_options.data_layout, &self.source_files),
+                Emit::Tast => write_typed_ast(&self.ast.lock().unwrap(), &self.source_files),
+                Emit::Header => write_c_header(&self.ast.lock().unwrap(), &self.lower_options.data_layout)
+            };
+            let path = self.output_file.with_extension(emit.ext());
+            if let Err(err) = std::fs::write(&path, format!("{} {stamp}\n{contents}", emit.comment())) {
+                self.fatal_error(&format!("error writing `{path}`: {err}."));
+            }
+            self.add_artifact(path);
+        }
+        self.record_phase("emit", start);
+
+        if let BuildKind::Object = self.build_kind {
+            let path = self.output_file.metadata_filename();
+            if let Err(err) = write_metadata(&path, &self.ast.lock().unwrap(), &stamp) {
+                self.fatal_error(&err);
+            }
+            self.add_artifact(path);
+        }
+
+        if self.output_format != OutputFormat::Elf {
+            match self.build_kind {
+                BuildKind::Executable => self.write_image(),
+                _ => self.fatal_error("`--output-format` only applies to executables.")
+            }
+        }
+
+        if interrupted() {
+            return self.interrupt(warnings)
+        }
+
+        log!(Trace, "program: {:#?}", self.ast.lock().unwrap());
+        CompileResult::from(warnings)
     }
 }
 
 pub enum CompileResult {
     Ok,
     Warn(Vec<Located<CompilerError>>),
-    Err(Vec<Located<CompilerError>>)
+    Err(Vec<Located<CompilerError>>),
+    // stopped by Ctrl-C, with the diagnostics found until then
+    Interrupted(Vec<Located<CompilerError>>)
+}
+
+impl From<Vec<Located<CompilerError>>> for CompileResult {
+    fn from(diagnostics: Vec<Located<CompilerError>>) -> Self {
+        if diagnostics.iter().any(|diag| matches!(diag.severity(), Severity::Error)) {
+            Self::Err(diagnostics)
+        }
+        else if !diagnostics.is_empty() {
+            Self::Warn(diagnostics)
+        }
+        else {
+            Self::Ok
+        }
+    }
 }
@@ -0,0 +1,88 @@
+use std::{
+    collections::HashMap,
+    io::{BufRead, Write},
+    panic::AssertUnwindSafe,
+    sync::{atomic::{AtomicBool, AtomicUsize, Ordering}, Arc, Mutex},
+    time::SystemTime
+};
+
+use crate::source_file::{SourceText, ReadLimits};
+
+// Set while `bcplpp daemon` is running: ending a compilation must not end the process then
+static SERVING: AtomicBool = AtomicBool::new(false);
+
+// marks the end of a request's output, followed by its exit code
+pub const DONE_MARKER: &str = "#bcplpp-done";
+
+// unwinds out of a compilation in daemon mode, carrying its exit code
+pub struct Terminated(pub i32);
+
+pub fn is_serving() -> bool {
+    SERVING.load(Ordering::Relaxed)
+}
+
+// Files read by earlier requests, kept until they change on disk
+#[derive(Default)]
+pub struct FileCache {
+    files: Mutex<HashMap<String, (SystemTime, Arc<SourceText>)>>,
+    hits: AtomicUsize,
+    misses: AtomicUsize
+}
+
+impl FileCache {
+    pub fn read(&self, path: &str) -> std::io::Result<Arc<SourceText>> {
+        let modified = std::fs::metadata(path)?.modified()?;
+        if let Some((cached_at, contents)) = self.files.lock().unwrap().get(path)
+            && *cached_at == modified {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(contents.clone())
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let contents = Arc::new(SourceText::read(path, ReadLimits::default())?);
+        self.files.lock().unwrap().insert(path.to_string(), (modified, contents.clone()));
+        Ok(contents)
+    }
+
+    // since the daemon started
+    pub fn stats(&self) -> (usize, usize) {
+        (self.hits.load(Ordering::Relaxed), self.misses.load(Ordering::Relaxed))
+    }
+}
+
+// Reads one request per line from stdin: the working directory followed by the compiler's
+// arguments, separated by tabs. Each request's output ends with `#bcplpp-done <exit code>`.
+pub fn serve(program_name: &str, compile: impl Fn(Vec<String>, Arc<FileCache>)) -> ! {
+    SERVING.store(true, Ordering::Relaxed);
+    let cache = Arc::new(FileCache::default());
+
+    for line in std::io::stdin().lock().lines() {
+        let Ok(line) = line else {
+            break
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let mut fields = line.split('\t').map(str::to_string);
+        let cwd = fields.next().unwrap();
+        let args = fields.filter(|arg| !arg.is_empty()).collect::<Vec<_>>();
+
+        let code = if let Err(err) = std::env::set_current_dir(&cwd) {
+            eprintln!("{program_name}: cannot change to directory `{cwd}`: {err}.");
+            1
+        }
+        else {
+            match std::panic::catch_unwind(AssertUnwindSafe(|| compile(args, cache.clone()))) {
+                Ok(()) => 0,
+                Err(payload) => payload.downcast_ref::<Terminated>().map_or(101, |terminated| terminated.0)
+            }
+        };
+
+        std::io::stderr().flush().ok();
+        println!("{DONE_MARKER} {code}");
+        std::io::stdout().flush().ok();
+    }
+
+    std::process::exit(0)
+}
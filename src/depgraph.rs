@@ -0,0 +1,194 @@
+use std::{collections::{BTreeMap, HashSet, VecDeque}, path::{Path, PathBuf}};
+
+use crate::{
+    fingerprint::Fingerprint,
+    source_file::{SourceFile, Located, WithLocation},
+    token::{TokenKind, lexer::{Lexer, unescape}}
+};
+
+pub const DEPS_EXT: &str = ".bdeps";
+
+const HEADER: &str = "# bcplpp dependency graph; read by the next build to find dirty units";
+
+struct Dependency {
+    hash: String,
+    // the file whose `get` read this one, `None` for the unit itself
+    included_by: Option<String>
+}
+
+// Translation units of a build: each input file together with the files it reads by `get`,
+// transitively, hashed by content. Written next to the artifacts of a build without
+// diagnostics, so the next one can tell which units are dirty and why.
+pub struct DepGraph {
+    options: String,
+    //              unit         file
+    units: BTreeMap<String, BTreeMap<String, Dependency>>
+}
+
+impl DepGraph {
+    // fails if an included file is missing, which the compilation itself reports
+    pub fn scan<'a>(inputs: impl Iterator<Item=&'a SourceFile>, options: &str, tags: &[String], read: impl Fn(&str) -> std::io::Result<SourceFile>) -> Result<Self, String> {
+        let mut options_hash = Fingerprint::default();
+        options_hash.add(options.as_bytes());
+
+        let mut graph = Self {
+            options: options_hash.hex(),
+            units: BTreeMap::new()
+        };
+        for input in inputs {
+            let mut files = BTreeMap::new();
+            let mut seen = HashSet::from([canonical(input.path())]);
+            let mut pending = VecDeque::new();
+            add_file(input, None, tags, &mut files, &mut pending)?;
+            while let Some((path, included_by)) = pending.pop_front() {
+                if !seen.insert(canonical(&path)) {
+                    continue
+                }
+                let file = read(&path).map_err(|err| format!("cannot read `{path}`: {err}"))?;
+                add_file(&file, Some(included_by), tags, &mut files, &mut pending)?;
+            }
+            graph.units.insert(input.path().clone(), files);
+        }
+        Ok(graph)
+    }
+
+    // `None` if there is no graph at `path`, or it cannot be used
+    pub fn read(path: &str) -> Option<Self> {
+        let contents = std::fs::read_to_string(path).ok()?;
+
+        let mut graph = Self {
+            options: String::new(),
+            units: BTreeMap::new()
+        };
+        let mut unit = None;
+        for line in contents.lines().filter(|line| !line.is_empty() && !line.starts_with('#')) {
+            match line.split('\t').collect::<Vec<_>>().as_slice() {
+                ["options", hash] => graph.options = hash.to_string(),
+                ["unit", path, hash] => {
+                    unit = Some(path.to_string());
+                    let files = graph.units.entry(path.to_string()).or_default();
+                    files.insert(path.to_string(), Dependency { hash: hash.to_string(), included_by: None });
+                }
+                ["get", path, hash, included_by] => {
+                    graph.units.get_mut(unit.as_ref()?)?
+                        .insert(path.to_string(), Dependency { hash: hash.to_string(), included_by: Some(included_by.to_string()) });
+                }
+                _ => return None
+            }
+        }
+        Some(graph)
+    }
+
+    pub fn write(&self, path: &str) -> Result<(), String> {
+        let mut contents = format!("{HEADER}\noptions\t{}\n", self.options);
+        for (unit, files) in &self.units {
+            contents.push_str(&format!("unit\t{unit}\t{}\n", files[unit].hash));
+            for (path, dependency) in files {
+                if let Some(included_by) = &dependency.included_by {
+                    contents.push_str(&format!("get\t{path}\t{}\t{included_by}\n", dependency.hash));
+                }
+            }
+        }
+        std::fs::write(path, contents).map_err(|err| format!("error writing `{path}`: {err}."))
+    }
+
+    pub fn units(&self) -> impl Iterator<Item=&String> {
+        self.units.keys()
+    }
+
+    // the units that changed since the build that wrote `previous`, with the first reason found
+    pub fn dirty_units(&self, previous: Option<&Self>) -> Vec<(&String, String)> {
+        self.units.iter()
+            .filter_map(|(unit, files)| {
+                let reason = match previous {
+                    None => Some("no earlier build without diagnostics was recorded".into()),
+                    Some(previous) if previous.options != self.options => Some("the options changed".into()),
+                    Some(previous) => match previous.units.get(unit) {
+                        None => Some("it is a new input".into()),
+                        Some(previous_files) => dirty_reason(unit, files, previous_files)
+                    }
+                };
+                reason.map(|reason| (unit, reason))
+            })
+            .collect()
+    }
+}
+
+fn dirty_reason(unit: &String, files: &BTreeMap<String, Dependency>, previous: &BTreeMap<String, Dependency>) -> Option<String> {
+    if previous.get(unit).is_none_or(|dependency| dependency.hash != files[unit].hash) {
+        return Some(format!("`{unit}` changed"))
+    }
+
+    for (path, dependency) in files {
+        let Some(included_by) = &dependency.included_by else {
+            continue
+        };
+        match previous.get(path) {
+            None => return Some(format!("`{included_by}` now includes `{path}`")),
+            Some(before) if before.hash != dependency.hash => return Some(format!("`{path}` changed, included by `{included_by}`")),
+            Some(_) => ()
+        }
+    }
+
+    previous.keys()
+        .find(|path| !files.contains_key(*path))
+        .map(|path| format!("`{path}` is no longer included"))
+}
+
+fn canonical(path: &str) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or(PathBuf::from(path))
+}
+
+// hashes `file` into `files` and queues the files it reads by `get` and `needs`
+fn add_file(file: &SourceFile, included_by: Option<String>, tags: &[String], files: &mut BTreeMap<String, Dependency>, pending: &mut VecDeque<(String, String)>) -> Result<(), String> {
+    let path = file.path();
+    let dir = Path::new(path).parent().map(Path::to_path_buf).unwrap_or_default();
+    for include in includes(file, tags) {
+        let included = resolve_include(&dir, &include)
+            .ok_or_else(|| format!("cannot find `{}` included by `{path}`", *include))?;
+        pending.push_back((included.to_string_lossy().into_owned(), path.clone()));
+    }
+    // needed sections are read from files only if no input defines them, so a
+    // missing file is not an error here
+    for need in needs(file, tags) {
+        if let Some(needed) = resolve_include(&dir, &need) {
+            pending.push_back((needed.to_string_lossy().into_owned(), path.clone()));
+        }
+    }
+
+    let mut hash = Fingerprint::default();
+    hash.add(file.contents().as_bytes());
+    files.insert(path.clone(), Dependency { hash: hash.hex(), included_by });
+    Ok(())
+}
+
+// the files named by `get` directives in `file`, found by the lexer alone; those in sections
+// skipped for `tags` are not
+pub fn includes(file: &SourceFile, tags: &[String]) -> Vec<Located<String>> {
+    let tokens = Lexer::from(file).with_tags(tags).take_while(|token| !token.is_eof()).collect::<Vec<_>>();
+    tokens.windows(2)
+        .filter_map(|pair| match (pair[0].kind(), pair[1].kind()) {
+            (TokenKind::Get, TokenKind::StringLit(raw)) => unescape(raw).ok()
+                .map(|include| include.with_location(pair[1].location().clone())),
+            _ => None
+        })
+        .collect()
+}
+
+// the sections named by `needs` declarations in `file`
+pub fn needs(file: &SourceFile, tags: &[String]) -> Vec<Located<String>> {
+    let tokens = Lexer::from(file).with_tags(tags).take_while(|token| !token.is_eof()).collect::<Vec<_>>();
+    tokens.windows(2)
+        .filter_map(|pair| match (pair[0].kind(), pair[1].kind()) {
+            (TokenKind::Needs, TokenKind::StringLit(raw)) => unescape(raw).ok()
+                .map(|need| need.with_location(pair[1].location().clone())),
+            (TokenKind::Needs, TokenKind::Ident(need)) => Some(need.to_string().with_location(pair[1].location().clone())),
+            _ => None
+        })
+        .collect()
+}
+
+// `get "name"` reads `name` or `name.bpp`, relative to the directory of the including file
+pub fn resolve_include(dir: &Path, include: &str) -> Option<PathBuf> {
+    [dir.join(include), dir.join(format!("{include}.bpp"))].into_iter().find(|path| path.is_file())
+}
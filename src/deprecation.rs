@@ -0,0 +1,139 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    match_decl,
+    ast::{Program, Function, FunctionBody, Variadic, GlobalDecl, ManifestDecl, attribute::Deprecation, expr::{Expr, ExprKind}, stmt::{Stmt, StmtKind}},
+    source_file::{Location, Located, WithLocation},
+    error::{IntoCompilerError, CompilerError, Severity},
+    freestanding::bound_by,
+    lint::Lint
+};
+
+#[derive(Clone, Debug)]
+pub enum DeprecationWarning {
+    //  name    message          declaration
+    Use(String, Option<String>, Location)
+}
+
+impl DeprecationWarning {
+    fn severity(&self) -> Severity {
+        Severity::Warning
+    }
+
+    pub fn lint(&self) -> Option<Lint> {
+        Some(Lint::Deprecated)
+    }
+
+    fn hint(&self) -> Option<String> {
+        match self {
+            Self::Use(_, message, _) => message.clone()
+        }
+    }
+
+    fn additional(&self) -> Vec<Located<CompilerError>> {
+        match self {
+            Self::Use(ident, _, decl) => vec![
+                CompilerError::new(Severity::Hint, format!("`{ident}` is declared deprecated here."), None, vec![])
+                    .with_location(decl.clone())
+            ]
+        }
+    }
+}
+
+impl WithLocation for DeprecationWarning {}
+
+impl ToString for DeprecationWarning {
+    fn to_string(&self) -> String {
+        match self {
+            Self::Use(ident, ..) => format!("Use of deprecated `{ident}`.")
+        }
+    }
+}
+
+impl IntoCompilerError for DeprecationWarning {}
+impl Into<CompilerError> for DeprecationWarning {
+    fn into(self) -> CompilerError {
+        CompilerError::new(self.severity(), self.to_string(), self.hint(), self.additional())
+    }
+}
+
+// Warns about every use of a routine, global or manifest marked `[[deprecated]]`, except
+// within its own declaration. Names are no longer reported once a local binding shadows them.
+pub fn check_deprecated(ast: &Program) -> Vec<Located<DeprecationWarning>> {
+    let deprecated: HashMap<&String, (&Deprecation, &Location)> = ast.sections()
+        .flat_map(|section| section.declarations())
+        .filter_map(|decl| decl.deprecation().map(|deprecation| (decl.ident(), (deprecation, decl.location()))))
+        .collect();
+    if deprecated.is_empty() {
+        return vec![]
+    }
+
+    let mut warnings = vec![];
+    for decl in ast.sections().flat_map(|section| section.declarations()) {
+        let mut uses = vec![];
+        let mut locals = HashSet::new();
+        match_decl!{
+            decl;
+            func as Function => {
+                func.params().iter().for_each(|param| bound_by(param.pattern(), &mut locals));
+                locals.extend(func.variadic().and_then(Variadic::ident));
+                match func.body() {
+                    FunctionBody::Expr(expr) => uses_in_expr(expr, &mut uses, &mut locals),
+                    FunctionBody::Stmt(stmt) => uses_in_stmt(stmt, &mut uses, &mut locals),
+                    FunctionBody::PatternMatchedExpr(branches) => for (patterns, expr) in branches {
+                        patterns.iter().for_each(|pattern| bound_by(pattern, &mut locals));
+                        uses_in_expr(expr, &mut uses, &mut locals);
+                    },
+                    FunctionBody::PatternMatchedStmt(branches) => for (patterns, stmt) in branches {
+                        patterns.iter().for_each(|pattern| bound_by(pattern, &mut locals));
+                        uses_in_stmt(stmt, &mut uses, &mut locals);
+                    }
+                }
+            },
+            global as GlobalDecl => if let Some(value) = global.value() {
+                uses_in_expr(value, &mut uses, &mut locals)
+            },
+            manifest as ManifestDecl => uses_in_expr(manifest.value(), &mut uses, &mut locals),
+            _ => ()
+        }
+
+        warnings.extend(uses.into_iter()
+            .filter(|(ident, _)| *ident != decl.ident())
+            .filter_map(|(ident, loc)| deprecated.get(ident).map(|(deprecation, decl_loc)|
+                DeprecationWarning::Use(ident.clone(), deprecation.message().cloned(), (*decl_loc).clone()).with_location(loc.clone())
+            ))
+        );
+    }
+
+    warnings.sort_by(|a, b| a.location().cmp(b.location()));
+    warnings
+}
+
+// collects every name referred to before a local binding shadows it
+fn uses_in_expr<'a>(expr: &'a Expr, uses: &mut Vec<(&'a String, &'a Location)>, locals: &mut HashSet<&'a String>) {
+    match expr.kind() {
+        ExprKind::Ident(ident) if !locals.contains(ident) => uses.push((ident, expr.location())),
+        ExprKind::ValOf(body) => uses_in_stmt(body, uses, locals),
+        ExprKind::Match(_, branches) | ExprKind::Every(_, branches) => branches.iter()
+            .flat_map(|(patterns, _)| patterns)
+            .for_each(|pattern| bound_by(pattern, locals)),
+        _ => ()
+    }
+
+    expr.kind().children().into_iter().for_each(|child| uses_in_expr(child, uses, locals));
+}
+
+fn uses_in_stmt<'a>(stmt: &'a Stmt, uses: &mut Vec<(&'a String, &'a Location)>, locals: &mut HashSet<&'a String>) {
+    match stmt.kind() {
+        StmtKind::Binding(bindings) => bindings.iter().for_each(|(pattern, _)| bound_by(pattern, locals)),
+        StmtKind::For(iter, ..) => bound_by(iter, locals),
+        StmtKind::Try(_, caught, _) => bound_by(caught, locals),
+        StmtKind::Match(_, branches) | StmtKind::Every(_, branches) => branches.iter()
+            .flat_map(|(patterns, _)| patterns)
+            .for_each(|pattern| bound_by(pattern, locals)),
+        _ => ()
+    }
+
+    stmt.kind().exprs().into_iter().for_each(|expr| uses_in_expr(expr, uses, locals));
+    stmt.kind().stmts().into_iter().for_each(|stmt| uses_in_stmt(stmt, uses, locals));
+}
@@ -0,0 +1,96 @@
+use std::process::Command;
+
+// a way of running a BCPL program, invoked as `<command> [args...] <source file>`
+pub struct Runner {
+    name: String,
+    command: Vec<String>
+}
+
+impl Runner {
+    pub fn parse(name: &str, command: &str) -> Option<Self> {
+        let command: Vec<String> = command.split_whitespace().map(String::from).collect();
+        (!command.is_empty()).then(|| Self {
+            name: name.into(),
+            command
+        })
+    }
+
+    fn run(&self, path: &str) -> Result<Outcome, String> {
+        let output = Command::new(&self.command[0])
+            .args(&self.command[1..])
+            .arg(path)
+            .output()
+            .map_err(|err| format!("error running {} `{}`: {err}.", self.name, self.command[0]))?;
+
+        Ok(Outcome {
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            exit_code: output.status.code()
+        })
+    }
+}
+
+// everything a program is allowed to observably do
+#[derive(Debug, PartialEq)]
+struct Outcome {
+    stdout: String,
+    exit_code: Option<i32>
+}
+
+impl Outcome {
+    fn describe_exit(&self) -> String {
+        match self.exit_code {
+            Some(code) => format!("exit code {code}"),
+            None => "killed by signal".into()
+        }
+    }
+}
+
+pub enum Mismatch {
+    ExitCode(String, String),
+    //     line   interpreter       native
+    Output(usize, Option<String>, Option<String>)
+}
+
+impl ToString for Mismatch {
+    fn to_string(&self) -> String {
+        match self {
+            Self::ExitCode(interp, native) => format!("interpreter ended with {interp}, native with {native}"),
+            Self::Output(line, interp, native) => format!(
+                "output differs in line {line}: interpreter printed {}, native printed {}",
+                interp.as_ref().map_or("nothing".into(), |line| format!("`{line}`")),
+                native.as_ref().map_or("nothing".into(), |line| format!("`{line}`"))
+            )
+        }
+    }
+}
+
+fn compare(interp: &Outcome, native: &Outcome) -> Option<Mismatch> {
+    let (mut interp_lines, mut native_lines) = (interp.stdout.lines(), native.stdout.lines());
+    for line in 1.. {
+        match (interp_lines.next(), native_lines.next()) {
+            (None, None) => break,
+            (a, b) if a == b => continue,
+            (a, b) => return Some(Mismatch::Output(line, a.map(String::from), b.map(String::from)))
+        }
+    }
+
+    (interp.exit_code != native.exit_code).then(|| Mismatch::ExitCode(interp.describe_exit(), native.describe_exit()))
+}
+
+// runs every program under both runners; returns the number of programs that behaved differently
+pub fn run_differential(interp: &Runner, native: &Runner, paths: &[String]) -> Result<usize, String> {
+    let mut failures = 0;
+    for path in paths {
+        let (a, b) = (interp.run(path)?, native.run(path)?);
+        match compare(&a, &b) {
+            Some(mismatch) => {
+                println!("MISMATCH {path}: {}", mismatch.to_string());
+                failures += 1;
+            }
+            None => println!("ok       {path}")
+        }
+    }
+
+    println!("{} of {} programs behaved identically.", paths.len() - failures, paths.len());
+    Ok(failures)
+}
@@ -0,0 +1,96 @@
+use crate::{
+    match_decl,
+    ast::{Program, Function, Decl, types::{TypeList, TypeKind, TypeIndex}},
+    source_file::{Location, WithLocation},
+    error::{IntoCompilerError, CompilerError, Severity}
+};
+
+pub const DEFAULT_ENTRY: &str = "start";
+
+#[derive(Clone, Debug)]
+pub enum EntryError {
+    // the entry routine does not exist; holds all routines that could serve as one
+    Missing(String, Vec<String>),
+    IncompatibleSignature(String, Location, bool)
+}
+
+impl EntryError {
+    fn severity(&self) -> Severity {
+        Severity::Error
+    }
+
+    fn hint(&self) -> Option<String> {
+        match self {
+            Self::IncompatibleSignature(entry, _, true) => Some(format!("Expected `let {entry} be ...` in freestanding builds.")),
+            Self::IncompatibleSignature(entry, ..) => Some(format!("Expected `let {entry}() be ...` or `let {entry}(argc :: Int32, argv :: &&Char) be ...`.")),
+            _ => None
+        }
+    }
+}
+
+impl WithLocation for EntryError {}
+
+impl ToString for EntryError {
+    fn to_string(&self) -> String {
+        match self {
+            Self::Missing(entry, candidates) if candidates.is_empty() => format!("entry routine `{entry}` not found."),
+            Self::Missing(entry, candidates) => format!(
+                "entry routine `{entry}` not found; candidates are: {}.",
+                candidates.iter().map(|candidate| format!("`{candidate}`")).collect::<Vec<_>>().join(", ")
+            ),
+            Self::IncompatibleSignature(entry, ..) => format!("Entry routine `{entry}` has an incompatible signature.")
+        }
+    }
+}
+
+impl IntoCompilerError for EntryError {}
+impl Into<CompilerError> for EntryError {
+    fn into(self) -> CompilerError {
+        CompilerError::new(self.severity(), self.to_string(), self.hint(), vec![])
+    }
+}
+
+// checks that `entry` names a routine executables can start in
+// Without a runtime, nothing passes `argc` and `argv`, so freestanding entries take no parameters.
+pub fn check_entry(ast: &Program, entry: &str, freestanding: bool) -> Result<(), EntryError> {
+    let mut candidates = vec![];
+    for decl in ast.sections().flat_map(|section| section.declarations()) {
+        match_decl!{
+            decl;
+            func as Function => {
+                let compatible = is_entry_signature(func, ast.types()) && (!freestanding || func.params().is_empty());
+                if func.ident() == entry {
+                    return compatible.then_some(())
+                        .ok_or_else(|| EntryError::IncompatibleSignature(entry.to_string(), func.location().clone(), freestanding))
+                }
+                else if compatible {
+                    candidates.push(func.ident().clone());
+                }
+            },
+            _ => ()
+        }
+    }
+
+    candidates.sort();
+    Err(EntryError::Missing(entry.to_string(), candidates))
+}
+
+// `() -> Unit | word` or `(argc :: word, argv :: &...) -> Unit | word`
+fn is_entry_signature(func: &Function, types: &TypeList) -> bool {
+    let kind = |typ: TypeIndex| types.get(types.resolve(typ)).map(|typ| typ.kind());
+
+    let returns = match func.return_type() {
+        None => true,
+        Some(typ) => matches!(kind(*typ), Some(TypeKind::Unit)) || kind(*typ).is_some_and(TypeKind::is_integer)
+    };
+
+    let params = func.params();
+    let takes = match params.as_slice() {
+        [] => true,
+        [argc, argv] => argc.typ().is_none_or(|typ| kind(typ).is_some_and(TypeKind::is_integer))
+            && argv.typ().is_none_or(|typ| matches!(kind(typ), Some(TypeKind::Pointer(_)))),
+        _ => false
+    };
+
+    returns && takes && func.variadic().is_none()
+}
@@ -41,6 +41,11 @@ impl CompilerError {
         }
     }
 
+    pub fn with_severity(mut self, severity: Severity) -> Self {
+        self.severity = severity;
+        self
+    }
+
     pub fn severity(&self) -> &Severity {
         &self.severity
     }
@@ -60,3 +65,121 @@ pub trait IntoCompilerError: Into<CompilerError> {}
 
 impl IntoCompilerError for CompilerError {}
 
+
+// how the column in `file:line:column` is counted
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum ColumnStyle {
+    // the byte offset into the line, starting at 0
+    #[default]
+    Bytes,
+    // the column an editor shows, starting at 1 and with tabs expanded
+    Display
+}
+
+impl TryFrom<&str> for ColumnStyle {
+    type Error = ();
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "bytes" => Ok(Self::Bytes),
+            "display" => Ok(Self::Display),
+            _ => Err(())
+        }
+    }
+}
+
+pub const DEFAULT_TAB_WIDTH: usize = 4;
+const ELLIPSIS: &str = "...";
+// the line number and separator printed before each source line
+const GUTTER_WIDTH: usize = 8;
+// narrower widths are raised to this, keeping some context around the marked text
+const MIN_EXCERPT_WIDTH: usize = 20;
+
+#[derive(Clone)]
+pub struct DiagnosticStyle {
+    pub tab_width: usize,
+    // the width of the terminal; longer source lines are cut around the marked text
+    pub width: Option<usize>,
+    pub columns: ColumnStyle
+}
+
+impl Default for DiagnosticStyle {
+    fn default() -> Self {
+        Self {
+            tab_width: DEFAULT_TAB_WIDTH,
+            width: None,
+            columns: ColumnStyle::default()
+        }
+    }
+}
+
+// a source line prepared for printing, split around the marked text
+pub struct Excerpt {
+    pub before: String,
+    pub marked: String,
+    pub after: String,
+    // where the marked text starts in the printed line
+    pub mark_column: usize,
+    pub mark_width: usize
+}
+
+impl DiagnosticStyle {
+    // the column of the byte offset `column` of `line`, as reported after the file name
+    pub fn column(&self, line: &str, column: usize) -> usize {
+        match self.columns {
+            ColumnStyle::Bytes => column,
+            ColumnStyle::Display => self.expand(line, column, column).1 + 1
+        }
+    }
+
+    // expands the tabs of `line` and returns it with the display columns of the byte offsets `start` and `end`
+    fn expand(&self, line: &str, start: usize, end: usize) -> (Vec<char>, usize, usize) {
+        let mut expanded = vec![];
+        let (mut mark_start, mut mark_end) = (None, None);
+        for (offset, c) in line.char_indices().chain([(line.len(), '\n')]) {
+            if offset >= start && mark_start.is_none() {
+                mark_start = Some(expanded.len());
+            }
+            if offset >= end && mark_end.is_none() {
+                mark_end = Some(expanded.len());
+            }
+            match c {
+                '\n' => (),
+                '\t' => {
+                    let tab_width = self.tab_width.max(1);
+                    expanded.resize((expanded.len() / tab_width + 1) * tab_width, ' ');
+                }
+                c => expanded.push(c)
+            }
+        }
+        let len = expanded.len();
+        (expanded, mark_start.unwrap_or(len), mark_end.unwrap_or(len))
+    }
+
+    // prepares `line` with the bytes `start..end` marked, fitting it into the width of the terminal
+    pub fn excerpt(&self, line: &str, start: usize, end: usize) -> Excerpt {
+        let (line, mut mark_start, mut mark_end) = self.expand(line, start, end);
+        let (mut from, mut to) = (0, line.len());
+
+        if let Some(width) = self.width && line.len() > width.saturating_sub(GUTTER_WIDTH) {
+            let budget = width.saturating_sub(GUTTER_WIDTH).max(MIN_EXCERPT_WIDTH) - 2 * ELLIPSIS.len();
+            let mark_width = (mark_end - mark_start).min(budget);
+            from = mark_start.saturating_sub((budget - mark_width) / 2);
+            to = (from + budget).min(line.len());
+            from = to.saturating_sub(budget).min(from);
+            mark_start = mark_start.clamp(from, to);
+            mark_end = mark_end.clamp(mark_start, to);
+        }
+
+        let text = |from: usize, to: usize| line[from..to].iter().collect::<String>();
+        let left = if from > 0 { ELLIPSIS } else { "" };
+        let right = if to < line.len() { ELLIPSIS } else { "" };
+        Excerpt {
+            before: format!("{left}{}", text(from, mark_start)),
+            marked: text(mark_start, mark_end),
+            after: format!("{}{right}", text(mark_end, to)),
+            mark_column: left.len() + mark_start - from,
+            mark_width: mark_end - mark_start
+        }
+    }
+}
@@ -0,0 +1,188 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    match_decl,
+    ast::{Program, Decl, Function, FunctionBody, Variadic, GlobalDecl, ManifestDecl, expr::{Expr, ExprKind}, stmt::{Stmt, StmtKind}, pattern::{Pattern, PatternTerm}},
+    source_file::{SourceFile, SourceFileId, Located},
+    token::{TokenKind, lexer::Lexer}
+};
+
+// The slice of a program reachable from one routine, for `bcplpp extract`. Top-level items are
+// copied as they are written, with their comments; items declaring nothing, like `section`,
+// `get`, `type` and `assert`, are always kept. Files included by `get` are left as they are.
+pub fn extract(ast: &Program, entry: &str, source_files: &HashMap<SourceFileId, SourceFile>, tags: &[String]) -> Result<String, String> {
+    let decls = ast.sections()
+        .flat_map(|section| section.declarations())
+        .map(|decl| (decl.ident().as_str(), &**decl))
+        .collect::<HashMap<_, _>>();
+    if !decls.get(entry).is_some_and(|decl| decl.as_any().is::<Function>()) {
+        return Err(format!("no routine `{entry}` to extract from."))
+    }
+
+    // assertions stay with their section, and so does what they refer to
+    let mut pending = vec![entry];
+    for section in ast.sections() {
+        let mut idents = vec![];
+        section.assertions().iter().for_each(|(condition, _)| expr_refs(condition, &mut idents));
+        pending.extend(idents.into_iter().map(String::as_str));
+    }
+
+    let mut reachable = HashSet::new();
+    while let Some(ident) = pending.pop() {
+        if let Some(decl) = decls.get(ident) && reachable.insert(ident) {
+            pending.extend(references(*decl).into_iter().map(String::as_str));
+        }
+    }
+
+    let mut ids = source_files.iter()
+        .filter(|(_, file)| file.included_from().is_none())
+        .map(|(id, _)| *id)
+        .collect::<Vec<_>>();
+    ids.sort();
+
+    let slices = ids.into_iter()
+        .map(|id| {
+            // declarations of this file by the line they start on
+            let lines = decls.values()
+                .filter(|decl| decl.location().file_id() == id)
+                .map(|decl| (decl.location().line(), reachable.contains(decl.ident().as_str())))
+                .collect::<Vec<_>>();
+
+            let file = &source_files[&id];
+            let source_lines = file.contents().lines().collect::<Vec<_>>();
+            items(file, tags).into_iter()
+                .filter(|(first, end)| {
+                    let mut declared = lines.iter().filter(|(line, _)| (*first..*end).contains(line)).peekable();
+                    declared.peek().is_none() || declared.any(|(_, reachable)| *reachable)
+                })
+                .flat_map(|(first, end)| &source_lines[first - 1..end - 1])
+                .map(|line| format!("{line}\n"))
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>();
+    Ok(slices.join("\n"))
+}
+
+// the first line and the line after the last of each top-level item; blank lines and comments
+// above an item belong to it
+pub(crate) fn items(file: &SourceFile, tags: &[String]) -> Vec<(usize, usize)> {
+    let tokens = Lexer::from(file).with_tags(tags).take_while(|token| !token.is_eof()).collect::<Vec<_>>();
+    let lines = file.contents().lines().collect::<Vec<_>>();
+
+    let mut starts = vec![];
+    let mut depth = 0usize;
+    // attributes and `_section(...)` are part of the declaration following them
+    let mut in_prefix = false;
+    for (i, token) in tokens.iter().enumerate() {
+        let is_prefix = matches!(token.kind(), TokenKind::Ident("_section"))
+            || token.kind() == &TokenKind::LBracket && tokens.get(i + 1).is_some_and(|next| next.kind() == &TokenKind::LBracket);
+        let is_item = matches!(token.kind(),
+            TokenKind::Section | TokenKind::Let | TokenKind::And | TokenKind::Global | TokenKind::Static
+                | TokenKind::Manifest | TokenKind::Type | TokenKind::Require | TokenKind::Get | TokenKind::Assert
+        );
+
+        if depth == 0 && (is_prefix || is_item) {
+            if !in_prefix {
+                let mut line = token.location().line();
+                while line > 1 && (lines[line - 2].trim().is_empty() || lines[line - 2].trim_start().starts_with("//")) {
+                    line -= 1;
+                }
+                starts.push(line.max(starts.last().copied().unwrap_or(1)));
+            }
+            in_prefix = is_prefix;
+        }
+
+        match token.kind() {
+            TokenKind::LParen | TokenKind::LBrace | TokenKind::LBracket => depth += 1,
+            TokenKind::RParen | TokenKind::RBrace | TokenKind::RBracket => depth = depth.saturating_sub(1),
+            _ => ()
+        }
+    }
+    starts.dedup();
+
+    // anything before the first item, like a leading comment, is kept with it
+    if let Some(first) = starts.first_mut() {
+        *first = 1;
+    }
+    let ends = starts.iter().skip(1).copied().chain([lines.len() + 1]);
+    starts.iter().copied().zip(ends).collect()
+}
+
+// names a declaration refers to, including locals that shadow other declarations
+fn references(decl: &dyn Decl) -> Vec<&String> {
+    let mut idents = vec![];
+    match_decl!{
+        decl;
+        func as Function => {
+            for param in func.params() {
+                pattern_refs(param.pattern(), &mut idents);
+                if let Some(value) = param.default_value() {
+                    expr_refs(value, &mut idents);
+                }
+            }
+            idents.extend(func.variadic().and_then(Variadic::ident));
+            match func.body() {
+                FunctionBody::Expr(expr) => expr_refs(expr, &mut idents),
+                FunctionBody::Stmt(stmt) => stmt_refs(stmt, &mut idents),
+                FunctionBody::PatternMatchedExpr(branches) => for (patterns, expr) in branches {
+                    patterns.iter().for_each(|pattern| pattern_refs(pattern, &mut idents));
+                    expr_refs(expr, &mut idents);
+                },
+                FunctionBody::PatternMatchedStmt(branches) => for (patterns, stmt) in branches {
+                    patterns.iter().for_each(|pattern| pattern_refs(pattern, &mut idents));
+                    stmt_refs(stmt, &mut idents);
+                }
+            }
+        },
+        global as GlobalDecl => if let Some(value) = global.value() {
+            expr_refs(value, &mut idents)
+        },
+        manifest as ManifestDecl => expr_refs(manifest.value(), &mut idents),
+        _ => ()
+    }
+    idents
+}
+
+fn expr_refs<'a>(expr: &'a Expr, idents: &mut Vec<&'a String>) {
+    match expr.kind() {
+        ExprKind::Ident(ident) => idents.push(ident),
+        ExprKind::ValOf(body) => stmt_refs(body, idents),
+        ExprKind::Match(_, branches) | ExprKind::Every(_, branches) => branches.iter()
+            .flat_map(|(patterns, _)| patterns)
+            .for_each(|pattern| pattern_refs(pattern, idents)),
+        _ => ()
+    }
+    expr.kind().children().into_iter().for_each(|child| expr_refs(child, idents));
+}
+
+fn stmt_refs<'a>(stmt: &'a Stmt, idents: &mut Vec<&'a String>) {
+    match stmt.kind() {
+        StmtKind::Binding(bindings) => bindings.iter().for_each(|(pattern, _)| pattern_refs(pattern, idents)),
+        StmtKind::Match(_, branches) | StmtKind::Every(_, branches) => branches.iter()
+            .flat_map(|(patterns, _)| patterns)
+            .for_each(|pattern| pattern_refs(pattern, idents)),
+        _ => ()
+    }
+    stmt.kind().exprs().into_iter().for_each(|expr| expr_refs(expr, idents));
+    stmt.kind().stmts().into_iter().for_each(|stmt| stmt_refs(stmt, idents));
+}
+
+// constants compared against, like the manifest in `: >= Limit be`
+fn pattern_refs<'a>(pattern: &'a Located<Pattern>, idents: &mut Vec<&'a String>) {
+    match &**pattern {
+        Pattern::Term(term) => match term {
+            PatternTerm::Basic(expr) | PatternTerm::Lt(expr) | PatternTerm::Le(expr) | PatternTerm::Gt(expr)
+                | PatternTerm::Ge(expr) | PatternTerm::Ne(expr) | PatternTerm::Eq(expr) => expr_refs(expr, idents),
+            PatternTerm::Range(low, high) => {
+                expr_refs(low, idents);
+                expr_refs(high, idents);
+            }
+        },
+        Pattern::Or(lhs, rhs) | Pattern::And(lhs, rhs) => {
+            pattern_refs(lhs, idents);
+            pattern_refs(rhs, idents);
+        }
+        Pattern::Variant(_, args) | Pattern::List(args) => args.iter().for_each(|arg| pattern_refs(arg, idents)),
+        Pattern::Query(_) | Pattern::Any | Pattern::Remaining => ()
+    }
+}
@@ -0,0 +1,57 @@
+// Artifacts are stamped with the compiler version and a hash of everything they were built
+// from, so external build systems can ask whether they are up to date without recompiling:
+//
+//   fingerprint bcplpp-0.1.0 8c1e0f5a2b9d4e71
+//
+// The stamp is the second line of metadata files and a comment in the first line of others.
+
+const STAMP_PREFIX: &str = "fingerprint";
+
+fn compiler() -> String {
+    format!("bcplpp-{}", env!("CARGO_PKG_VERSION"))
+}
+
+// FNV-1a; unlike `DefaultHasher` it is the same for every build of the compiler
+pub struct Fingerprint(u64);
+
+impl Default for Fingerprint {
+    fn default() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+}
+
+impl Fingerprint {
+    pub fn add(&mut self, bytes: &[u8]) {
+        // the separator keeps `ab`, `c` apart from `a`, `bc`
+        for byte in bytes.iter().chain(&[0xff]) {
+            self.0 = (self.0 ^ *byte as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+
+    pub fn hex(&self) -> String {
+        format!("{:016x}", self.0)
+    }
+
+    pub fn stamp(&self) -> String {
+        format!("{STAMP_PREFIX} {} {}", compiler(), self.hex())
+    }
+
+    // why the artifact at `path` does not match this fingerprint, if it does not
+    pub fn check(&self, path: &str) -> Result<(), String> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Err(format!("`{path}` does not exist")),
+            Err(err) => return Err(format!("`{path}` cannot be read: {err}"))
+        };
+
+        let stamp = contents.lines()
+            .take(2)
+            .find_map(|line| line.find(STAMP_PREFIX).map(|start| &line[start..]));
+        match stamp.map(|stamp| stamp.split_whitespace().collect::<Vec<_>>()).as_deref() {
+            Some([_, compiler, _]) if *compiler != self::compiler() => Err(format!("`{path}` was built by {compiler}")),
+            Some([_, _, hash]) if *hash == self.hex() => Ok(()),
+            Some([_, _, _]) => Err(format!("`{path}` is out of date")),
+            _ => Err(format!("`{path}` has no fingerprint"))
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use std::collections::HashSet;
+
+use crate::{
+    match_decl,
+    ast::{Program, Decl, Function, FunctionBody, Variadic, GlobalDecl, ManifestDecl, expr::{Expr, ExprKind}, stmt::{Stmt, StmtKind}, pattern::Pattern},
+    source_file::{Location, Located, WithLocation},
+    error::{IntoCompilerError, CompilerError, Severity}
+};
+
+// Things freestanding programs cannot rely on, since no runtime library is linked
+#[derive(Clone, Debug)]
+pub enum FreestandingError {
+    UnavailableSection(String),
+    UndefinedRoutine(String),
+    //   routine kind of hook
+    Hook(String, &'static str)
+}
+
+impl FreestandingError {
+    fn severity(&self) -> Severity {
+        Severity::Error
+    }
+
+    fn hint(&self) -> Option<String> {
+        match self {
+            Self::UnavailableSection(section) => Some(format!("Compile section `{section}` along with this program or import its `.bmeta` file.")),
+            Self::UndefinedRoutine(_) => Some("Define the routine in the program, e.g. in assembly or as a primitive of your platform layer.".into()),
+            Self::Hook(..) => Some("Call it from the entry routine instead.".into())
+        }
+    }
+}
+
+impl WithLocation for FreestandingError {}
+
+impl ToString for FreestandingError {
+    fn to_string(&self) -> String {
+        match self {
+            Self::UnavailableSection(section) => format!("Section `{section}` is not available in freestanding builds."),
+            Self::UndefinedRoutine(routine) => format!("Routine `{routine}` is not defined; freestanding builds have no runtime library."),
+            Self::Hook(routine, hook) => format!("Routine `{routine}` is a {hook}, but freestanding builds have no runtime library to call it.")
+        }
+    }
+}
+
+impl IntoCompilerError for FreestandingError {}
+impl Into<CompilerError> for FreestandingError {
+    fn into(self) -> CompilerError {
+        CompilerError::new(self.severity(), self.to_string(), self.hint(), vec![])
+    }
+}
+
+// Checks that a program does not depend on the runtime library: every required section
+// and every called routine has to be part of this compilation or an imported module.
+pub fn check_freestanding(ast: &Program) -> Vec<Located<FreestandingError>> {
+    let sections: HashSet<&String> = ast.sections().map(|section| section.ident())
+        .chain(ast.interfaces().map(|interface| interface.ident()))
+        .collect();
+    let defined: HashSet<&String> = ast.sections()
+        .flat_map(|section| section.declarations().map(|decl| decl.ident()))
+        .chain(ast.interfaces().flat_map(|interface| interface.routines().iter().map(|routine| &routine.ident)
+            .chain(interface.globals().iter().map(|global| &global.ident))
+        ))
+        .collect();
+
+    let mut errors = vec![];
+    for section in ast.sections() {
+        for require in section.required() {
+            if !sections.contains(&**require) {
+                errors.push(FreestandingError::UnavailableSection((**require).clone()).with_location(require.location().clone()));
+            }
+        }
+
+        for decl in section.declarations() {
+            let mut calls = vec![];
+            let mut locals = HashSet::new();
+            match_decl!{
+                decl;
+                func as Function => {
+                    if let Some(hook) = func.hook() {
+                        errors.push(FreestandingError::Hook(func.ident().clone(), hook.name()).with_location(func.location().clone()));
+                    }
+                    func.params().iter().for_each(|param| bound_by(param.pattern(), &mut locals));
+                    locals.extend(func.variadic().and_then(Variadic::ident));
+                    match func.body() {
+                        FunctionBody::Expr(expr) => calls_in_expr(expr, &mut calls, &mut locals),
+                        FunctionBody::Stmt(stmt) => calls_in_stmt(stmt, &mut calls, &mut locals),
+                        FunctionBody::PatternMatchedExpr(branches) => for (patterns, expr) in branches {
+                            patterns.iter().for_each(|pattern| bound_by(pattern, &mut locals));
+                            calls_in_expr(expr, &mut calls, &mut locals);
+                        },
+                        FunctionBody::PatternMatchedStmt(branches) => for (patterns, stmt) in branches {
+                            patterns.iter().for_each(|pattern| bound_by(pattern, &mut locals));
+                            calls_in_stmt(stmt, &mut calls, &mut locals);
+                        }
+                    }
+                },
+                global as GlobalDecl => if let Some(value) = global.value() {
+                    calls_in_expr(value, &mut calls, &mut locals)
+                },
+                manifest as ManifestDecl => calls_in_expr(manifest.value(), &mut calls, &mut locals),
+                _ => ()
+            }
+
+            errors.extend(calls.into_iter()
+                .filter(|(routine, _)| !defined.contains(routine) && !locals.contains(routine))
+                .map(|(routine, loc)| FreestandingError::UndefinedRoutine(routine.clone()).with_location(loc.clone()))
+            );
+        }
+    }
+
+    errors.sort_by(|a, b| a.location().cmp(b.location()));
+    errors
+}
+
+// collects callees referred to by name, and names bound by patterns on the way
+fn calls_in_expr<'a>(expr: &'a Expr, calls: &mut Vec<(&'a String, &'a Location)>, locals: &mut HashSet<&'a String>) {
+    match expr.kind() {
+        ExprKind::FuncCall(callee, _) if let ExprKind::Ident(ident) = callee.kind() => calls.push((ident, callee.location())),
+        ExprKind::ValOf(body) => calls_in_stmt(body, calls, locals),
+        ExprKind::Match(_, branches) | ExprKind::Every(_, branches) => branches.iter()
+            .flat_map(|(patterns, _)| patterns)
+            .for_each(|pattern| bound_by(pattern, locals)),
+        _ => ()
+    }
+
+    expr.kind().children().into_iter().for_each(|child| calls_in_expr(child, calls, locals));
+}
+
+fn calls_in_stmt<'a>(stmt: &'a Stmt, calls: &mut Vec<(&'a String, &'a Location)>, locals: &mut HashSet<&'a String>) {
+    match stmt.kind() {
+        StmtKind::Binding(bindings) => bindings.iter().for_each(|(pattern, _)| bound_by(pattern, locals)),
+        StmtKind::For(iter, ..) => bound_by(iter, locals),
+        StmtKind::Try(_, caught, _) => bound_by(caught, locals),
+        StmtKind::Match(_, branches) | StmtKind::Every(_, branches) => branches.iter()
+            .flat_map(|(patterns, _)| patterns)
+            .for_each(|pattern| bound_by(pattern, locals)),
+        _ => ()
+    }
+
+    stmt.kind().exprs().into_iter().for_each(|expr| calls_in_expr(expr, calls, locals));
+    stmt.kind().stmts().into_iter().for_each(|stmt| calls_in_stmt(stmt, calls, locals));
+}
+
+pub(crate) fn bound_by<'a>(pattern: &'a Located<Pattern>, locals: &mut HashSet<&'a String>) {
+    match &**pattern {
+        Pattern::Query(ident) => { locals.insert(ident); }
+        Pattern::Or(lhs, rhs) | Pattern::And(lhs, rhs) => {
+            bound_by(lhs, locals);
+            bound_by(rhs, locals);
+        }
+        Pattern::Variant(_, args) | Pattern::List(args) => args.iter().for_each(|arg| bound_by(arg, locals)),
+        Pattern::Any | Pattern::Remaining | Pattern::Term(_) => ()
+    }
+}
@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+
+use crate::{
+    match_decl,
+    ast::{Program, Function, FunctionBody, expr::{Expr, ExprKind}, stmt::{Stmt, StmtKind}, pattern::{Pattern, bound_idents}},
+    source_file::{Location, Located, WithLocation},
+    error::{IntoCompilerError, CompilerError, Severity},
+    lint::{Lint, LintConfig, LintLevel}
+};
+
+#[derive(Clone, Debug)]
+pub enum IdentifierWarning {
+    //          name    earlier name  significant characters
+    Indistinct(String, String, usize),
+    //          name    character
+    NonClassic(String, char)
+}
+
+impl IdentifierWarning {
+    fn severity(&self) -> Severity {
+        Severity::Warning
+    }
+
+    pub fn lint(&self) -> Option<Lint> {
+        match self {
+            Self::Indistinct(..) => Some(Lint::IdentifierLength),
+            Self::NonClassic(..) => Some(Lint::IdentifierCharset)
+        }
+    }
+
+    fn hint(&self) -> Option<String> {
+        match self {
+            Self::Indistinct(..) => Some("Compilers looking at fewer characters take both for the same name; rename one of them.".into()),
+            Self::NonClassic(..) => Some("Classic BCPL identifiers consist of ASCII letters, digits and `_`.".into())
+        }
+    }
+}
+
+impl WithLocation for IdentifierWarning {}
+
+impl ToString for IdentifierWarning {
+    fn to_string(&self) -> String {
+        match self {
+            Self::Indistinct(ident, earlier, significant) => format!("`{ident}` and `{earlier}` only differ after their first {significant} characters."),
+            Self::NonClassic(ident, ch) => format!("Identifier `{ident}` contains `{ch}`.")
+        }
+    }
+}
+
+impl IntoCompilerError for IdentifierWarning {}
+impl Into<CompilerError> for IdentifierWarning {
+    fn into(self) -> CompilerError {
+        CompilerError::new(self.severity(), self.to_string(), self.hint(), vec![])
+    }
+}
+
+// Reports identifiers older compilers would not tell apart or reject, for the `identifier-length`
+// and `identifier-charset` lints. Both are allowed by default; they help porting programs from and
+// to compilers which only look at the first `significant-chars` characters of a name. Globals are
+// compared with each other, and the locals of each routine with those and each other.
+pub fn check_identifiers(ast: &Program, lints: &LintConfig) -> Vec<Located<IdentifierWarning>> {
+    let lengths = lints.level(Lint::IdentifierLength) != LintLevel::Allow;
+    let charset = lints.level(Lint::IdentifierCharset) != LintLevel::Allow;
+    if !lengths && !charset {
+        return vec![]
+    }
+
+    let mut globals = vec![];
+    let mut routines = vec![];
+    for decl in ast.sections().flat_map(|section| section.declarations()) {
+        globals.push((decl.ident(), decl.location()));
+        match_decl!{
+            decl;
+            func as Function => routines.push(func),
+            _ => ()
+        }
+    }
+
+    let mut check = IdentifierCheck {
+        significant: lints.significant_chars(),
+        lengths,
+        charset,
+        warnings: vec![]
+    };
+    let globals = check.distinct(globals, &HashMap::new());
+    for func in routines {
+        check.distinct(locals_of(func), &globals);
+    }
+
+    let mut warnings = check.warnings;
+    warnings.sort_by(|a, b| a.location().cmp(b.location()));
+    // instances of generic routines share the locations of their locals
+    warnings.dedup_by(|a, b| a.location() == b.location());
+    warnings
+}
+
+struct IdentifierCheck {
+    significant: usize,
+    lengths: bool,
+    charset: bool,
+    warnings: Vec<Located<IdentifierWarning>>
+}
+
+impl IdentifierCheck {
+    // checks `idents` in source order against each other and the names in `outer`, keyed by
+    // their significant characters
+    fn distinct<'a>(&mut self, mut idents: Vec<(&'a String, &'a Location)>, outer: &HashMap<String, &'a String>) -> HashMap<String, &'a String> {
+        idents.sort_by_key(|(_, loc)| *loc);
+
+        let mut seen = HashMap::new();
+        // hoisted routines, methods, environment records and instances of generic routines
+        // have symbols made up by the compiler
+        for (ident, loc) in idents.into_iter().filter(|(ident, _)| !ident.contains(['.', '<'])) {
+            if self.charset && let Some(ch) = ident.chars().find(|ch| !ch.is_ascii_alphanumeric() && *ch != '_') {
+                self.warnings.push(IdentifierWarning::NonClassic(ident.clone(), ch).with_location(loc.clone()));
+            }
+
+            let prefix = ident.chars().take(self.significant).collect::<String>();
+            match seen.get(&prefix).or_else(|| outer.get(&prefix)) {
+                Some(earlier) if *earlier != ident && self.lengths => self.warnings.push(
+                    IdentifierWarning::Indistinct(ident.clone(), (*earlier).clone(), self.significant).with_location(loc.clone())
+                ),
+                Some(_) => (),
+                None => { seen.insert(prefix, ident); }
+            }
+        }
+        seen
+    }
+}
+
+fn locals_of(func: &Function) -> Vec<(&String, &Location)> {
+    let mut locals = func.params().iter().flat_map(|param| bound_idents(param.pattern())).collect::<Vec<_>>();
+    match func.body() {
+        FunctionBody::Expr(expr) => locals_in_expr(expr, &mut locals),
+        FunctionBody::Stmt(stmt) => locals_in_stmt(stmt, &mut locals),
+        FunctionBody::PatternMatchedExpr(branches) => for (patterns, expr) in branches {
+            locals.extend(patterns.iter().flat_map(bound_idents));
+            locals_in_expr(expr, &mut locals);
+        },
+        FunctionBody::PatternMatchedStmt(branches) => for (patterns, stmt) in branches {
+            locals.extend(patterns.iter().flat_map(bound_idents));
+            locals_in_stmt(stmt, &mut locals);
+        }
+    }
+    locals
+}
+
+fn locals_in_expr<'a>(expr: &'a Expr, locals: &mut Vec<(&'a String, &'a Location)>) {
+    match expr.kind() {
+        ExprKind::ValOf(body) => locals_in_stmt(body, locals),
+        ExprKind::Match(_, branches) | ExprKind::Every(_, branches) => locals.extend(branches.iter()
+            .flat_map(|(patterns, _)| patterns)
+            .flat_map(bound_idents)
+        ),
+        _ => ()
+    }
+
+    expr.kind().children().into_iter().for_each(|child| locals_in_expr(child, locals));
+}
+
+fn locals_in_stmt<'a>(stmt: &'a Stmt, locals: &mut Vec<(&'a String, &'a Location)>) {
+    let patterns: Vec<&Located<Pattern>> = match stmt.kind() {
+        StmtKind::Binding(bindings) => bindings.iter().map(|(pattern, _)| pattern).collect(),
+        StmtKind::For(iter, ..) => vec![iter],
+        StmtKind::Try(_, caught, _) => vec![caught],
+        StmtKind::Match(_, branches) | StmtKind::Every(_, branches) => branches.iter().flat_map(|(patterns, _)| patterns).collect(),
+        _ => vec![]
+    };
+    locals.extend(patterns.into_iter().flat_map(bound_idents));
+
+    stmt.kind().exprs().into_iter().for_each(|expr| locals_in_expr(expr, locals));
+    stmt.kind().stmts().into_iter().for_each(|stmt| locals_in_stmt(stmt, locals));
+}
@@ -0,0 +1,161 @@
+// Flat memory images of linked executables for flashing onto embedded targets
+
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum OutputFormat {
+    #[default]
+    Elf,
+    Binary,
+    IntelHex
+}
+
+impl OutputFormat {
+    pub fn ext(&self) -> &'static str {
+        match self {
+            Self::Elf => "",
+            Self::Binary => ".bin",
+            Self::IntelHex => ".hex"
+        }
+    }
+}
+
+impl TryFrom<&str> for OutputFormat {
+    type Error = ();
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "elf" => Ok(Self::Elf),
+            "bin" | "binary" => Ok(Self::Binary),
+            "ihex" | "hex" => Ok(Self::IntelHex),
+            _ => Err(())
+        }
+    }
+}
+
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+const PT_LOAD: u32 = 1;
+
+pub fn is_elf(bytes: &[u8]) -> bool {
+    bytes.starts_with(ELF_MAGIC)
+}
+
+// the loadable contents of an executable, by physical address
+pub struct Image {
+    segments: Vec<(u64, Vec<u8>)>
+}
+
+impl Image {
+    // collects the file contents of all `PT_LOAD` segments of a little-endian ELF file
+    pub fn from_elf(bytes: &[u8]) -> Result<Self, String> {
+        if !is_elf(bytes) || bytes.len() < 0x34 {
+            return Err("not an ELF file".into())
+        }
+        if bytes[5] != 1 {
+            return Err("big-endian ELF files are not supported".into())
+        }
+
+        let is_64 = match bytes[4] {
+            1 => false,
+            2 => true,
+            _ => return Err("invalid ELF class".into())
+        };
+
+        let read = |offset: usize, size: usize| -> Result<u64, String> {
+            let field = bytes.get(offset..offset + size).ok_or("truncated ELF file")?;
+            Ok(field.iter().rev().fold(0u64, |value, byte| value << 8 | *byte as u64))
+        };
+
+        let (phoff, phentsize, phnum) = if is_64 {
+            (read(0x20, 8)?, read(0x36, 2)?, read(0x38, 2)?)
+        }
+        else {
+            (read(0x1c, 4)?, read(0x2a, 2)?, read(0x2c, 2)?)
+        };
+
+        let mut segments = vec![];
+        for i in 0..phnum {
+            let header = (phoff + i * phentsize) as usize;
+            if read(header, 4)? as u32 != PT_LOAD {
+                continue;
+            }
+
+            let (offset, paddr, filesz) = if is_64 {
+                (read(header + 0x08, 8)?, read(header + 0x18, 8)?, read(header + 0x20, 8)?)
+            }
+            else {
+                (read(header + 0x04, 4)?, read(header + 0x0c, 4)?, read(header + 0x10, 4)?)
+            };
+
+            if filesz == 0 {
+                continue;
+            }
+            let contents = bytes.get(offset as usize..(offset + filesz) as usize).ok_or("truncated ELF segment")?;
+            segments.push((paddr, contents.to_vec()));
+        }
+
+        if segments.is_empty() {
+            return Err("ELF file has no loadable segments".into())
+        }
+
+        segments.sort_by_key(|(addr, _)| *addr);
+        Ok(Self { segments })
+    }
+
+    // all segments from the lowest address on, with gaps filled with zeros
+    pub fn to_binary(&self) -> Vec<u8> {
+        let base = self.segments[0].0;
+        let mut out = vec![];
+        for (addr, contents) in &self.segments {
+            let start = (addr - base) as usize;
+            if out.len() < start + contents.len() {
+                out.resize(start + contents.len(), 0);
+            }
+            out[start..start + contents.len()].copy_from_slice(contents);
+        }
+        out
+    }
+
+    // 16-byte data records, with extended linear address records whenever the upper 16 bits change
+    pub fn to_intel_hex(&self) -> Result<String, String> {
+        let mut out = String::new();
+        let mut upper = None;
+
+        for (addr, contents) in &self.segments {
+            for (i, chunk) in contents.chunks(16).enumerate() {
+                let addr = addr + i as u64 * 16;
+                if addr + chunk.len() as u64 > 1 << 32 {
+                    return Err(format!("address {addr:#x} does not fit into an Intel HEX file"))
+                }
+
+                // records must not cross a 64k boundary
+                let split = (0x10000 - (addr & 0xffff) as usize).min(chunk.len());
+                for (addr, chunk) in [(addr, &chunk[..split]), (addr + split as u64, &chunk[split..])] {
+                    if chunk.is_empty() {
+                        continue;
+                    }
+                    if upper != Some(addr >> 16) {
+                        upper = Some(addr >> 16);
+                        out.push_str(&hex_record(0, 0x04, &[(addr >> 24) as u8, (addr >> 16) as u8]));
+                    }
+                    out.push_str(&hex_record(addr as u16, 0x00, chunk));
+                }
+            }
+        }
+
+        out.push_str(&hex_record(0, 0x01, &[]));
+        Ok(out)
+    }
+}
+
+fn hex_record(addr: u16, kind: u8, data: &[u8]) -> String {
+    let bytes = [data.len() as u8, (addr >> 8) as u8, addr as u8, kind].into_iter()
+        .chain(data.iter().copied())
+        .collect::<Vec<_>>();
+    let checksum = bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)).wrapping_neg();
+
+    let mut record = String::from(":");
+    for byte in bytes.iter().chain(std::iter::once(&checksum)) {
+        record.push_str(&format!("{byte:02X}"));
+    }
+    record.push('\n');
+    record
+}
@@ -0,0 +1,36 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// `128 + SIGINT`, like shells report processes ended by Ctrl-C
+pub const EXIT_CODE: i32 = 130;
+
+#[cfg(unix)]
+const SIGINT: i32 = 2;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+unsafe extern "C" {
+    fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+    fn _exit(status: i32) -> !;
+}
+
+// only sets a flag, so files being written are finished before the compilation stops;
+// a second Ctrl-C exits right away
+#[cfg(unix)]
+extern "C" fn on_interrupt(_: i32) {
+    if INTERRUPTED.swap(true, Ordering::SeqCst) {
+        unsafe { _exit(EXIT_CODE) }
+    }
+}
+
+// Ctrl-C stops the compilation at the next phase instead of killing the process
+pub fn install() {
+    #[cfg(unix)]
+    unsafe {
+        signal(SIGINT, on_interrupt);
+    }
+}
+
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
@@ -0,0 +1,313 @@
+use std::{collections::HashMap, fmt::Write};
+
+use crate::{
+    ast::{expr::{Expr, ExprKind, OverflowMode}, attribute::{CallingConvention, Hook}, pattern::{Pattern, PatternTerm}, types::TypeList, Storage},
+    source_file::{SourceFile, SourceFileId, Location, Located},
+    target::Target
+};
+
+use super::{Module, Function, Instr, Terminator, Scrutinee, CaseLabel, Trap, Global};
+
+// Textual listing of a lowered module, one instruction per line. With `sources`, every
+// instruction coming from a new source line is preceded by that line as a comment.
+pub fn write_asm(module: &Module, types: &TypeList, target: &Target, sources: Option<&HashMap<SourceFileId, SourceFile>>) -> String {
+    let mut writer = AsmWriter {
+        out: String::new(),
+        types,
+        sources,
+        last_line: None,
+        section: String::new()
+    };
+
+    writer.write_target(target);
+    writer.write_module(module);
+    writer.out
+}
+
+struct AsmWriter<'a> {
+    out: String,
+    types: &'a TypeList,
+    sources: Option<&'a HashMap<SourceFileId, SourceFile>>,
+    last_line: Option<(SourceFileId, usize)>,
+    section: String
+}
+
+impl<'a> AsmWriter<'a> {
+    fn line(&mut self, indent: usize, text: impl AsRef<str>) {
+        writeln!(self.out, "{:indent$}{}", "", text.as_ref(), indent = indent * 4).unwrap();
+    }
+
+    // prints the source line of `loc` once, when lowering moved on to it
+    fn annotate(&mut self, loc: Option<&Location>) {
+        let (Some(sources), Some(loc)) = (self.sources, loc) else {
+            return
+        };
+
+        let key = (loc.file_id(), loc.line());
+        if self.last_line == Some(key) {
+            return
+        }
+        self.last_line = Some(key);
+
+        if let Some(file) = sources.get(&loc.file_id()) && let Some(text) = file.line(loc.line()) {
+            let text = format!("; {}:{}: {}", file.path(), loc.line(), text.trim());
+            self.line(1, text);
+        }
+    }
+
+    // switches to `section`, or back to `default` for declarations without `_section`
+    fn enter_section(&mut self, section: Option<&String>, default: &'static str) {
+        let directive = section.map_or(default.to_string(), |section| format!(".section {section}"));
+        if self.section != directive {
+            self.line(0, &directive);
+            self.section = directive;
+        }
+    }
+
+    fn write_target(&mut self, target: &Target) {
+        if let Some(cpu) = target.cpu() {
+            self.line(0, format!(".cpu {cpu}"));
+        }
+        if !target.features().is_empty() {
+            self.line(0, format!(".features {}", target.features_string()));
+        }
+        if target.soft_float() {
+            self.line(0, "; floating point operations are emulated in software");
+        }
+        if target.cpu().is_some() || !target.features().is_empty() {
+            self.line(0, "");
+        }
+    }
+
+    fn write_module(&mut self, module: &Module) {
+        if !module.rodata().constants().is_empty() {
+            self.enter_section(None, ".rodata");
+            for (i, constant) in module.rodata().constants().iter().enumerate() {
+                self.line(0, format!(".S{i}: {:?} ; {} use(s)", constant.value(), constant.uses()));
+            }
+            self.line(0, "");
+        }
+
+        if !module.globals().is_empty() {
+            self.enter_section(None, ".data");
+            for global in module.globals() {
+                self.write_global(global);
+            }
+            self.line(0, "");
+        }
+
+        // functions are lowered in hash order; list them as they appear in the source
+        let mut functions = module.functions().iter().collect::<Vec<_>>();
+        functions.sort_by_key(|function| function.location().clone());
+
+        self.enter_section(None, ".text");
+        for &function in &functions {
+            self.write_function(function);
+        }
+
+        // hooks in the order the runtime calls them; equal priorities keep the source order
+        let mut hooks = functions.into_iter()
+            .filter_map(|function| function.hook().map(|hook| (hook, function.ident())))
+            .collect::<Vec<_>>();
+        hooks.sort_by_key(|(hook, _)| hook.priority());
+        let (constructors, mut destructors): (Vec<_>, Vec<_>) = hooks.into_iter().partition(|(hook, _)| matches!(hook, Hook::Constructor(_)));
+        destructors.reverse();
+
+        for (section, hooks) in [(".init_array", constructors), (".fini_array", destructors)] {
+            if hooks.is_empty() {
+                continue
+            }
+            self.line(0, "");
+            self.enter_section(None, section);
+            for (_, ident) in hooks {
+                self.line(1, ident);
+            }
+        }
+    }
+
+    fn write_global(&mut self, global: &Global) {
+        self.annotate(Some(global.location()));
+        self.enter_section(global.link_section(), ".data");
+        let typ = global.typ().map(|typ| format!(" :: {}", self.types.type_name(typ))).unwrap_or_default();
+        let value = global.value().as_ref().map(|value| format!(" = {}", expr(value))).unwrap_or_default();
+        let storage = match global.storage() {
+            Storage::Global => "global",
+            Storage::Static => "static"
+        };
+        self.line(0, format!("{}: {storage}{typ}{value}", global.ident()));
+    }
+
+    fn write_function(&mut self, function: &Function) {
+        self.last_line = None;
+        self.annotate(Some(function.location()));
+        self.enter_section(function.link_section(), ".text");
+        self.line(0, format!("{}:", function.ident()));
+        if function.is_inline() {
+            self.line(1, ".inline");
+        }
+        if function.calling_convention() == CallingConvention::C {
+            self.line(1, ".ccall");
+        }
+        if let Some(hook) = function.hook() {
+            self.line(1, format!(".{} {}", hook.name(), hook.priority()));
+        }
+        match function.variadic() {
+            Some((fixed, Some(rest))) => self.line(1, format!(".variadic {fixed} {rest}")),
+            Some((fixed, None)) => self.line(1, format!(".variadic {fixed}")),
+            None => ()
+        }
+
+        for (i, temp) in function.temporaries().iter().enumerate() {
+            let typ = temp.map(|typ| self.types.type_name(typ)).unwrap_or("?".into());
+            self.line(1, format!(".temp %{i} :: {typ}"));
+        }
+
+        let reachable = function.reachable_blocks();
+        for (id, block) in function.blocks().iter().enumerate() {
+            if !reachable[id] {
+                continue;
+            }
+
+            self.line(0, format!(".L{id}:"));
+            for instr in block.instrs() {
+                let text = match instr {
+                    Instr::Eval(value) => format!("eval {}", expr(value)),
+                    Instr::Bind(pattern, value) => format!("bind {} := {}", located_pattern(pattern), expr(value)),
+                    Instr::Assign(temp, value) => format!("mov %{temp}, {}", expr(value)),
+                    Instr::Store(target, value) => format!("store {} := {}", expr(target), expr(value)),
+                    Instr::InlineAsm(_, template, outputs, inputs) => format!("asm {template:?}, out({}), in({})",
+                        outputs.iter().map(expr).collect::<Vec<_>>().join(", "),
+                        inputs.iter().map(expr).collect::<Vec<_>>().join(", ")
+                    )
+                };
+                self.annotate(Some(instr.location()));
+                self.line(1, text);
+            }
+
+            if let Some(terminator) = block.terminator() {
+                self.annotate(terminator.location());
+                self.line(1, terminator_text(terminator));
+            }
+        }
+
+        self.line(0, "");
+    }
+}
+
+fn terminator_text(terminator: &Terminator) -> String {
+    match terminator {
+        Terminator::Jump(target) => format!("jmp .L{target}"),
+        Terminator::Branch(cond, on_true, on_false) => format!("br {}, .L{on_true}, .L{on_false}", expr(cond)),
+        Terminator::Switch(cond, cases, default) => {
+            let cases = cases.iter()
+                .map(|(label, target)| match label {
+                    CaseLabel::Value(value) => format!("{} => .L{target}", expr(value)),
+                    CaseLabel::Range(low, high) => format!("{} to {} => .L{target}", expr(low), expr(high))
+                })
+                .collect::<Vec<_>>()
+                .join(", ");
+            format!("switch {}, [{cases}], default .L{default}", expr(cond))
+        }
+        Terminator::Test(scrutinee, patterns, matched, failed) => {
+            let scrutinee = match scrutinee {
+                Scrutinee::Params => "params".into(),
+                Scrutinee::Exprs(exprs) => exprs.iter().map(expr).collect::<Vec<_>>().join(", ")
+            };
+            let patterns = patterns.iter().map(located_pattern).collect::<Vec<_>>().join(", ");
+            format!("test {scrutinee} : {patterns}, .L{matched}, .L{failed}")
+        }
+        Terminator::Try(body, handler) => format!("try .L{body}, catch .L{handler}"),
+        Terminator::Return(Some(value)) => format!("ret {}", expr(value)),
+        Terminator::Return(None) => "ret".into(),
+        Terminator::Trap(Trap::NilDeref, _) => "trap nil-deref".into(),
+        Terminator::Unreachable => "unreachable".into()
+    }
+}
+
+fn expr(expr: &Expr) -> String {
+    let binary = |op: &str, lhs: &Expr, rhs: &Expr| format!("({} {op} {})", self::expr(lhs), self::expr(rhs));
+    match expr.kind() {
+        ExprKind::Ident(ident) => ident.clone(),
+        ExprKind::Atom(atom) => format!("#{atom}"),
+        ExprKind::IntLit(int) => int.to_string(),
+        ExprKind::FloatLit(float) => float.to_string(),
+        ExprKind::CharLit(ch) => format!("{ch:?}"),
+        ExprKind::StringLit(s) => format!("{s:?}"),
+        ExprKind::True => "true".into(),
+        ExprKind::False => "false".into(),
+        ExprKind::Nil => "nil".into(),
+        ExprKind::Error => "<error>".into(),
+        ExprKind::Add(lhs, rhs) => binary("+", lhs, rhs),
+        ExprKind::Sub(lhs, rhs) => binary("-", lhs, rhs),
+        ExprKind::Mul(lhs, rhs) => binary("*", lhs, rhs),
+        ExprKind::Div(lhs, rhs) => binary("/", lhs, rhs),
+        ExprKind::Mod(lhs, rhs) => binary("mod", lhs, rhs),
+        ExprKind::And(lhs, rhs) | ExprKind::LogAnd(lhs, rhs) => binary("&", lhs, rhs),
+        ExprKind::Or(lhs, rhs) | ExprKind::LogOr(lhs, rhs) => binary("|", lhs, rhs),
+        ExprKind::XOr(lhs, rhs) => binary("^", lhs, rhs),
+        ExprKind::Eq(lhs, rhs) => binary("=", lhs, rhs),
+        ExprKind::Ne(lhs, rhs) => binary("~=", lhs, rhs),
+        ExprKind::Gt(lhs, rhs) => binary(">", lhs, rhs),
+        ExprKind::Ge(lhs, rhs) => binary(">=", lhs, rhs),
+        ExprKind::Lt(lhs, rhs) => binary("<", lhs, rhs),
+        ExprKind::Le(lhs, rhs) => binary("<=", lhs, rhs),
+        ExprKind::LShift(lhs, rhs) => binary("<<", lhs, rhs),
+        ExprKind::RShift(lhs, rhs) => binary(">>", lhs, rhs),
+        ExprKind::Abs(inner) => format!("abs {}", self::expr(inner)),
+        ExprKind::Not(inner) => format!("~{}", self::expr(inner)),
+        ExprKind::Ref(inner) => format!("@{}", self::expr(inner)),
+        ExprKind::Deref(inner) => format!("!{}", self::expr(inner)),
+        ExprKind::Cast(inner) | ExprKind::ImplicitCast(inner) => format!("cast {}", self::expr(inner)),
+        ExprKind::Overflow(mode, inner) => format!("{} {}", match mode {
+            OverflowMode::Wrap => "wrapping",
+            OverflowMode::Trap => "checked",
+            OverflowMode::Saturate => "saturating"
+        }, self::expr(inner)),
+        ExprKind::Index(lhs, rhs) => format!("{}[{}]", self::expr(lhs), self::expr(rhs)),
+        ExprKind::Slice(lhs, low, high) => format!("{}[{}..{}]", self::expr(lhs), self::expr(low), self::expr(high)),
+        ExprKind::SizeOf(typ) => format!("sizeof {typ}"),
+        ExprKind::OffsetOf(typ, field) => format!("offsetof({typ}, {field})"),
+        ExprKind::Vec(size) => format!("vec {}", self::expr(size)),
+        ExprKind::FuncCall(callee, args) => {
+            let args = args.iter().map(self::expr).collect::<Vec<_>>().join(", ");
+            match callee.kind() {
+                ExprKind::Ident(routine) => format!("{routine}({args})"),
+                // through a routine pointer
+                _ => format!("call *{}({args})", self::expr(callee))
+            }
+        }
+        ExprKind::VarArgs(args) => format!("...[{}]", args.iter().map(self::expr).collect::<Vec<_>>().join(", ")),
+        ExprKind::NamedArg(name, arg) => format!("{name} := {}", self::expr(arg)),
+        ExprKind::Conditional(cond, a, b) => format!("({} -> {}, {})", self::expr(cond), self::expr(a), self::expr(b)),
+        ExprKind::Selector(size, shift, offset) => format!("slct {}:{}:{}", self::expr(size), self::expr(shift), self::expr(offset)),
+        ExprKind::FieldSelect(selector, ptr) => format!("({} of {})", self::expr(selector), self::expr(ptr)),
+        ExprKind::Member(value, field) if matches!(value.kind(), ExprKind::Deref(_)) => format!("({}).{field}", self::expr(value)),
+        ExprKind::Member(value, field) => format!("{}.{field}", self::expr(value)),
+        // hoisted during lowering
+        ExprKind::ValOf(_) | ExprKind::Match(..) | ExprKind::Every(..) => "<unlowered>".into(),
+        ExprKind::Temporary(temp) => format!("%{temp}"),
+        ExprKind::Constant(index) => format!(".S{index}")
+    }
+}
+
+fn located_pattern(pattern: &Located<Pattern>) -> String {
+    match &**pattern {
+        Pattern::Any => "?".into(),
+        Pattern::Remaining => "..".into(),
+        Pattern::Query(ident) => ident.clone(),
+        Pattern::Term(term) => match term {
+            PatternTerm::Basic(value) => expr(value),
+            PatternTerm::Lt(value) => format!("< {}", expr(value)),
+            PatternTerm::Le(value) => format!("<= {}", expr(value)),
+            PatternTerm::Gt(value) => format!("> {}", expr(value)),
+            PatternTerm::Ge(value) => format!(">= {}", expr(value)),
+            PatternTerm::Ne(value) => format!("~= {}", expr(value)),
+            PatternTerm::Eq(value) => format!("= {}", expr(value)),
+            PatternTerm::Range(low, high) => format!("{}..{}", expr(low), expr(high))
+        },
+        Pattern::Or(lhs, rhs) => format!("{} | {}", located_pattern(lhs), located_pattern(rhs)),
+        Pattern::And(lhs, rhs) => format!("{} & {}", located_pattern(lhs), located_pattern(rhs)),
+        Pattern::Variant(ident, args) => format!("{ident}({})", args.iter().map(located_pattern).collect::<Vec<_>>().join(", ")),
+        Pattern::List(args) => format!("[{}]", args.iter().map(located_pattern).collect::<Vec<_>>().join(", "))
+    }
+}
@@ -0,0 +1,111 @@
+use std::collections::HashMap;
+
+use crate::{
+    match_decl,
+    ast::{self, Decl, GlobalDecl, ManifestDecl},
+    source_file::{Located, Location, WithLocation}
+};
+
+use super::LowerError;
+
+struct Node<'a> {
+    ident: &'a String,
+    loc: &'a Location,
+    deps: Vec<&'a String>,
+    global: Option<&'a GlobalDecl>
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mark {
+    Unvisited,
+    Visiting,
+    Done
+}
+
+// Orders `global` and `static` declarations such that every initializer only refers
+// to variables initialized before it. Manifest constants take part in the ordering,
+// since they may depend on variables and each other, but are not emitted.
+pub(super) fn order_initializers(ast: &ast::Program) -> Result<Vec<&GlobalDecl>, Located<LowerError>> {
+    let mut nodes = vec![];
+    for decl in ast.sections().flat_map(ast::Section::declarations) {
+        match_decl!{
+            decl;
+            global as GlobalDecl => nodes.push(Node {
+                ident: global.ident(),
+                loc: global.location(),
+                deps: global.value().iter().fold(vec![], |mut deps, value| { value.referenced_idents(&mut deps); deps }),
+                global: Some(global)
+            }),
+            manifest as ManifestDecl => nodes.push(Node {
+                ident: manifest.ident(),
+                loc: manifest.location(),
+                deps: { let mut deps = vec![]; manifest.value().referenced_idents(&mut deps); deps },
+                global: None
+            }),
+            _ => ()
+        }
+    }
+
+    // visit in source order, so the resulting order is deterministic
+    nodes.sort_by(|a, b| a.loc.cmp(b.loc));
+    let by_ident = nodes.iter()
+        .enumerate()
+        .rev()
+        .map(|(i, node)| (node.ident, i))
+        .collect::<HashMap<_, _>>();
+
+    let mut sorter = Sorter {
+        nodes: &nodes,
+        by_ident,
+        marks: vec![Mark::Unvisited; nodes.len()],
+        path: vec![],
+        order: vec![]
+    };
+    for i in 0..nodes.len() {
+        sorter.visit(i)?;
+    }
+
+    Ok(sorter.order)
+}
+
+struct Sorter<'a, 'b> {
+    nodes: &'b [Node<'a>],
+    by_ident: HashMap<&'a String, usize>,
+    marks: Vec<Mark>,
+    path: Vec<usize>,
+    order: Vec<&'a GlobalDecl>
+}
+
+impl<'a, 'b> Sorter<'a, 'b> {
+    fn visit(&mut self, i: usize) -> Result<(), Located<LowerError>> {
+        match self.marks[i] {
+            Mark::Done => return Ok(()),
+            Mark::Visiting => {
+                let start = self.path.iter().position(|node| *node == i).unwrap();
+                let cycle = self.path[start..].iter()
+                    .chain(std::iter::once(&i))
+                    .map(|node| self.nodes[*node].ident.clone().with_location(self.nodes[*node].loc.clone()))
+                    .collect();
+                return Err(LowerError::InitializerCycle(cycle).with_location(self.nodes[i].loc.clone()))
+            }
+            Mark::Unvisited => ()
+        }
+
+        self.marks[i] = Mark::Visiting;
+        self.path.push(i);
+
+        let node = &self.nodes[i];
+        for dep in &node.deps {
+            if let Some(dep) = self.by_ident.get(dep).copied() {
+                self.visit(dep)?;
+            }
+        }
+
+        self.path.pop();
+        self.marks[i] = Mark::Done;
+        if let Some(global) = node.global {
+            self.order.push(global);
+        }
+        Ok(())
+    }
+}
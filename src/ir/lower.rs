@@ -0,0 +1,1286 @@
+use std::{collections::{HashMap, HashSet}, sync::{Arc, Mutex}};
+
+use crate::{
+    log,
+    ast::{self, Decl, FunctionBody, ManifestDecl, stmt::{Stmt, StmtKind}, expr::{Expr, ExprKind, TempIndex, OverflowMode, unpack_selector}, types::{TypeKind, TypeIndex, TypeList}, layout::DataLayout},
+    source_file::{Location, Located, WithLocation},
+    error::{IntoCompilerError, CompilerError, Severity},
+    freestanding::bound_by,
+    lint::Lint
+};
+
+use super::{init_order::order_initializers, Module, Function, Global, ReadOnlyData, BasicBlock, BlockId, Instr, Terminator, Scrutinee, CaseLabel, Trap};
+
+// `case lo to hi` ranges up to this width are expanded into single values,
+// so that dense switches can still be emitted as jump tables
+const MAX_EXPANDED_CASE_RANGE: i64 = 16;
+
+// runtime routines a `switchon` over strings is lowered to; `strhash` has to match `string_hash`
+pub(super) const STRING_HASH_ROUTINE: &str = "strhash";
+const STRING_COMPARE_ROUTINE: &str = "compstring";
+
+// runtime routines behind `try`: `throw` unwinds to the innermost handler registered by a
+// `Terminator::Try`, a body left any other way drops its handler, and the handler asks for
+// the value thrown
+const THROW_ROUTINE: &str = "throwvalue";
+const LEAVE_TRY_ROUTINE: &str = "leavetry";
+const CAUGHT_ROUTINE: &str = "caughtvalue";
+
+fn runtime_call(routine: &str, args: Vec<Expr>, loc: &Location) -> Expr {
+    Expr::new(loc.clone(), None, ExprKind::FuncCall(Box::new(Expr::new(loc.clone(), None, ExprKind::Ident(routine.into()))), args))
+}
+
+// 32-bit FNV-1a over the bytes of a string
+fn string_hash(value: &str) -> u32 {
+    value.bytes().fold(0x811c9dc5, |hash, byte| (hash ^ byte as u32).wrapping_mul(0x01000193))
+}
+
+#[derive(Clone, Debug)]
+pub enum LowerError {
+    NoJumpTarget(&'static str, &'static str),
+    CaseOutsideSwitchOn(&'static str),
+    ValOfWithoutResult,
+    UnknownLayout(String),
+    NoSuchField(String, String),
+    InitializerCycle(Vec<Located<String>>),
+    AssertionFailed(String),
+    NonConstAssertion,
+    //             condition  branch
+    ConstantCondition(bool, &'static str),
+    NonConstVecSize,
+    VecOutsideRoutine,
+    //              word size in bits
+    InvalidSelector(u64),
+    EvaluationOrder
+}
+
+impl LowerError {
+    fn severity(&self) -> Severity {
+        match self {
+            Self::ValOfWithoutResult | Self::ConstantCondition(..) | Self::EvaluationOrder => Severity::Warning,
+            _ => Severity::Error
+        }
+    }
+
+    fn hint(&self) -> Option<String> {
+        match self {
+            Self::NoJumpTarget(..) => Some("Jumps cannot leave the enclosing `valof` or function.".into()),
+            Self::ValOfWithoutResult => Some("The result is undefined on this path.".into()),
+            Self::NonConstAssertion => Some("Only literals, manifest constants and layout queries are known at compile time.".into()),
+            Self::ConstantCondition(..) => Some("The dead branch is not compiled.".into()),
+            Self::NonConstVecSize => Some("Only literals, manifest constants and layout queries are known at compile time.".into()),
+            Self::VecOutsideRoutine => Some("Declare a `global` array type instead.".into()),
+            Self::InvalidSelector(bits) => Some(format!("Sizes and shifts are given in bits and have to stay within a word of {bits} bits.")),
+            Self::EvaluationOrder => Some("They are evaluated from left to right here; bind them with `let` first to make the order explicit.".into()),
+            _ => None
+        }
+    }
+
+    fn additional(&self) -> Vec<Located<CompilerError>> {
+        match self {
+            Self::InitializerCycle(cycle) => cycle[1..cycle.len() - 1].iter()
+                .map(|ident| CompilerError::new(Severity::Hint, format!("`{}` is initialized here.", **ident), None, vec![])
+                    .with_location(ident.location().clone())
+                )
+                .collect(),
+            _ => vec![]
+        }
+    }
+
+    pub fn lint(&self) -> Option<Lint> {
+        match self {
+            Self::ValOfWithoutResult => Some(Lint::ValOfWithoutResult),
+            Self::ConstantCondition(..) => Some(Lint::ConstantCondition),
+            Self::EvaluationOrder => Some(Lint::EvaluationOrder),
+            _ => None
+        }
+    }
+}
+
+impl WithLocation for LowerError {}
+
+impl ToString for LowerError {
+    fn to_string(&self) -> String {
+        match self {
+            Self::NoJumpTarget(stmt, target) => format!("`{stmt}` has no enclosing {target} to jump to."),
+            Self::CaseOutsideSwitchOn(label) => format!("Encountered `{label}` label outside of `switchon`."),
+            Self::ValOfWithoutResult => format!("End of `valof` block is reachable without `resultis`."),
+            Self::UnknownLayout(typ) => format!("Size of type `{typ}` is not known at compile time."),
+            Self::NoSuchField(typ, field) => format!("Type `{typ}` has no field `{field}`."),
+            Self::InitializerCycle(cycle) => format!(
+                "Initializer of `{}` depends on itself: {}.",
+                *cycle[0],
+                cycle.iter().map(|ident| format!("`{}`", **ident)).collect::<Vec<_>>().join(" -> ")
+            ),
+            Self::AssertionFailed(message) => format!("Assertion failed: {message}"),
+            Self::NonConstAssertion => format!("Condition of `assert` is not a constant expression."),
+            Self::ConstantCondition(value, branch) => format!("Condition is always {value}; the {branch} is never taken."),
+            Self::NonConstVecSize => format!("Size of `vec` is not a non-negative constant expression."),
+            Self::VecOutsideRoutine => format!("`vec` can only be used inside routines."),
+            Self::InvalidSelector(_) => format!("Selector is not a constant `slct size:shift:offset` fitting a word."),
+            Self::EvaluationOrder => format!("Result may depend on the order in which operands with side effects are evaluated.")
+        }
+    }
+}
+
+impl IntoCompilerError for LowerError {}
+impl Into<CompilerError> for LowerError {
+    fn into(self) -> CompilerError {
+        CompilerError::new(self.severity(), self.to_string(), self.hint(), self.additional())
+    }
+}
+
+type LowerResult<T> = Result<T, Located<LowerError>>;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LowerOptions {
+    // guard every pointer dereference with a trap on `nil`
+    pub checked: bool,
+    pub data_layout: DataLayout,
+    // default for arithmetic outside of `checked`, `wrapping` and `saturating`
+    pub overflow: OverflowMode
+}
+
+// Routines are lowered in codegen units of consecutive routines, on up to `codegen_units`
+// threads (by default one per core). Each unit collects its own read-only data, which is merged
+// into the module afterwards in unit order, so the result does not depend on the split.
+pub fn lower_ast(ast: Arc<Mutex<ast::Program>>, options: LowerOptions, codegen_units: Option<usize>, warnings: &mut Vec<Located<LowerError>>) -> Result<Module, Vec<Located<LowerError>>> {
+    let ast = ast.lock().unwrap();
+    let bool_typ = ast.types().by_kind(&TypeKind::Bool);
+
+    let mut module = Module::default();
+    let mut errors = vec![];
+
+    let manifests = ast.sections()
+        .flat_map(|section| section.declarations())
+        .filter_map(|decl| decl.as_any().downcast_ref::<ManifestDecl>())
+        .map(|manifest| (manifest.ident(), manifest.value()))
+        .collect::<HashMap<_, _>>();
+
+    for section in ast.sections() {
+        for (condition, message) in section.assertions() {
+            if let Err(err) = check_assertion(condition, message, ast.types(), &options.data_layout, &manifests) {
+                errors.push(err);
+            }
+        }
+    }
+
+    // in source order, so constants are numbered the same in every build
+    let mut routines = ast.sections()
+        .flat_map(|section| section.declarations())
+        .filter_map(|decl| decl.as_any().downcast_ref::<ast::Function>())
+        .collect::<Vec<_>>();
+    routines.sort_by_key(|routine| routine.location().clone());
+    if !routines.is_empty() {
+        let units = codegen_units.unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get())).clamp(1, routines.len());
+        let unit_size = routines.len().div_ceil(units);
+        log!(Debug, "lowering {} routine(s) in {} codegen unit(s)", routines.len(), routines.len().div_ceil(unit_size));
+
+        let types = ast.types();
+        let manifests = &manifests;
+        let lowered = std::thread::scope(|s| {
+            let handles = routines.chunks(unit_size)
+                .map(|unit| s.spawn(move || lower_unit(unit, types, bool_typ, options, manifests)))
+                .collect::<Vec<_>>();
+
+            handles.into_iter()
+                .map(|handle| handle.join().expect("codegen unit thread panicked"))
+                .collect::<Vec<_>>()
+        });
+
+        for unit in lowered {
+            let indices = module.rodata.merge(unit.rodata);
+            for mut func in unit.functions {
+                func.relocate_constants(&indices);
+                module.add_function(func);
+            }
+            warnings.extend(unit.warnings);
+            errors.extend(unit.errors);
+        }
+    }
+
+    match order_initializers(&ast) {
+        Ok(globals) => for global in globals {
+            if let Some(value) = global.value() && value.contains(|kind| matches!(kind, ExprKind::Vec(_))) {
+                errors.push(LowerError::VecOutsideRoutine.with_location(value.location().clone()));
+                continue;
+            }
+            let value = global.value().clone().map(|mut value| { intern_strings(&mut value, &mut module.rodata); value });
+            module.add_global(Global {
+                loc: global.location().clone(),
+                ident: global.ident().clone(),
+                storage: global.storage(),
+                typ: *global.typ(),
+                value,
+                link_section: global.link_section().cloned()
+            })
+        },
+        Err(err) => errors.push(err)
+    }
+
+    if errors.is_empty() {
+        Ok(module)
+    }
+    else {
+        Err(errors)
+    }
+}
+
+// the routines of a codegen unit, lowered with their own read-only data
+struct CodegenUnit {
+    functions: Vec<Function>,
+    rodata: ReadOnlyData,
+    warnings: Vec<Located<LowerError>>,
+    errors: Vec<Located<LowerError>>
+}
+
+fn lower_unit(routines: &[&ast::Function], types: &TypeList, bool_typ: Option<TypeIndex>, options: LowerOptions, manifests: &HashMap<&String, &Expr>) -> CodegenUnit {
+    let mut unit = CodegenUnit {
+        functions: vec![],
+        rodata: ReadOnlyData::default(),
+        warnings: vec![],
+        errors: vec![]
+    };
+
+    for func in routines {
+        match FunctionBuilder::new(types, &mut unit.rodata, bool_typ, options, manifests).lower_function(func, &mut unit.warnings) {
+            Ok(func) => {
+                log!(Debug, "lowered `{}` to {} block(s), {} op(s)", func.ident(), func.blocks().len(), func.op_count());
+                unit.functions.push(func)
+            }
+            Err(err) => unit.errors.push(err)
+        }
+    }
+    unit
+}
+
+// manifest constants can refer to each other; cycles are reported by `order_initializers`
+const MAX_MANIFEST_DEPTH: usize = 64;
+
+// folds manifest constants and layout queries in `expr`, so that the constant evaluator can handle it
+fn fold_constants(expr: &Expr, types: &TypeList, data_layout: &DataLayout, manifests: &HashMap<&String, &Expr>, depth: usize) -> LowerResult<Expr> {
+    let loc = expr.location().clone();
+    match expr.kind() {
+        ExprKind::Ident(ident) if depth < MAX_MANIFEST_DEPTH && let Some(value) = manifests.get(ident) => {
+            fold_constants(value, types, data_layout, manifests, depth + 1)
+        }
+        ExprKind::SizeOf(typ) => {
+            let layout = types.layout(*typ, data_layout)
+                .ok_or_else(|| LowerError::UnknownLayout(types.type_name(*typ)).with_location(loc.clone()))?;
+            Ok(Expr::new(loc, *expr.typ(), ExprKind::IntLit(layout.size())))
+        }
+        ExprKind::OffsetOf(typ, field) => {
+            let offset = types.field_offset(*typ, field, data_layout)
+                .ok_or_else(|| LowerError::NoSuchField(types.type_name(*typ), field.clone()).with_location(loc.clone()))?;
+            Ok(Expr::new(loc, *expr.typ(), ExprKind::IntLit(offset)))
+        }
+        _ => {
+            let mut folded = expr.clone();
+            for child in folded.kind_mut().children_mut() {
+                *child = fold_constants(child, types, data_layout, manifests, depth)?;
+            }
+            Ok(folded)
+        }
+    }
+}
+
+fn check_assertion(condition: &Expr, message: &String, types: &TypeList, data_layout: &DataLayout, manifests: &HashMap<&String, &Expr>) -> LowerResult<()> {
+    let loc = condition.location().clone();
+    match fold_constants(condition, types, data_layout, manifests, 0)?.const_bool() {
+        Some(true) => Ok(()),
+        Some(false) => Err(LowerError::AssertionFailed(message.clone()).with_location(loc)),
+        None => Err(LowerError::NonConstAssertion.with_location(loc))
+    }
+}
+
+pub fn bound_in_function(func: &ast::Function) -> HashSet<String> {
+    let mut locals = HashSet::new();
+    func.params().iter().for_each(|param| bound_by(param.pattern(), &mut locals));
+    locals.extend(func.variadic().and_then(ast::Variadic::ident));
+    match func.body() {
+        FunctionBody::Expr(expr) => bound_in_expr(expr, &mut locals),
+        FunctionBody::Stmt(stmt) => bound_in_stmt(stmt, &mut locals),
+        FunctionBody::PatternMatchedExpr(branches) => for (patterns, expr) in branches {
+            patterns.iter().for_each(|pattern| bound_by(pattern, &mut locals));
+            bound_in_expr(expr, &mut locals);
+        },
+        FunctionBody::PatternMatchedStmt(branches) => for (patterns, stmt) in branches {
+            patterns.iter().for_each(|pattern| bound_by(pattern, &mut locals));
+            bound_in_stmt(stmt, &mut locals);
+        }
+    }
+    locals.into_iter().cloned().collect()
+}
+
+fn bound_in_expr<'a>(expr: &'a Expr, locals: &mut HashSet<&'a String>) {
+    match expr.kind() {
+        ExprKind::ValOf(body) => bound_in_stmt(body, locals),
+        ExprKind::Match(_, branches) | ExprKind::Every(_, branches) => branches.iter()
+            .flat_map(|(patterns, _)| patterns)
+            .for_each(|pattern| bound_by(pattern, locals)),
+        _ => ()
+    }
+    expr.kind().children().into_iter().for_each(|child| bound_in_expr(child, locals));
+}
+
+fn bound_in_stmt<'a>(stmt: &'a Stmt, locals: &mut HashSet<&'a String>) {
+    match stmt.kind() {
+        StmtKind::Binding(bindings) => bindings.iter().for_each(|(pattern, _)| bound_by(pattern, locals)),
+        StmtKind::For(iter, ..) => bound_by(iter, locals),
+        StmtKind::Try(_, caught, _) => bound_by(caught, locals),
+        StmtKind::Match(_, branches) | StmtKind::Every(_, branches) => branches.iter()
+            .flat_map(|(patterns, _)| patterns)
+            .for_each(|pattern| bound_by(pattern, locals)),
+        _ => ()
+    }
+    stmt.kind().exprs().into_iter().for_each(|expr| bound_in_expr(expr, locals));
+    stmt.kind().stmts().into_iter().for_each(|stmt| bound_in_stmt(stmt, locals));
+}
+
+// Constructs that `break`, `next`, `resultis` and case labels can refer to.
+// Each frame knows the blocks its jumps resolve to; `valof` frames are opaque
+// to everything but `resultis`, since jumps cannot leave an expression.
+// Jumps out of a `try` body drop its handler, and `rethrow` throws what the
+// innermost handler caught again.
+enum Frame {
+    Loop { exit: BlockId, next: BlockId },
+    SwitchOn { exit: BlockId, cases: Vec<(CaseLabel, BlockId)>, default: Option<BlockId> },
+    Match { exit: BlockId, next: BlockId },
+    ValOf { exit: BlockId, result: TempIndex },
+    Try,
+    Handler { caught: TempIndex }
+}
+
+struct FunctionBuilder<'a> {
+    types: &'a TypeList,
+    rodata: &'a mut ReadOnlyData,
+    bool_typ: Option<TypeIndex>,
+    options: LowerOptions,
+    manifests: &'a HashMap<&'a String, &'a Expr>,
+
+    blocks: Vec<BasicBlock>,
+    current: BlockId,
+    frames: Vec<Frame>,
+    overflow: OverflowMode,
+
+    temporaries: Vec<Option<TypeIndex>>,
+    valof_ends: Vec<(BlockId, Location)>,
+    pruned: Vec<Located<LowerError>>,
+    unordered: Vec<Located<LowerError>>,
+    // names bound anywhere in the function, which may shadow manifest constants
+    locals: HashSet<String>,
+    // the blocks of labelled statements, created by the label or the first `goto` to it
+    labels: HashMap<String, BlockId>
+}
+
+impl<'a> FunctionBuilder<'a> {
+    fn new(types: &'a TypeList, rodata: &'a mut ReadOnlyData, bool_typ: Option<TypeIndex>, options: LowerOptions, manifests: &'a HashMap<&'a String, &'a Expr>) -> Self {
+        Self {
+            types,
+            rodata,
+            bool_typ,
+            options,
+            manifests,
+            blocks: vec![BasicBlock::default()],
+            current: 0,
+            frames: vec![],
+            overflow: options.overflow,
+            temporaries: vec![],
+            valof_ends: vec![],
+            pruned: vec![],
+            unordered: vec![],
+            locals: HashSet::new(),
+            labels: HashMap::new()
+        }
+    }
+
+    fn new_temporary(&mut self, typ: Option<TypeIndex>) -> TempIndex {
+        self.temporaries.push(typ);
+        self.temporaries.len() as TempIndex - 1
+    }
+
+    // frames that `break`, `next` and case labels may resolve to
+    fn jump_frames(&mut self) -> impl Iterator<Item=&mut Frame> {
+        self.frames.iter_mut().rev()
+            .take_while(|frame| !matches!(frame, Frame::ValOf { .. }))
+    }
+
+    // the innermost frame `target` resolves a `break`, `next` or `loop` to, with its place in `frames`
+    fn jump_target(&self, target: impl Fn(&Frame) -> Option<BlockId>) -> Option<(usize, BlockId)> {
+        self.frames.iter().enumerate().rev()
+            .take_while(|(_, frame)| !matches!(frame, Frame::ValOf { .. }))
+            .find_map(|(depth, frame)| Some((depth, target(frame)?)))
+    }
+
+    // drops the handlers of the `try` bodies a jump to the frame at `depth` leaves
+    fn leave_tries(&mut self, depth: usize, loc: &Location) {
+        let tries = self.frames[depth..].iter().filter(|frame| matches!(frame, Frame::Try)).count();
+        for _ in 0..tries {
+            self.push(Instr::Eval(runtime_call(LEAVE_TRY_ROUTINE, vec![], loc)));
+        }
+    }
+
+    fn new_block(&mut self) -> BlockId {
+        self.blocks.push(BasicBlock::default());
+        self.blocks.len() as BlockId - 1
+    }
+
+    fn label_block(&mut self, label: &String) -> BlockId {
+        if let Some(block) = self.labels.get(label) {
+            return *block
+        }
+        let block = self.new_block();
+        self.labels.insert(label.clone(), block);
+        block
+    }
+
+    fn block_mut(&mut self, id: BlockId) -> &mut BasicBlock {
+        &mut self.blocks[id as usize]
+    }
+
+    fn push(&mut self, instr: Instr) {
+        let current = self.current;
+        self.block_mut(current).instrs.push(instr);
+    }
+
+    fn terminate_block(&mut self, id: BlockId, terminator: Terminator) {
+        let block = self.block_mut(id);
+        if !block.is_terminated() {
+            block.terminator = Some(terminator);
+        }
+    }
+
+    // terminates the current block; everything following it is unreachable
+    // until the next label, so continue in a fresh block
+    fn terminate(&mut self, terminator: Terminator) {
+        self.terminate_block(self.current, terminator);
+        self.current = self.new_block();
+    }
+
+    // continues lowering in `block`, falling through from the current one
+    fn switch_to(&mut self, block: BlockId) {
+        self.terminate_block(self.current, Terminator::Jump(block));
+        self.current = block;
+    }
+
+    fn is_string(&self, typ: Option<TypeIndex>) -> bool {
+        let kind = |typ| self.types.get(self.types.resolve(typ)).map(|typ| typ.kind());
+        matches!(typ.and_then(kind), Some(TypeKind::Pointer(inner)) if matches!(kind(*inner), Some(TypeKind::Char)))
+    }
+
+    // switches on the hash of `cond`, then compares it with each label of that hash in turn
+    fn string_dispatch(&mut self, dispatch: BlockId, mut cond: Expr, cases: Vec<(CaseLabel, BlockId)>, default: BlockId) -> LowerResult<Terminator> {
+        let loc = cond.location().clone();
+        if !matches!(cond.kind(), ExprKind::Ident(_) | ExprKind::Temporary(_)) {
+            let temp = self.new_temporary(*cond.typ());
+            self.block_mut(dispatch).instrs.push(Instr::Assign(temp, cond.clone()));
+            cond = Self::temporary(&cond, temp);
+        }
+
+        let call = |routine: &str, args: Vec<Expr>| Expr::new(
+            loc.clone(), None,
+            ExprKind::FuncCall(Box::new(Expr::new(loc.clone(), None, ExprKind::Ident(routine.into()))), args)
+        );
+
+        let mut buckets: Vec<(u32, Vec<(Expr, BlockId)>)> = vec![];
+        for (label, block) in cases {
+            let CaseLabel::Value(label) = label else {
+                unreachable!("string cases are never ranges")
+            };
+            let ExprKind::StringLit(value) = label.kind() else {
+                unreachable!("string cases are always literals")
+            };
+
+            let hash = string_hash(value);
+            let label = self.lower_expr(&label)?;
+            match buckets.iter_mut().find(|(other, _)| *other == hash) {
+                Some((_, labels)) => labels.push((label, block)),
+                None => buckets.push((hash, vec![(label, block)]))
+            }
+        }
+
+        let mut hash_cases = vec![];
+        for (hash, labels) in buckets {
+            let compare = self.new_block();
+            hash_cases.push((CaseLabel::Value(Expr::new(loc.clone(), None, ExprKind::IntLit(hash as u64))), compare));
+
+            let mut current = compare;
+            for (i, (label, block)) in labels.iter().enumerate() {
+                let next = if i + 1 == labels.len() { default } else { self.new_block() };
+                let zero = Expr::new(loc.clone(), None, ExprKind::IntLit(0));
+                let equal = Expr::new(loc.clone(), self.bool_typ, ExprKind::Eq(
+                    Box::new(call(STRING_COMPARE_ROUTINE, vec![cond.clone(), label.clone()])),
+                    Box::new(zero)
+                ));
+                self.terminate_block(current, Terminator::Branch(equal, *block, next));
+                current = next;
+            }
+        }
+
+        Ok(Terminator::Switch(call(STRING_HASH_ROUTINE, vec![cond]), hash_cases, default))
+    }
+
+    fn lower_function(mut self, func: &ast::Function, warnings: &mut Vec<Located<LowerError>>) -> LowerResult<Function> {
+        self.locals = bound_in_function(func);
+
+        match func.body() {
+            FunctionBody::Expr(expr) => {
+                let value = self.lower_expr(expr)?;
+                self.terminate(Terminator::Return(Some(value)));
+            }
+            FunctionBody::Stmt(stmt) => {
+                self.lower_stmt(stmt)?;
+                self.terminate(Terminator::Return(None));
+            }
+            FunctionBody::PatternMatchedExpr(branches) => {
+                let mut test = self.current;
+                for (i, (patterns, expr)) in branches.iter().enumerate() {
+                    let body = self.new_block();
+                    let failed = self.new_block();
+                    self.terminate_block(test, Terminator::Test(Scrutinee::Params, patterns.clone(), body, failed));
+
+                    self.current = body;
+                    let value = self.lower_expr(expr)?;
+                    self.terminate(Terminator::Return(Some(value)));
+
+                    test = failed;
+                    if i == branches.len() - 1 {
+                        self.terminate_block(failed, Terminator::Unreachable);
+                    }
+                }
+            }
+            FunctionBody::PatternMatchedStmt(branches) => {
+                let exit = self.new_block();
+                let mut test = self.current;
+                for (patterns, stmt) in branches {
+                    let body = self.new_block();
+                    let failed = self.new_block();
+                    self.terminate_block(test, Terminator::Test(Scrutinee::Params, patterns.clone(), body, failed));
+
+                    self.current = body;
+                    self.frames.push(Frame::Match { exit, next: failed });
+                    self.lower_stmt(stmt)?;
+                    self.frames.pop();
+                    self.switch_to(exit);
+
+                    test = failed;
+                }
+                self.terminate_block(test, Terminator::Unreachable);
+                self.terminate_block(exit, Terminator::Return(None));
+            }
+        }
+
+        // drop the empty block opened after the final terminator
+        let last = &self.blocks[self.current as usize];
+        if self.current as usize == self.blocks.len() - 1 && last.instrs.is_empty() && !last.is_terminated() {
+            self.blocks.pop();
+        }
+        else {
+            self.terminate_block(self.current, Terminator::Unreachable);
+        }
+
+        let function = Function {
+            loc: func.location().clone(),
+            ident: func.ident().clone(),
+            link_section: func.link_section().cloned(),
+            inline: func.is_inline(),
+            calling_convention: func.calling_convention(),
+            hook: func.hook(),
+            variadic: func.variadic().map(|variadic| (func.params().len(), variadic.ident().cloned())),
+            entry: 0,
+            blocks: self.blocks,
+            temporaries: self.temporaries
+        };
+
+        warnings.extend(self.pruned);
+        warnings.extend(self.unordered);
+        let reachable = function.reachable_blocks();
+        warnings.extend(self.valof_ends.into_iter()
+            .filter(|(block, _)| reachable[*block as usize])
+            .map(|(_, loc)| LowerError::ValOfWithoutResult.with_location(loc))
+        );
+
+        Ok(function)
+    }
+
+    fn temporary(expr: &Expr, temp: TempIndex) -> Expr {
+        Expr::new(expr.location().clone(), expr.typ().clone(), ExprKind::Temporary(temp))
+    }
+
+    fn needs_lowering(&self, expr: &Expr) -> bool {
+        self.needs_ordering(expr) || expr.contains_valof() || expr.contains_layout_query() || (self.options.checked && expr.contains_deref())
+            || expr.contains(|kind| matches!(kind, ExprKind::StringLit(_)))
+            || expr.contains(|kind| matches!(kind, ExprKind::Overflow(..)))
+            || (self.overflow != OverflowMode::Wrap && expr.contains(is_arithmetic))
+            || self.has_const_conditional(expr)
+            || expr.contains(|kind| matches!(kind, ExprKind::Vec(_) | ExprKind::Selector(..) | ExprKind::FieldSelect(..)))
+    }
+
+    // whether evaluating `expr` has effects, or lowering it emits instructions
+    fn has_effects(&self, expr: &Expr) -> bool {
+        expr.has_sideeffect() || expr.contains_valof() || (self.options.checked && expr.contains_deref())
+    }
+
+    // Operands and arguments are evaluated from left to right. Those of `kind` which have to be
+    // evaluated into temporaries beforehand to keep that order: all left of the last one with
+    // effects, and that one too if any follow it. Values that cannot change, like literals and
+    // the routine a call names, are left in place; a routine pointer in a local may be assigned
+    // by the arguments. What remains can be evaluated in any order.
+    fn hoisted_operands(&self, kind: &ExprKind) -> Vec<usize> {
+        // these fix the order by control flow already
+        if matches!(kind, ExprKind::Conditional(..) | ExprKind::LogAnd(..) | ExprKind::LogOr(..) | ExprKind::Match(..) | ExprKind::Every(..)) {
+            return vec![]
+        }
+        let children = kind.children();
+        let Some(last) = children.iter().rposition(|child| self.has_effects(child)) else {
+            return vec![]
+        };
+
+        let is_routine = |i: usize| i == 0 && matches!(kind, ExprKind::FuncCall(callee, _) if matches!(callee.kind(), ExprKind::Ident(routine) if !self.locals.contains(routine)));
+        let varies = |i: &usize| !is_routine(*i) && !is_constant(children[*i].kind());
+        let mut hoisted = (0..last).filter(varies).collect::<Vec<_>>();
+        if (last + 1..children.len()).any(|i| varies(&i)) {
+            hoisted.push(last);
+        }
+        hoisted
+    }
+
+    fn needs_ordering(&self, expr: &Expr) -> bool {
+        !self.hoisted_operands(expr.kind()).is_empty() || expr.kind().children().into_iter().any(|child| self.needs_ordering(child))
+    }
+
+    // lowers the operands of `expr` in order, evaluating the ones `hoisted_operands` names into temporaries
+    fn lower_operands(&mut self, expr: &Expr) -> LowerResult<Expr> {
+        let hoisted = self.hoisted_operands(expr.kind());
+        if expr.kind().children().into_iter().filter(|child| child.has_sideeffect()).count() > 1 {
+            self.unordered.push(LowerError::EvaluationOrder.with_location(expr.location().clone()));
+        }
+
+        let mut lowered = expr.clone();
+        for (i, child) in lowered.kind_mut().children_mut().into_iter().enumerate() {
+            *child = self.lower_expr(child)?;
+            if hoisted.contains(&i) && !matches!(child.kind(), ExprKind::Temporary(_)) {
+                let temp = self.new_temporary(*child.typ());
+                self.push(Instr::Assign(temp, child.clone()));
+                *child = Self::temporary(child, temp);
+            }
+        }
+        Ok(lowered)
+    }
+
+    // the packed value of a constant selector whose field lies within a word
+    fn const_selector(&self, selector: &Expr) -> LowerResult<i64> {
+        let bits = self.options.data_layout.word_size() * 8;
+        self.fold_constants(selector)
+            .and_then(|folded| folded.const_int())
+            .filter(|value| *value >= 0 && {
+                let (size, shift, _) = unpack_selector(*value);
+                (shift as u64) < bits && (size + shift) as u64 <= bits
+            })
+            .ok_or_else(|| LowerError::InvalidSelector(bits).with_location(selector.location().clone()))
+    }
+
+    // `expr` with manifest constants substituted, unless locals shadow any of them
+    fn fold_constants(&self, expr: &Expr) -> Option<Expr> {
+        let mut idents = vec![];
+        expr.referenced_idents(&mut idents);
+        if idents.iter().any(|ident| self.locals.contains(*ident)) {
+            return None
+        }
+        fold_constants(expr, self.types, &self.options.data_layout, self.manifests, 0).ok()
+    }
+
+    // value of a condition made up of literals and manifest constants
+    fn const_condition(&self, cond: &Expr) -> Option<bool> {
+        self.fold_constants(cond)?.const_bool()
+    }
+
+    fn has_const_conditional(&self, expr: &Expr) -> bool {
+        match expr.kind() {
+            ExprKind::Conditional(cond, ..) if self.const_condition(cond).is_some() => true,
+            kind => kind.children().into_iter().any(|child| self.has_const_conditional(child))
+        }
+    }
+
+    // records that `branch` of a conditional with a constant condition is dropped
+    fn prune(&mut self, cond: &Expr, value: bool, branch: &'static str) {
+        self.pruned.push(LowerError::ConstantCondition(value, branch).with_location(cond.location().clone()));
+    }
+
+    // untyped values are words, which behave like integers
+    fn is_integer(&self, expr: &Expr) -> bool {
+        expr.typ().is_none_or(|typ| self.types.get(self.types.resolve(typ)).is_some_and(|typ| typ.kind().is_word()))
+    }
+
+    // hoists all `valof` blocks (and in checked mode `nil` checks) out of `expr`, so that only
+    // plain expressions remain in the IR. Branching expressions are turned into control flow,
+    // such that hoisted blocks are only executed when their branch is taken.
+    // Layout queries are folded for the target and string literals moved to read-only data.
+    // Conditionals with a constant condition are reduced to the branch that is taken, and
+    // operands evaluated into temporaries where needed to keep them in order.
+    fn lower_expr(&mut self, expr: &Expr) -> LowerResult<Expr> {
+        if !self.needs_lowering(expr) {
+            return Ok(expr.clone())
+        }
+
+        match expr.kind() {
+            ExprKind::Conditional(cond, if_branch, else_branch) if let Some(value) = self.const_condition(cond) => {
+                self.prune(cond, value, if value { "else branch" } else { "then branch" });
+                self.lower_expr(if value { if_branch } else { else_branch })
+            }
+            ExprKind::Overflow(mode, inner) => {
+                let outer = std::mem::replace(&mut self.overflow, *mode);
+                let lowered = self.lower_expr(inner);
+                self.overflow = outer;
+                lowered
+            }
+            kind if is_arithmetic(kind) && self.overflow != OverflowMode::Wrap && self.is_integer(expr) => {
+                let lowered = self.lower_operands(expr)?;
+                Ok(Expr::new(expr.location().clone(), *expr.typ(), ExprKind::Overflow(self.overflow, Box::new(lowered))))
+            }
+            ExprKind::StringLit(value) => {
+                let index = self.rodata.intern(value, expr.location());
+                Ok(Expr::new(expr.location().clone(), *expr.typ(), ExprKind::Constant(index)))
+            }
+            ExprKind::SizeOf(typ) => {
+                let layout = self.types.layout(*typ, &self.options.data_layout)
+                    .ok_or_else(|| LowerError::UnknownLayout(self.types.type_name(*typ)).with_location(expr.location().clone()))?;
+                Ok(Expr::new(expr.location().clone(), *expr.typ(), ExprKind::IntLit(layout.size())))
+            }
+            ExprKind::OffsetOf(typ, field) => {
+                let offset = self.types.field_offset(*typ, field, &self.options.data_layout)
+                    .ok_or_else(|| LowerError::NoSuchField(self.types.type_name(*typ), field.clone()).with_location(expr.location().clone()))?;
+                Ok(Expr::new(expr.location().clone(), *expr.typ(), ExprKind::IntLit(offset)))
+            }
+            ExprKind::Vec(size) => {
+                let value = self.fold_constants(size)
+                    .and_then(|folded| folded.const_int())
+                    .and_then(|value| u64::try_from(value).ok())
+                    .ok_or_else(|| LowerError::NonConstVecSize.with_location(size.location().clone()))?;
+                let size = Expr::new(size.location().clone(), *size.typ(), ExprKind::IntLit(value));
+                Ok(Expr::new(expr.location().clone(), *expr.typ(), ExprKind::Vec(Box::new(size))))
+            }
+            ExprKind::Selector(..) => {
+                let value = self.const_selector(expr)?;
+                Ok(Expr::new(expr.location().clone(), *expr.typ(), ExprKind::IntLit(value as u64)))
+            }
+            // `(word >> shift) & mask` of the word at `ptr + offset`
+            ExprKind::FieldSelect(selector, ptr) => {
+                let (size, shift, offset) = unpack_selector(self.const_selector(selector)?);
+                let ptr = self.lower_expr(ptr)?;
+
+                let (loc, typ) = (expr.location().clone(), *expr.typ());
+                let int = |value: u64| Box::new(Expr::new(loc.clone(), typ, ExprKind::IntLit(value)));
+                let word = Expr::new(loc.clone(), None, ExprKind::Index(Box::new(ptr), int(offset)));
+                // unsigned, so that shifting does not extend the sign
+                let mut field = Expr::new(loc.clone(), typ, ExprKind::Cast(Box::new(word)));
+                if shift > 0 {
+                    field = Expr::new(loc.clone(), typ, ExprKind::RShift(Box::new(field), int(shift as u64)));
+                }
+                if size > 0 && ((size + shift) as u64) < self.options.data_layout.word_size() * 8 {
+                    field = Expr::new(loc.clone(), typ, ExprKind::And(Box::new(field), int((1 << size) - 1)));
+                }
+                Ok(field)
+            }
+            ExprKind::Deref(ptr) if self.options.checked => {
+                let mut ptr = self.lower_expr(ptr)?;
+                if !matches!(ptr.kind(), ExprKind::Ident(_) | ExprKind::Temporary(_)) {
+                    let temp = self.new_temporary(*ptr.typ());
+                    self.push(Instr::Assign(temp, ptr.clone()));
+                    ptr = Self::temporary(&ptr, temp);
+                }
+
+                let loc = expr.location().clone();
+                let nil = Expr::new(loc.clone(), *ptr.typ(), ExprKind::Nil);
+                let is_nil = Expr::new(loc.clone(), self.bool_typ, ExprKind::Eq(Box::new(ptr.clone()), Box::new(nil)));
+                let trap = self.new_block();
+                let valid = self.new_block();
+                self.terminate_block(self.current, Terminator::Branch(is_nil, trap, valid));
+                self.terminate_block(trap, Terminator::Trap(Trap::NilDeref, loc.clone()));
+
+                self.current = valid;
+                Ok(Expr::new(loc, *expr.typ(), ExprKind::Deref(Box::new(ptr))))
+            }
+            ExprKind::ValOf(body) => {
+                let result = self.new_temporary(*expr.typ());
+                let exit = self.new_block();
+
+                self.frames.push(Frame::ValOf { exit, result });
+                self.lower_stmt(body)?;
+                self.frames.pop();
+
+                self.valof_ends.push((self.current, expr.location().clone()));
+                self.switch_to(exit);
+                Ok(Self::temporary(expr, result))
+            }
+            ExprKind::Conditional(cond, if_branch, else_branch) => {
+                let cond = self.lower_expr(cond)?;
+                let result = self.new_temporary(*expr.typ());
+                let then_block = self.new_block();
+                let else_block = self.new_block();
+                let exit = self.new_block();
+                self.terminate_block(self.current, Terminator::Branch(cond, then_block, else_block));
+
+                for (block, branch) in [(then_block, if_branch), (else_block, else_branch)] {
+                    self.current = block;
+                    let value = self.lower_expr(branch)?;
+                    self.push(Instr::Assign(result, value));
+                    self.switch_to(exit);
+                }
+
+                Ok(Self::temporary(expr, result))
+            }
+            ExprKind::LogAnd(lhs, rhs) | ExprKind::LogOr(lhs, rhs) if self.needs_lowering(rhs) => {
+                let lhs = self.lower_expr(lhs)?;
+                let result = self.new_temporary(*expr.typ());
+                self.push(Instr::Assign(result, lhs));
+
+                let rhs_block = self.new_block();
+                let exit = self.new_block();
+                let decided = Self::temporary(expr, result);
+                let (on_true, on_false) = if let ExprKind::LogAnd(..) = expr.kind() { (rhs_block, exit) } else { (exit, rhs_block) };
+                self.terminate_block(self.current, Terminator::Branch(decided, on_true, on_false));
+
+                self.current = rhs_block;
+                let value = self.lower_expr(rhs)?;
+                self.push(Instr::Assign(result, value));
+                self.switch_to(exit);
+
+                Ok(Self::temporary(expr, result))
+            }
+            ExprKind::Match(args, branches) | ExprKind::Every(args, branches) => {
+                let args = args.iter()
+                    .map(|arg| self.lower_expr(arg))
+                    .collect::<LowerResult<Vec<_>>>()?;
+                let result = self.new_temporary(*expr.typ());
+                let exit = self.new_block();
+
+                for (patterns, branch) in branches {
+                    let body_block = self.new_block();
+                    let failed = self.new_block();
+                    self.terminate_block(self.current, Terminator::Test(Scrutinee::Exprs(args.clone()), patterns.clone(), body_block, failed));
+
+                    self.current = body_block;
+                    let value = self.lower_expr(branch)?;
+                    self.push(Instr::Assign(result, value));
+                    self.switch_to(exit);
+
+                    self.current = failed;
+                }
+
+                self.terminate_block(self.current, Terminator::Unreachable);
+                self.current = exit;
+                Ok(Self::temporary(expr, result))
+            }
+            _ => self.lower_operands(expr)
+        }
+    }
+
+    fn lower_stmt(&mut self, stmt: &Stmt) -> LowerResult<()> {
+        match stmt.kind() {
+            StmtKind::Nop => (),
+            StmtKind::Assert(condition, message) => check_assertion(condition, message, self.types, &self.options.data_layout, self.manifests)?,
+            // programs with parse errors are never lowered
+            StmtKind::Error => unreachable!(),
+            StmtKind::Expr(expr) => {
+                let expr = self.lower_expr(expr)?;
+                self.push(Instr::Eval(expr));
+            }
+            StmtKind::Block(stmts) => for stmt in stmts {
+                self.lower_stmt(stmt)?;
+            }
+            StmtKind::ResultIs(expr) => {
+                let (depth, exit, result) = self.frames.iter().enumerate().rev()
+                    .find_map(|(depth, frame)| match frame {
+                        Frame::ValOf { exit, result } => Some((depth, *exit, *result)),
+                        _ => None
+                    })
+                .ok_or_else(|| LowerError::NoJumpTarget("resultis", "`valof`").with_location(stmt.location().clone()))?;
+
+                let value = self.lower_expr(expr)?;
+                self.push(Instr::Assign(result, value));
+                self.leave_tries(depth, stmt.location());
+                self.terminate(Terminator::Jump(exit));
+            }
+            StmtKind::Return => {
+                self.leave_tries(0, stmt.location());
+                self.terminate(Terminator::Return(None));
+            }
+            StmtKind::If(cond, if_branch, else_branch) if let Some(value) = self.const_condition(cond) => {
+                if value {
+                    if else_branch.is_some() {
+                        self.prune(cond, value, "else branch");
+                    }
+                    self.lower_stmt(if_branch)?;
+                }
+                else {
+                    self.prune(cond, value, "then branch");
+                    if let Some(else_branch) = else_branch {
+                        self.lower_stmt(else_branch)?;
+                    }
+                }
+            }
+            StmtKind::If(cond, if_branch, else_branch) => {
+                let cond = self.lower_expr(cond)?;
+                let then_block = self.new_block();
+                let else_block = self.new_block();
+                let exit = if else_branch.is_some() { self.new_block() } else { else_block };
+                self.terminate_block(self.current, Terminator::Branch(cond, then_block, else_block));
+
+                self.current = then_block;
+                self.lower_stmt(if_branch)?;
+                self.switch_to(exit);
+
+                if let Some(else_branch) = else_branch {
+                    self.current = else_block;
+                    self.lower_stmt(else_branch)?;
+                    self.switch_to(exit);
+                }
+            }
+            StmtKind::Unless(cond, body) if let Some(value) = self.const_condition(cond) => {
+                if value {
+                    self.prune(cond, value, "body");
+                }
+                else {
+                    self.lower_stmt(body)?;
+                }
+            }
+            StmtKind::Unless(cond, body) => {
+                let cond = self.lower_expr(cond)?;
+                let body_block = self.new_block();
+                let exit = self.new_block();
+                self.terminate_block(self.current, Terminator::Branch(cond, exit, body_block));
+
+                self.current = body_block;
+                self.lower_stmt(body)?;
+                self.switch_to(exit);
+            }
+            StmtKind::While(cond, body) | StmtKind::Until(cond, body) => {
+                let header = self.new_block();
+                let body_block = self.new_block();
+                let exit = self.new_block();
+
+                self.switch_to(header);
+                let cond = self.lower_expr(cond)?;
+                let (on_true, on_false) = if let StmtKind::While(..) = stmt.kind() { (body_block, exit) } else { (exit, body_block) };
+                self.terminate_block(self.current, Terminator::Branch(cond, on_true, on_false));
+
+                self.current = body_block;
+                self.lower_loop_body(body, exit, header)?;
+                self.switch_to(exit);
+            }
+            StmtKind::Repeat(body) => {
+                let body_block = self.new_block();
+                let exit = self.new_block();
+
+                self.switch_to(body_block);
+                self.lower_loop_body(body, exit, body_block)?;
+                self.terminate_block(self.current, Terminator::Jump(body_block));
+                self.current = exit;
+            }
+            StmtKind::RepeatWhile(body, cond) | StmtKind::RepeatUntil(body, cond) => {
+                let body_block = self.new_block();
+                let cond_block = self.new_block();
+                let exit = self.new_block();
+
+                self.switch_to(body_block);
+                self.lower_loop_body(body, exit, cond_block)?;
+                self.switch_to(cond_block);
+
+                let cond = self.lower_expr(cond)?;
+                let (on_true, on_false) = if let StmtKind::RepeatWhile(..) = stmt.kind() { (body_block, exit) } else { (exit, body_block) };
+                self.terminate_block(self.current, Terminator::Branch(cond, on_true, on_false));
+                self.current = exit;
+            }
+            StmtKind::For(iter, init, limit, step, body) => {
+                let header = self.new_block();
+                let body_block = self.new_block();
+                let step_block = self.new_block();
+                let exit = self.new_block();
+
+                let init_value = self.lower_expr(init)?;
+                self.push(Instr::Bind(iter.clone(), init_value));
+                self.switch_to(header);
+
+                let counter = Expr::new(iter.location().clone(), init.typ().clone(), ExprKind::Ident(iter_ident(iter)));
+                if let Some(limit) = limit {
+                    let limit = self.lower_expr(limit)?;
+                    // loops with a constant negative step count downwards
+                    let compare = match step.as_ref().and_then(|step| self.fold_constants(step)?.const_int()) {
+                        Some(step) if step < 0 => ExprKind::Ge,
+                        _ => ExprKind::Le
+                    };
+                    let cond = Expr::new(limit.location().clone(), self.bool_typ, compare(Box::new(counter.clone()), Box::new(limit)));
+                    self.terminate_block(self.current, Terminator::Branch(cond, body_block, exit));
+                }
+                else {
+                    self.terminate_block(self.current, Terminator::Jump(body_block));
+                }
+
+                self.current = body_block;
+                self.lower_loop_body(body, exit, step_block)?;
+                self.switch_to(step_block);
+
+                let step = match step {
+                    Some(step) => self.lower_expr(step)?,
+                    None => Expr::new(iter.location().clone(), init.typ().clone(), ExprKind::IntLit(1))
+                };
+                let next = Expr::new(step.location().clone(), init.typ().clone(), ExprKind::Add(Box::new(counter), Box::new(step)));
+                self.push(Instr::Bind(iter.clone(), next));
+                self.terminate(Terminator::Jump(header));
+                self.current = exit;
+            }
+            StmtKind::SwitchOn(cond, body) => {
+                let is_string = self.is_string(*cond.typ());
+                let cond = self.lower_expr(cond)?;
+                let dispatch = self.current;
+                let exit = self.new_block();
+
+                // code in front of the first label is unreachable
+                self.current = self.new_block();
+                self.frames.push(Frame::SwitchOn { exit, cases: vec![], default: None });
+                self.lower_stmt(body)?;
+                self.switch_to(exit);
+
+                if let Some(Frame::SwitchOn { cases, default, .. }) = self.frames.pop() {
+                    let terminator = if is_string || cases.iter().any(|(label, _)| matches!(label, CaseLabel::Value(value) if matches!(value.kind(), ExprKind::StringLit(_)))) {
+                        self.string_dispatch(dispatch, cond, cases, default.unwrap_or(exit))?
+                    }
+                    else {
+                        Terminator::Switch(cond, cases, default.unwrap_or(exit))
+                    };
+                    self.terminate_block(dispatch, terminator);
+                }
+            }
+            StmtKind::Case(values) => {
+                let label = self.new_block();
+                let cases = self.innermost_switchon("case", stmt)?.0;
+                for (expr, upper) in values {
+                    match upper {
+                        None => cases.push((CaseLabel::Value(expr.clone()), label)),
+                        Some(upper) => match (expr.const_int(), upper.const_int()) {
+                            (Some(low), Some(high)) if low >= 0 && high - low < MAX_EXPANDED_CASE_RANGE => {
+                                cases.extend((low..=high).map(|value| (
+                                    CaseLabel::Value(Expr::new(expr.location().clone(), expr.typ().clone(), ExprKind::IntLit(value as u64))),
+                                    label
+                                )))
+                            }
+                            _ => cases.push((CaseLabel::Range(expr.clone(), upper.clone()), label))
+                        }
+                    }
+                }
+                self.switch_to(label);
+            }
+            StmtKind::DefaultCase => {
+                let label = self.new_block();
+                *self.innermost_switchon("default", stmt)?.1 = Some(label);
+                self.switch_to(label);
+            }
+            StmtKind::Break => {
+                let (depth, target) = self.jump_target(|frame| match frame {
+                    Frame::Loop { exit, .. }
+                        | Frame::SwitchOn { exit, .. }
+                        | Frame::Match { exit, .. } => Some(*exit),
+                    Frame::ValOf { .. } | Frame::Try | Frame::Handler { .. } => None
+                })
+                .ok_or_else(|| LowerError::NoJumpTarget("break", "loop, `switchon` or `match`").with_location(stmt.location().clone()))?;
+                self.leave_tries(depth, stmt.location());
+                self.terminate(Terminator::Jump(target));
+            }
+            StmtKind::Next => {
+                let (depth, target) = self.jump_target(|frame| match frame {
+                    Frame::Loop { next, .. } | Frame::Match { next, .. } => Some(*next),
+                    Frame::SwitchOn { .. } | Frame::ValOf { .. } | Frame::Try | Frame::Handler { .. } => None
+                })
+                .ok_or_else(|| LowerError::NoJumpTarget("next", "loop, `match` or `every`").with_location(stmt.location().clone()))?;
+                self.leave_tries(depth, stmt.location());
+                self.terminate(Terminator::Jump(target));
+            }
+            StmtKind::Loop => {
+                let (depth, target) = self.jump_target(|frame| match frame {
+                    Frame::Loop { next, .. } => Some(*next),
+                    Frame::SwitchOn { .. } | Frame::Match { .. } | Frame::ValOf { .. } | Frame::Try | Frame::Handler { .. } => None
+                })
+                .ok_or_else(|| LowerError::NoJumpTarget("loop", "loop").with_location(stmt.location().clone()))?;
+                self.leave_tries(depth, stmt.location());
+                self.terminate(Terminator::Jump(target));
+            }
+            StmtKind::Try(body, caught, handler) => {
+                let body_block = self.new_block();
+                let handler_block = self.new_block();
+                let exit = self.new_block();
+                self.terminate_block(self.current, Terminator::Try(body_block, handler_block));
+
+                self.current = body_block;
+                self.frames.push(Frame::Try);
+                self.lower_stmt(body)?;
+                self.frames.pop();
+                self.push(Instr::Eval(runtime_call(LEAVE_TRY_ROUTINE, vec![], stmt.location())));
+                self.switch_to(exit);
+
+                // the handler was dropped by unwinding to it
+                self.current = handler_block;
+                let temp = self.new_temporary(None);
+                let value = runtime_call(CAUGHT_ROUTINE, vec![], caught.location());
+                self.push(Instr::Assign(temp, value.clone()));
+                self.push(Instr::Bind(caught.clone(), Self::temporary(&value, temp)));
+
+                self.frames.push(Frame::Handler { caught: temp });
+                self.lower_stmt(handler)?;
+                self.frames.pop();
+                self.switch_to(exit);
+            }
+            StmtKind::Throw(value) => {
+                let value = self.lower_expr(value)?;
+                let loc = value.location().clone();
+                self.push(Instr::Eval(runtime_call(THROW_ROUTINE, vec![value], &loc)));
+                self.terminate(Terminator::Unreachable);
+            }
+            StmtKind::Rethrow => {
+                let caught = self.frames.iter().rev()
+                    .find_map(|frame| match frame {
+                        Frame::Handler { caught } => Some(*caught),
+                        _ => None
+                    })
+                .ok_or_else(|| LowerError::NoJumpTarget("rethrow", "`catch`").with_location(stmt.location().clone()))?;
+                let value = Expr::new(stmt.location().clone(), None, ExprKind::Temporary(caught));
+                self.push(Instr::Eval(runtime_call(THROW_ROUTINE, vec![value], stmt.location())));
+                self.terminate(Terminator::Unreachable);
+            }
+            StmtKind::Goto(label) => {
+                let target = self.label_block(label);
+                self.terminate(Terminator::Jump(target));
+            }
+            StmtKind::Labelled(label, body) => {
+                let block = self.label_block(label);
+                self.switch_to(block);
+                self.lower_stmt(body)?;
+            }
+            StmtKind::Match(args, branches) | StmtKind::Every(args, branches) => {
+                let is_every = matches!(stmt.kind(), StmtKind::Every(..));
+                let args = args.iter()
+                    .map(|arg| self.lower_expr(arg))
+                    .collect::<LowerResult<Vec<_>>>()?;
+                let exit = self.new_block();
+
+                for (patterns, body) in branches {
+                    let body_block = self.new_block();
+                    let failed = self.new_block();
+                    self.terminate_block(self.current, Terminator::Test(Scrutinee::Exprs(args.clone()), patterns.clone(), body_block, failed));
+
+                    self.current = body_block;
+                    self.frames.push(Frame::Match { exit, next: failed });
+                    self.lower_stmt(body)?;
+                    self.frames.pop();
+                    // `every` tries all remaining branches, `match` stops at the first one
+                    self.switch_to(if is_every { failed } else { exit });
+
+                    self.current = failed;
+                }
+
+                self.switch_to(exit);
+            }
+            StmtKind::Binding(bindings) => {
+                // values referring to names bound alongside them see the outer ones,
+                // so all of them are computed before anything is bound
+                let mut bound = HashSet::new();
+                bindings.iter().for_each(|(pattern, _)| bound_by(pattern, &mut bound));
+                let simultaneous = bindings.len() > 1 && bindings.iter().any(|(_, expr)| {
+                    let mut idents = vec![];
+                    expr.referenced_idents(&mut idents);
+                    idents.into_iter().any(|ident| bound.contains(ident))
+                });
+
+                let mut values = vec![];
+                for (_, expr) in bindings {
+                    let value = self.lower_expr(expr)?;
+                    values.push(if simultaneous {
+                        let temp = self.new_temporary(*value.typ());
+                        self.push(Instr::Assign(temp, value.clone()));
+                        Self::temporary(&value, temp)
+                    }
+                    else {
+                        value
+                    });
+                }
+
+                for ((pattern, _), value) in bindings.iter().zip(values) {
+                    self.push(Instr::Bind(pattern.clone(), value));
+                }
+            }
+            StmtKind::InlineAsm(template, outputs, inputs) => self.push(Instr::InlineAsm(stmt.location().clone(), template.clone(), outputs.clone(), inputs.clone())),
+            StmtKind::Assign(pairs) => {
+                // targets are computed before any value, and a single value is written directly
+                let targets = pairs.iter()
+                    .map(|(target, _)| self.lower_expr(target))
+                    .collect::<LowerResult<Vec<_>>>()?;
+
+                let mut values = vec![];
+                for (_, expr) in pairs {
+                    let value = self.lower_expr(expr)?;
+                    values.push(if pairs.len() > 1 {
+                        let temp = self.new_temporary(*value.typ());
+                        self.push(Instr::Assign(temp, value.clone()));
+                        Self::temporary(&value, temp)
+                    }
+                    else {
+                        value
+                    });
+                }
+
+                for (target, value) in targets.into_iter().zip(values) {
+                    self.push(Instr::Store(target, value));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn lower_loop_body(&mut self, body: &Stmt, exit: BlockId, next: BlockId) -> LowerResult<()> {
+        self.frames.push(Frame::Loop { exit, next });
+        let result = self.lower_stmt(body);
+        self.frames.pop();
+        result
+    }
+
+    fn innermost_switchon(&mut self, label: &'static str, stmt: &Stmt) -> LowerResult<(&mut Vec<(CaseLabel, BlockId)>, &mut Option<BlockId>)> {
+        self.jump_frames()
+            .find_map(|frame| match frame {
+                Frame::SwitchOn { cases, default, .. } => Some((cases, default)),
+                _ => None
+            })
+            .ok_or_else(|| LowerError::CaseOutsideSwitchOn(label).with_location(stmt.location().clone()))
+    }
+}
+
+// moves the string literals of an initializer to read-only data
+fn intern_strings(expr: &mut Expr, rodata: &mut ReadOnlyData) {
+    if let ExprKind::StringLit(value) = expr.kind() {
+        let index = rodata.intern(value, expr.location());
+        *expr.kind_mut() = ExprKind::Constant(index);
+    }
+
+    for child in expr.kind_mut().children_mut() {
+        intern_strings(child, rodata);
+    }
+}
+
+// values which are the same whenever they are evaluated
+fn is_constant(kind: &ExprKind) -> bool {
+    matches!(kind, ExprKind::IntLit(_) | ExprKind::FloatLit(_) | ExprKind::CharLit(_) | ExprKind::StringLit(_)
+        | ExprKind::Atom(_) | ExprKind::True | ExprKind::False | ExprKind::Nil
+        | ExprKind::SizeOf(_) | ExprKind::OffsetOf(..) | ExprKind::Constant(_) | ExprKind::Temporary(_))
+}
+
+// operations that can overflow
+fn is_arithmetic(kind: &ExprKind) -> bool {
+    matches!(kind, ExprKind::Add(..) | ExprKind::Sub(..) | ExprKind::Mul(..) | ExprKind::Div(..) | ExprKind::Abs(_))
+}
+
+fn iter_ident(iter: &Located<ast::pattern::Pattern>) -> String {
+    match &**iter {
+        ast::pattern::Pattern::Query(ident) => ident.clone(),
+        _ => "_".into()
+    }
+}
@@ -0,0 +1,117 @@
+use std::{collections::{HashMap, BTreeMap}, fmt::Write};
+
+use crate::{
+    ast::{Program, layout::DataLayout},
+    source_file::{SourceFile, SourceFileId}
+};
+
+use super::{Module, Function, declaring_sections, section_at};
+
+pub const LINE_TABLE_HEADER: &str = "line table:";
+
+struct Symbol<'a> {
+    name: String,
+    // the BCPL section declaring the symbol, or using a constant first
+    module: Option<&'a String>,
+    section: String,
+    size: u64,
+    unit: &'static str
+}
+
+// Symbol map of a lowered module, followed by the total size per BCPL section and a line
+// table for `symbolize`. Data sizes are in bytes; routines are measured in IR operations,
+// since there is no backend yet, and laid out in map order.
+pub fn write_map(module: &Module, ast: &Program, target: &DataLayout, sources: &HashMap<SourceFileId, SourceFile>) -> String {
+    let declared_in = declaring_sections(ast);
+
+    let mut symbols = vec![];
+    for function in module.functions() {
+        symbols.push(Symbol {
+            name: function.ident().clone(),
+            module: declared_in.get(function.ident()).copied(),
+            section: function.link_section().cloned().unwrap_or(".text".into()),
+            size: function.op_count() as u64,
+            unit: "ops"
+        });
+    }
+    for global in module.globals() {
+        symbols.push(Symbol {
+            name: global.ident().clone(),
+            module: declared_in.get(global.ident()).copied(),
+            section: global.link_section().cloned().unwrap_or(".data".into()),
+            size: global.size(ast.types(), target),
+            unit: "bytes"
+        });
+    }
+    for (i, constant) in module.rodata().constants().iter().enumerate() {
+        symbols.push(Symbol {
+            name: format!(".S{i}"),
+            module: section_at(ast, constant.location()),
+            section: ".rodata".into(),
+            size: constant.size(target),
+            unit: "bytes"
+        });
+    }
+    symbols.sort_by(|a, b| (&a.section, &a.name).cmp(&(&b.section, &b.name)));
+
+    let width = symbols.iter().map(|symbol| symbol.name.len()).max().unwrap_or(0).max("symbol".len());
+    let mut out = String::new();
+    writeln!(out, "{:width$}  {:16}  {:16}  size", "symbol", "section", "module").unwrap();
+    for symbol in &symbols {
+        writeln!(out, "{:width$}  {:16}  {:16}  {} {}",
+            symbol.name, symbol.section, symbol.module.map_or("-", String::as_str), symbol.size, symbol.unit).unwrap();
+    }
+
+    //                  module              section  (size, unit)
+    let mut totals: BTreeMap<&str, BTreeMap<&str, (u64, &str)>> = BTreeMap::new();
+    for symbol in &symbols {
+        let total = totals.entry(symbol.module.map_or("-", String::as_str))
+            .or_default()
+            .entry(&symbol.section)
+            .or_insert((0, symbol.unit));
+        total.0 += symbol.size;
+    }
+
+    writeln!(out, "\nsize by module:").unwrap();
+    for (module, sections) in totals {
+        let sections = sections.into_iter()
+            .map(|(section, (size, unit))| format!("{section} {size} {unit}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        writeln!(out, "  {module}: {sections}").unwrap();
+    }
+
+    let functions: HashMap<&String, &Function> = module.functions().iter().map(|function| (function.ident(), function)).collect();
+    let mut offsets: HashMap<&str, u64> = HashMap::new();
+    writeln!(out, "\n{LINE_TABLE_HEADER}").unwrap();
+    for symbol in symbols.iter().filter(|symbol| symbol.unit == "ops") {
+        let Some(function) = functions.get(&symbol.name) else {
+            continue
+        };
+        let start = *offsets.get(symbol.section.as_str()).unwrap_or(&0);
+        offsets.insert(&symbol.section, start + symbol.size);
+
+        // one entry where the routine starts and one wherever the source line changes;
+        // ops without a location, like jumps, belong to the line before them
+        let mut last = None;
+        for (op, loc) in function.op_locations().into_iter().enumerate() {
+            let loc = match loc {
+                Some(loc) => loc,
+                None if op == 0 => function.location(),
+                None => continue
+            };
+            let key = (loc.file_id(), loc.line());
+            if last == Some(key) {
+                continue
+            }
+            last = Some(key);
+
+            let path = sources.get(&loc.file_id()).map_or("<unknown>", |file| file.path());
+            let address = format!("{}+{:#x}", symbol.section, start + op as u64);
+            let routine = format!("{}+{op:#x}", symbol.name);
+            writeln!(out, "  {address:16}  {routine:routine_width$}  {path}:{}", loc.line(), routine_width = width + 6).unwrap();
+        }
+    }
+
+    out
+}
@@ -0,0 +1,448 @@
+use std::collections::{HashMap, BTreeMap};
+
+use crate::{
+    source_file::{Location, Located},
+    ast::{Program, expr::{Expr, ExprKind, TempIndex, ConstIndex}, attribute::{CallingConvention, Hook}, pattern::Pattern, types::{TypeIndex, TypeList}, layout::DataLayout, Storage}
+};
+
+mod lower;
+mod init_order;
+mod asm;
+mod map;
+mod switches;
+
+pub use lower::{lower_ast, LowerError, LowerOptions, bound_in_function};
+pub use asm::write_asm;
+pub use map::{write_map, LINE_TABLE_HEADER};
+pub use switches::write_switch_report;
+
+pub type BlockId = u32;
+
+#[derive(Default, Debug)]
+pub struct Module {
+    functions: Vec<Function>,
+
+    // initialized in this order before entering `main`
+    globals: Vec<Global>,
+
+    rodata: ReadOnlyData
+}
+
+impl Module {
+    pub fn add_function(&mut self, function: Function) {
+        self.functions.push(function);
+    }
+
+    pub fn functions(&self) -> &Vec<Function> {
+        &self.functions
+    }
+
+    pub fn add_global(&mut self, global: Global) {
+        self.globals.push(global);
+    }
+
+    pub fn globals(&self) -> &Vec<Global> {
+        &self.globals
+    }
+
+    pub fn rodata(&self) -> &ReadOnlyData {
+        &self.rodata
+    }
+
+    // static data per BCPL section, with constants counted for the section using them first;
+    // globals and constants no section declares are listed under "-"
+    pub fn data_size(&self, ast: &Program, target: &DataLayout) -> BTreeMap<String, DataSize> {
+        let declared_in = declaring_sections(ast);
+        let mut sizes: BTreeMap<String, DataSize> = BTreeMap::new();
+        for constant in &self.rodata.constants {
+            let size = sizes.entry(section_at(ast, constant.location()).map_or("-".into(), Clone::clone)).or_default();
+            size.rodata += constant.size(target);
+            size.constants += 1;
+            // uses that share a constant instead of having their own copy
+            size.merged += constant.uses - 1;
+        }
+        for global in &self.globals {
+            let size = sizes.entry(declared_in.get(global.ident()).map_or("-".into(), |section| (*section).clone())).or_default();
+            size.globals += global.size(ast.types(), target);
+        }
+        sizes
+    }
+}
+
+// maps each declaration to the BCPL section declaring it
+pub(crate) fn declaring_sections(ast: &Program) -> HashMap<&String, &String> {
+    ast.sections()
+        .flat_map(|section| section.declarations().map(move |decl| (decl.ident(), section.ident())))
+        .collect()
+}
+
+// the BCPL section whose source contains `loc`, i.e. the last one starting before it in its file
+pub(crate) fn section_at<'a>(ast: &'a Program, loc: &Location) -> Option<&'a String> {
+    ast.sections()
+        .filter(|section| section.location().file_id() == loc.file_id() && section.location() <= loc)
+        .max_by_key(|section| section.location())
+        .map(|section| section.ident())
+}
+
+// string constants of a module, each stored once no matter how often it is used
+#[derive(Default, Debug)]
+pub struct ReadOnlyData {
+    constants: Vec<Constant>,
+    by_value: HashMap<String, ConstIndex>
+}
+
+#[derive(Debug)]
+pub struct Constant {
+    // first use in source order of lowering
+    loc: Location,
+    value: String,
+    uses: u32
+}
+
+impl Constant {
+    pub fn location(&self) -> &Location {
+        &self.loc
+    }
+
+    pub fn value(&self) -> &String {
+        &self.value
+    }
+
+    pub fn uses(&self) -> u32 {
+        self.uses
+    }
+
+    // strings are packed with a leading length byte and padded to whole words
+    pub fn size(&self, target: &DataLayout) -> u64 {
+        (self.value.len() as u64 + 1).next_multiple_of(target.word_size())
+    }
+}
+
+impl ReadOnlyData {
+    pub fn intern(&mut self, value: &String, loc: &Location) -> ConstIndex {
+        if let Some(index) = self.by_value.get(value) {
+            self.constants[*index as usize].uses += 1;
+            return *index
+        }
+
+        self.constants.push(Constant { loc: loc.clone(), value: value.clone(), uses: 1 });
+        let index = self.constants.len() as ConstIndex - 1;
+        self.by_value.insert(value.clone(), index);
+        index
+    }
+
+    // takes over the constants of a codegen unit lowered on its own, giving the index each has here
+    pub fn merge(&mut self, unit: ReadOnlyData) -> Vec<ConstIndex> {
+        unit.constants.into_iter()
+            .map(|constant| {
+                let index = self.intern(&constant.value, &constant.loc);
+                self.constants[index as usize].uses += constant.uses - 1;
+                index
+            })
+            .collect()
+    }
+
+    pub fn constants(&self) -> &Vec<Constant> {
+        &self.constants
+    }
+}
+
+// static data of a section in bytes
+#[derive(Default)]
+pub struct DataSize {
+    pub rodata: u64,
+    pub constants: usize,
+    pub merged: u32,
+    pub globals: u64
+}
+
+impl ToString for DataSize {
+    fn to_string(&self) -> String {
+        format!("{} bytes of read-only data in {} constants ({} duplicates merged), {} bytes of globals",
+            self.rodata, self.constants, self.merged, self.globals)
+    }
+}
+
+#[derive(Debug)]
+pub struct Global {
+    loc: Location,
+    ident: String,
+    storage: Storage,
+    typ: Option<TypeIndex>,
+    value: Option<Expr>,
+    link_section: Option<String>
+}
+
+impl Global {
+    pub fn location(&self) -> &Location {
+        &self.loc
+    }
+
+    pub fn ident(&self) -> &String {
+        &self.ident
+    }
+
+    pub fn storage(&self) -> Storage {
+        self.storage
+    }
+
+    pub fn typ(&self) -> &Option<TypeIndex> {
+        &self.typ
+    }
+
+    pub fn value(&self) -> &Option<Expr> {
+        &self.value
+    }
+
+    pub fn link_section(&self) -> Option<&String> {
+        self.link_section.as_ref()
+    }
+
+    // in bytes, padded to whole words; untyped globals are a single word
+    pub fn size(&self, types: &TypeList, target: &DataLayout) -> u64 {
+        self.typ
+            .and_then(|typ| types.layout(typ, target))
+            .map_or(target.word_size(), |layout| layout.size().next_multiple_of(target.word_size()))
+    }
+}
+
+#[derive(Debug)]
+pub struct Function {
+    loc: Location,
+    ident: String,
+    link_section: Option<String>,
+    inline: bool,
+    calling_convention: CallingConvention,
+    hook: Option<Hook>,
+    // the number of fixed parameters of variadic routines, and the name of the vector
+    // of further arguments following them
+    variadic: Option<(usize, Option<String>)>,
+
+    entry: BlockId,
+    blocks: Vec<BasicBlock>,
+    temporaries: Vec<Option<TypeIndex>>
+}
+
+impl Function {
+    pub fn location(&self) -> &Location {
+        &self.loc
+    }
+
+    pub fn ident(&self) -> &String {
+        &self.ident
+    }
+
+    pub fn link_section(&self) -> Option<&String> {
+        self.link_section.as_ref()
+    }
+
+    pub fn is_inline(&self) -> bool {
+        self.inline
+    }
+
+    pub fn calling_convention(&self) -> CallingConvention {
+        self.calling_convention
+    }
+
+    pub fn hook(&self) -> Option<Hook> {
+        self.hook
+    }
+
+    pub fn variadic(&self) -> Option<&(usize, Option<String>)> {
+        self.variadic.as_ref()
+    }
+
+    pub fn blocks(&self) -> &Vec<BasicBlock> {
+        &self.blocks
+    }
+
+    pub fn temporaries(&self) -> &Vec<Option<TypeIndex>> {
+        &self.temporaries
+    }
+
+    // number of instructions and terminators in reachable blocks, a target-independent
+    // measure of code size until there is a backend to ask
+    pub fn op_count(&self) -> usize {
+        let reachable = self.reachable_blocks();
+        self.blocks.iter()
+            .enumerate()
+            .filter(|(id, _)| reachable[*id])
+            .map(|(_, block)| block.instrs.len() + block.terminator.is_some() as usize)
+            .sum()
+    }
+
+    // source location of each op counted by `op_count`, in layout order
+    pub fn op_locations(&self) -> Vec<Option<&Location>> {
+        let reachable = self.reachable_blocks();
+        self.blocks.iter()
+            .enumerate()
+            .filter(|(id, _)| reachable[*id])
+            .flat_map(|(_, block)| block.instrs.iter()
+                .map(|instr| Some(instr.location()))
+                .chain(block.terminator.as_ref().map(Terminator::location)))
+            .collect()
+    }
+
+    // points the constants used by the function to `indices`, their places in the module's read-only data
+    pub fn relocate_constants(&mut self, indices: &[ConstIndex]) {
+        fn relocate(expr: &mut Expr, indices: &[ConstIndex]) {
+            if let ExprKind::Constant(index) = expr.kind_mut() {
+                *index = indices[*index as usize];
+            }
+            expr.kind_mut().children_mut().into_iter().for_each(|child| relocate(child, indices));
+        }
+
+        for block in &mut self.blocks {
+            for instr in &mut block.instrs {
+                match instr {
+                    Instr::Eval(value) | Instr::Bind(_, value) | Instr::Assign(_, value) => relocate(value, indices),
+                    Instr::Store(target, value) => {
+                        relocate(target, indices);
+                        relocate(value, indices);
+                    }
+                    Instr::InlineAsm(..) => ()
+                }
+            }
+            match &mut block.terminator {
+                Some(Terminator::Branch(cond, ..) | Terminator::Return(Some(cond))) => relocate(cond, indices),
+                Some(Terminator::Switch(cond, cases, _)) => {
+                    relocate(cond, indices);
+                    for (label, _) in cases {
+                        match label {
+                            CaseLabel::Value(value) => relocate(value, indices),
+                            CaseLabel::Range(low, high) => {
+                                relocate(low, indices);
+                                relocate(high, indices);
+                            }
+                        }
+                    }
+                }
+                Some(Terminator::Test(Scrutinee::Exprs(exprs), ..)) => exprs.iter_mut().for_each(|expr| relocate(expr, indices)),
+                _ => ()
+            }
+        }
+    }
+
+    pub fn reachable_blocks(&self) -> Vec<bool> {
+        let mut reachable = vec![false; self.blocks.len()];
+        let mut worklist = vec![self.entry];
+
+        while let Some(id) = worklist.pop() {
+            if std::mem::replace(&mut reachable[id as usize], true) {
+                continue;
+            }
+
+            if let Some(terminator) = &self.blocks[id as usize].terminator {
+                worklist.extend(terminator.successors());
+            }
+        }
+
+        reachable
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct BasicBlock {
+    instrs: Vec<Instr>,
+    terminator: Option<Terminator>
+}
+
+impl BasicBlock {
+    pub fn instrs(&self) -> &Vec<Instr> {
+        &self.instrs
+    }
+
+    pub fn terminator(&self) -> &Option<Terminator> {
+        &self.terminator
+    }
+
+    pub fn is_terminated(&self) -> bool {
+        self.terminator.is_some()
+    }
+}
+
+// operands are evaluated from left to right; lowering moves any whose order matters into
+// temporaries, so that backends may evaluate what is left in an expression in any order
+#[derive(Debug)]
+pub enum Instr {
+    Eval(Expr),
+    Bind(Located<Pattern>, Expr),
+    Assign(TempIndex, Expr),
+    //    target value
+    Store(Expr, Expr),
+    // operands are locals, `{n}` in the template is the n-th of the outputs followed by the inputs
+    //                  template outputs    inputs
+    InlineAsm(Location, String, Vec<Expr>, Vec<Expr>)
+}
+
+impl Instr {
+    pub fn location(&self) -> &Location {
+        match self {
+            Self::Eval(value) | Self::Assign(_, value) => value.location(),
+            Self::Store(target, _) => target.location(),
+            Self::Bind(pattern, _) => pattern.location(),
+            Self::InlineAsm(loc, ..) => loc
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CaseLabel {
+    Value(Expr),
+    Range(Expr, Expr)
+}
+
+#[derive(Debug)]
+pub enum Scrutinee {
+    Params,
+    Exprs(Vec<Expr>)
+}
+
+#[derive(Debug)]
+pub enum Terminator {
+    Jump(BlockId),
+    //     cond  then     else
+    Branch(Expr, BlockId, BlockId),
+    //     cond  cases                        default
+    Switch(Expr, Vec<(CaseLabel, BlockId)>, BlockId),
+    //   scrutinee  patterns                 matched  failed
+    Test(Scrutinee, Vec<Located<Pattern>>, BlockId, BlockId),
+    Return(Option<Expr>),
+    // registers a handler continuing at `handler` and enters `body`. A throw while the handler
+    // is registered unwinds to this routine's frame, drops the handler and jumps to it.
+    //  body     handler
+    Try(BlockId, BlockId),
+    // aborts the program with a runtime error reported at the given location
+    Trap(Trap, Location),
+    Unreachable
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Trap {
+    NilDeref
+}
+
+impl Terminator {
+    pub fn location(&self) -> Option<&Location> {
+        match self {
+            Self::Branch(cond, ..) | Self::Switch(cond, ..) => Some(cond.location()),
+            Self::Return(Some(value)) => Some(value.location()),
+            Self::Test(_, patterns, ..) => patterns.first().map(Located::location),
+            Self::Trap(_, loc) => Some(loc),
+            Self::Jump(_) | Self::Try(..) | Self::Return(None) | Self::Unreachable => None
+        }
+    }
+
+    pub fn successors(&self) -> Vec<BlockId> {
+        match self {
+            Self::Jump(target) => vec![*target],
+            Self::Branch(_, on_true, on_false) | Self::Test(_, _, on_true, on_false) => vec![*on_true, *on_false],
+            Self::Try(body, handler) => vec![*body, *handler],
+            Self::Switch(_, cases, default) => cases.iter()
+                .map(|(_, target)| *target)
+                .chain(std::iter::once(*default))
+                .collect(),
+            Self::Return(_) | Self::Trap(..) | Self::Unreachable => vec![]
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use std::{collections::HashMap, fmt::Write};
+
+use crate::{
+    ast::expr::ExprKind,
+    source_file::{Location, SourceFile, SourceFileId}
+};
+
+use super::{Module, Function, Terminator, CaseLabel, lower::STRING_HASH_ROUTINE};
+
+// switches with fewer cases compare the value with each label in turn
+const MIN_TABLE_CASES: u64 = 4;
+// a jump table needs this share of the values from the lowest to the highest label to be cases,
+// and has at most `MAX_TABLE_SPAN` entries
+const MIN_TABLE_DENSITY: f64 = 0.4;
+const MAX_TABLE_SPAN: u64 = 4096;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum SwitchStrategy {
+    // with the reason a jump table cannot be used
+    Compares(&'static str),
+    JumpTable,
+    BinarySearch(&'static str),
+    StringHash
+}
+
+impl ToString for SwitchStrategy {
+    fn to_string(&self) -> String {
+        match self {
+            Self::Compares(reason) => format!("compare chain ({reason})"),
+            Self::JumpTable => "jump table".into(),
+            Self::BinarySearch(reason) => format!("binary search ({reason})"),
+            Self::StringHash => "string hash dispatch".into()
+        }
+    }
+}
+
+struct SwitchStats<'a> {
+    loc: &'a Location,
+    routine: &'a String,
+    // case values, counting every value of a range
+    values: u64,
+    // lowest and highest value, if all labels are constant
+    range: Option<(i64, i64)>,
+    strategy: SwitchStrategy
+}
+
+impl SwitchStats<'_> {
+    // the share of the values from the lowest to the highest label that are cases
+    fn density(&self) -> Option<f64> {
+        self.range.map(|(low, high)| self.values as f64 / span(low, high) as f64)
+    }
+}
+
+fn span(low: i64, high: i64) -> u64 {
+    high.abs_diff(low).saturating_add(1)
+}
+
+// the `switchon`s in reachable code of `function`, in block order
+fn function_switches(function: &Function) -> Vec<SwitchStats<'_>> {
+    let reachable = function.reachable_blocks();
+    function.blocks().iter()
+        .enumerate()
+        .filter(|(id, _)| reachable[*id])
+        .filter_map(|(_, block)| match block.terminator() {
+            Some(Terminator::Switch(cond, cases, _)) => Some((cond, cases)),
+            _ => None
+        })
+        .map(|(cond, cases)| {
+            let loc = cond.location();
+            let routine = function.ident();
+            // the labels of switches over strings are hashes, each comparing one or more strings
+            if let ExprKind::FuncCall(callee, _) = cond.kind() && matches!(callee.kind(), ExprKind::Ident(ident) if ident == STRING_HASH_ROUTINE) {
+                return SwitchStats { loc, routine, values: cases.len() as u64, range: None, strategy: SwitchStrategy::StringHash }
+            }
+
+            let bounds = cases.iter()
+                .map(|(label, _)| match label {
+                    CaseLabel::Value(value) => value.const_int().map(|value| (value, value)),
+                    CaseLabel::Range(low, high) => low.const_int().zip(high.const_int())
+                })
+                .collect::<Option<Vec<_>>>();
+            let values = match &bounds {
+                Some(bounds) => bounds.iter().map(|(low, high)| span(*low, *high)).fold(0, u64::saturating_add),
+                None => cases.len() as u64
+            };
+            let range = bounds.as_ref().and_then(|bounds| Some((
+                bounds.iter().map(|(low, _)| *low).min()?,
+                bounds.iter().map(|(_, high)| *high).max()?
+            )));
+
+            let strategy = match range {
+                None if bounds.is_none() => SwitchStrategy::Compares("labels are not constant"),
+                _ if values < MIN_TABLE_CASES => SwitchStrategy::Compares("too few cases"),
+                Some((low, high)) if span(low, high) > MAX_TABLE_SPAN => SwitchStrategy::BinarySearch("values too far apart"),
+                Some((low, high)) if (values as f64 / span(low, high) as f64) < MIN_TABLE_DENSITY => SwitchStrategy::BinarySearch("too sparse"),
+                _ => SwitchStrategy::JumpTable
+            };
+            SwitchStats { loc, routine, values, range, strategy }
+        })
+        .collect()
+}
+
+// one line per `switchon` of the module in source order, for `--report-switches`
+pub fn write_switch_report(module: &Module, sources: &HashMap<SourceFileId, SourceFile>) -> String {
+    let mut switches = module.functions().iter().flat_map(function_switches).collect::<Vec<_>>();
+    switches.sort_by_key(|switch| switch.loc);
+
+    let mut out = String::new();
+    for switch in switches {
+        let path = sources.get(&switch.loc.file_id()).map_or("<unknown>", |file| file.path().as_str());
+        write!(out, "  {path}:{}:{} in `{}`: {} case(s)", switch.loc.line(), switch.loc.column(), switch.routine, switch.values).unwrap();
+        if let (Some((low, high)), Some(density)) = (switch.range, switch.density()) {
+            write!(out, " from {low} to {high}, {:.0}% dense", density * 100.0).unwrap();
+        }
+        writeln!(out, ", {}", switch.strategy.to_string()).unwrap();
+    }
+    out
+}
@@ -0,0 +1,61 @@
+#![feature(let_chains)]
+#![feature(impl_trait_in_assoc_type)]
+#![feature(trait_alias)]
+
+// The compiler as a library. `api` is the stable part of it, versioned by `api::VERSION`;
+// every other module is public only for the `bcplpp` driver and changes without notice.
+pub mod api;
+
+#[doc(hidden)] pub mod context;
+#[doc(hidden)] pub mod source_file;
+#[doc(hidden)] pub mod token;
+#[doc(hidden)] pub mod ast;
+#[doc(hidden)] pub mod parser;
+#[doc(hidden)] pub mod error;
+mod typechecker;
+mod ir;
+#[doc(hidden)] pub mod lint;
+#[doc(hidden)] pub mod config;
+#[doc(hidden)] pub mod cli;
+mod entry;
+#[doc(hidden)] pub mod metadata;
+#[doc(hidden)] pub mod tast;
+mod cheader;
+#[doc(hidden)] pub mod difftest;
+mod target;
+mod freestanding;
+mod needs;
+mod deprecation;
+mod link;
+#[doc(hidden)] pub mod image;
+#[doc(hidden)] pub mod daemon;
+mod report;
+mod baseline;
+#[doc(hidden)] pub mod interrupt;
+mod fingerprint;
+#[doc(hidden)] pub mod symbolize;
+#[doc(hidden)] pub mod mangle;
+mod writef;
+mod metrics;
+mod identifiers;
+mod depgraph;
+mod extract;
+#[doc(hidden)] pub mod reduce;
+#[doc(hidden)] pub mod modernize;
+#[doc(hidden)] pub mod log;
+#[doc(hidden)] pub mod memory;
+
+#[doc(hidden)]
+pub fn terminate() -> ! {
+    println!("compilation terminated.");
+    exit(1);
+}
+
+// ends the current compilation; the daemon keeps running and only reports the code
+#[doc(hidden)]
+pub fn exit(code: i32) -> ! {
+    if daemon::is_serving() {
+        std::panic::resume_unwind(Box::new(daemon::Terminated(code)))
+    }
+    std::process::exit(code)
+}
@@ -0,0 +1,355 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    match_decl,
+    ast::{Program, Decl, GlobalDecl, ManifestDecl, Function, FunctionBody, Storage, types::{TypeIndex, Coercion}, expr::{Expr, ExprKind}, stmt::{Stmt, StmtKind}, pattern::Pattern},
+    source_file::{Location, Located, WithLocation},
+    error::{IntoCompilerError, CompilerError, Severity},
+    lint::{Lint, LintConfig}
+};
+
+// where a global was declared: in this compilation or in an imported metadata file
+#[derive(Clone, Debug)]
+pub enum Site {
+    Local(Location),
+    //       section file
+    Imported(String, String)
+}
+
+impl Site {
+    fn describe(&self, first: bool) -> String {
+        match self {
+            Self::Local(_) if first => "here".into(),
+            Self::Local(_) => "at another declaration".into(),
+            Self::Imported(section, file) => format!("in section `{section}` of `{file}`")
+        }
+    }
+
+    pub fn location(&self) -> Option<&Location> {
+        match self {
+            Self::Local(loc) => Some(loc),
+            Self::Imported(..) => None
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum LinkError {
+    //           global  slots     sites
+    SlotMismatch(String, u32, u32, Site, Site),
+    //           global  types             sites
+    TypeMismatch(String, String, String, Site, Site),
+    //         slot globals          sites
+    SharedSlot(u32, String, String, Site, Site),
+    //                  global  read      declaration
+    UninitializedGlobal(String, Location, Location),
+    //             section script  placement
+    UnplacedSection(String, String, Location)
+}
+
+impl LinkError {
+    fn severity(&self) -> Severity {
+        match self {
+            Self::UninitializedGlobal(..) | Self::UnplacedSection(..) => Severity::Warning,
+            _ => Severity::Error
+        }
+    }
+
+    pub fn lint(&self) -> Option<Lint> {
+        match self {
+            Self::UninitializedGlobal(..) => Some(Lint::UninitializedGlobal),
+            _ => None
+        }
+    }
+
+    fn sites(&self) -> Option<(&Site, &Site)> {
+        match self {
+            Self::SlotMismatch(.., first, second)
+                | Self::TypeMismatch(.., first, second)
+                | Self::SharedSlot(.., first, second) => Some((first, second)),
+            Self::UninitializedGlobal(..) | Self::UnplacedSection(..) => None
+        }
+    }
+
+    pub fn location(&self) -> Option<&Location> {
+        match self {
+            Self::UninitializedGlobal(_, read, _) | Self::UnplacedSection(_, _, read) => Some(read),
+            _ => self.sites().and_then(|(first, _)| first.location())
+        }
+    }
+
+    fn hint(&self) -> Option<String> {
+        match self {
+            Self::UninitializedGlobal(..) => Some("Initialize it, or list it in `provided-globals` if the runtime library sets it.".into()),
+            Self::UnplacedSection(section, ..) => Some(format!("Add an output section collecting `{section}` to the linker script.")),
+            _ => None
+        }
+    }
+
+    fn additional(&self) -> Vec<Located<CompilerError>> {
+        let (msg, loc) = match self {
+            Self::UninitializedGlobal(ident, _, decl) => (format!("`{ident}` is declared here."), Some(decl)),
+            Self::UnplacedSection(..) => return vec![],
+            _ => ("Other declaration here.".into(), self.sites().and_then(|(_, second)| second.location()))
+        };
+
+        match loc {
+            Some(loc) => vec![
+                CompilerError::new(Severity::Hint, msg, None, vec![])
+                    .with_location(loc.clone())
+            ],
+            None => vec![]
+        }
+    }
+}
+
+impl ToString for LinkError {
+    fn to_string(&self) -> String {
+        match self {
+            Self::UninitializedGlobal(ident, ..) => return format!("Global `{ident}` is read, but never initialized."),
+            Self::UnplacedSection(section, script, _) => return format!("Section `{section}` is not mentioned in linker script `{script}`."),
+            _ => ()
+        }
+
+        let (first, second) = self.sites().unwrap();
+        let (first, second) = (first.describe(true), second.describe(false));
+        match self {
+            Self::SlotMismatch(ident, a, b, ..) => format!("Global `{ident}` has slot {a} {first}, but slot {b} {second}."),
+            Self::TypeMismatch(ident, a, b, ..) => format!("Global `{ident}` has type `{a}` {first}, but type `{b}` {second}."),
+            Self::SharedSlot(slot, a, b, ..) => format!("Global slot {slot} is used by `{a}` {first}, but by `{b}` {second}."),
+            Self::UninitializedGlobal(..) | Self::UnplacedSection(..) => unreachable!()
+        }
+    }
+}
+
+impl IntoCompilerError for LinkError {}
+impl Into<CompilerError> for LinkError {
+    fn into(self) -> CompilerError {
+        CompilerError::new(self.severity(), self.to_string(), self.hint(), self.additional())
+    }
+}
+
+struct GlobalSite<'a> {
+    ident: &'a String,
+    slot: Option<u32>,
+    typ: Option<TypeIndex>,
+    site: Site
+}
+
+// Checks that every global agrees on its slot and type across all sections of this
+// compilation and all imported modules, and that no slot is shared by different globals.
+pub fn check_globals(ast: &Program) -> Vec<LinkError> {
+    let mut globals = vec![];
+    for decl in ast.sections().flat_map(|section| section.declarations()) {
+        match_decl!{
+            decl;
+            global as GlobalDecl => if global.storage() == Storage::Global {
+                globals.push(GlobalSite { ident: global.ident(), slot: global.slot(), typ: *global.typ(), site: Site::Local(global.location().clone()) })
+            },
+            _ => ()
+        }
+    }
+    globals.sort_by(|a, b| a.site.location().cmp(&b.site.location()));
+
+    let mut interfaces = ast.interfaces().collect::<Vec<_>>();
+    interfaces.sort_by(|a, b| a.ident().cmp(b.ident()));
+    for interface in interfaces {
+        let origin = interface.origin().clone().unwrap_or_default();
+        globals.extend(interface.globals().iter().map(|global| GlobalSite {
+            ident: &global.ident,
+            slot: global.slot,
+            typ: global.typ,
+            site: Site::Imported(interface.ident().clone(), origin.clone())
+        }));
+    }
+
+    let types = ast.types();
+    let mut errors = vec![];
+    let mut by_ident: HashMap<&String, &GlobalSite> = HashMap::new();
+    let mut by_slot: HashMap<u32, &GlobalSite> = HashMap::new();
+
+    for global in &globals {
+        if let Some(first) = by_ident.get(global.ident) {
+            if let (Some(a), Some(b)) = (first.slot, global.slot) && a != b {
+                errors.push(LinkError::SlotMismatch(global.ident.clone(), a, b, first.site.clone(), global.site.clone()));
+            }
+            if let (Some(a), Some(b)) = (first.typ, global.typ) && types.coercion(a, b) != Coercion::Identity {
+                errors.push(LinkError::TypeMismatch(global.ident.clone(), types.type_name(a), types.type_name(b), first.site.clone(), global.site.clone()));
+            }
+        }
+        else {
+            by_ident.insert(global.ident, global);
+        }
+
+        if let Some(slot) = global.slot {
+            match by_slot.get(&slot) {
+                Some(first) if first.ident != global.ident => errors.push(LinkError::SharedSlot(slot, first.ident.clone(), global.ident.clone(), first.site.clone(), global.site.clone())),
+                Some(_) => (),
+                None => { by_slot.insert(slot, global); }
+            }
+        }
+    }
+
+    errors
+}
+
+// Warns about `global` variables that are read somewhere, but initialized nowhere in
+// this compilation. Globals declared by imported modules or listed in `provided-globals`
+// are assumed to be set elsewhere.
+pub fn check_uninitialized_globals(ast: &Program, lints: &LintConfig) -> Vec<LinkError> {
+    let mut declared: HashMap<&String, &Location> = HashMap::new();
+    let mut initialized: HashSet<&String> = ast.interfaces()
+        .flat_map(|interface| interface.globals().iter().map(|global| &global.ident))
+        .collect();
+    let mut reads = GlobalReads::default();
+
+    for decl in ast.sections().flat_map(|section| section.declarations()) {
+        match_decl!{
+            decl;
+            global as GlobalDecl => {
+                if let Some(value) = global.value() {
+                    initialized.insert(global.ident());
+                    reads.expr(value);
+                }
+                if global.storage() == Storage::Global {
+                    declared.entry(global.ident())
+                        .and_modify(|loc| *loc = (*loc).min(global.location()))
+                        .or_insert(global.location());
+                }
+            },
+            manifest as ManifestDecl => reads.expr(manifest.value()),
+            func as Function => reads.function(func),
+            _ => ()
+        }
+    }
+
+    // an assignment anywhere initializes the global, and so may writing through its address
+    initialized.extend(reads.assigned);
+
+    // report only the first read of each global
+    let mut reads = reads.reads;
+    reads.sort_by(|(_, a), (_, b)| a.cmp(b));
+    let mut reported = HashSet::new();
+    reads.into_iter()
+        .filter(|(ident, _)| !initialized.contains(ident) && !lints.is_global_provided(ident) && reported.insert(*ident))
+        .filter_map(|(ident, loc)| declared.get(ident).map(|decl| LinkError::UninitializedGlobal(ident.clone(), loc.clone(), (*decl).clone())))
+        .collect()
+}
+
+// Collects the identifiers read and assigned, or whose address is taken, by routines and
+// initializers. Names bound by parameters, `let`, `for` and patterns shadow globals until their
+// scope ends.
+#[derive(Default)]
+struct GlobalReads<'a> {
+    locals: Vec<&'a String>,
+    reads: Vec<(&'a String, &'a Location)>,
+    assigned: Vec<&'a String>
+}
+
+impl<'a> GlobalReads<'a> {
+    fn function(&mut self, func: &'a Function) {
+        let outer = self.locals.len();
+        func.params().iter().for_each(|param| self.bind(param.pattern()));
+        match func.body() {
+            FunctionBody::Expr(expr) => self.expr(expr),
+            FunctionBody::Stmt(stmt) => self.stmt(stmt),
+            FunctionBody::PatternMatchedExpr(branches) => branches.iter().for_each(|(patterns, expr)| self.scoped(patterns, |reads| reads.expr(expr))),
+            FunctionBody::PatternMatchedStmt(branches) => branches.iter().for_each(|(patterns, stmt)| self.scoped(patterns, |reads| reads.stmt(stmt)))
+        }
+        self.locals.truncate(outer);
+    }
+
+    fn stmt(&mut self, stmt: &'a Stmt) {
+        match stmt.kind() {
+            StmtKind::Block(stmts) => self.scoped(&[], |reads| stmts.iter().for_each(|stmt| reads.stmt(stmt))),
+            // the values are evaluated before any of the names are bound
+            StmtKind::Binding(bindings) => {
+                bindings.iter().for_each(|(_, value)| self.expr(value));
+                bindings.iter().for_each(|(pattern, _)| self.bind(pattern));
+            }
+            StmtKind::For(iter, _, _, _, body) => {
+                stmt.kind().exprs().into_iter().for_each(|expr| self.expr(expr));
+                self.scoped(std::slice::from_ref(iter), |reads| reads.stmt(body));
+            }
+            StmtKind::Match(args, branches) | StmtKind::Every(args, branches) => {
+                args.iter().for_each(|arg| self.expr(arg));
+                branches.iter().for_each(|(patterns, body)| self.scoped(patterns, |reads| reads.stmt(body)));
+            }
+            // a plain name as target is written, not read
+            StmtKind::Assign(pairs) => for (target, value) in pairs {
+                match target.kind() {
+                    ExprKind::Ident(ident) if !self.locals.contains(&ident) => self.assigned.push(ident),
+                    ExprKind::Ident(_) => (),
+                    _ => self.expr(target)
+                }
+                self.expr(value);
+            }
+            kind => {
+                kind.exprs().into_iter().for_each(|expr| self.expr(expr));
+                kind.stmts().into_iter().for_each(|stmt| self.stmt(stmt));
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &'a Expr) {
+        match expr.kind() {
+            ExprKind::Ident(ident) if !self.locals.contains(&ident) => self.reads.push((ident, expr.location())),
+            // the address is taken to write through it as often as to read, like in `set(@a)`
+            ExprKind::Ref(inner) if let ExprKind::Ident(ident) = inner.kind() => if !self.locals.contains(&ident) {
+                self.assigned.push(ident);
+            },
+            ExprKind::ValOf(body) => self.stmt(body),
+            ExprKind::Match(args, branches) | ExprKind::Every(args, branches) => {
+                args.iter().for_each(|arg| self.expr(arg));
+                branches.iter().for_each(|(patterns, body)| self.scoped(patterns, |reads| reads.expr(body)));
+            }
+            kind => kind.children().into_iter().for_each(|child| self.expr(child))
+        }
+    }
+
+    fn scoped(&mut self, patterns: &'a [Located<Pattern>], body: impl FnOnce(&mut Self)) {
+        let outer = self.locals.len();
+        patterns.iter().for_each(|pattern| self.bind(pattern));
+        body(self);
+        self.locals.truncate(outer);
+    }
+
+    fn bind(&mut self, pattern: &'a Pattern) {
+        match pattern {
+            Pattern::Query(ident) => self.locals.push(ident),
+            Pattern::Or(lhs, rhs) | Pattern::And(lhs, rhs) => {
+                self.bind(lhs);
+                self.bind(rhs);
+            }
+            Pattern::Variant(_, patterns) | Pattern::List(patterns) => patterns.iter().for_each(|pattern| self.bind(pattern)),
+            _ => ()
+        }
+    }
+}
+
+// Warns about `_section` placements the linker script does not know about; the linker
+// would otherwise silently put them wherever its orphan section rules say.
+pub fn check_linker_script(ast: &Program, path: &str, script: &str) -> Vec<LinkError> {
+    let names: HashSet<&str> = script
+        .split(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '*'))
+        .map(|name| name.trim_matches('*'))
+        .collect();
+
+    let mut placements = vec![];
+    for decl in ast.sections().flat_map(|section| section.declarations()) {
+        match_decl!{
+            decl;
+            func as Function => placements.extend(func.link_section().map(|section| (section, func.location()))),
+            global as GlobalDecl => placements.extend(global.link_section().map(|section| (section, global.location()))),
+            _ => ()
+        }
+    }
+    placements.sort_by(|(_, a), (_, b)| a.cmp(b));
+
+    let mut reported = HashSet::new();
+    placements.into_iter()
+        .filter(|(section, _)| !names.contains(section.as_str()) && !names.contains(format!(".{section}").as_str()))
+        .filter(|(section, _)| reported.insert(*section))
+        .map(|(section, loc)| LinkError::UnplacedSection(section.clone(), path.to_string(), loc.clone()))
+        .collect()
+}
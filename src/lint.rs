@@ -0,0 +1,244 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::config::{Config, ConfigValue};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Lint {
+    UnusedExpression,
+    UnusedResult,
+    RequireAfterDecl,
+    ValOfWithoutResult,
+    ShortCircuit,
+    UninitializedGlobal,
+    LoopBounds,
+    UnknownAttribute,
+    Deprecated,
+    ConstantCondition,
+    FormatString,
+    LongRoutine,
+    DeepNesting,
+    ComplexRoutine,
+    Capture,
+    EvaluationOrder,
+    IdentifierLength,
+    IdentifierCharset
+}
+
+impl Lint {
+    pub const ALL: [Lint; 18] = [
+        Lint::UnusedExpression,
+        Lint::UnusedResult,
+        Lint::RequireAfterDecl,
+        Lint::ValOfWithoutResult,
+        Lint::ShortCircuit,
+        Lint::UninitializedGlobal,
+        Lint::LoopBounds,
+        Lint::UnknownAttribute,
+        Lint::Deprecated,
+        Lint::ConstantCondition,
+        Lint::FormatString,
+        Lint::LongRoutine,
+        Lint::DeepNesting,
+        Lint::ComplexRoutine,
+        Lint::Capture,
+        Lint::EvaluationOrder,
+        Lint::IdentifierLength,
+        Lint::IdentifierCharset
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::UnusedExpression => "unused-expression",
+            Self::UnusedResult => "unused-result",
+            Self::RequireAfterDecl => "require-after-decl",
+            Self::ValOfWithoutResult => "valof-without-result",
+            Self::ShortCircuit => "short-circuit",
+            Self::UninitializedGlobal => "uninitialized-global",
+            Self::LoopBounds => "loop-bounds",
+            Self::UnknownAttribute => "unknown-attribute",
+            Self::Deprecated => "deprecated",
+            Self::ConstantCondition => "constant-condition",
+            Self::FormatString => "format-string",
+            Self::LongRoutine => "long-routine",
+            Self::DeepNesting => "deep-nesting",
+            Self::ComplexRoutine => "complex-routine",
+            Self::Capture => "capture",
+            Self::EvaluationOrder => "evaluation-order",
+            Self::IdentifierLength => "identifier-length",
+            Self::IdentifierCharset => "identifier-charset"
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|lint| lint.name() == name)
+    }
+
+    fn default_level(&self) -> LintLevel {
+        match self {
+            // BCPL code conventionally discards routine results
+            Self::UnusedResult => LintLevel::Allow,
+            // metrics of legacy code are only reported once a project opts into them
+            Self::LongRoutine | Self::DeepNesting | Self::ComplexRoutine => LintLevel::Allow,
+            // denied for classic BCPL semantics, where nested routines only see globals and statics
+            Self::Capture => LintLevel::Allow,
+            // the order is fixed here; this is for code also built with other BCPL compilers
+            Self::EvaluationOrder => LintLevel::Allow,
+            // for porting from and to compilers with a shorter or narrower notion of names
+            Self::IdentifierLength | Self::IdentifierCharset => LintLevel::Allow,
+            _ => LintLevel::Warn
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LintLevel {
+    Allow,
+    Warn,
+    Deny
+}
+
+impl TryFrom<&str> for LintLevel {
+    type Error = ();
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "allow" => Ok(Self::Allow),
+            "warn" => Ok(Self::Warn),
+            "deny" => Ok(Self::Deny),
+            _ => Err(())
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct LintConfig {
+    levels: HashMap<Lint, LintLevel>,
+
+    // routines whose results may be discarded without `unused-result` firing
+    ignored_results: HashSet<String>,
+
+    // globals set by the runtime library, which `uninitialized-global` does not report
+    provided_globals: HashSet<String>,
+
+    // limits of `long-routine`, `deep-nesting` and `complex-routine`; defaults when unset
+    max_statements: Option<usize>,
+    max_nesting: Option<usize>,
+    max_complexity: Option<usize>,
+
+    // characters of a name `identifier-length` compares; default when unset
+    significant_chars: Option<usize>
+}
+
+const DEFAULT_MAX_STATEMENTS: usize = 60;
+const DEFAULT_MAX_NESTING: usize = 5;
+const DEFAULT_MAX_COMPLEXITY: usize = 15;
+const DEFAULT_SIGNIFICANT_CHARS: usize = 8;
+
+impl LintConfig {
+    pub fn level(&self, lint: Lint) -> LintLevel {
+        self.levels.get(&lint).copied().unwrap_or_else(|| lint.default_level())
+    }
+
+    pub fn set_level(&mut self, lint: Lint, level: LintLevel) {
+        self.levels.insert(lint, level);
+    }
+
+    pub fn ignore_result_of(&mut self, routine: String) {
+        self.ignored_results.insert(routine);
+    }
+
+    pub fn is_result_ignored(&self, routine: &str) -> bool {
+        self.ignored_results.contains(routine)
+    }
+
+    pub fn provide_global(&mut self, global: String) {
+        self.provided_globals.insert(global);
+    }
+
+    pub fn is_global_provided(&self, global: &str) -> bool {
+        self.provided_globals.contains(global)
+    }
+
+    pub fn max_statements(&self) -> usize {
+        self.max_statements.unwrap_or(DEFAULT_MAX_STATEMENTS)
+    }
+
+    pub fn max_nesting(&self) -> usize {
+        self.max_nesting.unwrap_or(DEFAULT_MAX_NESTING)
+    }
+
+    pub fn max_complexity(&self) -> usize {
+        self.max_complexity.unwrap_or(DEFAULT_MAX_COMPLEXITY)
+    }
+
+    pub fn significant_chars(&self) -> usize {
+        self.significant_chars.unwrap_or(DEFAULT_SIGNIFICANT_CHARS)
+    }
+
+    // applies the `[lints]` table of a project configuration:
+    //
+    //   [lints]
+    //   unused-result = "warn"
+    //   ignored-results = ["donothing", "writef"]
+    //   provided-globals = ["stdin", "stdout"]
+    //   max-statements = 60
+    //   max-nesting = 5
+    //   max-complexity = 15
+    //   significant-chars = 8
+    pub fn apply(&mut self, config: &Config) -> Result<(), String> {
+        let Some(table) = config.table("lints") else {
+            return Ok(())
+        };
+
+        for (key, value) in table {
+            if key == "ignored-results" || key == "provided-globals" {
+                let idents = value.as_array()
+                    .and_then(|idents| idents.iter().map(ConfigValue::as_str).collect::<Option<Vec<_>>>())
+                    .ok_or_else(|| format!("{}: `{key}` has to be an array of names", config.path()))?;
+                for ident in idents {
+                    if key == "ignored-results" {
+                        self.ignore_result_of(ident.to_string());
+                    }
+                    else {
+                        self.provide_global(ident.to_string());
+                    }
+                }
+                continue;
+            }
+
+            if key == "max-statements" || key == "max-nesting" || key == "max-complexity" || key == "significant-chars" {
+                let limit = value.as_integer()
+                    .filter(|limit| *limit > 0)
+                    .ok_or_else(|| format!("{}: `{key}` has to be a positive integer", config.path()))? as usize;
+                match key.as_str() {
+                    "max-statements" => self.max_statements = Some(limit),
+                    "max-nesting" => self.max_nesting = Some(limit),
+                    "max-complexity" => self.max_complexity = Some(limit),
+                    _ => self.significant_chars = Some(limit)
+                }
+                continue;
+            }
+
+            let lint = Lint::from_name(key)
+                .ok_or_else(|| format!("{}: unknown lint `{key}`", config.path()))?;
+            let level = value.as_str()
+                .and_then(|level| LintLevel::try_from(level).ok())
+                .ok_or_else(|| format!("{}: lint level of `{key}` has to be one of \"allow\", \"warn\" or \"deny\"", config.path()))?;
+            self.set_level(lint, level);
+        }
+
+        Ok(())
+    }
+
+    // every setting in a stable order, for deciding whether earlier results can be reused
+    pub fn describe(&self) -> String {
+        let mut ignored_results = self.ignored_results.iter().collect::<Vec<_>>();
+        ignored_results.sort();
+        let mut provided_globals = self.provided_globals.iter().collect::<Vec<_>>();
+        provided_globals.sort();
+
+        let levels = Lint::ALL.iter().map(|lint| self.level(*lint)).collect::<Vec<_>>();
+        let limits = (self.max_statements(), self.max_nesting(), self.max_complexity(), self.significant_chars());
+        format!("{levels:?} {ignored_results:?} {provided_globals:?} {limits:?}")
+    }
+}
@@ -0,0 +1,120 @@
+use std::{fmt::Arguments, fs::File, io::Write, sync::Mutex};
+
+// Diagnostics about the compiler itself, for finding out what it did with a program. Enabled
+// per module with `--log`, a list of `module=level` and bare `level` entries:
+//
+//   --log parser=debug,ir=info      --log trace,typechecker=off
+//
+// A module's entry also applies to its submodules, the longest matching entry wins, and a bare
+// level applies to every module without an entry of its own.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace
+}
+
+impl Level {
+    pub const ALL: [Level; 5] = [Level::Error, Level::Warn, Level::Info, Level::Debug, Level::Trace];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Error => "error",
+            Self::Warn => "warn",
+            Self::Info => "info",
+            Self::Debug => "debug",
+            Self::Trace => "trace"
+        }
+    }
+}
+
+struct Logger {
+    //          module  `None` for `off`
+    filters: Vec<(String, Option<Level>)>,
+    file: Option<File>
+}
+
+impl Logger {
+    fn level(&self, module: &str) -> Option<Level> {
+        self.filters.iter()
+            .filter(|(prefix, _)| prefix.is_empty() || module == prefix || module.starts_with(&format!("{prefix}::")))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .and_then(|(_, level)| *level)
+    }
+}
+
+// set once per compilation; the daemon replaces it for each request
+static LOGGER: Mutex<Option<Logger>> = Mutex::new(None);
+
+fn parse_filters(spec: &str) -> Result<Vec<(String, Option<Level>)>, String> {
+    let level = |name: &str| match name {
+        "off" => Ok(None),
+        _ => Level::ALL.into_iter()
+            .find(|level| level.name() == name)
+            .map(Some)
+            .ok_or_else(|| format!("invalid log level `{name}`; expected one of `off`, `error`, `warn`, `info`, `debug`, `trace`."))
+    };
+
+    spec.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| match entry.split_once('=') {
+            Some((module, name)) => Ok((module.trim().to_string(), level(name.trim())?)),
+            None => Ok((String::new(), level(entry)?))
+        })
+        .collect()
+}
+
+// logs to `path` if given, to stderr otherwise; no filters disable logging
+pub fn configure(spec: Option<&str>, path: Option<&str>) -> Result<(), String> {
+    let mut logger = LOGGER.lock().unwrap();
+    let Some(spec) = spec else {
+        *logger = None;
+        return Ok(())
+    };
+
+    let filters = parse_filters(spec)?;
+    let file = path.map(|path| File::create(path).map_err(|err| format!("error opening log file `{path}`: {err}.")))
+        .transpose()?;
+    *logger = Some(Logger { filters, file });
+    Ok(())
+}
+
+// `module` is a path from `module_path!`; filters name it without the crate, `main` for the root
+fn module_name(module: &str) -> &str {
+    module.split_once("::").map_or("main", |(_, module)| module)
+}
+
+pub fn enabled(module: &str, level: Level) -> bool {
+    let module = module_name(module);
+    LOGGER.lock().unwrap().as_ref()
+        .and_then(|logger| logger.level(module))
+        .is_some_and(|max| level <= max)
+}
+
+pub fn write(module: &str, level: Level, args: Arguments) {
+    let module = module_name(module);
+    let mut logger = LOGGER.lock().unwrap();
+    let Some(logger) = logger.as_mut() else {
+        return
+    };
+
+    let line = format!("[{} {module}] {args}\n", level.name());
+    // losing a log line is no reason to stop the compilation
+    let _ = match &mut logger.file {
+        Some(file) => file.write_all(line.as_bytes()),
+        None => std::io::stderr().write_all(line.as_bytes())
+    };
+}
+
+// `log!(Debug, "format", args...)`; the arguments are only evaluated if the level is enabled
+#[macro_export]
+macro_rules! log {
+    ($level: ident, $($arg: tt)*) => {
+        if $crate::log::enabled(module_path!(), $crate::log::Level::$level) {
+            $crate::log::write(module_path!(), $crate::log::Level::$level, format_args!($($arg)*));
+        }
+    };
+}
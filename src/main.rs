@@ -1,119 +1,401 @@
 #![feature(let_chains)]
-#![feature(impl_trait_in_assoc_type)]
-#![feature(trait_alias)]
 
-use std::collections::{HashSet, HashMap};
+use std::{collections::HashMap, path::Path, sync::Arc};
 
 use colorize::AnsiColor;
-use source_file::Located;
 
-use crate:: {
-    error::CompilerError,
-    source_file::{SourceFile, SourceFileId},
-    context::{Context, BuildKind, OutputFile},
+use bcplpp::{
+    exit, terminate,
+    cli, daemon, interrupt, log, metadata, tast,
+    memory::CountingAllocator,
+    error::{CompilerError, ColumnStyle, DiagnosticStyle},
+    source_file::{SourceFile, SourceFileId, Located},
+    context::{self, Context, BuildKind, Emit},
+    config::Config,
+    cli::{Command, Invocation},
+    lint::{Lint, LintLevel},
+    ast::{layout::DataLayout, expr::OverflowMode},
+    image::OutputFormat,
+    difftest::{self, Runner},
+    reduce, mangle,
+    daemon::FileCache,
+    symbolize::LineTable,
 };
 
-mod context;
-mod source_file;
-mod token;
-mod ast;
-mod parser;
-mod error;
-mod typechecker;
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
 
-trait ExpectArg<T> {
-    fn expect_arg(self, program_name: &str, arg: &str) -> T;
+fn main() {
+    let mut args = std::env::args();
+    let program_name = args.next().expect("Error getting program name");
+    let args = args.collect::<Vec<_>>();
+
+    if args.first().is_some_and(|arg| arg == "daemon") {
+        daemon::serve(&program_name, |args, cache| run(program_name.clone(), args, Some(cache)))
+    }
+
+    run(program_name, args, None)
 }
 
-impl<T> ExpectArg<T> for Option<T> {
-    fn expect_arg(self, program_name: &str, arg: &str) -> T {
-        match self {
-            None => {
-                eprintln!("{program_name}: option requires an argument -- '{arg}'");
-                eprintln!("Try `{program_name} --help` for more information.");
-                std::process::exit(1);
-            }
-            Some(val) => val
+fn run(program_name: String, args: Vec<String>, cache: Option<Arc<FileCache>>) {
+    let mut ctx = Context::from_program_name(program_name);
+    if let Some(cache) = &cache {
+        ctx.set_file_cache(cache.clone());
+    }
+
+    let config = Config::find().unwrap_or_else(|err| ctx.fatal_error(&err));
+    if let Some(config) = &config {
+        ctx.apply_config(config);
+    }
+
+    let invocation = match cli::parse_args(args, config.as_ref()) {
+        Ok(invocation) => invocation,
+        Err(err) => {
+            eprintln!("{}: {err}", ctx.program_name());
+            eprintln!("Try `{} --help` for more information.", ctx.program_name());
+            exit(1);
+        }
+    };
+
+    if invocation.is_set("--dump-cli-json") {
+        print!("{}", cli::cli_json());
+        exit(0);
+    }
+    if let Some(shell) = invocation.value("--completions") {
+        let name = Path::new(ctx.program_name()).file_name().map_or(ctx.program_name().clone(), |name| name.to_string_lossy().into_owned());
+        match cli::completions(&name, shell) {
+            Ok(script) => print!("{script}"),
+            Err(err) => ctx.fatal_error(&err)
         }
+        exit(0);
     }
-}
 
-fn main() {
-    let mut args = std::env::args();
-    let mut ctx = Context::from_program_name(args.next().expect("Error getting program name"));
-
-    let mut input_files = HashSet::new();
-
-    while let Some(arg) = args.next() {
-        match arg.as_str() {
-            "-h" | "--help" => help(ctx.program_name()),
-            "-o" => ctx.set_output_file(args.next().expect_arg(ctx.program_name(), arg.as_str())),
-            "-D" => ctx.define_tag(args.next().expect_arg(ctx.program_name(), arg.as_str())),
-            "-c" => ctx.set_build_kind(BuildKind::Object),
-            "--shared" => ctx.set_build_kind(BuildKind::SharedObject),
-            _ if arg.starts_with("-") => {
-                eprintln!("{}: invalid option -- {}", ctx.program_name(), arg);
-                eprintln!("Try `{} --help` for more information.", ctx.program_name());
+    if invocation.help {
+        print!("{}", cli::help_text(ctx.program_name(), invocation.command, invocation.explicit_command));
+        exit(0);
+    }
+
+    match invocation.command {
+        Command::Fmt | Command::Lsp => ctx.fatal_error(&format!("`{}` is not implemented yet.", invocation.command.name())),
+        Command::Test => run_differential_test(&ctx, &invocation),
+        Command::Symbolize => run_symbolize(&ctx, &invocation),
+        Command::Demangle => run_demangle(&ctx, &invocation),
+        Command::Reduce => run_reduce(&ctx, &invocation, config.as_ref()),
+        Command::Build | Command::Check | Command::Run | Command::Extract | Command::Modernize => ()
+    }
+
+    if let Err(err) = log::configure(invocation.value("--log").map(String::as_str), invocation.value("--log-file").map(String::as_str)) {
+        ctx.fatal_error(&err);
+    }
+
+    for (spec, value) in &invocation.options {
+        apply_option(&mut ctx, spec.long, value.clone().unwrap_or_default());
+    }
+
+    let mut input_files = vec![];
+    for path in &invocation.inputs {
+        if path.ends_with(metadata::METADATA_EXT) {
+            ctx.add_metadata_file(path.clone());
+        }
+        else if path.ends_with(tast::TAST_EXT) {
+            ctx.add_typed_ast_file(path.clone());
+        }
+        else if !input_files.contains(path) {
+            input_files.push(path.clone());
+        }
+    }
+
+    let source_files = input_files.into_iter()
+        .enumerate()
+        .map(|(id, path)| {
+            let file = match &cache {
+                Some(cache) => cache.read(&path).map(|text| SourceFile::with_text(path.clone(), id as SourceFileId, text)),
+                None => SourceFile::read(path.clone(), id as SourceFileId)
+            };
+            (id as SourceFileId, file.unwrap_or_else(|err| ctx.fatal_error(&format!("error reading `{path}`: {err}."))))
+        })
+        .collect();
+    ctx.add_source_files(source_files);
+
+    if invocation.is_set("--check-artifacts") {
+        match ctx.check_artifacts() {
+            Ok(()) => {
+                println!("{} artifacts are up to date", "Checked:".bold().magenta());
+                exit(0)
             }
-            _ => {
-                input_files.insert(arg);
+            Err(stale) => {
+                stale.iter().for_each(|reason| println!("{} {reason}", "Stale:".bold().magenta()));
+                exit(1)
             }
         }
     }
 
-    ctx.add_source_files(input_files.into_iter()
-        .enumerate()
-        .map(|(id, path)| (id as SourceFileId, SourceFile::read(path, id as SourceFileId).expect("error opening file")))
-        .collect()
-    );
+    if !daemon::is_serving() {
+        interrupt::install();
+    }
+
+    if let Command::Extract = invocation.command {
+        run_extract(&mut ctx)
+    }
+    if let Command::Modernize = invocation.command {
+        run_modernize(&mut ctx, invocation.is_set("--dry-run"))
+    }
 
     use context::CompileResult as C;
-    match ctx.compile() {
+    let result = ctx.compile();
+    ctx.write_build_report(Some(&result));
+    ctx.print_memory_stats();
+    match result {
         C::Ok => (),
-        C::Warn(warns) => warns.into_iter().for_each(|warn| highlight_error(warn, ctx.source_files())),
+        C::Warn(warns) => warns.into_iter().for_each(|warn| highlight_error(warn, ctx.source_files(), ctx.diagnostic_style())),
         C::Err(errors) => {
-            errors.into_iter().for_each(|err| highlight_error(err, ctx.source_files()));
+            errors.into_iter().for_each(|err| highlight_error(err, ctx.source_files(), ctx.diagnostic_style()));
             terminate()
         }
+        C::Interrupted(diagnostics) => {
+            diagnostics.into_iter().for_each(|diag| highlight_error(diag, ctx.source_files(), ctx.diagnostic_style()));
+            println!("compilation interrupted.");
+            exit(interrupt::EXIT_CODE)
+        }
+    }
+
+    if let Command::Run = invocation.command {
+        run_program(&ctx, &invocation.program_args);
+    }
+}
+
+// sets the option `long` of the compilation; `value` is empty for flags
+fn apply_option(ctx: &mut Context, long: &str, value: String) {
+    match long {
+        "--output" => ctx.set_output_file(value),
+        "--define" => ctx.define_tag(value),
+        "--object" => ctx.set_build_kind(BuildKind::Object),
+        "--shared" => ctx.set_build_kind(BuildKind::SharedObject),
+        "--entry" => ctx.set_entry(value),
+        "--freestanding" => ctx.set_freestanding(true),
+        "--checked" => ctx.set_checked(true),
+        "-m32" => ctx.set_data_layout(DataLayout::from_bits(32).unwrap()),
+        "-m64" => ctx.set_data_layout(DataLayout::from_bits(64).unwrap()),
+        "--linker-script" => ctx.set_linker_script(value),
+        "--output-format" => match OutputFormat::try_from(value.as_str()) {
+            Ok(OutputFormat::Elf) => ctx.set_output_format(OutputFormat::Elf),
+            // images are converted from the linked executable, which needs a native backend first
+            Ok(_) => ctx.fatal_error(&format!("`--output-format {value}` requires a native backend and linker, which are not available yet.")),
+            Err(()) => ctx.fatal_error(&format!("invalid output format `{value}`; expected one of `elf`, `bin`, `ihex`."))
+        },
+        "--target-cpu" => ctx.target_mut().set_cpu(value),
+        "--target-feature" => if let Err(err) = ctx.target_mut().add_features(&value) {
+            ctx.fatal_error(&err);
+        },
+        "--overflow" => match OverflowMode::try_from(value.as_str()) {
+            Ok(mode) => ctx.set_overflow_mode(mode),
+            Err(()) => ctx.fatal_error(&format!("invalid overflow mode `{value}`; expected one of `wrap`, `trap`, `saturate`."))
+        },
+        "--codegen-units" => match value.parse() {
+            Ok(units) if units > 0 => ctx.set_codegen_units(units),
+            _ => ctx.fatal_error(&format!("invalid number of codegen units `{value}`; expected a positive number."))
+        },
+        "--allow" | "--warn" | "--deny" => {
+            let Some(lint) = Lint::from_name(&value) else {
+                ctx.fatal_error(&format!("unknown lint `{value}`."))
+            };
+            ctx.lints_mut().set_level(lint, LintLevel::try_from(&long[2..]).unwrap());
+        }
+        "--warning-baseline" => ctx.set_warning_baseline(value),
+        "--ignore-result" => ctx.lints_mut().ignore_result_of(value),
+        "--provided-global" => ctx.lints_mut().provide_global(value),
+        "--diagnostic-width" => match value.parse() {
+            Ok(width) => ctx.diagnostic_style_mut().width = Some(width),
+            Err(_) => ctx.fatal_error(&format!("invalid diagnostic width `{value}`; expected a number of columns."))
+        },
+        "--tab-width" => match value.parse() {
+            Ok(width) if width > 0 => ctx.diagnostic_style_mut().tab_width = width,
+            _ => ctx.fatal_error(&format!("invalid tab width `{value}`; expected a positive number of columns."))
+        },
+        "--diagnostic-columns" => match ColumnStyle::try_from(value.as_str()) {
+            Ok(columns) => ctx.diagnostic_style_mut().columns = columns,
+            Err(()) => ctx.fatal_error(&format!("invalid column style `{value}`; expected `bytes` or `display`."))
+        },
+        "--dump-ast" => ctx.set_dump_ast(true),
+        "--emit" => match Emit::try_from(value.as_str()) {
+            Ok(emit) => ctx.add_emit(emit),
+            Err(()) => ctx.fatal_error(&format!("invalid emit kind `{value}`; expected `asm`, `map`, `tast` or `h`."))
+        },
+        "--asm-source" => ctx.set_asm_source(true),
+        "--report-data-size" => ctx.set_report_data_size(true),
+        "--report-switches" => ctx.set_report_switches(true),
+        "-Zmemory-stats" => ctx.set_memory_stats(true),
+        "--build-report" => ctx.set_build_report(value),
+        "--explain-rebuild" => ctx.set_explain_rebuild(true),
+        // read by the subcommands and the driver itself
+        "--interpreter" | "--native" | "--map" | "--check" | "--dry-run" | "--completions" | "--dump-cli-json" | "--check-artifacts" | "--log" | "--log-file" => (),
+        _ => unreachable!("unhandled option `{long}`")
     }
 }
 
-fn usage(program_name: &str) {
-    println!("Usage: {program_name} <input file> [OPTIONS]\n");
+fn run_differential_test(ctx: &Context, invocation: &Invocation) -> ! {
+    let runner = |name: &str, option: &str| invocation.value(option)
+        .and_then(|command| Runner::parse(name, command))
+        .unwrap_or_else(|| ctx.fatal_error(&format!("`test` expects a non-empty `{option}` command.")));
+    let (interp, native) = (runner("interpreter", "--interpreter"), runner("native", "--native"));
+
+    let mut paths = invocation.inputs.clone();
+    paths.sort();
+    paths.dedup();
+    match difftest::run_differential(&interp, &native, &paths) {
+        Ok(0) => exit(0),
+        Ok(_) => exit(1),
+        Err(err) => ctx.fatal_error(&err)
+    }
+}
+
+fn run_symbolize(ctx: &Context, invocation: &Invocation) -> ! {
+    let Some(map) = invocation.value("--map") else {
+        ctx.fatal_error("`symbolize` expects a symbol map given with `--map`.")
+    };
+    let table = LineTable::read(map).unwrap_or_else(|err| ctx.fatal_error(&err));
+
+    if invocation.inputs.is_empty() {
+        for line in std::io::stdin().lines() {
+            match line {
+                Ok(line) => println!("{}", table.symbolize_line(&line)),
+                Err(err) => ctx.fatal_error(&format!("error reading stdin: {err}."))
+            }
+        }
+    }
+    for address in &invocation.inputs {
+        println!("{address}: {}", table.resolve(address).unwrap_or("??".into()));
+    }
+    exit(0)
+}
+
+fn run_demangle(ctx: &Context, invocation: &Invocation) -> ! {
+    if invocation.inputs.is_empty() {
+        for line in std::io::stdin().lines() {
+            match line {
+                Ok(line) => println!("{}", mangle::demangle_line(&line)),
+                Err(err) => ctx.fatal_error(&format!("error reading stdin: {err}."))
+            }
+        }
+    }
+    for symbol in &invocation.inputs {
+        match mangle::demangle(symbol) {
+            Some(demangled) => println!("{symbol}: {demangled}"),
+            None => println!("{symbol}: ??")
+        }
+    }
+    exit(0)
+}
+
+fn run_reduce(ctx: &Context, invocation: &Invocation, config: Option<&Config>) -> ! {
+    let Some(pattern) = invocation.value("--check") else {
+        ctx.fatal_error("`reduce` expects the text to look for in the build output, given with `--check`.")
+    };
+    let [path] = invocation.inputs.as_slice() else {
+        ctx.fatal_error("`reduce` expects a single input file.")
+    };
+
+    // the tags each build sets, from its arguments, the environment or `bcpl.toml`
+    let tags = cli::parse_args(std::iter::once("build".to_string()).chain(invocation.program_args.iter().cloned()).collect(), config)
+        .map(|build| build.options.into_iter().filter(|(spec, _)| spec.long == "--define").filter_map(|(_, tag)| tag).collect::<Vec<_>>())
+        .unwrap_or_default();
+
+    match reduce::reduce(path, pattern, &tags, &invocation.program_args) {
+        Ok(reduced) => {
+            print!("{}", reduced.source);
+            // the program goes to stdout
+            eprintln!("{} to {} line(s) in {} build(s)", "Reduced:".bold().magenta(), reduced.source.lines().count(), reduced.compilations);
+            exit(0)
+        }
+        Err(err) => ctx.fatal_error(&err)
+    }
 }
 
-fn help(program_name: &str) -> ! {
-    usage(program_name);
+fn run_extract(ctx: &mut Context) -> ! {
+    // the slice goes to stdout
+    ctx.set_quiet(true);
 
-    println!("Options:
-  -o <output file>  Set an output file; default: `{}`
-  -D <tag name>     Set a BCPL tag.
-  -c                Skip linking and emit `.o` file.
-  --shared          Create a shared library.
-  -h, --help        Print this help text and exit.",
-    OutputFile::default().to_filename(&BuildKind::default())); 
+    use context::CompileResult as C;
+    match ctx.extract() {
+        Ok(source) => {
+            print!("{source}");
+            exit(0)
+        }
+        Err(C::Interrupted(diagnostics)) => {
+            diagnostics.into_iter().for_each(|diag| highlight_error(diag, ctx.source_files(), ctx.diagnostic_style()));
+            println!("compilation interrupted.");
+            exit(interrupt::EXIT_CODE)
+        }
+        Err(C::Err(errors)) => {
+            errors.into_iter().for_each(|err| highlight_error(err, ctx.source_files(), ctx.diagnostic_style()));
+            terminate()
+        }
+        Err(C::Ok | C::Warn(_)) => exit(0)
+    }
+}
+
+fn run_modernize(ctx: &mut Context, dry_run: bool) -> ! {
+    use context::CompileResult as C;
+    let modernized = match ctx.modernize() {
+        Ok(modernized) => modernized,
+        Err(C::Interrupted(diagnostics)) => {
+            diagnostics.into_iter().for_each(|diag| highlight_error(diag, ctx.source_files(), ctx.diagnostic_style()));
+            println!("compilation interrupted.");
+            exit(interrupt::EXIT_CODE)
+        }
+        Err(C::Err(errors)) => {
+            errors.into_iter().for_each(|err| highlight_error(err, ctx.source_files(), ctx.diagnostic_style()));
+            terminate()
+        }
+        Err(C::Ok | C::Warn(_)) => exit(0)
+    };
 
-    std::process::exit(0);
+    modernized.warnings.into_iter().for_each(|warn| highlight_error(warn.map(Into::into), ctx.source_files(), ctx.diagnostic_style()));
+    for (id, contents) in &modernized.files {
+        let path = ctx.source_files()[id].path();
+        if !dry_run && let Err(err) = std::fs::write(path, contents) {
+            ctx.fatal_error(&format!("could not write `{path}`: {err}"));
+        }
+        println!("{} {path}", if dry_run { "Would modernize:" } else { "Modernized:" }.bold().magenta());
+    }
+    println!(
+        "{} section brackets, {} keywords and {} `goto`s converted, {} result types written out",
+        modernized.brackets, modernized.keywords, modernized.gotos, modernized.annotations
+    );
+    exit(0)
 }
 
-fn terminate() -> ! {
-    println!("compilation terminated.");
-    std::process::exit(1);
+fn run_program(ctx: &Context, args: &[String]) -> ! {
+    let path = ctx.output_path();
+    // run the output, not a program of the same name found in `PATH`
+    let path = if Path::new(&path).components().count() == 1 { format!("./{path}") } else { path };
+
+    match std::process::Command::new(&path).args(args).status() {
+        Ok(status) => exit(status.code().unwrap_or(1)),
+        Err(err) => ctx.fatal_error(&format!("error running `{path}`: {err}."))
+    }
 }
-    
-fn highlight_error(err: Located<CompilerError>, source_files: &HashMap<SourceFileId, SourceFile>) {
+
+fn highlight_error(err: Located<CompilerError>, source_files: &HashMap<SourceFileId, SourceFile>, style: &DiagnosticStyle) {
     let loc = err.location();
     let file = source_files.get(&loc.file_id()).expect("invalid file id");
 
-    println!("{} {}:{}:{}: {}", err.severity(), file.path(), loc.line(), loc.column(), err.message());
-    print!("{} {} ", format!(" {: >4}", loc.line()).bold().b_black(), "|".b_black());
+    let mut included_from = file.included_from();
+    while let Some(loc) = included_from {
+        let file = source_files.get(&loc.file_id()).expect("invalid file id");
+        let line = file.line(loc.line()).unwrap_or("");
+        println!("In file included from {}:{}:{}:", file.path(), loc.line(), style.column(line, loc.column()));
+        included_from = file.included_from();
+    }
 
     let line = file.line(loc.line()).unwrap();
-    let mark_start = loc.column();
-    let mark_end = loc.column() + loc.width();
-    println!("{}{}{}", &line[..mark_start], (&line[mark_start..mark_end]).to_owned().bold().b_yellow(), &line[mark_end..]);
+    println!("{} {}:{}:{}: {}", err.severity(), file.path(), loc.line(), style.column(line, loc.column()), err.message());
+    print!("{} {} ", format!(" {: >4}", loc.line()).bold().b_black(), "|".b_black());
+
+    let excerpt = style.excerpt(line, loc.column(), loc.column() + loc.width());
+    println!("{}{}{}", excerpt.before, excerpt.marked.bold().b_yellow(), excerpt.after);
 
-    print!("      {} {}{}", "|".b_black(), " ".repeat(mark_start), "~".repeat(loc.width()).yellow());
+    print!("      {} {}{}", "|".b_black(), " ".repeat(excerpt.mark_column), "~".repeat(excerpt.mark_width).yellow());
 
     if let Some(hint) = err.hint() {
         print!(" {} {} {}", "<-".b_black(), "hint:".bold().b_grey(), hint.clone().b_grey());
@@ -122,7 +404,6 @@ fn highlight_error(err: Located<CompilerError>, source_files: &HashMap<SourceFil
     println!();
 
     for additional in &err.additional {
-        highlight_error(additional.clone().clone(), source_files); 
+        highlight_error(additional.clone().clone(), source_files, style); 
     }
 }
- 
@@ -0,0 +1,258 @@
+use std::fmt::{self, Write};
+
+// Symbol names of routines for native backends. A routine's identifier is not usable as a
+// linker symbol as it is: hoisted routines and methods are named `outer.inner`, instances of
+// generic routines `routine<T1, T2>`, and routines with the same name get a `.n` suffix. The
+// mangled symbol keeps all of this, so `demangle` gives back the identifier and section:
+//
+//     symbol = "_B" [ "M" name ] part { part }
+//     part   = name [ "I" name { name } "E" ] { "D" number "_" }
+//     name   = number chars
+//
+// `M` names the BCPL section (module) declaring the routine. The parts are the routine and
+// the routines it is nested in, outermost first; methods are nested in their record type. `I`
+// and `E` enclose the names of the type arguments of a generic instance, and `D` is the
+// discriminator of the `.n` suffix. A name is its length followed by its characters, where
+// every byte other than ASCII letters, digits and `_`, and a digit at the start, is written as
+// `$` and two lowercase hexadecimal digits; the length counts the written characters.
+//
+//     start                  _B5start
+//     main in section app    _BM3app4main
+//     outer.inner.2          _B5outer5innerD2_
+//     max<Int64, &Point>     _B3maxI5Int648$26PointE
+
+pub const SYMBOL_PREFIX: &str = "_B";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Demangled {
+    // the BCPL section declaring the routine, if the symbol names it
+    pub module: Option<String>,
+    pub routine: String
+}
+
+impl fmt::Display for Demangled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.module {
+            Some(module) => write!(f, "{} [{module}]", self.routine),
+            None => write!(f, "{}", self.routine)
+        }
+    }
+}
+
+// the symbol of the routine `ident`, declared in the section `module`
+pub fn mangle(ident: &str, module: Option<&str>) -> String {
+    let mut symbol = SYMBOL_PREFIX.to_string();
+    if let Some(module) = module {
+        symbol.push('M');
+        write_name(&mut symbol, module);
+    }
+
+    for (i, component) in split_top_level(ident, ".").into_iter().enumerate() {
+        if i > 0 && !component.is_empty() && component.bytes().all(|byte| byte.is_ascii_digit()) {
+            write!(symbol, "D{component}_").unwrap();
+            continue
+        }
+
+        let generic = component.split_once('<')
+            .and_then(|(name, args)| Some((name, args.strip_suffix('>')?)))
+            .filter(|(_, args)| depths_balanced(args));
+        match generic {
+            Some((name, args)) => {
+                write_name(&mut symbol, name);
+                symbol.push('I');
+                split_top_level(args, ", ").into_iter().for_each(|arg| write_name(&mut symbol, arg));
+                symbol.push('E');
+            }
+            None => write_name(&mut symbol, component)
+        }
+    }
+    symbol
+}
+
+// the routine and section named by `symbol`, `None` if it is not a mangled routine symbol
+pub fn demangle(symbol: &str) -> Option<Demangled> {
+    match Demangler::new(symbol).symbol()? {
+        (demangled, len) if len == symbol.len() => Some(demangled),
+        _ => None
+    }
+}
+
+// `line` with every mangled symbol in it replaced by its demangled form, like `c++filt`
+pub fn demangle_line(line: &str) -> String {
+    let mut out = String::new();
+    let mut rest = line;
+    while let Some(start) = rest.find(SYMBOL_PREFIX) {
+        let (before, candidate) = rest.split_at(start);
+        out.push_str(before);
+
+        let at_boundary = !before.ends_with(is_symbol_char);
+        match Demangler::new(candidate).symbol() {
+            Some((demangled, len)) if at_boundary && !candidate[len..].starts_with(is_symbol_char) => {
+                write!(out, "{demangled}").unwrap();
+                rest = &candidate[len..];
+            }
+            _ => {
+                out.push_str(SYMBOL_PREFIX);
+                rest = &candidate[SYMBOL_PREFIX.len()..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+fn is_symbol_char(ch: char) -> bool {
+    ch.is_ascii_alphanumeric() || ch == '_' || ch == '$'
+}
+
+fn write_name(out: &mut String, name: &str) {
+    let mut encoded = String::new();
+    for (i, byte) in name.bytes().enumerate() {
+        if byte.is_ascii_alphabetic() || byte == b'_' || (byte.is_ascii_digit() && i > 0) {
+            encoded.push(byte as char);
+        }
+        else {
+            write!(encoded, "${byte:02x}").unwrap();
+        }
+    }
+    write!(out, "{}{encoded}", encoded.len()).unwrap();
+}
+
+// splits `text` at each `separator` outside of brackets, as in type names
+fn split_top_level<'a>(text: &'a str, separator: &str) -> Vec<&'a str> {
+    let mut parts = vec![];
+    let (mut depth, mut start) = (0i32, 0);
+    for (i, ch) in text.char_indices() {
+        match ch {
+            '<' | '(' | '[' | '{' => depth += 1,
+            '>' | ')' | ']' | '}' => depth -= 1,
+            _ if depth == 0 && text[i..].starts_with(separator) => {
+                parts.push(&text[start..i]);
+                start = i + separator.len();
+            }
+            _ => ()
+        }
+    }
+    parts.push(&text[start..]);
+    parts
+}
+
+// whether the brackets in `text` never close more than were opened and all are closed at
+// the end, so `name<text>` is one list of type arguments
+fn depths_balanced(text: &str) -> bool {
+    let mut depth = 0i32;
+    for ch in text.chars() {
+        match ch {
+            '<' | '(' | '[' | '{' => depth += 1,
+            '>' | ')' | ']' | '}' => depth -= 1,
+            _ => ()
+        }
+        if depth < 0 {
+            return false
+        }
+    }
+    depth == 0
+}
+
+struct Demangler<'a> {
+    text: &'a [u8],
+    pos: usize
+}
+
+impl<'a> Demangler<'a> {
+    fn new(text: &'a str) -> Self {
+        Self { text: text.as_bytes(), pos: 0 }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        let found = self.peek() == Some(byte);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    // the longest mangled symbol at the start of the text and its length
+    fn symbol(&mut self) -> Option<(Demangled, usize)> {
+        if !self.text.starts_with(SYMBOL_PREFIX.as_bytes()) {
+            return None
+        }
+        self.pos = SYMBOL_PREFIX.len();
+
+        let module = if self.eat(b'M') { Some(self.name()?) } else { None };
+        let mut routine = self.part()?;
+        // text following a symbol may start with a digit; stop at the last complete part
+        loop {
+            let start = self.pos;
+            match self.part() {
+                Some(part) if self.pos > start => {
+                    routine.push('.');
+                    routine.push_str(&part);
+                }
+                _ => {
+                    self.pos = start;
+                    break
+                }
+            }
+        }
+        Some((Demangled { module, routine }, self.pos))
+    }
+
+    fn part(&mut self) -> Option<String> {
+        let mut part = self.name()?;
+        if self.eat(b'I') {
+            let mut args = vec![];
+            while !self.eat(b'E') {
+                args.push(self.name()?);
+            }
+            if args.is_empty() {
+                return None
+            }
+            write!(part, "<{}>", args.join(", ")).unwrap();
+        }
+        while self.eat(b'D') {
+            let discriminator = self.number()?;
+            if !self.eat(b'_') {
+                return None
+            }
+            write!(part, ".{discriminator}").unwrap();
+        }
+        Some(part)
+    }
+
+    fn number(&mut self) -> Option<usize> {
+        let start = self.pos;
+        while self.peek().is_some_and(|byte| byte.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        std::str::from_utf8(&self.text[start..self.pos]).ok()?.parse().ok()
+    }
+
+    fn name(&mut self) -> Option<String> {
+        let len = self.number()?;
+        let encoded = self.text.get(self.pos..self.pos.checked_add(len)?)?;
+        self.pos += len;
+
+        let mut bytes = vec![];
+        let mut i = 0;
+        while i < encoded.len() {
+            match encoded[i] {
+                b'$' => {
+                    let hex = std::str::from_utf8(encoded.get(i + 1..i + 3)?).ok()?;
+                    bytes.push(u8::from_str_radix(hex, 16).ok()?);
+                    i += 3;
+                }
+                byte if byte.is_ascii_alphanumeric() || byte == b'_' => {
+                    bytes.push(byte);
+                    i += 1;
+                }
+                _ => return None
+            }
+        }
+        String::from_utf8(bytes).ok()
+    }
+}
@@ -0,0 +1,119 @@
+use std::{alloc::{GlobalAlloc, Layout, System}, sync::atomic::{AtomicUsize, Ordering}};
+
+// The system allocator, counting what passes through it for `-Zmemory-stats`. The driver
+// installs it; in other programs using the library every count stays zero.
+pub struct CountingAllocator;
+
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+fn grow(size: usize) {
+    ALLOCATED.fetch_add(size, Ordering::Relaxed);
+    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    let live = LIVE.fetch_add(size, Ordering::Relaxed) + size;
+    PEAK.fetch_max(live, Ordering::Relaxed);
+}
+
+fn shrink(size: usize) {
+    LIVE.fetch_sub(size, Ordering::Relaxed);
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc(layout) };
+        if !ptr.is_null() {
+            grow(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc_zeroed(layout) };
+        if !ptr.is_null() {
+            grow(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) };
+        shrink(layout.size());
+    }
+
+    // counted as freeing the old block and allocating the new one
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = unsafe { System.realloc(ptr, layout, new_size) };
+        if !new_ptr.is_null() {
+            shrink(layout.size());
+            grow(new_size);
+        }
+        new_ptr
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct Snapshot {
+    pub live: usize,
+    // highest `live` since the last `reset_peak`
+    pub peak: usize,
+    pub allocated: usize,
+    pub allocations: usize
+}
+
+pub fn snapshot() -> Snapshot {
+    Snapshot {
+        live: LIVE.load(Ordering::Relaxed),
+        peak: PEAK.load(Ordering::Relaxed),
+        allocated: ALLOCATED.load(Ordering::Relaxed),
+        allocations: ALLOCATIONS.load(Ordering::Relaxed)
+    }
+}
+
+pub fn reset_peak() {
+    PEAK.store(LIVE.load(Ordering::Relaxed), Ordering::Relaxed);
+}
+
+// whether `CountingAllocator` is installed; every program allocates before `main`
+pub fn is_counting() -> bool {
+    ALLOCATIONS.load(Ordering::Relaxed) > 0
+}
+
+// what a phase allocated, and how much was in use during and after it
+#[derive(Clone, Copy)]
+pub struct PhaseMemory {
+    pub allocated: usize,
+    pub allocations: usize,
+    pub peak: usize,
+    pub live: usize
+}
+
+impl PhaseMemory {
+    // since `start`, resetting the peak for the next phase
+    pub fn since(start: &Snapshot) -> (Self, Snapshot) {
+        let now = snapshot();
+        reset_peak();
+        let phase = Self {
+            allocated: now.allocated - start.allocated,
+            allocations: now.allocations - start.allocations,
+            peak: now.peak,
+            live: now.live
+        };
+        (phase, now)
+    }
+}
+
+pub fn format_bytes(bytes: usize) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{bytes} B"),
+        _ => format!("{value:.1} {}", UNITS[unit])
+    }
+}
@@ -0,0 +1,290 @@
+use crate::{
+    ast::{
+        Program,
+        interface::{SectionInterface, RoutineInterface, GlobalInterface},
+        types::{TypeList, TypeIndex, TypeKind, Type, SumVariant},
+        expr::{Expr, ExprKind}
+    },
+    source_file::Location
+};
+
+// Metadata files describe the public interface of compiled sections, one declaration per line:
+//
+//   bcplpp-metadata 1
+//   fingerprint bcplpp-0.1.0 8c1e0f5a2b9d4e71
+//   section Main
+//   type OptionI32 Some(Int32):None
+//   type Point struct(x=Int32,y=Int32)
+//   type Color enum(Color,Red,Green,Blue)
+//   routine fact (Int32) Int32
+//   global counter 150 Int32
+//   manifest size Int32 16
+//
+// Types are written without whitespace, unknown types and values as `?`. Structs declared
+// `[[repr("C")]]` are written as `cstruct(...)`, and variadic routines end their parameters with `...`.
+
+pub const METADATA_EXT: &str = ".bmeta";
+const METADATA_HEADER: &str = "bcplpp-metadata 1";
+
+pub fn write_metadata(path: &str, ast: &Program, stamp: &str) -> Result<(), String> {
+    let types = ast.types();
+    let mut out = vec![METADATA_HEADER.to_string(), stamp.to_string()];
+
+    let mut sections = ast.sections().collect::<Vec<_>>();
+    sections.sort_by(|a, b| a.ident().cmp(b.ident()));
+
+    // aliases are shared by all sections of a compilation unit
+    let aliases = types.iter()
+        .filter_map(|typ| match typ.kind() {
+            TypeKind::Alias(ident, Some(inner)) => Some(format!("type {ident} {}", write_type(types, *inner, true))),
+            _ => None
+        })
+        .collect::<Vec<_>>();
+
+    for section in sections {
+        let interface = SectionInterface::of(section);
+        out.push(format!("section {}", interface.ident()));
+        out.extend(aliases.iter().cloned());
+
+        for routine in interface.routines() {
+            out.push(format!(
+                "routine {} ({}) {}",
+                routine.ident,
+                routine.params.iter().map(|param| write_opt_type(types, *param))
+                    .chain(routine.variadic.then(|| "...".to_string()))
+                    .collect::<Vec<_>>().join(","),
+                write_opt_type(types, routine.return_type)
+            ));
+        }
+
+        for global in interface.globals() {
+            out.push(format!(
+                "global {} {} {}",
+                global.ident,
+                global.slot.map_or("?".into(), |slot| slot.to_string()),
+                write_opt_type(types, global.typ)
+            ));
+        }
+
+        for (ident, typ, value) in interface.manifests() {
+            out.push(format!("manifest {ident} {} {}", write_opt_type(types, *typ), value.map_or("?".into(), |value| value.to_string())));
+        }
+    }
+
+    out.push(String::new());
+    std::fs::write(path, out.join("\n"))
+        .map_err(|err| format!("could not write `{path}`: {err}"))
+}
+
+pub fn read_metadata(path: &str, ast: &mut Program) -> Result<(), String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|err| format!("could not read `{path}`: {err}"))?;
+    parse_metadata(path, &contents, ast)
+}
+
+pub fn parse_metadata(path: &str, contents: &str, ast: &mut Program) -> Result<(), String> {
+    let mut lines = contents.lines().enumerate();
+    if lines.next().map(|(_, header)| header) != Some(METADATA_HEADER) {
+        return Err(format!("`{path}` is not a metadata file."))
+    }
+
+    let mut interface: Option<SectionInterface> = None;
+    for (i, line) in lines {
+        let err = || format!("{path}:{}: malformed metadata", i + 1);
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+
+        match fields.as_slice() {
+            [] | ["fingerprint", ..] => (),
+            ["section", ident] => {
+                if let Some(interface) = interface.replace(SectionInterface::new(ident.to_string(), Some(path.to_string()))) {
+                    ast.import(interface);
+                }
+            }
+            ["type", ident, typ] => {
+                let inner = read_type(ast.types_mut(), typ).ok_or_else(err)?;
+                let types = ast.types_mut();
+                match types.find_alias(ident) {
+                    Some(alias) => types.get_mut(alias).unwrap().set_kind(TypeKind::Alias(ident.to_string(), Some(inner))),
+                    None => { types.define(Type::new(None, TypeKind::Alias(ident.to_string(), Some(inner)))); }
+                }
+            }
+            ["routine", ident, params, return_type] => {
+                let params = params.strip_prefix('(').and_then(|params| params.strip_suffix(')')).ok_or_else(err)?;
+                let mut params = split_top_level(params, ',');
+                let variadic = params.last() == Some(&"...");
+                if variadic {
+                    params.pop();
+                }
+                let params = params.into_iter()
+                    .filter(|param| !param.is_empty())
+                    .map(|param| read_opt_type(ast.types_mut(), param))
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(err)?;
+                let return_type = read_opt_type(ast.types_mut(), return_type).ok_or_else(err)?;
+                interface.as_mut().ok_or_else(err)?.add_routine(RoutineInterface { ident: ident.to_string(), params, variadic, return_type });
+            }
+            ["global", ident, slot, typ] => {
+                let slot = if *slot == "?" { None } else { Some(slot.parse().map_err(|_| err())?) };
+                let typ = read_opt_type(ast.types_mut(), typ).ok_or_else(err)?;
+                interface.as_mut().ok_or_else(err)?.add_global(GlobalInterface { ident: ident.to_string(), slot, typ });
+            }
+            ["manifest", ident, typ, value] => {
+                let typ = read_opt_type(ast.types_mut(), typ).ok_or_else(err)?;
+                let value = if *value == "?" { None } else { Some(value.parse().map_err(|_| err())?) };
+                interface.as_mut().ok_or_else(err)?.add_manifest(ident.to_string(), typ, value);
+            }
+            _ => return Err(err())
+        }
+    }
+
+    if let Some(interface) = interface {
+        ast.import(interface);
+    }
+    Ok(())
+}
+
+fn write_opt_type(types: &TypeList, typ: Option<TypeIndex>) -> String {
+    typ.map_or("?".into(), |typ| write_type(types, typ, false))
+}
+
+// `expand_alias` writes the definition of an alias instead of its name
+fn write_type(types: &TypeList, index: TypeIndex, expand_alias: bool) -> String {
+    let Some(typ) = types.get(index) else {
+        return "?".into()
+    };
+
+    match typ.kind() {
+        TypeKind::Pointer(inner) => format!("&{}", write_type(types, *inner, false)),
+        TypeKind::Slice(inner) => format!("[{}]", write_type(types, *inner, false)),
+        TypeKind::Array(inner, len) => match len.const_int() {
+            Some(len) => format!("[{},{len}]", write_type(types, *inner, false)),
+            None => "?".into()
+        },
+        TypeKind::Alias(_, Some(inner)) if expand_alias => write_type(types, *inner, false),
+        TypeKind::Alias(ident, _) => ident.clone(),
+        TypeKind::Sum(variants) => variants.iter()
+            .map(|SumVariant::Basic(ident, fields)| if fields.is_empty() {
+                ident.clone()
+            }
+            else {
+                format!("{ident}({})", fields.iter().map(|field| write_type(types, *field, false)).collect::<Vec<_>>().join(","))
+            })
+            .collect::<Vec<_>>()
+            .join(":"),
+        TypeKind::Struct(fields) => format!("{}({})", if typ.is_c_layout() { "cstruct" } else { "struct" }, fields.iter()
+            .map(|(ident, typ)| format!("{ident}={}", write_type(types, *typ, false)))
+            .collect::<Vec<_>>()
+            .join(",")
+        ),
+        TypeKind::Enum(ident, constants) => format!("enum({ident},{})", constants.join(",")),
+        // the result first, so that routines without parameters need no special case
+        TypeKind::Routine(params, result) => format!("let({})", std::iter::once(result).chain(params)
+            .map(|typ| write_type(types, *typ, false))
+            .collect::<Vec<_>>()
+            .join(",")
+        ),
+        kind => format!("{kind:?}")
+    }
+}
+
+fn read_opt_type(types: &mut TypeList, src: &str) -> Option<Option<TypeIndex>> {
+    if src == "?" {
+        Some(None)
+    }
+    else {
+        read_type(types, src).map(Some)
+    }
+}
+
+fn read_type(types: &mut TypeList, src: &str) -> Option<TypeIndex> {
+    let c_layout = src.starts_with("cstruct(");
+    if let Some(fields) = src.strip_prefix("struct(").or_else(|| src.strip_prefix("cstruct(")).and_then(|fields| fields.strip_suffix(')')) {
+        let fields = split_top_level(fields, ',').into_iter()
+            .filter(|field| !field.is_empty())
+            .map(|field| {
+                let (ident, typ) = field.split_once('=')?;
+                Some((ident.to_string(), read_type(types, typ)?))
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let typ = types.define(Type::new(None, TypeKind::Struct(fields)));
+        if c_layout {
+            types.get_mut(typ).unwrap().set_c_layout();
+        }
+        return Some(typ)
+    }
+
+    if let Some(constants) = src.strip_prefix("enum(").and_then(|constants| constants.strip_suffix(')')) {
+        let mut constants = constants.split(',').map(String::from);
+        let ident = constants.next()?;
+        return Some(types.define(Type::new(None, TypeKind::Enum(ident, constants.filter(|constant| !constant.is_empty()).collect()))))
+    }
+
+    // before sums, which `let(...)` would look like otherwise
+    if let Some(types_src) = src.strip_prefix("let(").and_then(|types_src| types_src.strip_suffix(')')) {
+        let mut signature = split_top_level(types_src, ',').into_iter()
+            .map(|typ| read_type(types, typ))
+            .collect::<Option<Vec<_>>>()?;
+        let result = signature.remove(0);
+        return Some(types.intern(TypeKind::Routine(signature, result)))
+    }
+
+    let variants = split_top_level(src, ':');
+    if variants.len() > 1 || src.ends_with(')') {
+        let variants = variants.into_iter()
+            .map(|variant| match variant.split_once('(') {
+                Some((ident, fields)) => split_top_level(fields.strip_suffix(')')?, ',').into_iter()
+                    .map(|field| read_type(types, field))
+                    .collect::<Option<Vec<_>>>()
+                    .map(|fields| SumVariant::Basic(ident.to_string(), fields)),
+                None => Some(SumVariant::Basic(variant.to_string(), vec![]))
+            })
+            .collect::<Option<Vec<_>>>()?;
+        return Some(types.intern(TypeKind::Sum(variants)))
+    }
+
+    if let Some(inner) = src.strip_prefix('&') {
+        let inner = read_type(types, inner)?;
+        return Some(types.intern(TypeKind::Pointer(inner)))
+    }
+
+    if let Some(inner) = src.strip_prefix('[').and_then(|inner| inner.strip_suffix(']')) {
+        return match split_top_level(inner, ',').as_slice() {
+            [inner] => {
+                let inner = read_type(types, inner)?;
+                Some(types.intern(TypeKind::Slice(inner)))
+            }
+            [inner, len] => {
+                let inner = read_type(types, inner)?;
+                let len = Expr::new(Location::default(), types.builtin_by_ident("UInt64"), ExprKind::IntLit(len.parse().ok()?));
+                Some(types.intern(TypeKind::Array(inner, Box::new(len))))
+            }
+            _ => None
+        }
+    }
+
+    types.builtin_by_ident(src)
+        .or_else(|| types.find_alias(src))
+        .or_else(|| Some(types.define(Type::new(None, TypeKind::Alias(src.to_string(), None)))))
+}
+
+// splits `src` at `sep`, ignoring separators nested in brackets or parentheses
+fn split_top_level(src: &str, sep: char) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut depth = 0;
+    let mut start = 0;
+
+    for (i, ch) in src.char_indices() {
+        match ch {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth -= 1,
+            _ if ch == sep && depth == 0 => {
+                parts.push(&src[start..i]);
+                start = i + 1;
+            }
+            _ => ()
+        }
+    }
+
+    parts.push(&src[start..]);
+    parts
+}
@@ -0,0 +1,154 @@
+use crate::{
+    match_decl,
+    ast::{Program, Function, FunctionBody, expr::{Expr, ExprKind}, stmt::{Stmt, StmtKind}},
+    source_file::{Located, WithLocation},
+    error::{IntoCompilerError, CompilerError, Severity},
+    lint::{Lint, LintConfig, LintLevel}
+};
+
+#[derive(Clone, Debug)]
+pub enum MetricsWarning {
+    //         routine statements limit
+    Statements(String, usize, usize),
+    //      routine depth  limit
+    Nesting(String, usize, usize),
+    //         routine complexity limit
+    Complexity(String, usize, usize)
+}
+
+impl MetricsWarning {
+    fn severity(&self) -> Severity {
+        Severity::Warning
+    }
+
+    pub fn lint(&self) -> Option<Lint> {
+        match self {
+            Self::Statements(..) => Some(Lint::LongRoutine),
+            Self::Nesting(..) => Some(Lint::DeepNesting),
+            Self::Complexity(..) => Some(Lint::ComplexRoutine)
+        }
+    }
+
+    fn hint(&self) -> Option<String> {
+        match self {
+            Self::Nesting(..) => Some("Move inner blocks into routines of their own, or leave early with `return` or `break`.".into()),
+            Self::Statements(..) | Self::Complexity(..) => Some("Split it into smaller routines.".into())
+        }
+    }
+}
+
+impl WithLocation for MetricsWarning {}
+
+impl ToString for MetricsWarning {
+    fn to_string(&self) -> String {
+        match self {
+            Self::Statements(routine, count, limit) => format!("Routine `{routine}` has {count} statements, more than the limit of {limit}."),
+            Self::Nesting(routine, depth, limit) => format!("Routine `{routine}` nests statements {depth} levels deep, more than the limit of {limit}."),
+            Self::Complexity(routine, complexity, limit) => format!("Routine `{routine}` has a cyclomatic complexity of {complexity}, more than the limit of {limit}.")
+        }
+    }
+}
+
+impl IntoCompilerError for MetricsWarning {}
+impl Into<CompilerError> for MetricsWarning {
+    fn into(self) -> CompilerError {
+        CompilerError::new(self.severity(), self.to_string(), self.hint(), vec![])
+    }
+}
+
+#[derive(Default)]
+struct Metrics {
+    // statements other than blocks and empty statements, including those in `valof` blocks
+    statements: usize,
+    // the deepest nesting of conditionals, loops, `switchon`, `match` and `every`
+    nesting: usize,
+    // one more than the number of decisions: conditions, case labels, pattern branches and `&`/`|`
+    complexity: usize
+}
+
+// Reports routines exceeding the limits of the `long-routine`, `deep-nesting` and
+// `complex-routine` lints. All three are allowed by default; they are meant for
+// gradually cleaning up large legacy programs.
+pub fn check_metrics(ast: &Program, lints: &LintConfig) -> Vec<Located<MetricsWarning>> {
+    if [Lint::LongRoutine, Lint::DeepNesting, Lint::ComplexRoutine].iter().all(|lint| lints.level(*lint) == LintLevel::Allow) {
+        return vec![]
+    }
+
+    let mut warnings = vec![];
+    for decl in ast.sections().flat_map(|section| section.declarations()) {
+        match_decl!{
+            decl;
+            func as Function => {
+                let metrics = routine_metrics(func);
+                let (ident, loc) = (decl.ident(), decl.location());
+                if metrics.statements > lints.max_statements() {
+                    warnings.push(MetricsWarning::Statements(ident.clone(), metrics.statements, lints.max_statements()).with_location(loc.clone()));
+                }
+                if metrics.nesting > lints.max_nesting() {
+                    warnings.push(MetricsWarning::Nesting(ident.clone(), metrics.nesting, lints.max_nesting()).with_location(loc.clone()));
+                }
+                if metrics.complexity > lints.max_complexity() {
+                    warnings.push(MetricsWarning::Complexity(ident.clone(), metrics.complexity, lints.max_complexity()).with_location(loc.clone()));
+                }
+            },
+            _ => ()
+        }
+    }
+
+    warnings.sort_by(|a, b| a.location().cmp(b.location()));
+    warnings
+}
+
+fn routine_metrics(func: &Function) -> Metrics {
+    let mut metrics = Metrics { complexity: 1, ..Default::default() };
+    match func.body() {
+        FunctionBody::Expr(expr) => expr_metrics(expr, 0, &mut metrics),
+        FunctionBody::Stmt(stmt) => stmt_metrics(stmt, 0, &mut metrics),
+        FunctionBody::PatternMatchedExpr(branches) => {
+            metrics.complexity += branches.len();
+            branches.iter().for_each(|(_, expr)| expr_metrics(expr, 0, &mut metrics));
+        }
+        FunctionBody::PatternMatchedStmt(branches) => {
+            metrics.complexity += branches.len();
+            branches.iter().for_each(|(_, stmt)| stmt_metrics(stmt, 0, &mut metrics));
+        }
+    }
+    metrics
+}
+
+fn expr_metrics(expr: &Expr, depth: usize, metrics: &mut Metrics) {
+    match expr.kind() {
+        ExprKind::LogAnd(..) | ExprKind::LogOr(..) | ExprKind::Conditional(..) => metrics.complexity += 1,
+        ExprKind::Match(_, branches) | ExprKind::Every(_, branches) => metrics.complexity += branches.len(),
+        ExprKind::ValOf(body) => stmt_metrics(body, depth, metrics),
+        _ => ()
+    }
+
+    expr.kind().children().into_iter().for_each(|child| expr_metrics(child, depth, metrics));
+}
+
+fn stmt_metrics(stmt: &Stmt, depth: usize, metrics: &mut Metrics) {
+    let kind = stmt.kind();
+    if !matches!(kind, StmtKind::Block(_) | StmtKind::Nop) {
+        metrics.statements += 1;
+    }
+
+    match kind {
+        StmtKind::If(..) | StmtKind::Unless(..) | StmtKind::While(..) | StmtKind::Until(..)
+            | StmtKind::RepeatWhile(..) | StmtKind::RepeatUntil(..) | StmtKind::For(..) | StmtKind::Try(..) => metrics.complexity += 1,
+        StmtKind::Case(values) => metrics.complexity += values.len(),
+        StmtKind::Match(_, branches) | StmtKind::Every(_, branches) => metrics.complexity += branches.len(),
+        _ => ()
+    }
+
+    let inner_depth = match kind {
+        StmtKind::If(..) | StmtKind::Unless(..) | StmtKind::While(..) | StmtKind::Until(..)
+            | StmtKind::Repeat(_) | StmtKind::RepeatWhile(..) | StmtKind::RepeatUntil(..) | StmtKind::For(..)
+            | StmtKind::SwitchOn(..) | StmtKind::Match(..) | StmtKind::Every(..) | StmtKind::Try(..) => depth + 1,
+        _ => depth
+    };
+    metrics.nesting = metrics.nesting.max(inner_depth);
+
+    kind.exprs().into_iter().for_each(|expr| expr_metrics(expr, depth, metrics));
+    kind.stmts().into_iter().for_each(|stmt| stmt_metrics(stmt, inner_depth, metrics));
+}
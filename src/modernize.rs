@@ -0,0 +1,654 @@
+use std::{collections::{HashMap, HashSet}, ops::Range};
+
+use crate::{
+    ast::{Program, Decl, Function, FunctionBody, expr::{Expr, ExprKind}, stmt::{Stmt, StmtKind}, types::{TypeList, TypeKind, TypeIndex}},
+    source_file::{SourceFile, SourceFileId, Location, Located, WithLocation},
+    error::{IntoCompilerError, CompilerError, Severity},
+    token::{Token, TokenKind, lexer::Lexer}
+};
+
+#[derive(Clone, Debug)]
+pub enum ModernizeWarning {
+    //         label   reason
+    Unconverted(String, &'static str),
+    ClassicNumber(String),
+    SwitchOnBreak
+}
+
+impl ModernizeWarning {
+    fn severity(&self) -> Severity {
+        Severity::Warning
+    }
+
+    fn hint(&self) -> Option<String> {
+        match self {
+            Self::Unconverted(..) => Some("Restructure it by hand, or leave it; `goto` keeps working as it is.".into()),
+            Self::ClassicNumber(atom) => classic_number(atom).map(|(prefix, digits)| format!("Write `{prefix}{digits}` for the number.")),
+            Self::SwitchOnBreak => Some("Leave the loop after the `switchon`, or with `goto`; `ENDCASE` is what became `break`.".into())
+        }
+    }
+}
+
+impl WithLocation for ModernizeWarning {}
+
+impl ToString for ModernizeWarning {
+    fn to_string(&self) -> String {
+        match self {
+            Self::Unconverted(label, reason) => format!("`goto {label}` was not converted: {reason}."),
+            Self::ClassicNumber(digits) => format!("`#{digits}` is an atom now, not a number as in classic BCPL."),
+            Self::SwitchOnBreak => "`break` leaves the `switchon` now, not the loop around it as in classic BCPL.".into()
+        }
+    }
+}
+
+impl IntoCompilerError for ModernizeWarning {}
+impl Into<CompilerError> for ModernizeWarning {
+    fn into(self) -> CompilerError {
+        CompilerError::new(self.severity(), self.to_string(), self.hint(), vec![])
+    }
+}
+
+pub struct Modernized {
+    // the new contents of the files with changes
+    pub files: Vec<(SourceFileId, String)>,
+    pub brackets: usize,
+    pub keywords: usize,
+    pub annotations: usize,
+    pub gotos: usize,
+    pub warnings: Vec<Located<ModernizeWarning>>
+}
+
+type Edit = (Range<usize>, String);
+
+// what `respell` changed in a file
+pub struct Respelled {
+    original: String,
+    brackets: usize,
+    keywords: usize,
+    // offsets of the `break`s written as `ENDCASE`, which left the `switchon` already
+    endcases: HashSet<usize>
+}
+
+// the keywords of classic BCPL, with their spelling in this dialect
+const CLASSIC_KEYWORDS: &[(&str, &str)] = &[
+    ("LET", "let"), ("AND", "and"), ("BE", "be"), ("VALOF", "valof"), ("RESULTIS", "resultis"),
+    ("RETURN", "return"), ("IF", "if"), ("UNLESS", "unless"), ("TEST", "if"),
+    ("THEN", "do"), ("DO", "do"), ("OR", "else"), ("ELSE", "else"), ("WHILE", "while"),
+    ("UNTIL", "until"), ("REPEAT", "repeat"), ("REPEATWHILE", "repeatwhile"),
+    ("REPEATUNTIL", "repeatuntil"), ("FOR", "for"), ("TO", "to"), ("BY", "by"), ("BREAK", "break"),
+    ("LOOP", "loop"), ("ENDCASE", "break"), ("GOTO", "goto"), ("SWITCHON", "switchon"),
+    ("INTO", "into"), ("CASE", "case"), ("DEFAULT", "default"), ("GLOBAL", "global"),
+    ("MANIFEST", "manifest"), ("STATIC", "static"), ("GET", "get"), ("SECTION", "section"),
+    ("NEEDS", "needs"),
+    ("VEC", "vec"), ("ABS", "abs"), ("TRUE", "true"), ("FALSE", "false"), ("SLCT", "slct"),
+    ("OF", "of"), ("REM", "mod"), ("MOD", "mod"), ("EQ", "="), ("NE", "~="), ("LS", "<"),
+    ("GR", ">"), ("LE", "<="), ("GE", ">="), ("NOT", "~"), ("LOGAND", "&"), ("LOGOR", "|"),
+    ("NEQV", "^"), ("LSHIFT", "<<"), ("RSHIFT", ">>")
+];
+
+// Rewrites the spellings of classic BCPL the parser does not accept: `$( $)` become braces, with
+// their tags dropped, and uppercase keywords and operator words become those of this dialect.
+// This runs on tokens before parsing, so that classic sources can be parsed for the rewrites of
+// `modernize` at all.
+pub fn respell(file: &SourceFile, tags: &[String]) -> (String, Respelled) {
+    let original = file.contents().to_string();
+    let source = SourceText::new(file, tags);
+    let mut contents = String::with_capacity(original.len());
+    let mut respelled = Respelled { original: String::new(), brackets: 0, keywords: 0, endcases: HashSet::new() };
+
+    let mut copied = 0;
+    for (i, token) in source.tokens.iter().enumerate() {
+        let span = source.start(i)..source.end(i);
+        let replacement = match token.kind() {
+            TokenKind::LBrace | TokenKind::RBrace if original[span.clone()].starts_with('$') => {
+                respelled.brackets += 1;
+                if token.kind() == &TokenKind::LBrace { "{" } else { "}" }
+            }
+            TokenKind::Ident(word) => match CLASSIC_KEYWORDS.iter().find(|(classic, _)| classic == word) {
+                Some((classic, modern)) => {
+                    respelled.keywords += 1;
+                    if *classic == "ENDCASE" {
+                        respelled.endcases.insert(contents.len() + span.start - copied);
+                    }
+                    *modern
+                }
+                None => continue
+            },
+            _ => continue
+        };
+        contents.push_str(&original[copied..span.start]);
+        contents.push_str(replacement);
+        copied = span.end;
+    }
+    contents.push_str(&original[copied..]);
+
+    respelled.original = original;
+    (contents, respelled)
+}
+
+// Rewrites a type checked program in this dialect, for `bcplpp modernize`:
+//  - routines defined with `=` get the result type inferred for them written out,
+//  - simple `goto`s become loops and conditionals, see `modernize_routine`,
+//  - and constructs which mean something else than in classic BCPL are reported.
+// The sources are edited in place of reprinting the ast, so comments and layout are kept.
+// `respelled` holds what `respell` changed before, to report changes of both steps together.
+pub fn modernize(ast: &Program, source_files: &HashMap<SourceFileId, SourceFile>, skip: &HashSet<SourceFileId>, respelled: &HashMap<SourceFileId, Respelled>, tags: &[String]) -> Modernized {
+    let mut edits: HashMap<SourceFileId, Vec<Edit>> = HashMap::new();
+    let mut texts = HashMap::new();
+    let mut annotations = 0;
+    let mut gotos = 0;
+    let mut warnings = vec![];
+
+    let mut routines = ast.sections()
+        .flat_map(|section| section.declarations())
+        .filter_map(|decl| decl.as_any().downcast_ref::<Function>())
+        .filter(|func| !skip.contains(&func.location().file_id()))
+        .collect::<Vec<_>>();
+    routines.sort_by_key(|func| func.location().clone());
+
+    for func in routines {
+        let file_id = func.location().file_id();
+        let Some(file) = source_files.get(&file_id) else {
+            continue
+        };
+        let source = texts.entry(file_id).or_insert_with(|| SourceText::new(file, tags));
+        let file_edits = edits.entry(file_id).or_default();
+
+        if let Some(edit) = result_annotation(func, ast, source) {
+            file_edits.push(edit);
+            annotations += 1;
+        }
+        let endcases = respelled.get(&file_id).map(|respelled| &respelled.endcases);
+        warnings.extend(switchon_breaks(func, source, endcases));
+
+        let result = modernize_routine(func, source, file_edits);
+        gotos += result.0;
+        warnings.extend(result.1);
+    }
+
+    for (id, file) in source_files {
+        if !skip.contains(id) {
+            let source = texts.entry(*id).or_insert_with(|| SourceText::new(file, tags));
+            warnings.extend(classic_numbers(source));
+        }
+    }
+
+    let mut files = source_files.iter()
+        .filter(|(id, _)| !skip.contains(id))
+        .filter_map(|(id, file)| {
+            let mut contents = file.contents().to_string();
+            let mut edits = edits.remove(id).unwrap_or_default();
+            edits.sort_by_key(|(range, _)| std::cmp::Reverse(range.start));
+            for (range, replacement) in edits {
+                contents.replace_range(range, &replacement);
+            }
+            let original = respelled.get(id).map_or(file.contents(), |respelled| respelled.original.as_str());
+            (contents != original).then_some((*id, contents))
+        })
+        .collect::<Vec<_>>();
+    files.sort_by_key(|(id, _)| *id);
+    warnings.sort_by(|a, b| a.location().cmp(b.location()));
+
+    Modernized {
+        files,
+        brackets: respelled.values().map(|respelled| respelled.brackets).sum(),
+        keywords: respelled.values().map(|respelled| respelled.keywords).sum(),
+        annotations,
+        gotos,
+        warnings
+    }
+}
+
+// ` :: T` after the parameters of `let f(...) = ...` without a result type, if the type of its
+// body is known and can be written
+fn result_annotation(func: &Function, ast: &Program, source: &SourceText) -> Option<Edit> {
+    if !matches!(func.body(), FunctionBody::Expr(_)) {
+        return None
+    }
+    let typ = (*func.return_type())?;
+    let typ = source_type(ast.types(), typ)?;
+
+    let start = source.token_at(func.location())?;
+    let mut i = start + source.tokens[start..].iter().position(|token| matches!(token.kind(), TokenKind::Ident(ident) if *ident == func.ident()))?;
+    if source.kind(i + 1) == Some(&TokenKind::LParen) {
+        let mut depth = 0;
+        loop {
+            i += 1;
+            match source.kind(i)? {
+                TokenKind::LParen => depth += 1,
+                TokenKind::RParen if depth == 1 => break,
+                TokenKind::RParen => depth -= 1,
+                _ => ()
+            }
+        }
+    }
+    // an explicit `:: T` follows otherwise
+    if source.kind(i + 1) != Some(&TokenKind::Eq) {
+        return None
+    }
+    let end = source.end(i);
+    Some((end..end, format!(" :: {typ}")))
+}
+
+// the spelling of a type in source, for those which have one
+fn source_type(types: &TypeList, typ: TypeIndex) -> Option<String> {
+    match types.get(typ)?.kind() {
+        TypeKind::Unit | TypeKind::Array(..) | TypeKind::Sum(_) | TypeKind::Struct(_) => None,
+        TypeKind::Pointer(inner) => Some(format!("&{}", source_type(types, *inner)?)),
+        TypeKind::Slice(inner) => Some(format!("[{}]", source_type(types, *inner)?)),
+        TypeKind::Routine(params, result) => {
+            let params = params.iter().map(|param| source_type(types, *param)).collect::<Option<Vec<_>>>()?.join(", ");
+            match types.get(*result)?.kind() {
+                TypeKind::Unit => Some(format!("let({params})")),
+                _ => Some(format!("let({params}) :: {}", source_type(types, *result)?))
+            }
+        }
+        _ => Some(types.type_name(typ))
+    }
+}
+
+// `break`s leaving a `switchon` inside a loop; in classic BCPL they left the loop
+fn switchon_breaks(func: &Function, source: &SourceText, endcases: Option<&HashSet<usize>>) -> Vec<Located<ModernizeWarning>> {
+    let mut breaks = vec![];
+    match func.body() {
+        FunctionBody::Expr(expr) => valof_switchon_breaks(expr, false, false, &mut breaks),
+        FunctionBody::Stmt(stmt) => switchon_breaks_in(stmt, false, false, &mut breaks),
+        FunctionBody::PatternMatchedExpr(branches) => branches.iter().for_each(|(_, expr)| valof_switchon_breaks(expr, false, false, &mut breaks)),
+        FunctionBody::PatternMatchedStmt(branches) => branches.iter().for_each(|(_, stmt)| switchon_breaks_in(stmt, false, false, &mut breaks))
+    }
+
+    breaks.into_iter()
+        .filter(|loc| !endcases.is_some_and(|endcases| endcases.contains(&source.offset(loc.line(), loc.column()))))
+        .map(|loc| ModernizeWarning::SwitchOnBreak.with_location(loc.clone()))
+        .collect()
+}
+
+// `in_switchon`: `break` leaves a `switchon`; `in_loop`: there is a loop around that
+fn switchon_breaks_in<'a>(stmt: &'a Stmt, in_switchon: bool, in_loop: bool, breaks: &mut Vec<&'a Location>) {
+    let (in_switchon, in_loop) = match stmt.kind() {
+        StmtKind::Break if in_switchon && in_loop => return breaks.push(stmt.location()),
+        StmtKind::While(..) | StmtKind::Until(..) | StmtKind::Repeat(_) | StmtKind::RepeatWhile(..)
+            | StmtKind::RepeatUntil(..) | StmtKind::For(..) => (false, true),
+        StmtKind::SwitchOn(..) => (true, in_loop),
+        StmtKind::Match(..) | StmtKind::Every(..) => (false, in_loop),
+        _ => (in_switchon, in_loop)
+    };
+    stmt.kind().stmts().into_iter().for_each(|inner| switchon_breaks_in(inner, in_switchon, in_loop, breaks));
+    stmt.kind().exprs().into_iter().for_each(|expr| valof_switchon_breaks(expr, in_switchon, in_loop, breaks));
+}
+
+fn valof_switchon_breaks<'a>(expr: &'a Expr, in_switchon: bool, in_loop: bool, breaks: &mut Vec<&'a Location>) {
+    if let ExprKind::ValOf(body) = expr.kind() {
+        switchon_breaks_in(body, in_switchon, in_loop, breaks);
+    }
+    expr.kind().children().into_iter().for_each(|child| valof_switchon_breaks(child, in_switchon, in_loop, breaks));
+}
+
+// `#777`, `#X1F`: octal and hexadecimal numbers in classic BCPL, atoms in this dialect
+fn classic_numbers(source: &SourceText) -> Vec<Located<ModernizeWarning>> {
+    source.tokens.iter()
+        .filter_map(|token| match token.kind() {
+            TokenKind::Atom(atom) if classic_number(atom).is_some() => Some(ModernizeWarning::ClassicNumber(atom.to_string()).with_location(token.location().clone())),
+            _ => None
+        })
+        .collect()
+}
+
+// the prefix of the number in this dialect and its digits
+fn classic_number(atom: &str) -> Option<(&'static str, &str)> {
+    let (prefix, radix, digits) = if let Some(hex) = atom.strip_prefix(['X', 'x']) {
+        ("0x", 16, hex)
+    }
+    else if let Some(binary) = atom.strip_prefix(['B', 'b']) {
+        ("0b", 2, binary)
+    }
+    else {
+        ("0o", 8, atom.strip_prefix(['O', 'o']).unwrap_or(atom))
+    };
+    (!digits.is_empty() && digits.chars().all(|ch| ch.is_digit(radix))).then_some((prefix, digits))
+}
+
+// the statements of a routine taking part in jumps
+#[derive(Default)]
+struct Jumps<'a> {
+    //          label                  `goto` statement
+    gotos: Vec<(&'a Located<String>, &'a Location)>,
+    labels: Vec<&'a Located<String>>,
+    // every block with its nesting depth
+    blocks: Vec<(usize, &'a [Stmt])>
+}
+
+impl<'a> Jumps<'a> {
+    fn of(func: &'a Function) -> Self {
+        let mut jumps = Self::default();
+        match func.body() {
+            FunctionBody::Expr(expr) => jumps.add_expr(expr, 0),
+            FunctionBody::Stmt(stmt) => jumps.add_stmt(stmt, 0),
+            FunctionBody::PatternMatchedExpr(branches) => branches.iter().for_each(|(_, expr)| jumps.add_expr(expr, 0)),
+            FunctionBody::PatternMatchedStmt(branches) => branches.iter().for_each(|(_, stmt)| jumps.add_stmt(stmt, 0))
+        }
+        jumps
+    }
+
+    fn add_stmt(&mut self, stmt: &'a Stmt, depth: usize) {
+        match stmt.kind() {
+            StmtKind::Goto(label) => self.gotos.push((label, stmt.location())),
+            StmtKind::Labelled(label, _) => self.labels.push(label),
+            StmtKind::Block(stmts) => self.blocks.push((depth, stmts.as_slice())),
+            _ => ()
+        }
+        stmt.kind().exprs().into_iter().for_each(|expr| self.add_expr(expr, depth + 1));
+        stmt.kind().stmts().into_iter().for_each(|inner| self.add_stmt(inner, depth + 1));
+    }
+
+    fn add_expr(&mut self, expr: &'a Expr, depth: usize) {
+        if let ExprKind::ValOf(body) = expr.kind() {
+            self.add_stmt(body, depth);
+        }
+        expr.kind().children().into_iter().for_each(|child| self.add_expr(child, depth));
+    }
+
+    fn uses(&self, label: &String) -> usize {
+        self.gotos.iter().filter(|(target, _)| ***target == *label).count()
+    }
+}
+
+// `goto L`, `if c goto L` or `unless c goto L` as a statement of a block
+struct GotoStmt<'a> {
+    stmt: &'a Stmt,
+    goto: &'a Stmt,
+    label: &'a String,
+    // `None` for unconditional jumps, `Some(true)` for `unless`
+    negated: Option<bool>
+}
+
+impl<'a> GotoStmt<'a> {
+    fn of(stmt: &'a Stmt) -> Option<Self> {
+        let (goto, negated) = match stmt.kind() {
+            StmtKind::Goto(_) => (stmt, None),
+            StmtKind::If(_, body, None) => (&**body, Some(false)),
+            StmtKind::Unless(_, body) => (&**body, Some(true)),
+            _ => return None
+        };
+        let StmtKind::Goto(label) = goto.kind() else {
+            return None
+        };
+        Some(Self { stmt, goto, label, negated })
+    }
+}
+
+struct Candidate<'a> {
+    depth: usize,
+    block: &'a [Stmt],
+    goto_index: usize,
+    label_index: usize,
+    goto: GotoStmt<'a>
+}
+
+impl<'a> Candidate<'a> {
+    // the statements moved into the new loop or conditional
+    fn moved(&self) -> Vec<&'a Stmt> {
+        let block = self.block;
+        if self.label_index < self.goto_index {
+            let StmtKind::Labelled(_, body) = block[self.label_index].kind() else {
+                unreachable!()
+            };
+            std::iter::once(&**body).chain(&block[self.label_index + 1..self.goto_index]).collect()
+        }
+        else {
+            block[self.goto_index + 1..self.label_index].iter().collect()
+        }
+    }
+
+    // indices of the block statements which may hold other converted jumps
+    fn inner_range(&self) -> Range<usize> {
+        if self.label_index < self.goto_index { self.label_index + 1..self.goto_index } else { self.goto_index + 1..self.label_index }
+    }
+}
+
+//                                                                 converted gotos  warnings
+fn modernize_routine(func: &Function, source: &SourceText, edits: &mut Vec<Edit>) -> (usize, Vec<Located<ModernizeWarning>>) {
+    let jumps = Jumps::of(func);
+    // converted labels, with the block and indices of their `goto` and label
+    let mut converted: HashMap<&String, Option<(*const Stmt, usize, usize)>> = HashMap::new();
+    let mut reasons: HashMap<&String, &'static str> = HashMap::new();
+
+    for label in &jumps.labels {
+        if jumps.uses(label) == 0 && let Some(edit) = source.label_removal(label.location()) {
+            edits.push(edit);
+            converted.insert(&**label, None);
+        }
+    }
+
+    let mut candidates = vec![];
+    for (depth, block) in &jumps.blocks {
+        for (goto_index, stmt) in block.iter().enumerate() {
+            let Some(goto) = GotoStmt::of(stmt) else {
+                continue
+            };
+            let label_index = block.iter().position(|stmt| matches!(stmt.kind(), StmtKind::Labelled(label, _) if **label == *goto.label));
+            match label_index {
+                Some(_) if jumps.uses(goto.label) > 1 => { reasons.insert(goto.label, "other `goto`s jump to the same label"); }
+                Some(label_index) => candidates.push(Candidate { depth: *depth, block: *block, goto_index, label_index, goto }),
+                None => ()
+            }
+        }
+    }
+    // inner blocks and shorter jumps first, so that enclosing jumps may contain them
+    candidates.sort_by_key(|candidate| (std::cmp::Reverse(candidate.depth), candidate.goto_index.abs_diff(candidate.label_index)));
+
+    for candidate in candidates {
+        let label = candidate.goto.label;
+        match check_candidate(&candidate, &converted).and_then(|()| conversion_edits(&candidate, source)) {
+            Ok(new_edits) if new_edits.iter().any(|(new, _)| edits.iter().any(|(old, _)| new.start < old.end && old.start < new.end)) => {
+                reasons.insert(label, "it overlaps another conversion");
+            }
+            Ok(new_edits) => {
+                edits.extend(new_edits);
+                let (low, high) = (candidate.goto_index.min(candidate.label_index), candidate.goto_index.max(candidate.label_index));
+                converted.insert(label, Some((candidate.block.as_ptr(), low, high)));
+            }
+            Err(reason) => { reasons.insert(label, reason); }
+        }
+    }
+
+    let warnings = jumps.gotos.iter()
+        .filter(|(label, _)| !converted.contains_key(&***label))
+        .map(|(label, loc)| {
+            let reason = reasons.get(&***label).copied().unwrap_or("its label is not a statement of the same block");
+            ModernizeWarning::Unconverted((***label).clone(), reason).with_location((*loc).clone())
+        })
+        .collect();
+    let count = jumps.gotos.iter().filter(|(label, _)| converted.get(&***label).is_some_and(Option::is_some)).count();
+    (count, warnings)
+}
+
+fn check_candidate(candidate: &Candidate, converted: &HashMap<&String, Option<(*const Stmt, usize, usize)>>) -> Result<(), &'static str> {
+    let backward = candidate.label_index < candidate.goto_index;
+    if !backward && candidate.goto.negated.is_none() {
+        return Err("the statements it skips are unreachable")
+    }
+
+    let moved = candidate.moved();
+    let inner = candidate.inner_range();
+    let block = candidate.block.as_ptr();
+    // jumps are only moved along with their label
+    let other_jump = |stmt: &Stmt| match stmt.kind() {
+        StmtKind::Goto(label) | StmtKind::Labelled(label, _) => match converted.get(&**label) {
+            None => true,
+            Some(None) => false,
+            Some(Some((pair_block, low, high))) => *pair_block == block && !(inner.contains(low) && inner.contains(high))
+        },
+        _ => false
+    };
+    if moved.iter().any(|stmt| any_stmt(stmt, &other_jump)) {
+        return Err("it crosses other labels or `goto`s")
+    }
+    if moved.iter().any(|stmt| declares(stmt)) {
+        return Err("declarations would move into a nested block")
+    }
+    if moved.iter().any(|stmt| has_case_label(stmt)) {
+        return Err("`case` labels would move into a nested block")
+    }
+    if backward && moved.iter().any(|stmt| exits_loop(stmt, false, false, false)) {
+        return Err("`break`, `next` or `loop` would refer to the new loop")
+    }
+    Ok(())
+}
+
+fn conversion_edits(candidate: &Candidate, source: &SourceText) -> Result<Vec<Edit>, &'static str> {
+    const LAYOUT: &str = "the source is laid out unexpectedly";
+    let StmtKind::Labelled(label, _) = candidate.block[candidate.label_index].kind() else {
+        unreachable!()
+    };
+    let label_token = source.token_at(label.location()).ok_or(LAYOUT)?;
+    if source.kind(label_token + 1) != Some(&TokenKind::Colon) {
+        return Err(LAYOUT)
+    }
+    let label_span = source.start(label_token)..source.end(label_token + 1);
+
+    let goto = &candidate.goto;
+    let goto_token = source.token_at(goto.goto.location()).ok_or(LAYOUT)?;
+    let target_end = source.end(goto_token + 1);
+
+    let Some(negated) = goto.negated else {
+        // only backward, checked before
+        return Ok(vec![(label_span, "{".into()), (source.start(goto_token)..target_end, "} repeat".into())])
+    };
+
+    let keyword = source.token_at(goto.stmt.location()).ok_or(LAYOUT)?;
+    let before_goto = if source.kind(goto_token - 1) == Some(&TokenKind::Do) { goto_token - 1 } else { goto_token };
+    let condition_end = source.end(before_goto - 1);
+    let keyword_span = source.start(keyword)..source.end(keyword);
+
+    if candidate.label_index < candidate.goto_index {
+        Ok(vec![
+            (label_span, "{".into()),
+            (keyword_span, if negated { "} repeatuntil" } else { "} repeatwhile" }.into()),
+            (condition_end..target_end, String::new())
+        ])
+    }
+    else {
+        let end = if source.kind(goto_token + 2) == Some(&TokenKind::Semicolon) { source.end(goto_token + 2) } else { target_end };
+        Ok(vec![
+            (keyword_span, if negated { "if" } else { "unless" }.into()),
+            (condition_end..end, " do {".into()),
+            (label_span, "}".into())
+        ])
+    }
+}
+
+// whether `pred` holds for `stmt` or a statement within it, including those of `valof` blocks
+fn any_stmt(stmt: &Stmt, pred: &impl Fn(&Stmt) -> bool) -> bool {
+    pred(stmt)
+        || stmt.kind().stmts().into_iter().any(|inner| any_stmt(inner, pred))
+        || stmt.kind().exprs().into_iter().any(|expr| any_valof_stmt(expr, pred))
+}
+
+fn any_valof_stmt(expr: &Expr, pred: &impl Fn(&Stmt) -> bool) -> bool {
+    matches!(expr.kind(), ExprKind::ValOf(body) if any_stmt(body, pred))
+        || expr.kind().children().into_iter().any(|child| any_valof_stmt(child, pred))
+}
+
+// bindings are visible until the end of their block
+fn declares(stmt: &Stmt) -> bool {
+    match stmt.kind() {
+        StmtKind::Binding(_) => true,
+        StmtKind::Labelled(_, body) => declares(body),
+        _ => false
+    }
+}
+
+fn has_case_label(stmt: &Stmt) -> bool {
+    match stmt.kind() {
+        StmtKind::Case(_) | StmtKind::DefaultCase => true,
+        StmtKind::SwitchOn(..) => false,
+        kind => kind.stmts().into_iter().any(has_case_label)
+    }
+}
+
+// whether `stmt` has a `break`, `next` or `loop` a loop around it would catch, given whether
+// the statements enclosing it already do
+fn exits_loop(stmt: &Stmt, breakable: bool, continuable: bool, looping: bool) -> bool {
+    let (inner_break, inner_next, inner_loop) = match stmt.kind() {
+        StmtKind::Break => return !breakable,
+        StmtKind::Next => return !continuable,
+        StmtKind::Loop => return !looping,
+        StmtKind::While(..) | StmtKind::Until(..) | StmtKind::Repeat(_) | StmtKind::RepeatWhile(..)
+            | StmtKind::RepeatUntil(..) | StmtKind::For(..) => (true, true, true),
+        StmtKind::SwitchOn(..) => (true, continuable, looping),
+        StmtKind::Match(..) | StmtKind::Every(..) => (true, true, looping),
+        _ => (breakable, continuable, looping)
+    };
+    stmt.kind().stmts().into_iter().any(|inner| exits_loop(inner, inner_break, inner_next, inner_loop))
+        || stmt.kind().exprs().into_iter().any(|expr| valof_exits_loop(expr, breakable, continuable, looping))
+}
+
+fn valof_exits_loop(expr: &Expr, breakable: bool, continuable: bool, looping: bool) -> bool {
+    matches!(expr.kind(), ExprKind::ValOf(body) if exits_loop(body, breakable, continuable, looping))
+        || expr.kind().children().into_iter().any(|child| valof_exits_loop(child, breakable, continuable, looping))
+}
+
+// the tokens of a source file with their byte offsets, without those of sections skipped for the
+// active tags
+struct SourceText<'a> {
+    contents: &'a str,
+    tokens: Vec<Token<'a>>,
+    line_starts: Vec<usize>
+}
+
+impl<'a> SourceText<'a> {
+    fn new(file: &'a SourceFile, tags: &[String]) -> Self {
+        let contents = file.contents();
+        Self {
+            contents,
+            tokens: Lexer::from(file).with_tags(tags).take_while(|token| !token.is_eof()).collect(),
+            line_starts: std::iter::once(0)
+                .chain(contents.match_indices('\n').map(|(i, _)| i + 1))
+                .collect()
+        }
+    }
+
+    // locations count lines from 1 and columns in characters
+    fn offset(&self, line: usize, column: usize) -> usize {
+        let start = self.line_starts.get(line - 1).copied().unwrap_or(self.contents.len());
+        self.contents[start..].char_indices().nth(column).map_or(self.contents.len(), |(i, _)| start + i)
+    }
+
+    fn token_at(&self, loc: &Location) -> Option<usize> {
+        self.tokens.iter().position(|token| token.location().line() == loc.line() && token.location().column() == loc.column())
+    }
+
+    fn kind(&self, index: usize) -> Option<&TokenKind<'_>> {
+        self.tokens.get(index).map(|token| token.kind())
+    }
+
+    fn start(&self, index: usize) -> usize {
+        let loc = self.tokens[index].location();
+        self.offset(loc.line(), loc.column())
+    }
+
+    fn end(&self, index: usize) -> usize {
+        let loc = self.tokens[index].location();
+        self.offset(loc.line(), loc.column() + loc.width())
+    }
+
+    // `label:` with the blanks after it, and its line if nothing else is left on it
+    fn label_removal(&self, loc: &Location) -> Option<Edit> {
+        let label = self.token_at(loc)?;
+        if self.kind(label + 1) != Some(&TokenKind::Colon) {
+            return None
+        }
+
+        let (mut start, mut end) = (self.start(label), self.end(label + 1));
+        let rest = &self.contents[end..];
+        end += rest.len() - rest.trim_start_matches([' ', '\t']).len();
+
+        let line_start = self.line_starts[loc.line() - 1];
+        if self.contents[line_start..start].trim().is_empty() && self.contents[end..].starts_with('\n') {
+            start = line_start;
+            end += 1;
+        }
+        Some((start..end, String::new()))
+    }
+}
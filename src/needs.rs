@@ -0,0 +1,130 @@
+use std::collections::HashSet;
+
+use crate::{
+    ast::{Program, Section},
+    source_file::{Located, WithLocation},
+    error::{IntoCompilerError, CompilerError, Severity}
+};
+
+// Problems with the sections named by `needs "name"`: each has to be part of the program or
+// imported from a `.bmeta` file, and sections cannot need each other in a cycle, since the
+// metadata of a needed section has to exist before the sections needing it are compiled.
+#[derive(Clone, Debug)]
+pub enum NeedsError {
+    //             section needed by
+    MissingSection(String, String),
+    //    section the needs leading back to it
+    Cycle(String, Vec<Located<String>>)
+}
+
+impl NeedsError {
+    fn severity(&self) -> Severity {
+        Severity::Error
+    }
+
+    fn hint(&self) -> Option<String> {
+        match self {
+            Self::MissingSection(section, _) => Some(format!("Put it into `{section}.bpp` next to this file, pass its file as input or import its `.bmeta` file.")),
+            Self::Cycle(..) => Some("Move what the sections share into a section both of them need.".into())
+        }
+    }
+
+    fn additional(&self) -> Vec<Located<CompilerError>> {
+        match self {
+            Self::Cycle(_, needs) => needs[..needs.len() - 1].iter()
+                .map(|need| CompilerError::new(Severity::Hint, format!("`{}` is needed here.", **need), None, vec![])
+                    .with_location(need.location().clone())
+                )
+                .collect(),
+            _ => vec![]
+        }
+    }
+}
+
+impl WithLocation for NeedsError {}
+
+impl ToString for NeedsError {
+    fn to_string(&self) -> String {
+        match self {
+            Self::MissingSection(section, needed_by) => format!("Section `{section}`, needed by `{needed_by}`, is not part of the program."),
+            Self::Cycle(section, needs) => format!(
+                "Section `{section}` needs itself: `{section}` -> {}.",
+                needs.iter().map(|need| format!("`{}`", **need)).collect::<Vec<_>>().join(" -> ")
+            )
+        }
+    }
+}
+
+impl IntoCompilerError for NeedsError {}
+impl Into<CompilerError> for NeedsError {
+    fn into(self) -> CompilerError {
+        CompilerError::new(self.severity(), self.to_string(), self.hint(), self.additional())
+    }
+}
+
+// reports every missing section and the first cycle found, in source order
+pub fn check_needs(ast: &Program) -> Vec<Located<NeedsError>> {
+    let mut sections = ast.sections().collect::<Vec<_>>();
+    sections.sort_by_key(|section| section.location().clone());
+
+    let mut errors = vec![];
+    for section in &sections {
+        for need in section.needs() {
+            if !is_available(ast, need) {
+                errors.push(NeedsError::MissingSection((**need).clone(), section.ident().clone()).with_location(need.location().clone()));
+            }
+        }
+    }
+
+    let mut graph = NeedsGraph {
+        ast,
+        stack: vec![],
+        path: vec![],
+        done: HashSet::new()
+    };
+    for section in sections {
+        if let Err(err) = graph.visit(section) {
+            errors.push(err);
+            break
+        }
+    }
+    errors
+}
+
+// whether the section `ident` is compiled along or imported
+pub fn is_available(ast: &Program, ident: &String) -> bool {
+    ast.section(ident).is_some() || ast.interface(ident).is_some()
+}
+
+struct NeedsGraph<'a> {
+    ast: &'a Program,
+    // the sections being visited, and the needs leading from each to the next
+    stack: Vec<&'a String>,
+    path: Vec<&'a Located<String>>,
+    done: HashSet<&'a String>
+}
+
+impl<'a> NeedsGraph<'a> {
+    fn visit(&mut self, section: &'a Section) -> Result<(), Located<NeedsError>> {
+        if self.done.contains(section.ident()) {
+            return Ok(())
+        }
+
+        self.stack.push(section.ident());
+        for need in section.needs() {
+            if let Some(start) = self.stack.iter().position(|ident| **ident == **need) {
+                let cycle = self.path[start..].iter().copied().chain(std::iter::once(need)).cloned().collect();
+                return Err(NeedsError::Cycle(self.stack[start].clone(), cycle).with_location(need.location().clone()))
+            }
+            // imported sections were compiled already, and missing ones are reported on their own
+            if let Some(needed) = self.ast.section(need) {
+                self.path.push(need);
+                self.visit(needed)?;
+                self.path.pop();
+            }
+        }
+        self.stack.pop();
+        self.done.insert(section.ident());
+        Ok(())
+    }
+}
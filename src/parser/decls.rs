@@ -1,19 +1,26 @@
 use std::cell::RefCell;
 
 use crate::{
-    token::TokenKind, 
+    token::{TokenKind, lexer::unescape}, 
     source_file::{Location, Located, WithLocation}, 
-    ast::{Decl, Function, FunctionBody, Param, IntoDecl, Section, types::TypeKind, BasicFunctionBody, pattern::Pattern}
+    match_decl, log,
+    ast::{attribute::{Attribute, Deprecation, CallingConvention, Hook}, Decl, Function, FunctionBody, Param, IntoDecl, Section, GlobalDecl, ManifestDecl, Storage, types::{TypeKind, TypeIndex}, expr::{Expr, ExprKind}, BasicFunctionBody, Variadic, pattern::Pattern, visitor::Traversable}
 };
 
-use super::{Parser, ParseResult, ParseError, stmt::StmtContext, pattern};
+use super::{Parser, ParseResult, ParseError, stmt::StmtContext, scope::{self, Local, PassEnvironment, method_symbol}, pattern};
+
+// tokens at which parsing can resume after an erroneous declaration
+const DECL_START: &[TokenKind] = &[
+    TokenKind::Semicolon, TokenKind::Let, TokenKind::And, TokenKind::Global, TokenKind::Static,
+    TokenKind::Manifest, TokenKind::Type, TokenKind::Require, TokenKind::Needs, TokenKind::Section
+];
 
 impl<'a> Parser<'a> {
     pub(super) fn parse_section(&mut self) -> ParseResult<'a, ()> {
         let section_loc = self.current_token.location().clone();
         self.expect(&[TokenKind::Section])?;
 
-        let mut section = Section::new(self.expect_ident()?.into(), section_loc);
+        let mut section = Section::new(self.parse_section_name()?, section_loc);
 
         let mut had_decls = false;
         loop {
@@ -25,25 +32,98 @@ impl<'a> Parser<'a> {
                     }
                     section.add_require(self.parse_require()?);
                 }
+                TokenKind::Needs => section.add_need(self.parse_needs()?),
                 TokenKind::Type => {
                     had_decls = true;
                     self.parse_type_alias()?;
                 }
+                TokenKind::Enum => {
+                    had_decls = true;
+                    self.parse_enum()?;
+                }
+                TokenKind::Get => self.parse_get()?,
+                TokenKind::Assert => {
+                    let (condition, message) = self.parse_assertion(&StmtContext::Empty)?;
+                    self.advance_if(&[TokenKind::Semicolon])?;
+                    section.add_assertion(condition, message);
+                }
                 _ => {
-                    let decl = self.parse_decl()?;
-                    if let Some(prev) = section.defines(decl.ident()) {
-                        return Err(ParseError::Redefinition(prev.location().clone(), decl.ident().clone()).with_location(decl.location().clone()))
+                    let mut decls = self.parse_decls()?;
+                    decls.extend(std::mem::take(&mut self.nested_routines).into_iter().map(Function::into_decl));
+                    // no `repeat` can follow anymore
+                    let loop_exits = std::mem::take(&mut self.loop_exits);
+                    self.errors.extend(loop_exits);
+                    for decl in decls {
+                        if let Some(prev) = section.defines(decl.ident()) {
+                            return Err(ParseError::Redefinition(prev.location().clone(), decl.ident().clone()).with_location(decl.location().clone()))
+                        }
+                        if let Some(global) = decl.as_any().downcast_ref::<GlobalDecl>() && let Some(typ) = global.typ() {
+                            self.global_types.insert(global.ident().clone(), *typ);
+                        }
+                        section.declare(decl);
                     }
-                    section.declare(decl);
                     had_decls = true;
                 }
             }
         }
 
-        self.ast.lock().unwrap().add_section(section);
+        log!(Debug, "section `{}` with {} declaration(s)", section.ident(), section.declarations().count());
+        let mut ast = self.ast.lock().unwrap();
+        // all files parse into the same program, where one name would stand for several symbols
+        if let Some(prev) = ast.section(section.ident()) {
+            return Err(ParseError::Redefinition(prev.location().clone(), section.ident().clone()).with_location(section.location().clone()))
+        }
+        for decl in section.declarations() {
+            if let Some(prev) = ast.defines(decl.ident()) {
+                self.errors.push(ParseError::Redefinition(prev.location().clone(), decl.ident().clone()).with_location(decl.location().clone()));
+            }
+            else if let Some((typ, _)) = ast.types().enum_constant(decl.ident()) && let Some(prev) = ast.types().get(typ).and_then(|typ| typ.location().clone()) {
+                self.errors.push(ParseError::Redefinition(prev, decl.ident().clone()).with_location(decl.location().clone()));
+            }
+        }
+        ast.add_section(section);
         Ok(())
     }
 
+    // `get "file"` includes another source file; it is parsed once the current one is done
+    pub(super) fn parse_get(&mut self) -> ParseResult<'a, ()> {
+        let loc = self.expect(&[TokenKind::Get])?.location().clone();
+        let TokenKind::StringLit(path) = *self.current().kind() else {
+            return self.unexpected(&[TokenKind::Ident("file name")])
+        };
+        let path = unescape(path).expect("escapes are checked by the lexer");
+        let mut loc = loc;
+        loc.extend_to(self.advance()?.location());
+        self.advance_if(&[TokenKind::Semicolon])?;
+
+        if path.is_empty() {
+            return Err(ParseError::Generic("File names cannot be empty.".into()).with_location(loc))
+        }
+        self.includes.push(path.with_location(loc));
+        Ok(())
+    }
+
+    // `name`, or `"name"` as in classic BCPL
+    fn parse_section_name(&mut self) -> ParseResult<'a, String> {
+        let TokenKind::StringLit(name) = *self.current().kind() else {
+            return self.expect_ident()
+        };
+        let name = unescape(name).expect("escapes are checked by the lexer");
+        let loc = self.advance()?.location().clone();
+        if name.is_empty() {
+            return Err(ParseError::Generic("Section names cannot be empty.".into()).with_location(loc))
+        }
+        Ok(name)
+    }
+
+    // `needs "name"` makes the section depend on another one, which is compiled along with it
+    fn parse_needs(&mut self) -> ParseResult<'a, Located<String>> {
+        let loc = self.expect(&[TokenKind::Needs])?.location().clone();
+        let name = self.parse_section_name()?;
+        self.advance_if(&[TokenKind::Semicolon])?;
+        Ok(name.with_location(loc))
+    }
+
     pub(super) fn parse_require(&mut self) -> ParseResult<'a, Located<String>> {
         let loc = self.current_token.location().clone();
         self.expect(&[TokenKind::Require])?;
@@ -51,31 +131,482 @@ impl<'a> Parser<'a> {
         Ok(self.expect_ident()?.to_string().with_location(loc))
     }
 
-    pub(super) fn parse_decl(&mut self) -> ParseResult<'a, Box<dyn Decl>> {
+    // a single declaration, or all globals of a `global { ... }` block
+    pub(super) fn parse_decls(&mut self) -> ParseResult<'a, Vec<Box<dyn Decl>>> {
+        let attributes = self.parse_attributes()?;
+        if !attributes.is_empty() && self.current().kind() == &TokenKind::Type {
+            let typ = self.parse_type_alias()?;
+            for attribute in attributes {
+                self.apply_type_attribute(typ, attribute)?;
+            }
+            return Ok(vec![])
+        }
+        let link_section = self.parse_link_section()?;
+
         let loc = self.current_token.location().clone();
         let decl_tok = self.expect(&[TokenKind::Let, TokenKind::And, TokenKind::Global, TokenKind::Manifest, TokenKind::Static])?;
-        match decl_tok.kind() {
-            TokenKind::Let => self.parse_function_decl(loc, false).map(Function::into_decl),
-            TokenKind::And => self.parse_function_decl(loc, true).map(Function::into_decl),
+        let mut decls = match (decl_tok.kind(), link_section) {
+            (TokenKind::Manifest, Some(_)) => Err(ParseError::Generic("Manifest constants occupy no storage and cannot be placed in a section.".into()).with_location(loc)),
+            (TokenKind::Let | TokenKind::And, link_section) => {
+                let mut func = self.parse_function_decl(loc, decl_tok.kind() == &TokenKind::And)?;
+                if let Some(section) = link_section {
+                    func.set_link_section(section);
+                }
+                Ok(vec![func.into_decl()])
+            }
+            (TokenKind::Global | TokenKind::Static, link_section) if self.current().kind() == &TokenKind::LBrace => {
+                let storage = if decl_tok.kind() == &TokenKind::Global { Storage::Global } else { Storage::Static };
+                let globals = self.parse_global_block(storage)?;
+                Ok(globals.into_iter().map(|mut global| {
+                    if let Some(section) = &link_section {
+                        global.set_link_section(section.clone());
+                    }
+                    global.into_decl()
+                }).collect())
+            }
+            (TokenKind::Global | TokenKind::Static, link_section) => {
+                let storage = if decl_tok.kind() == &TokenKind::Global { Storage::Global } else { Storage::Static };
+                let mut global = self.parse_global_decl(loc, storage)?;
+                if let Some(section) = link_section {
+                    global.set_link_section(section);
+                }
+                Ok(vec![global.into_decl()])
+            }
+            (TokenKind::Manifest, None) => self.parse_manifest_decl(loc).map(|manifest| vec![manifest.into_decl()]),
             _ => unreachable!()
+        }?;
+
+        for attribute in attributes {
+            for decl in &mut decls {
+                self.apply_attribute(decl, attribute.clone())?;
+            }
         }
+        Ok(decls)
     }
 
-    pub(super) fn parse_function_decl(&mut self, decl_loc: Location, tailcall_recursive: bool) -> ParseResult<'a, Function> {
+    // no expression can start with `[`, so `[[` never continues an index expression
+    pub(super) fn at_attributes(&mut self) -> bool {
+        self.current().kind() == &TokenKind::LBracket && self.lexer.peek_char() == Some('[')
+    }
+
+    // `[[name, name("argument"), ...]]`, possibly repeated
+    fn parse_attributes(&mut self) -> ParseResult<'a, Vec<Located<Attribute>>> {
+        let mut attributes = vec![];
+        while self.at_attributes() {
+            self.advance()?;
+            self.expect(&[TokenKind::LBracket])?;
+            attributes.extend(self.parse_list(TokenKind::RBracket, TokenKind::Comma, Self::parse_attribute, &())?);
+            self.expect(&[TokenKind::RBracket])?;
+        }
+        Ok(attributes)
+    }
+
+    fn parse_attribute(&mut self, _: &()) -> ParseResult<'a, Located<Attribute>> {
+        let loc = self.current().location().clone();
+        // keywords like `section` are valid attribute names
+        let name = match self.current().kind() {
+            TokenKind::Ident(ident) => ident.to_string(),
+            kind if kind.to_string().chars().all(|c| c.is_ascii_alphabetic()) => kind.to_string(),
+            _ => return self.unexpected(&[TokenKind::Ident("attribute name")])
+        };
+        self.advance()?;
+
+        let args = self.parse_optional_list(TokenKind::LParen, TokenKind::RParen, TokenKind::Comma, Self::parse_attribute_arg, &())?;
+        Ok(Attribute::new(name, args).with_location(loc))
+    }
+
+    fn parse_attribute_arg(&mut self, _: &()) -> ParseResult<'a, String> {
+        let TokenKind::StringLit(arg) = *self.current().kind() else {
+            return self.unexpected(&[TokenKind::Ident("string")])
+        };
+        let arg = unescape(arg).expect("escapes are checked by the lexer");
+        self.advance()?;
+        Ok(arg)
+    }
+
+    // hands known attributes to the declaration; unknown ones are only warned about
+    fn apply_attribute(&mut self, decl: &mut Box<dyn Decl>, attribute: Located<Attribute>) -> ParseResult<'a, ()> {
+        let loc = attribute.location().clone();
+        let name = attribute.name().clone();
+        let args = attribute.args();
+        let is_function = decl.as_any().is::<Function>();
+
+        match name.as_str() {
+            "deprecated" => {
+                if args.len() > 1 {
+                    return Err(ParseError::AttributeArgs(name, "at most a message").with_location(loc))
+                }
+                decl.set_deprecation(Deprecation::new(args.first().cloned()));
+            }
+            "section" => {
+                let [section] = args.as_slice() else {
+                    return Err(ParseError::AttributeArgs(name, "a section name").with_location(loc))
+                };
+                if section.is_empty() {
+                    return Err(ParseError::Generic("Section names cannot be empty.".into()).with_location(loc))
+                }
+                match_decl!{
+                    mut decl;
+                    func as Function => func.set_link_section(section.clone()),
+                    global as GlobalDecl => global.set_link_section(section.clone()),
+                    _ => return Err(ParseError::AttributeTarget(name, "manifest constants").with_location(loc))
+                }
+            }
+            "inline" | "ccall" => {
+                if !args.is_empty() {
+                    return Err(ParseError::AttributeArgs(name, "no arguments").with_location(loc))
+                }
+                if !is_function {
+                    return Err(ParseError::AttributeTarget(name, "variables and constants").with_location(loc))
+                }
+                let func = decl.as_mut_any().downcast_mut::<Function>().unwrap();
+                if name == "inline" {
+                    func.set_inline();
+                }
+                else {
+                    func.set_calling_convention(CallingConvention::C);
+                }
+            }
+            "constructor" | "destructor" => {
+                let priority = match args.as_slice() {
+                    [] => Hook::DEFAULT_PRIORITY,
+                    [priority] => priority.parse().map_err(|_| ParseError::AttributeArgs(name.clone(), "at most a priority from 0 to 65535").with_location(loc.clone()))?,
+                    _ => return Err(ParseError::AttributeArgs(name, "at most a priority from 0 to 65535").with_location(loc))
+                };
+                let Some(func) = decl.as_mut_any().downcast_mut::<Function>() else {
+                    return Err(ParseError::AttributeTarget(name, "variables and constants").with_location(loc))
+                };
+                // the runtime passes no arguments
+                if !func.params().is_empty() || func.variadic().is_some() {
+                    return Err(ParseError::AttributeTarget(name, "routines with parameters").with_location(loc))
+                }
+                if func.hook().is_some() {
+                    return Err(ParseError::AttributeTarget(name, "routines that are already a constructor or destructor").with_location(loc))
+                }
+                func.set_hook(if name == "constructor" { Hook::Constructor(priority) } else { Hook::Destructor(priority) });
+            }
+            "repr" => return Err(ParseError::AttributeTarget(name, "routines and variables").with_location(loc)),
+            _ => self.push_warning(ParseError::UnknownAttribute(name).with_location(loc))
+        }
+        Ok(())
+    }
+
+    // `[[repr("C")]] type Name = struct { ... }` lays out the struct compatibly with C
+    fn apply_type_attribute(&mut self, typ: TypeIndex, attribute: Located<Attribute>) -> ParseResult<'a, ()> {
+        let loc = attribute.location().clone();
+        let name = attribute.name().clone();
+
+        match name.as_str() {
+            "repr" => {
+                if attribute.args().as_slice() != ["C"] {
+                    return Err(ParseError::AttributeArgs(name, "the representation `\"C\"`").with_location(loc))
+                }
+                let mut ast = self.ast.lock().unwrap();
+                let typ = ast.types().resolve(typ);
+                let Some(typ) = ast.types_mut().get_mut(typ).filter(|typ| matches!(typ.kind(), TypeKind::Struct(_))) else {
+                    return Err(ParseError::AttributeTarget(name, "types other than structs").with_location(loc))
+                };
+                typ.set_c_layout();
+            }
+            "deprecated" | "section" | "inline" | "ccall" | "constructor" | "destructor" =>
+                return Err(ParseError::AttributeTarget(name, "types").with_location(loc)),
+            _ => self.push_warning(ParseError::UnknownAttribute(name).with_location(loc))
+        }
+        Ok(())
+    }
+
+    // `_section("name")` in front of a declaration places it in an object file section
+    fn parse_link_section(&mut self) -> ParseResult<'a, Option<String>> {
+        if self.current().kind() != &TokenKind::Ident("_section") {
+            return Ok(None)
+        }
+
+        self.advance()?;
+        self.expect(&[TokenKind::LParen])?;
+        let TokenKind::StringLit(section) = *self.current().kind() else {
+            return self.unexpected(&[TokenKind::Ident("section name")])
+        };
+        let section = unescape(section).expect("escapes are checked by the lexer");
+        let loc = self.advance()?.location().clone();
+        self.expect(&[TokenKind::RParen])?;
+
+        if section.is_empty() {
+            return Err(ParseError::Generic("Section names cannot be empty.".into()).with_location(loc))
+        }
+        Ok(Some(section))
+    }
+
+    // `global x : slot :: T = value`; either the type or the initializer may be omitted
+    fn parse_global_decl(&mut self, loc: Location, storage: Storage) -> ParseResult<'a, GlobalDecl> {
         let ident = self.expect_ident()?;
-        
-        let params = self.parse_optional_list(TokenKind::LParen, TokenKind::RParen, TokenKind::Comma, Self::parse_function_param, &())?;
+
+        let slot = if storage == Storage::Global && self.advance_if(&[TokenKind::Colon])?.is_some() {
+            Some(self.parse_global_slot()?)
+        }
+        else {
+            None
+        };
+
+        let (typ, value) = self.parse_typed_initializer()?;
+
+        if typ.is_none() && value.is_none() {
+            return Err(ParseError::Generic("Global variable requires either a type or an initial value.".into()).with_location(loc))
+        }
+
+        Ok(GlobalDecl::new(loc, storage, ident, slot, typ, value))
+    }
+
+    // `global { a: 200; b: 201 :: T = value; ... }` assigns slots in the traditional
+    // global vector; types and initializers are optional here.
+    // `static { counter = 0; ... }` declares several statics at once.
+    fn parse_global_block(&mut self, storage: Storage) -> ParseResult<'a, Vec<GlobalDecl>> {
+        self.expect(&[TokenKind::LBrace])?;
+
+        let mut globals = vec![];
+        while self.current().kind() != &TokenKind::RBrace {
+            let loc = self.current().location().clone();
+            let global = if storage == Storage::Global {
+                let ident = self.expect_ident()?;
+                self.expect(&[TokenKind::Colon])?;
+                let slot = self.parse_global_slot()?;
+                let (typ, value) = self.parse_typed_initializer()?;
+                GlobalDecl::new(loc, storage, ident, Some(slot), typ, value)
+            }
+            else {
+                self.parse_global_decl(loc, storage)?
+            };
+            globals.push(global);
+            self.advance_if(&[TokenKind::Semicolon])?;
+        }
+
+        self.expect(&[TokenKind::RBrace])?;
+        Ok(globals)
+    }
+
+    fn parse_global_slot(&mut self) -> ParseResult<'a, u32> {
+        let TokenKind::IntegerLit(slot) = *self.current().kind() else {
+            return self.unexpected(&[TokenKind::Ident("global slot")])
+        };
+        let slot_loc = self.advance()?.location().clone();
+        u32::try_from(slot).map_err(|_| ParseError::Generic("Global slot out of range.".into()).with_location(slot_loc))
+    }
+
+    fn parse_manifest_decl(&mut self, loc: Location) -> ParseResult<'a, ManifestDecl> {
+        let ident = self.expect_ident()?;
+        let (_, value) = self.parse_typed_initializer()?;
+
+        let value = value.ok_or_else(|| ParseError::Generic("Manifest constant requires a value.".into()).with_location(loc.clone()))?;
+        Ok(ManifestDecl::new(loc, ident, value))
+    }
+
+    fn parse_typed_initializer(&mut self) -> ParseResult<'a, (Option<TypeIndex>, Option<Expr>)> {
+        let typ = if self.advance_if(&[TokenKind::Of])?.is_some() {
+            Some(self.parse_type()?)
+        }
+        else {
+            None
+        };
+
+        let value = if self.advance_if(&[TokenKind::Eq])?.is_some() {
+            let start = self.current().location().clone();
+            let value = match self.parse_expr(&StmtContext::Empty) {
+                Ok(value) => value,
+                Err(err) if self.current().is_eof() => return Err(err),
+                Err(err) => {
+                    let loc = self.recover(err, start, DECL_START);
+                    return Ok((typ, Some(Expr::new(loc, None, ExprKind::Error))))
+                }
+            };
+            self.advance_if(&[TokenKind::Semicolon])?;
+            Some(match typ {
+                Some(typ) => self.coerce(value, typ)?,
+                None => value
+            })
+        }
+        else {
+            None
+        };
+
+        Ok((typ.or_else(|| value.as_ref().and_then(|value| *value.typ())), value))
+    }
+
+    pub(super) fn parse_function_decl(&mut self, decl_loc: Location, tailcall_recursive: bool) -> ParseResult<'a, Function> {
+        let mut ident = self.expect_ident()?;
+        // `let Type.method(receiver, ...)` attaches the routine to the record type `Type`
+        let record = match self.advance_if(&[TokenKind::Period])? {
+            Some(_) => {
+                let symbol = method_symbol(&ident, &self.expect_ident()?);
+                Some(std::mem::replace(&mut ident, symbol))
+            }
+            None => None
+        };
+        // instances of generic routines are copies of their declaration, which nested routines are not part of
+        if let Some(outer) = &self.routine && !self.type_params.is_empty() {
+            return Err(ParseError::NestedInGeneric(outer.clone()).with_location(decl_loc))
+        }
+        let type_params = self.parse_type_params()?;
+        // a routine nested in another one is hoisted out of it, visible for the rest of the enclosing block
+        if let Some(outer) = &self.routine {
+            let symbol = self.nested_symbol(outer, &ident);
+            self.scope.push((ident, Local::Routine(symbol.clone())));
+            ident = symbol;
+        }
+
+        let outer = self.routine.replace(ident.clone());
+        let outer_scope = std::mem::replace(&mut self.routine_scope, self.scope.len());
+        let outer_labels = std::mem::take(&mut self.labels);
+        let outer_gotos = std::mem::take(&mut self.gotos);
+        let outer_try_labels = std::mem::take(&mut self.try_labels);
+        let outer_try_exits = std::mem::take(&mut self.try_exits);
+        let outer_captures = std::mem::take(&mut self.captures);
+        let outer_type_params = std::mem::replace(&mut self.type_params, type_params);
+        let result = self.scoped(|parser| parser.parse_function(decl_loc, ident, tailcall_recursive));
+        if result.is_ok() {
+            self.check_labels();
+        }
+        self.routine = outer;
+        self.routine_scope = outer_scope;
+        self.labels = outer_labels;
+        self.gotos = outer_gotos;
+        self.try_labels = outer_try_labels;
+        self.try_exits = outer_try_exits;
+        self.captures = outer_captures;
+        self.type_params = outer_type_params;
+
+        let function = result?;
+        if let Some(record) = record {
+            self.check_receiver(&function, record)?;
+        }
+        Ok(function)
+    }
+
+    // methods take the value they are called on as their first parameter
+    fn check_receiver(&self, method: &Function, record: String) -> ParseResult<'a, ()> {
+        let Some(receiver) = method.params().first() else {
+            return Err(ParseError::MethodWithoutReceiver(method.ident().clone()).with_location(method.location().clone()))
+        };
+        if let Some(typ) = receiver.typ() && self.method_record(typ).as_ref() != Some(&record) {
+            return Err(ParseError::MethodReceiverType(method.ident().clone(), record).with_location(receiver.location().clone()))
+        }
+        Ok(())
+    }
+
+    // labels are unique within a routine, and every `goto` has one to jump to without entering
+    // or leaving the body of a `try`
+    fn check_labels(&mut self) {
+        let labels = std::mem::take(&mut self.labels);
+        for (i, label) in labels.iter().enumerate() {
+            if let Some(prev) = labels[..i].iter().find(|prev| ***prev == **label) {
+                self.errors.push(ParseError::Redefinition(prev.location().clone(), (**label).clone()).with_location(label.location().clone()));
+            }
+        }
+
+        let try_labels = std::mem::take(&mut self.try_labels);
+        let try_exits = std::mem::take(&mut self.try_exits);
+        let gotos = std::mem::take(&mut self.gotos);
+        for (leaves_try, target) in try_exits.into_iter().map(|goto| (true, goto)).chain(gotos.into_iter().map(|goto| (false, goto))) {
+            let loc = target.location().clone();
+            if !labels.iter().any(|label| **label == *target) {
+                self.errors.push(ParseError::UndefinedLabel(target.unwrap()).with_location(loc));
+            }
+            else if leaves_try || try_labels.iter().any(|label| **label == *target) {
+                self.errors.push(ParseError::GotoAcrossTry(target.unwrap()).with_location(loc));
+            }
+        }
+    }
+
+    fn parse_function(&mut self, decl_loc: Location, ident: String, tailcall_recursive: bool) -> ParseResult<'a, Function> {
+        let (mut params, variadic) = self.parse_function_params()?;
+        // defaults are filled in for arguments left out at the end of calls
+        if let Some(first) = params.iter().position(|param| param.default_value().is_some())
+            && let Some(param) = params[first..].iter().find(|param| param.default_value().is_none()) {
+            return Err(ParseError::NonTrailingDefault(params[first].location().clone()).with_location(param.location().clone()))
+        }
+        self.check_type_params(&ident, &params)?;
+        params.iter().for_each(|param| self.declare_typed(param.pattern(), param.typ()));
+        if let Some(rest) = variadic.as_ref().and_then(Variadic::ident) {
+            self.scope.push((rest.clone(), Local::Dynamic(None)));
+        }
+
+        let result_type = if self.advance_if(&[TokenKind::Of])?.is_some() {
+            let loc = self.current().location().clone();
+            Some(self.parse_result_type()?.with_location(loc))
+        }
+        else {
+            None
+        };
 
         let context = StmtContext::Function(&params);
         let body = if self.current().kind() == &TokenKind::Colon {
-            self.parse_pattern_matched_body(&context)? 
+            self.parse_pattern_matched_body(&context, result_type.as_ref())? 
         }
         else {
-            self.parse_function_body(&context)?.into() 
+            self.parse_function_body(&context, result_type.as_ref())?.into() 
         };
         
-        let return_type = self.get_return_type(&body);
-        Ok(Function::new(decl_loc, ident, params, return_type, tailcall_recursive, body))
+        let return_type = match result_type {
+            Some(typ) => Some(typ.unwrap()),
+            None => self.get_return_type(&body)
+        };
+        // the environment record of a routine capturing locals comes before its own parameters
+        let env = (!self.captures.is_empty()).then(|| scope::environment(&ident));
+        if let Some(env) = &env {
+            params.insert(0, Param::new(decl_loc.clone(), Pattern::Query(env.clone()).with_location(decl_loc.clone()), None, None));
+        }
+        let mut function = Function::new(decl_loc.clone(), ident.clone(), params, return_type, tailcall_recursive, body);
+        if let Some(variadic) = variadic {
+            function.set_variadic(variadic);
+        }
+        if !self.type_params.is_empty() {
+            function.set_type_params(self.type_params.iter().map(|(_, typ)| *typ).collect());
+        }
+        if let Some(env) = env {
+            let env = Expr::new(decl_loc, None, ExprKind::Ident(env));
+            function.traverse(&mut PassEnvironment::new(ident, Some(env)))?;
+            function.set_captures(std::mem::take(&mut self.captures));
+        }
+        Ok(function)
+    }
+
+    // type arguments are inferred from the types of the arguments, so each type parameter needs a parameter mentioning it
+    fn check_type_params(&self, routine: &str, params: &[Param]) -> ParseResult<'a, ()> {
+        let ast = self.ast.lock().unwrap();
+        let types = ast.types();
+        for (ident, typ) in &self.type_params {
+            if !params.iter().any(|param| param.typ().is_some_and(|param| types.mentions(param, *typ))) {
+                let loc = types.get(*typ).and_then(|typ| typ.location().clone()).unwrap_or_default();
+                return Err(ParseError::UninferableTypeParam(ident.clone(), routine.to_string()).with_location(loc))
+            }
+        }
+        Ok(())
+    }
+
+    // `(a, b of T, ...)`; a trailing `...`, optionally naming the vector of further arguments,
+    // makes the routine variadic
+    fn parse_function_params(&mut self) -> ParseResult<'a, (Vec<Param>, Option<Variadic>)> {
+        let mut params = vec![];
+        if self.advance_if(&[TokenKind::LParen])?.is_none() || self.advance_if(&[TokenKind::RParen])?.is_some() {
+            return Ok((params, None))
+        }
+
+        loop {
+            if let Some(ellipsis) = self.advance_if(&[TokenKind::Ellipsis])? {
+                let loc = ellipsis.location().clone();
+                let rest = if let TokenKind::Ident(_) = self.current().kind() {
+                    Some(self.expect_ident()?)
+                }
+                else {
+                    None
+                };
+                self.expect(&[TokenKind::RParen])?;
+                return Ok((params, Some(Variadic::new(loc, rest))))
+            }
+
+            params.push(self.parse_function_param(&())?);
+            if self.expect(&[TokenKind::RParen, TokenKind::Comma])?.kind() == &TokenKind::RParen {
+                return Ok((params, None))
+            }
+        }
     }
 
     fn parse_function_param(&mut self, _: &()) -> ParseResult<'a, Param> {
@@ -101,45 +632,58 @@ impl<'a> Parser<'a> {
                     ParseError::Generic("Parameter requires either a type or default value.".into())
                         .with_location(loc)
                 ),
-            (Some(typ), Some(value)) => {
-                if value.typ() != &Some(typ) {
-                    Ok(Param::new(loc, ident, Some(typ), Some(value.implicit_cast(typ)))) 
-                }
-                else {
-                    Ok(Param::new(loc, ident, Some(typ), Some(value)))
-                }
-            }
+            (Some(typ), Some(value)) => Ok(Param::new(loc, ident, Some(typ), Some(self.coerce(value, typ)?))),
             (_, value) => Ok(Param::new(loc, ident, typ, value))
         }
     }
 
-    fn parse_function_body(&mut self, context: &StmtContext) -> ParseResult<'a, BasicFunctionBody> {
+    fn parse_function_body(&mut self, context: &StmtContext, result_type: Option<&Located<TypeIndex>>) -> ParseResult<'a, BasicFunctionBody> {
         Ok(if let TokenKind::Eq = self.expect(&[TokenKind::Eq, TokenKind::Be])?.kind() {
-            let expr = self.parse_expr(context)?;
+            let expr = self.parse_result_expr(context, result_type.map(|typ| **typ))?;
             self.advance_if(&[TokenKind::Semicolon])?;
             BasicFunctionBody::Expr(expr)
         }
         else {
+            self.check_routine_result_type(result_type)?;
             BasicFunctionBody::Stmt(self.parse_stmt(context)?)
         })
     }
 
+    // routines defined with `be` return no value
+    fn check_routine_result_type(&self, result_type: Option<&Located<TypeIndex>>) -> ParseResult<'a, ()> {
+        match result_type {
+            Some(typ) if self.get_type(TypeKind::Unit) != **typ => Err(
+                ParseError::RoutineResultType(self.ast.lock().unwrap().types().type_name(**typ))
+                    .with_location(typ.location().clone())
+            ),
+            _ => Ok(())
+        }
+    }
+
     fn check_correct_pattern_length(&self, patterns: &Vec<Located<Pattern>>, num_params: usize) -> ParseResult<'a, ()> {
         (patterns.len() == num_params).then(|| ())
             .ok_or_else(|| ParseError::WrongNumOfPatterns(num_params)
                         .with_location(patterns[0].location().clone()))
     }
 
-    fn parse_pattern_matched_stmt_body(&mut self, context: &StmtContext, first_pattern: Vec<Located<Pattern>>) -> ParseResult<'a, FunctionBody> {
+    fn parse_pattern_matched_stmt_body(&mut self, context: &StmtContext, result_type: Option<&Located<TypeIndex>>, first_pattern: Vec<Located<Pattern>>) -> ParseResult<'a, FunctionBody> {
         let num_params = context.in_function().unwrap().len();
         self.check_correct_pattern_length(&first_pattern, num_params)?;
+        self.check_routine_result_type(result_type)?;
 
-        let mut branches = vec![(first_pattern, self.parse_stmt(context)?)];
+        let first_stmt = self.scoped(|parser| {
+            parser.declare_locals(&first_pattern);
+            parser.parse_stmt(context)
+        })?;
+        let mut branches = vec![(first_pattern, first_stmt)];
 
         while self.advance_if(&[TokenKind::Colon])?.is_some() {
-            let pattern = self.parse_pattern_list()?;
+            let pattern = self.parse_match_patterns()?;
             self.expect(&[TokenKind::Be])?;
-            let stmt = self.parse_stmt(context)?;
+            let stmt = self.scoped(|parser| {
+                parser.declare_locals(&pattern);
+                parser.parse_stmt(context)
+            })?;
             self.check_correct_pattern_length(&pattern, num_params)?;
             branches.push((pattern, stmt));
         }
@@ -147,16 +691,23 @@ impl<'a> Parser<'a> {
         Ok(FunctionBody::PatternMatchedStmt(branches))
     }
 
-    fn parse_pattern_matched_expr_body(&mut self, context: &StmtContext, first_pattern: Vec<Located<Pattern>>) -> ParseResult<'a, FunctionBody> {
+    fn parse_pattern_matched_expr_body(&mut self, context: &StmtContext, result_type: Option<TypeIndex>, first_pattern: Vec<Located<Pattern>>) -> ParseResult<'a, FunctionBody> {
         let num_params = context.in_function().unwrap().len();
         self.check_correct_pattern_length(&first_pattern, num_params)?;
 
-        let mut branches = vec![(first_pattern, self.parse_expr(context)?)];
+        let first_expr = self.scoped(|parser| {
+            parser.declare_locals(&first_pattern);
+            parser.parse_result_expr(context, result_type)
+        })?;
+        let mut branches = vec![(first_pattern, first_expr)];
 
         while self.advance_if(&[TokenKind::Colon])?.is_some() {
-            let pattern = self.parse_pattern_list()?;
+            let pattern = self.parse_match_patterns()?;
             self.expect(&[TokenKind::Arrow])?;
-            let expr = self.parse_expr(context)?;
+            let expr = self.scoped(|parser| {
+                parser.declare_locals(&pattern);
+                parser.parse_result_expr(context, result_type)
+            })?;
             self.check_correct_pattern_length(&pattern, num_params)?;
             branches.push((pattern, expr));
         }
@@ -164,15 +715,15 @@ impl<'a> Parser<'a> {
         Ok(FunctionBody::PatternMatchedExpr(branches))
     }
 
-    fn parse_pattern_matched_body(&mut self, context: &StmtContext) -> ParseResult<'a, FunctionBody> {
+    fn parse_pattern_matched_body(&mut self, context: &StmtContext, result_type: Option<&Located<TypeIndex>>) -> ParseResult<'a, FunctionBody> {
         self.expect(&[TokenKind::Colon])?;
 
-        let pattern = self.parse_pattern_list()?;
+        let pattern = self.parse_match_patterns()?;
         if self.expect(&[TokenKind::Arrow, TokenKind::Be])?.kind() == &TokenKind::Be {
-            self.parse_pattern_matched_stmt_body(context, pattern)
+            self.parse_pattern_matched_stmt_body(context, result_type, pattern)
         }
         else {
-            self.parse_pattern_matched_expr_body(context, pattern)
+            self.parse_pattern_matched_expr_body(context, result_type.map(|typ| **typ), pattern)
         }
     }
 
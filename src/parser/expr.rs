@@ -1,17 +1,18 @@
 use std::cell::RefCell;
 
 use crate::{
-    ast::{expr::{Expr, ExprKind},
-    types::TypeKind, stmt::StmtKind, pattern::Pattern},
-    token::TokenKind, source_file::{WithLocation, Located}
+    ast::{expr::{Expr, ExprKind, OverflowMode},
+    types::{TypeKind, TypeIndex}, stmt::StmtKind, pattern::Pattern},
+    token::{TokenKind, lexer::unescape}, source_file::{WithLocation, Located, Location}
 };
 
-use super::{Parser, ParseResult, stmt::StmtContext, ParseError};
+use super::{Parser, ParseResult, stmt::StmtContext, ParseError, scope::method_symbol};
 
 #[derive(PartialEq, PartialOrd)]
 enum OperatorPrecedence {
-    Call = 9,
-    Cast = 8,
+    Call = 10,
+    Cast = 9,
+    FieldOf = 8,
     Product = 7,
     Sum = 6,
     BitShift = 5,
@@ -27,7 +28,7 @@ impl<'a> TryFrom<&TokenKind<'a>> for OperatorPrecedence {
 
     fn try_from(value: &TokenKind<'a>) -> Result<Self, Self::Error> {
         match value {
-            TokenKind::LParen | TokenKind::LBracket => Ok(Self::Call),
+            TokenKind::LParen | TokenKind::LBracket | TokenKind::Period | TokenKind::Bang => Ok(Self::Call),
             TokenKind::Plus | TokenKind::Minus => Ok(Self::Sum),
             TokenKind::Star | TokenKind::Slash | TokenKind::Mod => Ok(Self::Product),
             TokenKind::Eq | TokenKind::Ne
@@ -38,6 +39,7 @@ impl<'a> TryFrom<&TokenKind<'a>> for OperatorPrecedence {
             TokenKind::LogAnd => Ok(Self::And),
             TokenKind::Condition => Ok(Self::Conditional),
             TokenKind::Of => Ok(Self::Cast),
+            TokenKind::FieldOf => Ok(Self::FieldOf),
             _ => Err(())
         }
     }
@@ -49,15 +51,34 @@ impl<'a> Parser<'a> {
     }
 
     fn parse_expr_with_precedence(&mut self, context: &StmtContext, precedence: OperatorPrecedence) -> ParseResult<'a, Expr> {
-        let mut expr = self.parse_prefix_expr(context)?;
+        let expr = self.parse_prefix_expr(context)?;
+        self.parse_infix_exprs(context, expr, precedence)
+    }
 
-        while let Ok(op_prec) = self.current().kind().try_into() && precedence < op_prec {
+    fn parse_infix_exprs(&mut self, context: &StmtContext, mut expr: Expr, precedence: OperatorPrecedence) -> ParseResult<'a, Expr> {
+        while let Ok(op_prec) = self.current().kind().try_into() && precedence < op_prec && !self.at_attributes() {
             expr = self.parse_infix_expr(context, expr)?; 
         }
 
         Ok(expr)
     }
 
+    // an expression whose value is returned as `result_type`; a leading `valof` takes it as its annotation
+    pub(super) fn parse_result_expr(&mut self, context: &StmtContext, result_type: Option<TypeIndex>) -> ParseResult<'a, Expr> {
+        let Some(result_type) = result_type else {
+            return self.parse_expr(context)
+        };
+
+        let expr = if self.current().kind() == &TokenKind::ValOf {
+            let valof = self.parse_annotated_valof(context, Some(result_type))?;
+            self.parse_infix_exprs(context, valof, OperatorPrecedence::Lowest)?
+        }
+        else {
+            self.parse_expr(context)?
+        };
+        self.coerce(expr, result_type)
+    }
+
     fn parse_infix_expr(&mut self, context: &StmtContext, left: Expr) -> ParseResult<'a, Expr> {
         match self.current().kind() {
             TokenKind::LParen => self.parse_function_call(context, left),
@@ -74,39 +95,62 @@ impl<'a> Parser<'a> {
             TokenKind::Le => self.parse_comparison_op(context, left, ExprKind::Le),
             TokenKind::LShift => self.parse_binop(context, left, ExprKind::LShift, OperatorPrecedence::BitShift),
             TokenKind::RShift => self.parse_binop(context, left, ExprKind::RShift, OperatorPrecedence::BitShift),
-            TokenKind::LogOr => self.parse_binop(context, left, ExprKind::Or, OperatorPrecedence::Or),
-            TokenKind::LogAnd => self.parse_binop(context, left, ExprKind::And, OperatorPrecedence::And),
+            TokenKind::LogOr => self.parse_logical_op(context, left, ExprKind::LogOr, ExprKind::Or, OperatorPrecedence::Or),
+            TokenKind::LogAnd => self.parse_logical_op(context, left, ExprKind::LogAnd, ExprKind::And, OperatorPrecedence::And),
             TokenKind::XOr => self.parse_binop(context, left, ExprKind::XOr, OperatorPrecedence::Or),
             TokenKind::Condition => self.parse_conditional(context, left),
             TokenKind::Of => self.parse_explicit_cast(left),
+            TokenKind::FieldOf => self.parse_field_select(context, left),
             TokenKind::LBracket => self.parse_index_expr(context, left),
+            TokenKind::Period => self.parse_member(context, left),
+            TokenKind::Bang => self.parse_subscript(left, context),
             _ => self.unexpected(&[TokenKind::Ident("operator".into())])
         }
     }
 
     fn parse_prefix_expr(&mut self, context: &StmtContext) -> ParseResult<'a, Expr> {
+        self.contextual_keyword(&["checked", "wrapping", "saturating"]);
         match self.current().kind() {
             TokenKind::Ident(ident) => self.parse_ident(ident.to_string()),
             TokenKind::Atom(atom) => self.parse_atom(atom.to_string()),
             TokenKind::True | TokenKind::False => self.parse_bool_lit(),
+            TokenKind::Nil => self.parse_nil(),
             TokenKind::IntegerLit(int) => self.parse_integer_lit(*int),
-            TokenKind::StringLit(str) => self.parse_string_lit(str.to_string()),
+            TokenKind::StringLit(str) => self.parse_string_lit(unescape(str).expect("escapes are checked by the lexer")),
+            TokenKind::CharLit(ch) => self.parse_char_lit(unescape(ch).expect("escapes are checked by the lexer")),
             TokenKind::ValOf => self.parse_valof(context),
             TokenKind::LParen => self.parse_parens(context),
             TokenKind::Match => self.parse_match_expr(context, ExprKind::Match),
             TokenKind::Every => self.parse_match_expr(context, ExprKind::Every),
             TokenKind::Abs => self.parse_prefix_op(context, ExprKind::Abs),
             TokenKind::Not => self.parse_prefix_op(context, ExprKind::Not),
-            TokenKind::LogAnd => self.parse_ref(context),
-            TokenKind::At => self.parse_deref(context),
+            TokenKind::Checked => self.parse_prefix_op(context, |expr| ExprKind::Overflow(OverflowMode::Trap, expr)),
+            TokenKind::Wrapping => self.parse_prefix_op(context, |expr| ExprKind::Overflow(OverflowMode::Wrap, expr)),
+            TokenKind::Saturating => self.parse_prefix_op(context, |expr| ExprKind::Overflow(OverflowMode::Saturate, expr)),
+            TokenKind::At | TokenKind::LogAnd => self.parse_ref(context),
+            TokenKind::Bang => self.parse_deref(context),
+            TokenKind::SizeOf => self.parse_sizeof(),
+            TokenKind::OffsetOf => self.parse_offsetof(),
+            TokenKind::Vec => self.parse_vec(context),
+            TokenKind::Slct => self.parse_selector(context),
             _ => self.unexpected(&[TokenKind::Ident("expression".into())])
         }
     }
 
     fn parse_ident(&mut self, ident: String) -> ParseResult<'a, Expr> {
         let loc = self.advance()?.location().clone();
+        if let Some((typ, value)) = self.enum_constant(&ident) {
+            return Ok(Expr::new(loc, Some(typ), ExprKind::IntLit(value)))
+        }
+        let expr = self.resolve(ident, loc.clone());
+        // a routine capturing locals is passed its environment record when called
+        if let ExprKind::Ident(symbol) = expr.kind()
+            && self.current().kind() != &TokenKind::LParen
+            && self.environment_of(symbol, &loc).is_some() {
+            return Err(ParseError::CapturingRoutineValue(symbol.clone()).with_location(loc))
+        }
 
-        Ok(Expr::new(loc, None, ExprKind::Ident(ident)))
+        Ok(expr)
     }
     
     fn parse_atom(&mut self, atom: String) -> ParseResult<'a, Expr> {
@@ -126,6 +170,13 @@ impl<'a> Parser<'a> {
         Ok(Expr::new(loc, Some(self.get_type(TypeKind::Bool)), if t { ExprKind::True } else { ExprKind::False} ))
     }
 
+    fn parse_nil(&mut self) -> ParseResult<'a, Expr> {
+        let loc = self.advance()?.location().clone();
+        let typ = self.pointer_to(self.get_type(TypeKind::Unit));
+
+        Ok(Expr::new(loc, Some(typ), ExprKind::Nil))
+    }
+
     fn parse_integer_lit(&mut self, value: u64) -> ParseResult<'a, Expr> {
         let loc = self.advance()?.location().clone();
 
@@ -144,11 +195,29 @@ impl<'a> Parser<'a> {
         Ok(Expr::new(loc, Some(self.get_string_type()), ExprKind::StringLit(value)))
     }
 
+    fn parse_char_lit(&mut self, value: String) -> ParseResult<'a, Expr> {
+        let loc = self.advance()?.location().clone();
+        let ch = value.chars().next().expect("character literals are checked by the lexer");
+        Ok(Expr::new(loc, Some(self.get_type(TypeKind::Char)), ExprKind::CharLit(ch)))
+    }
+
     fn parse_valof(&mut self, context: &StmtContext) -> ParseResult<'a, Expr> {
+        self.parse_annotated_valof(context, None)
+    }
+
+    // `valof :: T` fixes the result type, so `resultis` is checked against it instead of inferring it
+    fn parse_annotated_valof(&mut self, context: &StmtContext, result_type: Option<TypeIndex>) -> ParseResult<'a, Expr> {
         let loc = self.current().location().clone();
         self.expect(&[TokenKind::ValOf])?;
 
-        let typ = RefCell::new(None);
+        let annotation = if self.advance_if(&[TokenKind::Of])?.is_some() {
+            Some(self.parse_result_type()?)
+        }
+        else {
+            None
+        };
+
+        let typ = RefCell::new(annotation.or(result_type).map(Some));
         let stmt = self.parse_stmt(&StmtContext::ValOf(&typ, context))?;
             
         let typ = typ.take()
@@ -164,31 +233,150 @@ impl<'a> Parser<'a> {
     }
 
     fn parse_function_call(&mut self, context: &StmtContext, callee: Expr) -> ParseResult<'a, Expr> {
-        let loc = self.expect(&[TokenKind::LParen])?.location().clone();
+        let (loc, mut args) = self.parse_call_args(context)?;
+        if let ExprKind::Ident(symbol) = callee.kind()
+            && let Some(env) = self.environment_of(symbol, &loc) {
+            args.insert(0, env);
+        }
+
+        // calls through routine pointers pass exactly the parameters of their type; named
+        // arguments are reported by the typechecker
+        if let Some(typ) = self.type_of(&callee)
+            && let Some((params, result)) = self.routine_signature(typ)
+            && !args.iter().any(|arg| matches!(arg.kind(), ExprKind::NamedArg(..))) {
+            if args.len() != params.len() {
+                let typ = self.ast.lock().unwrap().types().type_name(typ);
+                return Err(ParseError::IndirectCallArgs(typ, params.len(), args.len()).with_location(loc))
+            }
 
-        let args = self.parse_list(TokenKind::RParen, TokenKind::Comma, Self::parse_expr, context)?;
+            let args = args.into_iter()
+                .zip(params)
+                .map(|(arg, param)| self.coerce(arg, param))
+                .collect::<ParseResult<'a, Vec<_>>>()?;
+            return Ok(Expr::new(loc, Some(result), ExprKind::FuncCall(Box::new(callee), args)))
+        }
 
         Ok(Expr::new(loc, None, ExprKind::FuncCall(Box::new(callee), args)))
     }
 
+    // `(args)`, with the location of the opening parenthesis
+    fn parse_call_args(&mut self, context: &StmtContext) -> ParseResult<'a, (Location, Vec<Expr>)> {
+        let loc = self.expect(&[TokenKind::LParen])?.location().clone();
+
+        let args = self.parse_list(TokenKind::RParen, TokenKind::Comma, Self::parse_call_arg, context)?;
+        // named arguments fill the parameters the positional ones leave
+        if let Some(first) = args.iter().position(|arg| matches!(arg.kind(), ExprKind::NamedArg(..)))
+            && let Some(positional) = args[first..].iter().find(|arg| !matches!(arg.kind(), ExprKind::NamedArg(..))) {
+            return Err(ParseError::PositionalAfterNamed.with_location(positional.location().clone()))
+        }
+        Ok((loc, args))
+    }
+
+    // `name := value` or a positional argument
+    fn parse_call_arg(&mut self, context: &StmtContext) -> ParseResult<'a, Expr> {
+        let TokenKind::Ident(name) = self.current().kind() else {
+            return self.parse_expr(context)
+        };
+        if !self.lexer.clone().next().is_some_and(|token| token.kind() == &TokenKind::Assign) {
+            return self.parse_expr(context)
+        }
+
+        let name = name.to_string();
+        let loc = self.advance()?.location().clone();
+        self.expect(&[TokenKind::Assign])?;
+        let value = self.parse_expr(context)?;
+        Ok(Expr::new(loc, None, ExprKind::NamedArg(name, Box::new(value))))
+    }
+
     fn parse_binop(&mut self, context: &StmtContext, left: Expr, op_init: fn(Box<Expr>, Box<Expr>) -> ExprKind, precedence: OperatorPrecedence) -> ParseResult<'a, Expr> {
         let tok = self.advance()?;
         let mut right = self.parse_expr_with_precedence(context, precedence)?;
 
         let typ = left.typ().clone();
-        if let Some(typ) = &typ && &Some(*typ) != right.typ() {
-            right = right.implicit_cast(*typ);
+        if let Some(typ) = typ {
+            right = self.coerce(right, typ)?;
         }
 
         Ok(Expr::new(tok.location().clone(), typ, op_init(Box::new(left), Box::new(right))))
     }
 
+    // `&` and `|` short-circuit if either operand is a boolean and act bitwise on words otherwise
+    fn parse_logical_op(&mut self, context: &StmtContext, left: Expr, short_init: fn(Box<Expr>, Box<Expr>) -> ExprKind, bitwise_init: fn(Box<Expr>, Box<Expr>) -> ExprKind, precedence: OperatorPrecedence) -> ParseResult<'a, Expr> {
+        let tok = self.advance()?;
+        let right = self.parse_expr_with_precedence(context, precedence)?;
+
+        let bool_typ = self.get_type(TypeKind::Bool);
+        let is_bool = |expr: &Expr| expr.typ().is_some_and(|typ| {
+            let ast = self.ast.lock().unwrap();
+            ast.types().resolve(typ) == bool_typ
+        });
+
+        if !is_bool(&left) && !is_bool(&right) {
+            let typ = left.typ().clone();
+            let right = match typ {
+                Some(typ) => self.coerce(right, typ)?,
+                None => right
+            };
+            return Ok(Expr::new(tok.location().clone(), typ, bitwise_init(Box::new(left), Box::new(right))))
+        }
+
+        if right.has_sideeffect() {
+            self.push_warning(ParseError::ShortCircuitSideEffect(tok.kind().to_string()).with_location(right.location().clone()));
+        }
+
+        let left = self.coerce(left, bool_typ)?;
+        let right = self.coerce(right, bool_typ)?;
+        Ok(Expr::new(tok.location().clone(), Some(bool_typ), short_init(Box::new(left), Box::new(right))))
+    }
+
     fn parse_comparison_op(&mut self, context: &StmtContext, left: Expr, op_init: fn(Box<Expr>, Box<Expr>) -> ExprKind) -> ParseResult<'a, Expr> {
         let mut binop = self.parse_binop(context, left, op_init, OperatorPrecedence::Comparison)?;
+        if let ExprKind::Eq(lhs, rhs) | ExprKind::Ne(lhs, rhs) = binop.kind() {
+            // `nil = x` coerced `x` to `nil`'s type, so check `x` itself
+            if lhs.is_nil() && let ExprKind::ImplicitCast(other) = rhs.kind() {
+                self.expect_pointer(other, binop.location())?;
+            }
+        }
+        else if binop.kind().children().into_iter().any(Expr::is_nil) {
+            return Err(ParseError::NilOrdering.with_location(binop.location().clone()))
+        }
+
+        // names are untyped, so operands are not coerced to their enumeration
+        let operand_type = |expr: &Expr| match expr.kind() {
+            ExprKind::ImplicitCast(inner) if inner.typ().is_none() => self.type_of(inner),
+            _ => self.type_of(expr)
+        };
+        if let [lhs, rhs] = binop.kind().children()[..] {
+            self.check_mixed_enums(operand_type(lhs), operand_type(rhs), binop.location())?;
+        }
+
         binop.set_typ(self.get_type(TypeKind::Bool));
         Ok(binop)
     }
 
+    fn expect_pointer(&self, expr: &Expr, loc: &Location) -> ParseResult<'a, ()> {
+        let Some(typ) = expr.typ() else {
+            return Ok(())
+        };
+
+        let ast = self.ast.lock().unwrap();
+        let types = ast.types();
+        match types.get(types.resolve(*typ)).map(|typ| typ.kind()) {
+            Some(TypeKind::Pointer(_) | TypeKind::Routine(..) | TypeKind::Alias(_, None)) => Ok(()),
+            _ => Err(ParseError::NilComparison(types.type_name(*typ)).with_location(loc.clone()))
+        }
+    }
+
+    // `-x` as `0 - x`, binding tighter than `+` and `-`
+    pub(super) fn parse_negation(&mut self, context: &StmtContext) -> ParseResult<'a, Expr> {
+        let loc = self.expect(&[TokenKind::Minus])?.location().clone();
+        let operand = self.parse_expr_with_precedence(context, OperatorPrecedence::Sum)?;
+
+        let zero = Expr::new(loc.clone(), operand.typ().clone(), ExprKind::IntLit(0));
+        let negation = Expr::new(loc, operand.typ().clone(), ExprKind::Sub(Box::new(zero), Box::new(operand)));
+        self.parse_infix_exprs(context, negation, OperatorPrecedence::Lowest)
+    }
+
     fn parse_prefix_op(&mut self, context: &StmtContext, op_init: fn(Box<Expr>) -> ExprKind) -> ParseResult<'a, Expr> {
         let loc = self.advance()?.location().clone();
         
@@ -197,26 +385,102 @@ impl<'a> Parser<'a> {
         Ok(Expr::new(loc, expr.typ().clone(), op_init(Box::new(expr))))
     }
 
-    fn parse_conditional(&mut self, context: &StmtContext, mut condition: Expr) -> ParseResult<'a, Expr> {
+    fn parse_conditional(&mut self, context: &StmtContext, condition: Expr) -> ParseResult<'a, Expr> {
         let loc = self.expect(&[TokenKind::Condition])?.location().clone();
 
-        let bool_typ = self.get_type(TypeKind::Bool);
-        if condition.typ() != &Some(bool_typ) {
-            condition = condition.implicit_cast(bool_typ);
-        }
+        let condition = self.coerce(condition, self.get_type(TypeKind::Bool))?;
 
-        let if_branch = self.parse_expr(context)?;
+        let mut if_branch = self.parse_expr(context)?;
         self.expect(&[TokenKind::Comma])?;
-        let mut else_branch = self.parse_expr_with_precedence(context, OperatorPrecedence::Conditional)?;
+        // right-associative: `a -> x, b -> y, z` is `a -> x, (b -> y, z)`
+        let mut else_branch = self.parse_expr(context)?;
 
-        let typ = if_branch.typ().clone();
-        if typ.is_some() && else_branch.typ() != &typ {
-            else_branch = else_branch.implicit_cast(typ.unwrap());
+        let typ = self.common_type(&if_branch, &else_branch);
+        if let Some(typ) = typ {
+            if_branch = self.coerce(if_branch, typ)?;
+            else_branch = self.coerce(else_branch, typ)?;
         }
 
         Ok(Expr::new(loc, typ, ExprKind::Conditional(Box::new(condition), Box::new(if_branch), Box::new(else_branch))))
     }
 
+    // `slct offset`, `slct shift:offset` or `slct size:shift:offset`; omitted parts are 0
+    fn parse_selector(&mut self, context: &StmtContext) -> ParseResult<'a, Expr> {
+        let loc = self.expect(&[TokenKind::Slct])?.location().clone();
+        let word = self.get_type(TypeKind::UInt64);
+
+        let mut parts = vec![];
+        loop {
+            let part = self.parse_expr_with_precedence(context, OperatorPrecedence::FieldOf)?;
+            parts.push(self.coerce(part, word)?);
+            if parts.len() == 3 || self.advance_if(&[TokenKind::Colon])?.is_none() {
+                break
+            }
+        }
+        while parts.len() < 3 {
+            parts.insert(0, Expr::new(loc.clone(), Some(word), ExprKind::IntLit(0)));
+        }
+
+        let [size, shift, offset] = parts.try_into().unwrap();
+        Ok(Expr::new(loc, Some(word), ExprKind::Selector(Box::new(size), Box::new(shift), Box::new(offset))))
+    }
+
+    // `selector of pointer`, right-associative: `a of b of p` selects `a` of the record at `b of p`
+    fn parse_field_select(&mut self, context: &StmtContext, selector: Expr) -> ParseResult<'a, Expr> {
+        let loc = self.expect(&[TokenKind::FieldOf])?.location().clone();
+        let word = self.get_type(TypeKind::UInt64);
+        let selector = self.coerce(selector, word)?;
+
+        let ptr = self.parse_expr_with_precedence(context, OperatorPrecedence::Product)?;
+        if ptr.is_nil() {
+            return Err(ParseError::NilDeref.with_location(ptr.location().clone()))
+        }
+
+        Ok(Expr::new(loc, Some(word), ExprKind::FieldSelect(Box::new(selector), Box::new(ptr))))
+    }
+
+    // `value.field`; pointers to structs are dereferenced first. Fields of types that are not
+    // defined yet cannot be checked and are left untyped.
+    fn parse_member(&mut self, context: &StmtContext, value: Expr) -> ParseResult<'a, Expr> {
+        let loc = self.expect(&[TokenKind::Period])?.location().clone();
+        let field = self.expect_ident()?;
+
+        let Some(typ) = self.type_of(&value) else {
+            return Err(ParseError::UntypedMember(field).with_location(loc))
+        };
+
+        // `value.method(args)` is `Type.method(value, args)`, unless `Type` has a field of that name
+        if self.current().kind() == &TokenKind::LParen
+            && self.ast.lock().unwrap().types().field_type(typ, &field).is_none()
+            && let Some(record) = self.method_record(typ) {
+            let callee = Expr::new(loc, None, ExprKind::Ident(method_symbol(&record, &field)));
+            let (loc, mut args) = self.parse_call_args(context)?;
+            args.insert(0, value);
+            return Ok(Expr::new(loc, None, ExprKind::FuncCall(Box::new(callee), args)))
+        }
+
+        let ast = self.ast.lock().unwrap();
+        let types = ast.types();
+        let kind_of = |typ: TypeIndex| types.get(types.resolve(typ)).map(|typ| typ.kind());
+        let pointee = match kind_of(typ) {
+            Some(TypeKind::Pointer(inner)) => Some(*inner),
+            _ => None
+        };
+
+        let field_typ = match types.field_type(typ, &field) {
+            Some(field_typ) => Some(field_typ),
+            None if matches!(kind_of(pointee.unwrap_or(typ)), Some(TypeKind::Alias(_, None))) => None,
+            None => return Err(ParseError::NoSuchField(types.type_name(typ), field).with_location(loc))
+        };
+        drop(ast);
+
+        let value = match pointee {
+            Some(pointee) => Expr::new(value.location().clone(), Some(pointee), ExprKind::Deref(Box::new(value))),
+            None => value
+        };
+        Ok(Expr::new(loc, field_typ, ExprKind::Member(Box::new(value), field)))
+    }
+
     fn parse_explicit_cast(&mut self, expr: Expr) -> ParseResult<'a, Expr> {
         let loc = self.expect(&[TokenKind::Of])?.location().clone();
         let typ = self.parse_type()?;
@@ -224,19 +488,79 @@ impl<'a> Parser<'a> {
         Ok(Expr::new(loc, Some(typ), ExprKind::Cast(Box::new(expr))))
     }
 
+    // `@x`, the address of `x`; `&&x` is the older spelling
     fn parse_ref(&mut self, context: &StmtContext) -> ParseResult<'a, Expr> {
-        let loc = self.expect(&[TokenKind::LogAnd])?.location().clone();
-        let expr = self.parse_expr(context)?;
-        let typ = expr.typ().map(|typ| self.pointer_to(typ));
+        let loc = self.expect(&[TokenKind::At, TokenKind::LogAnd])?.location().clone();
+        let expr = self.parse_expr_with_precedence(context, OperatorPrecedence::Cast)?;
+        if !expr.is_lvalue() {
+            return Err(ParseError::NotAddressable.with_location(expr.location().clone()))
+        }
+        let typ = self.type_of(&expr).map(|typ| self.pointer_to(typ));
 
         Ok(Expr::new(loc, typ, ExprKind::Ref(Box::new(expr))))
     }
 
+    // `!p`, the value `p` points to
     fn parse_deref(&mut self, context: &StmtContext) -> ParseResult<'a, Expr> {
-        let loc = self.expect(&[TokenKind::At])?.location().clone();
-        let expr = self.parse_expr(context)?;
-        
-        Ok(Expr::new(loc, None, ExprKind::Deref(Box::new(expr))))
+        let loc = self.expect(&[TokenKind::Bang])?.location().clone();
+        let expr = self.parse_expr_with_precedence(context, OperatorPrecedence::Cast)?;
+        if expr.is_nil() {
+            return Err(ParseError::NilDeref.with_location(loc))
+        }
+
+        let typ = self.pointee_of(&expr, false)?;
+        Ok(Expr::new(loc, typ, ExprKind::Deref(Box::new(expr))))
+    }
+
+    // `p!i`, the `i`th value after the one `p` points to; left-associative, so `p!i!j` is `(p!i)!j`
+    fn parse_subscript(&mut self, ptr: Expr, context: &StmtContext) -> ParseResult<'a, Expr> {
+        let loc = self.expect(&[TokenKind::Bang])?.location().clone();
+        if ptr.is_nil() {
+            return Err(ParseError::NilDeref.with_location(loc))
+        }
+        let index = self.parse_expr_with_precedence(context, OperatorPrecedence::Call)?;
+
+        let typ = self.pointee_of(&ptr, true)?;
+        Ok(Expr::new(loc, typ, ExprKind::Index(Box::new(ptr), Box::new(index))))
+    }
+
+    // the type `ptr` points to; subscripts also take arrays and slices. Values of unknown type are not checked.
+    fn pointee_of(&self, ptr: &Expr, subscript: bool) -> ParseResult<'a, Option<TypeIndex>> {
+        let Some(typ) = self.type_of(ptr) else {
+            return Ok(None)
+        };
+
+        let ast = self.ast.lock().unwrap();
+        let types = ast.types();
+        match types.get(types.resolve(typ)).map(|typ| typ.kind()) {
+            Some(TypeKind::Pointer(inner)) => Ok(Some(*inner)),
+            Some(TypeKind::Array(inner, _) | TypeKind::Slice(inner)) if subscript => Ok(Some(*inner)),
+            Some(TypeKind::Alias(_, None)) => Ok(None),
+            _ => Err(ParseError::DerefNonPointer(types.type_name(typ)).with_location(ptr.location().clone()))
+        }
+    }
+
+    fn parse_sizeof(&mut self) -> ParseResult<'a, Expr> {
+        let loc = self.expect(&[TokenKind::SizeOf])?.location().clone();
+        let typ = self.parse_type()?;
+
+        Ok(Expr::new(loc, Some(self.get_type(TypeKind::UInt64)), ExprKind::SizeOf(typ)))
+    }
+
+    fn parse_vec(&mut self, context: &StmtContext) -> ParseResult<'a, Expr> {
+        let loc = self.expect(&[TokenKind::Vec])?.location().clone();
+        let size = self.parse_expr(context)?;
+
+        Ok(Expr::new(loc, None, ExprKind::Vec(Box::new(size))))
+    }
+
+    fn parse_offsetof(&mut self) -> ParseResult<'a, Expr> {
+        let loc = self.expect(&[TokenKind::OffsetOf])?.location().clone();
+        let typ = self.parse_type()?;
+        self.expect(&[TokenKind::Period])?;
+        let field = self.expect_ident()?;
+
+        Ok(Expr::new(loc, Some(self.get_type(TypeKind::UInt64)), ExprKind::OffsetOf(typ, field)))
     }
 
     fn parse_index_expr(&mut self, context: &StmtContext, left: Expr) -> ParseResult<'a, Expr> {
@@ -256,6 +580,7 @@ impl<'a> Parser<'a> {
     }
 
     fn parse_match_expr(&mut self, context: &StmtContext, init: fn(Vec<Expr>, Vec<(Vec<Located<Pattern>>, Box<Expr>)>) -> ExprKind) -> ParseResult<'a, Expr> {
+        let keyword = self.current().kind().to_string();
         let loc = self.advance()?.location().clone();
 
         let args = if self.current().kind() != &TokenKind::LParen {
@@ -268,17 +593,26 @@ impl<'a> Parser<'a> {
         let mut branches = vec![];
         let mut typ = None;
         while self.advance_if(&[TokenKind::Colon])?.is_some() {
-            let patterns = self.parse_pattern_list()?;
+            let patterns = self.parse_match_patterns()?;
             if patterns.len() != args.len() {
                 return Err(ParseError::WrongNumOfPatterns(args.len()).with_location(loc))
             }
 
-            self.expect(&[TokenKind::Arrow])?;
-            let mut expr = self.parse_expr(&StmtContext::Match(context))?;
-            
+            let arm_context = StmtContext::Match(context);
+            let mut expr = self.scoped(|parser| {
+                parser.declare_locals(&patterns);
+                if parser.current().kind() == &TokenKind::Be {
+                    parser.parse_match_arm_stmt(&arm_context, typ.flatten())
+                }
+                else {
+                    parser.expect(&[TokenKind::Be, TokenKind::Arrow])?;
+                    parser.parse_expr(&arm_context)
+                }
+            })?;
+
             if let Some(typ) = typ {
-                if let Some(_) = typ && expr.typ() != &typ {
-                    expr = expr.implicit_cast(typ.unwrap());
+                if let Some(typ) = typ {
+                    expr = self.coerce(expr, typ)?;
                 }
             }
             else {
@@ -289,9 +623,21 @@ impl<'a> Parser<'a> {
         }
 
         if branches.is_empty() {
-            return Err(ParseError::MissingBranch("match".into()).with_location(loc))
+            return Err(ParseError::MissingBranch(keyword).with_location(loc))
         }
 
         Ok(Expr::new(loc, typ.unwrap(), init(args, branches)))
     }
+
+    // `: patterns be stmt` arms deliver their value with `resultis`, like a `valof` block
+    fn parse_match_arm_stmt(&mut self, context: &StmtContext, result_type: Option<TypeIndex>) -> ParseResult<'a, Expr> {
+        let loc = self.expect(&[TokenKind::Be])?.location().clone();
+
+        let typ = RefCell::new(result_type.map(Some));
+        let stmt = self.parse_stmt(&StmtContext::ValOf(&typ, context))?;
+
+        let typ = typ.take()
+            .ok_or_else(|| ParseError::NoResultValue.with_location(loc.clone()))?;
+        Ok(Expr::new(loc, typ, ExprKind::ValOf(Box::new(stmt))))
+    }
 }
@@ -1,10 +1,11 @@
-use std::{ops::Deref, sync::{Arc, Mutex}};
+use std::{collections::HashMap, ops::Deref, sync::{Arc, Mutex}};
 
 use crate::{
     token::{lexer::Lexer, Token, TokenKind},
     source_file::{Location, Located, WithLocation},
-    ast::{Program, stmt::StmtKind},
-    error::{IntoCompilerError, CompilerError, Severity}
+    ast::{Program, Function, stmt::StmtKind, types::TypeIndex},
+    error::{IntoCompilerError, CompilerError, Severity},
+    lint::{Lint, LintConfig}
 };
 
 mod types;
@@ -12,21 +13,66 @@ mod decls;
 mod expr;
 mod stmt;
 mod pattern;
+mod scope;
 
 pub struct Parser<'a> {
     lexer: Lexer<'a>,
     ast: Arc<Mutex<Program>>,
+    lints: &'a LintConfig,
     warnings: Vec<Located<ParseError<'a>>>,
+    // errors the parser recovered from, leaving `Error` nodes in the ast
+    errors: Vec<Located<ParseError<'a>>>,
+    // files requested by `get` directives, relative to this file
+    includes: Vec<Located<String>>,
+    // `break` and `next` outside of loops, valid if a trailing `repeat` follows
+    loop_exits: Vec<Located<ParseError<'a>>>,
+    // open `{` and `$(` brackets with their section tags
+    sections: Vec<(Option<&'a str>, Location)>,
+    // names declared within the routines being parsed, innermost last
+    scope: Vec<(String, scope::Local)>,
+    // the symbol of the innermost routine being parsed, and where its names start in `scope`
+    routine: Option<String>,
+    routine_scope: usize,
+    // labels and `goto` targets of the innermost routine being parsed
+    labels: Vec<Located<String>>,
+    gotos: Vec<Located<String>>,
+    // labels within the bodies of `try` statements, and the `goto`s leaving such a body
+    try_labels: Vec<Located<String>>,
+    try_exits: Vec<Located<String>>,
+    // locals of enclosing routines the innermost routine being parsed refers to
+    captures: Vec<String>,
+    // type parameters of the generic routine being parsed, by name
+    type_params: Vec<(String, TypeIndex)>,
+    // routines nested in the declaration being parsed, declared in its section after it
+    nested_routines: Vec<Function>,
+    // declared types of the globals and statics parsed so far
+    global_types: HashMap<String, TypeIndex>,
     current_token: Token<'a>
 }
 
 impl<'a> Parser<'a> {
-    pub fn new(lexer: Lexer<'a>, ast: Arc<Mutex<Program>>) -> Self {
+    pub fn new(lexer: Lexer<'a>, ast: Arc<Mutex<Program>>, lints: &'a LintConfig) -> Self {
         Self {
             warnings: vec![],
+            errors: vec![],
+            includes: vec![],
+            loop_exits: vec![],
+            sections: vec![],
+            scope: vec![],
+            routine: None,
+            routine_scope: 0,
+            labels: vec![],
+            gotos: vec![],
+            try_labels: vec![],
+            try_exits: vec![],
+            captures: vec![],
+            type_params: vec![],
+            nested_routines: vec![],
+            global_types: HashMap::new(),
             current_token: Token::eof(lexer.current_loc()),
             lexer,
-            ast
+            ast,
+            lints
         }
     }
 
@@ -38,6 +84,43 @@ impl<'a> Parser<'a> {
         &self.warnings
     }
 
+    pub fn errors(&self) -> &Vec<Located<ParseError<'a>>> {
+        &self.errors
+    }
+
+    pub fn includes(&self) -> &Vec<Located<String>> {
+        &self.includes
+    }
+
+    // records `err` and skips tokens up to one of `stop` outside of any brackets; a stopping
+    // `;` is consumed. Returns the location of the skipped region starting at `start`.
+    fn recover(&mut self, err: Located<ParseError<'a>>, mut start: Location, stop: &[TokenKind<'a>]) -> Location {
+        self.errors.push(err);
+
+        let mut depth = 0usize;
+        while !self.current().is_eof() {
+            let kind = self.current().kind().clone();
+            if depth == 0 && stop.contains(&kind) {
+                if kind == TokenKind::Semicolon {
+                    start.extend_to(self.current().location());
+                    self.advance().ok();
+                }
+                break;
+            }
+
+            match kind {
+                TokenKind::LParen | TokenKind::LBrace | TokenKind::LBracket => depth += 1,
+                TokenKind::RParen | TokenKind::RBrace | TokenKind::RBracket => depth = depth.saturating_sub(1),
+                _ => ()
+            }
+            start.extend_to(self.current().location());
+            // lexer errors in skipped regions are not reported separately
+            self.advance().ok();
+        }
+
+        start
+    }
+
     fn current(&self) -> &Token<'a> {
         &self.current_token
     }
@@ -49,6 +132,8 @@ impl<'a> Parser<'a> {
                 .unwrap_or_else(|| Token::error(self.lexer.current_loc(), Some("could not get next token".into())))
         );
 
+        self.match_section_brackets();
+
         if let TokenKind::Error(msg) = self.current().kind() { 
             Err(
                 ParseError::Generic(msg.clone().unwrap_or_default())
@@ -60,6 +145,22 @@ impl<'a> Parser<'a> {
         }
     }
 
+    // a tagged `$)tag` has to close the innermost open section; untagged brackets close any
+    fn match_section_brackets(&mut self) {
+        let token = &self.current_token;
+        match token.kind() {
+            TokenKind::LBrace => self.sections.push((token.tag(), token.location().clone())),
+            TokenKind::RBrace => match (self.sections.pop(), token.tag()) {
+                (Some((open, open_loc)), Some(close)) if open != Some(close) => {
+                    let err = ParseError::MismatchedSection(open_loc, open.map(str::to_string), close.to_string());
+                    self.errors.push(err.with_location(token.location().clone()));
+                }
+                _ => ()
+            }
+            _ => ()
+        }
+    }
+
     fn advance_if(&mut self, expect: &[TokenKind<'a>]) -> ParseResult<'a, Option<Token<'a>>> {
         if expect.contains(self.current().kind()) {
             self.advance().map(Some)
@@ -81,6 +182,40 @@ impl<'a> Parser<'a> {
         }
     }
 
+    // `try`, `catch`, `throw`, `rethrow`, `checked`, `wrapping` and `saturating` are lexed as names,
+    // so that programs using them as such keep their meaning. One of `words` becomes its keyword
+    // where the token after it could not follow a name; `catch` only right after a `try` body.
+    fn contextual_keyword(&mut self, words: &[&str]) {
+        let TokenKind::Ident(word) = *self.current().kind() else {
+            return
+        };
+        let keyword = match word {
+            "try" => TokenKind::Try,
+            "catch" => TokenKind::Catch,
+            "throw" => TokenKind::Throw,
+            "rethrow" => TokenKind::Rethrow,
+            "checked" => TokenKind::Checked,
+            "wrapping" => TokenKind::Wrapping,
+            "saturating" => TokenKind::Saturating,
+            _ => return
+        };
+        if !words.contains(&word) {
+            return
+        }
+
+        let next = self.lexer.clone().next();
+        let next = next.as_ref().map_or(&TokenKind::Eof, Token::kind);
+        let is_keyword = match keyword {
+            TokenKind::Catch => true,
+            // the only one not followed by an operand
+            TokenKind::Rethrow => !continues_name(next),
+            _ => !continues_name(next) && !ends_operand(next)
+        };
+        if is_keyword {
+            self.current_token = Token::new(self.current().location().clone(), keyword);
+        }
+    }
+
     fn expect_ident(&mut self) -> ParseResult<'a, String> {
         if let TokenKind::Ident(ident) = self.current().kind() {
             let ident = ident.to_string();
@@ -94,7 +229,7 @@ impl<'a> Parser<'a> {
 
     fn unexpected<T>(&mut self, want: &[TokenKind<'a>]) -> ParseResult<'a, T> {
         Err(
-            ParseError::UnexpectedToken(self.current().kind().to_string(), want.to_vec())
+            ParseError::UnexpectedToken(self.current().kind().to_string(), want.to_vec(), suggest_token(self.current().kind(), want))
                 .with_location(self.current().location().clone())
         )
     }
@@ -103,7 +238,12 @@ impl<'a> Parser<'a> {
         self.advance()?;
         
         while !self.current_token.is_eof() {
-            self.parse_section()?;
+            if self.current().kind() == &TokenKind::Get {
+                self.parse_get()?;
+            }
+            else {
+                self.parse_section()?;
+            }
         }
 
         Ok(())
@@ -120,8 +260,13 @@ impl<'a> Parser<'a> {
 
     fn parse_list<T, U>(&mut self, end: TokenKind<'a>, delim: TokenKind<'a>, parse_func: fn(&mut Self, &U) -> ParseResult<'a, T>, param: &U) -> ParseResult<'a, Vec<T>> {
         let mut elems = vec![];
+        // `()`, an empty list
+        if self.advance_if(&[end.clone()])?.is_some() {
+            return Ok(elems)
+        }
+
         let delims = [end.clone(), delim];
-        while self.current_token.kind() != &end {
+        loop {
             elems.push(parse_func(self, param)?); 
             
             if self.expect(&delims)?.kind() == &end {
@@ -147,14 +292,67 @@ pub enum ParseError<'a> {
     NotImplemented,
     Generic(String),
     UnexpectedEof(Vec<TokenKind<'a>>),
-    UnexpectedToken(String, Vec<TokenKind<'a>>),
+    //              found   expected              suggested replacement
+    UnexpectedToken(String, Vec<TokenKind<'a>>, Option<TokenKind<'a>>),
     Redefinition(Location, String),
     InvalidStmt(String, String),
     WrongNumOfPatterns(usize),
+    //               targets values
+    WrongNumOfValues(usize, usize),
+    NotAssignable,
     NoResultValue,
     RequireAfterDecl,
     ExprWithoutSideEffect,
+    UnusedResult(String),
     MissingBranch(String),
+    OverlappingCase(Location),
+    EmptyCaseRange(i64, i64),
+    StringCaseLabel,
+    InvalidCoercion(String, String),
+    NilOrdering,
+    NilComparison(String),
+    NilDeref,
+    DerefNonPointer(String),
+    NotAddressable,
+    ShortCircuitSideEffect(String),
+    //           init limit
+    EmptyForLoop(i64, i64),
+    InfiniteForLoop,
+    UnboundedForLoop,
+    RoutineResultType(String),
+    UnknownAttribute(String),
+    //             name    expected arguments
+    AttributeArgs(String, &'static str),
+    //               name    invalid target
+    AttributeTarget(String, &'static str),
+    //                opening   tag of opening  closing tag
+    MismatchedSection(Location, Option<String>, String),
+    //      local   routine
+    Capture(String, String),
+    CapturingRoutineValue(String),
+    NonTrailingDefault(Location),
+    UndefinedLabel(String),
+    PositionalAfterNamed,
+    RecursiveType(String),
+    //         type    field
+    NoSuchField(String, String),
+    UntypedMember(String),
+    MixedEnums(String, String),
+    //                 param   routine
+    UninferableTypeParam(String, String),
+    NestedInGeneric(String),
+    MethodWithoutReceiver(String),
+    //                method  record type
+    MethodReceiverType(String, String),
+    DeferInSwitchOn,
+    DeferredExit(String),
+    //           label
+    GotoAcrossTry(String),
+    CaseInTry,
+    AsmOperand(String),
+    UnknownAsmOperand(String),
+    //                routine type  params given
+    IndirectCallArgs(String, usize, usize)
 }
 
 impl<'a> ParseError<'a> {
@@ -162,13 +360,65 @@ impl<'a> ParseError<'a> {
         match self {
             Self::RequireAfterDecl => Severity::Warning,
             Self::ExprWithoutSideEffect => Severity::Warning,
+            Self::UnusedResult(_) => Severity::Warning,
+            Self::ShortCircuitSideEffect(_) => Severity::Warning,
+            Self::EmptyForLoop(..) | Self::InfiniteForLoop | Self::UnboundedForLoop => Severity::Warning,
+            Self::UnknownAttribute(_) => Severity::Warning,
+            Self::Capture(..) => Severity::Warning,
             _ => Severity::Error
         }
     }
 
+    pub fn lint(&self) -> Option<Lint> {
+        match self {
+            Self::RequireAfterDecl => Some(Lint::RequireAfterDecl),
+            Self::ExprWithoutSideEffect => Some(Lint::UnusedExpression),
+            Self::UnusedResult(_) => Some(Lint::UnusedResult),
+            Self::ShortCircuitSideEffect(_) => Some(Lint::ShortCircuit),
+            Self::EmptyForLoop(..) | Self::InfiniteForLoop | Self::UnboundedForLoop => Some(Lint::LoopBounds),
+            Self::UnknownAttribute(_) => Some(Lint::UnknownAttribute),
+            Self::Capture(..) => Some(Lint::Capture),
+            _ => None
+        }
+    }
+
     fn hint(&self) -> Option<String> {
         match self {
             Self::RequireAfterDecl => Some("Move this over the first declaration.".into()),
+            Self::UnexpectedToken(got, _, Some(fix)) => Some(format!("Replace `{got}` with `{fix}`.")),
+            Self::InvalidCoercion(..) => Some("Use an explicit cast with `::`.".into()),
+            Self::Capture(..) => Some("Pass it as an argument, or use a `static` or `global` variable.".into()),
+            Self::CapturingRoutineValue(_) => Some("Pass the locals it refers to as arguments instead.".into()),
+            Self::NonTrailingDefault(_) => Some("Give it a default value too, or move it before the parameters with default values.".into()),
+            Self::UndefinedLabel(_) => Some("`goto` can only jump to labels of the same routine.".into()),
+            Self::PositionalAfterNamed => Some("Pass it by name too, or move it before the named arguments.".into()),
+            Self::RecursiveType(typ) => Some(format!("Refer to it through a pointer, like `&{typ}`.")),
+            Self::UntypedMember(_) => Some("Declare its type with `::`, or cast it to a struct type.".into()),
+            Self::MixedEnums(..) => Some("Cast one of them explicitly with `::`.".into()),
+            Self::UninferableTypeParam(..) => Some("Type arguments are inferred from the arguments of each call, so every type parameter has to occur in the type of a parameter.".into()),
+            Self::NestedInGeneric(_) => Some("Declare the routine outside of it, passing what it needs as arguments.".into()),
+            Self::MethodWithoutReceiver(_) => Some("The value a method is called on is passed as its first parameter.".into()),
+            Self::DeferInSwitchOn => Some("Put the statements of the case into a block of their own.".into()),
+            Self::DeferredExit(_) => Some("Deferred statements run on the way out of their block and have to finish normally.".into()),
+            Self::GotoAcrossTry(_) => Some("A `try` is entered at its start; leave it with `break`, `next`, `loop`, `resultis` or `return`.".into()),
+            Self::CaseInTry => Some("Put the `try` statement after the label instead.".into()),
+            Self::AsmOperand(_) => Some("Operands live in the routine's frame; copy other values into a local first.".into()),
+            Self::UnknownAsmOperand(_) => Some("Bind it to a local with `in` or `out`, or write `{{` and `}}` for literal braces.".into()),
+            Self::IndirectCallArgs(..) => Some("Default values and variadic arguments are only known in calls of the routine by name.".into()),
+            Self::MethodReceiverType(_, record) => Some(format!("Declare the first parameter as `{record}` or `&{record}`, or leave out its type.")),
+            Self::NotAssignable => Some("Only names, dereferences, subscripts and field selections can be assigned to.".into()),
+            Self::NilComparison(_) => Some("Only pointers can be `nil`.".into()),
+            Self::DerefNonPointer(_) => Some("Cast it to a pointer type like `&Int64` with `::`.".into()),
+            Self::NotAddressable => Some("Only names, dereferences, subscripts and field selections have an address.".into()),
+            Self::ShortCircuitSideEffect(_) => Some("Bitwise operators on words always evaluate both operands.".into()),
+            Self::UnusedResult(routine) => Some(format!("Add `{routine}` to `ignored-results` if this is intended.")),
+            Self::EmptyForLoop(init, limit) if init > limit => Some("Use a negative step with `by` to count downwards.".into()),
+            Self::EmptyForLoop(..) => Some("Use a positive step with `by` to count upwards.".into()),
+            Self::InfiniteForLoop => Some("A step of zero never reaches the limit.".into()),
+            Self::UnboundedForLoop => Some("Leave the loop with `break`, `resultis` or `return`, or give a limit with `to`.".into()),
+            Self::RoutineResultType(_) => Some("Define the body with `=` to return a value.".into()),
+            Self::MismatchedSection(..) => Some("Close inner sections before their enclosing section.".into()),
+            Self::UnknownAttribute(_) => Some("Known attributes are `inline`, `deprecated`, `section`, `ccall` and `repr`.".into()),
             _ => None
         }
     }
@@ -179,11 +429,104 @@ impl<'a> ParseError<'a> {
                 CompilerError::new(Severity::Hint, "First defined here.".into(), None, vec![])
                     .with_location(prev_loc.clone())
             ],
+            Self::OverlappingCase(prev_loc) => vec![
+                CompilerError::new(Severity::Hint, "Previous label here.".into(), None, vec![])
+                    .with_location(prev_loc.clone())
+            ],
+            Self::NonTrailingDefault(default_loc) => vec![
+                CompilerError::new(Severity::Hint, "First parameter with a default value here.".into(), None, vec![])
+                    .with_location(default_loc.clone())
+            ],
+            Self::MismatchedSection(open_loc, ..) => vec![
+                CompilerError::new(Severity::Hint, "Section opened here.".into(), None, vec![])
+                    .with_location(open_loc.clone())
+            ],
             _ => vec![]
         }
     }
 }
 
+// spellings from other languages that are easily written in place of an expected token
+const NEAR_MISSES: &[(TokenKind, TokenKind)] = &[
+    (TokenKind::Ident("then"), TokenKind::Do),
+    (TokenKind::Ident("begin"), TokenKind::LBrace),
+    (TokenKind::Ident("end"), TokenKind::RBrace),
+    (TokenKind::Ident("elif"), TokenKind::Else),
+    (TokenKind::Ident("elsif"), TokenKind::Else),
+    (TokenKind::Ident("switch"), TokenKind::SwitchOn),
+    (TokenKind::Ident("valueof"), TokenKind::ValOf),
+    (TokenKind::Ident("in"), TokenKind::Into),
+    (TokenKind::Eq, TokenKind::Assign),
+    (TokenKind::Assign, TokenKind::Eq),
+    (TokenKind::Eq, TokenKind::Be),
+    (TokenKind::Be, TokenKind::Eq),
+    (TokenKind::Colon, TokenKind::Of),
+    (TokenKind::Arrow, TokenKind::Condition),
+    (TokenKind::Condition, TokenKind::Arrow),
+    (TokenKind::Comma, TokenKind::Semicolon),
+];
+
+// the expected token `got` was most likely meant to be, if any
+fn suggest_token<'a>(got: &TokenKind, want: &[TokenKind<'a>]) -> Option<TokenKind<'a>> {
+    if let Some(fix) = want.iter().find(|kind| NEAR_MISSES.iter().any(|(miss, fix)| miss == got && fix == *kind)) {
+        return Some(fix.clone())
+    }
+
+    // misspelled keywords
+    let TokenKind::Ident(ident) = got else {
+        return None
+    };
+    want.iter()
+        .filter_map(|kind| {
+            let keyword = kind.to_string();
+            let is_keyword = !matches!(TokenKind::from(keyword.as_str()), TokenKind::Ident(_));
+            is_keyword.then(|| (edit_distance(ident, &keyword), kind))
+        })
+        .filter(|(distance, _)| *distance > 0 && *distance <= if ident.len() > 5 { 2 } else { 1 })
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, kind)| kind.clone())
+}
+
+// Levenshtein distance counting a swap of adjacent characters as one edit
+fn edit_distance(a: &str, b: &str) -> usize {
+    let (a, b) = (a.chars().collect::<Vec<_>>(), b.chars().collect::<Vec<_>>());
+    let mut rows = vec![vec![0; b.len() + 1]; a.len() + 1];
+    (0..=a.len()).for_each(|i| rows[i][0] = i);
+    (0..=b.len()).for_each(|j| rows[0][j] = j);
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = (a[i - 1] != b[j - 1]) as usize;
+            rows[i][j] = (rows[i - 1][j] + 1).min(rows[i][j - 1] + 1).min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                rows[i][j] = rows[i][j].min(rows[i - 2][j - 2] + 1);
+            }
+        }
+    }
+
+    rows[a.len()][b.len()]
+}
+
+// tokens that can follow a name within an expression or as its statement, `f(`, `a!i`, `x :=` or `l:`
+fn continues_name(kind: &TokenKind) -> bool {
+    use TokenKind as TK;
+    matches!(kind,
+        TK::LParen | TK::LBracket | TK::Period | TK::Bang | TK::Assign | TK::Comma | TK::Colon | TK::Condition
+            | TK::Of | TK::FieldOf | TK::Range | TK::Plus | TK::Minus | TK::Star | TK::Slash | TK::Mod
+            | TK::Eq | TK::Ne | TK::Gt | TK::Ge | TK::Lt | TK::Le | TK::LogAnd | TK::LogOr | TK::XOr
+            | TK::LShift | TK::RShift | TK::QuestionMark
+    )
+}
+
+// tokens that end an operand, so none can start with them
+fn ends_operand(kind: &TokenKind) -> bool {
+    use TokenKind as TK;
+    matches!(kind,
+        TK::Eof | TK::RParen | TK::RBracket | TK::RBrace | TK::Semicolon | TK::Arrow | TK::Do | TK::To
+            | TK::By | TK::Into | TK::Else
+    )
+}
+
 fn tokens_to_string(list: &[TokenKind]) -> String {
     if list.len() == 1 {
         format!{"`{}`", list[0]}
@@ -207,14 +550,56 @@ impl<'a> ToString for ParseError<'a> {
             Self::NotImplemented => "<internal> Not Implemented.".into(),
             Self::Generic(err) => err.clone(),
             Self::UnexpectedEof(tk) => format!("Unexpected end of file; Expected {}.", tokens_to_string(tk)),
-            Self::UnexpectedToken(got, want) => format!("Unexpected token `{got}`; Expected {}.", tokens_to_string(want)),
+            Self::UnexpectedToken(got, want, _) => format!("Unexpected token `{got}`; Expected {}.", tokens_to_string(want)),
             Self::Redefinition(_, ident) => format!("Redefinition of `{ident}`."),
             Self::RequireAfterDecl => format!("Encountered `require` after declarations."),
             Self::InvalidStmt(stmt, err) => format!("Encountered `{stmt}` statement outside of `{err}`."),
             Self::NoResultValue => format!("No `resultis` statement found in `valof` body."),
             Self::ExprWithoutSideEffect => format!("Resuld of expression is unused."),
+            Self::UnusedResult(routine) => format!("Result of `{routine}` is discarded."),
             Self::WrongNumOfPatterns(expect) => format!("Wrong number of patterns, expected {expect}."),
-            Self::MissingBranch(expr) => format!("Expect at least one branch in `{expr}` expression.")
+            Self::WrongNumOfValues(targets, values) => format!("Assignment to {targets} targets has {values} values."),
+            Self::NotAssignable => format!("Cannot assign to this expression."),
+            Self::MissingBranch(keyword) => format!("Expect at least one branch in `{keyword}`."),
+            Self::OverlappingCase(_) => format!("Case label overlaps with a previous label."),
+            Self::EmptyCaseRange(low, high) => format!("Case range `{low} to {high}` is empty."),
+            Self::StringCaseLabel => format!("Cases of a `switchon` over strings have to be single string literals."),
+            Self::InvalidCoercion(from, to) => format!("Cannot implicitly convert `{from}` to `{to}`."),
+            Self::NilOrdering => format!("`nil` can only be compared using `=` or `~=`."),
+            Self::NilComparison(typ) => format!("Cannot compare `nil` with non-pointer type `{typ}`."),
+            Self::NilDeref => format!("Dereferencing `nil`."),
+            Self::DerefNonPointer(typ) => format!("Cannot dereference a value of non-pointer type `{typ}`."),
+            Self::NotAddressable => format!("Cannot take the address of this expression."),
+            Self::ShortCircuitSideEffect(op) => format!("Right operand of `{op}` has side effects, but is not always evaluated."),
+            Self::EmptyForLoop(init, limit) => format!("Loop from {init} to {limit} never executes its body."),
+            Self::InfiniteForLoop => format!("Loop never terminates."),
+            Self::UnboundedForLoop => format!("Loop without limit is never left."),
+            Self::RoutineResultType(typ) => format!("Routine defined with `be` cannot return `{typ}`."),
+            Self::UnknownAttribute(name) => format!("Unknown attribute `{name}` is ignored."),
+            Self::AttributeArgs(name, expected) => format!("Attribute `{name}` takes {expected}."),
+            Self::AttributeTarget(name, target) => format!("Attribute `{name}` cannot be applied to {target}."),
+            Self::MismatchedSection(_, Some(open), close) => format!("`$){close}` does not close section `$({open}`."),
+            Self::MismatchedSection(_, None, close) => format!("`$){close}` closes an untagged section."),
+            Self::Capture(ident, routine) => format!("Nested routine `{routine}` captures `{ident}`, a local of an enclosing routine."),
+            Self::CapturingRoutineValue(routine) => format!("`{routine}` captures locals of its enclosing routine and can only be called from it."),
+            Self::NonTrailingDefault(_) => format!("Parameter without default value follows a parameter with one."),
+            Self::UndefinedLabel(label) => format!("No label `{label}` to jump to."),
+            Self::PositionalAfterNamed => format!("Positional argument follows a named argument."),
+            Self::RecursiveType(typ) => format!("Type `{typ}` contains itself."),
+            Self::NoSuchField(typ, field) => format!("Type `{typ}` has no field `{field}`."),
+            Self::UntypedMember(field) => format!("Cannot select field `{field}` of an untyped value."),
+            Self::MixedEnums(a, b) => format!("Cannot mix `{a}` and `{b}`, values of different enumerations."),
+            Self::UninferableTypeParam(param, routine) => format!("Type parameter `{param}` of `{routine}` is not used by any of its parameters."),
+            Self::NestedInGeneric(routine) => format!("Routines cannot be nested in generic routine `{routine}`."),
+            Self::MethodWithoutReceiver(method) => format!("Method `{method}` has no parameter for its receiver."),
+            Self::MethodReceiverType(method, record) => format!("The receiver of method `{method}` is not of type `{record}`."),
+            Self::DeferInSwitchOn => "Cannot defer statements in the body of a `switchon`, which its cases jump into.".into(),
+            Self::DeferredExit(exit) => format!("Deferred statement cannot leave its block with `{exit}`."),
+            Self::GotoAcrossTry(label) => format!("`goto {label}` jumps into or out of a `try` statement."),
+            Self::CaseInTry => "Case label of the enclosing `switchon` cannot be inside a `try` statement.".into(),
+            Self::AsmOperand(ident) => format!("Operand `{ident}` of `asm` is not a local of this routine."),
+            Self::UnknownAsmOperand(ident) => format!("Assembly refers to `{{{ident}}}`, which is not an operand."),
+            Self::IndirectCallArgs(typ, params, given) => format!("Routine of type `{typ}` takes {params} argument(s), but {given} were given.")
         }
     }
 }
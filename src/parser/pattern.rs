@@ -1,4 +1,4 @@
-use crate::{ast::{pattern::{Pattern, PatternTerm}, expr::Expr}, source_file::{WithLocation, Located}, token::TokenKind};
+use crate::{ast::{pattern::{Pattern, PatternTerm}, expr::{Expr, ExprKind}}, source_file::{WithLocation, Located}, token::TokenKind};
 
 use super::{Parser, ParseResult, ParseError, stmt::StmtContext};
 
@@ -44,6 +44,29 @@ impl<'a> Parser<'a> {
         Ok(patterns)
     }
 
+    // patterns a value is matched against, where a name of an enumeration constant stands for its
+    // value instead of binding the name
+    pub(super) fn parse_match_patterns(&mut self) -> ParseResult<'a, Vec<Located<Pattern>>> {
+        let mut patterns = self.parse_pattern_list()?;
+        patterns.iter_mut().for_each(|pattern| self.match_enum_constants(pattern));
+        Ok(patterns)
+    }
+
+    fn match_enum_constants(&self, pattern: &mut Located<Pattern>) {
+        match &mut **pattern {
+            Pattern::Query(ident) => if let Some((typ, value)) = self.enum_constant(ident) {
+                let constant = Expr::new(pattern.location().clone(), Some(typ), ExprKind::IntLit(value));
+                **pattern = Pattern::Term(PatternTerm::Basic(constant));
+            },
+            Pattern::Or(lhs, rhs) | Pattern::And(lhs, rhs) => {
+                self.match_enum_constants(lhs);
+                self.match_enum_constants(rhs);
+            }
+            Pattern::Variant(_, args) | Pattern::List(args) => args.iter_mut().for_each(|arg| self.match_enum_constants(arg)),
+            Pattern::Term(_) | Pattern::Any | Pattern::Remaining => ()
+        }
+    }
+
     fn parse_prefix_pattern(&mut self) -> ParseResult<'a, Located<Pattern>> {
         let loc = self.current().location().clone();
 
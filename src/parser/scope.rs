@@ -0,0 +1,246 @@
+use crate::{
+    ast::{
+        self, Decl, Function, pattern::{Pattern, bound_idents}, types::{TypeIndex, TypeKind}, expr::{Expr, ExprKind},
+        stmt::{Stmt, StmtKind}, visitor::{Action, Visitor}
+    },
+    source_file::{Located, Location, WithLocation}
+};
+
+use super::{Parser, ParseResult, ParseError};
+
+// a name declared within a routine
+#[derive(Clone, Debug)]
+pub(super) enum Local {
+    // a nested routine, with the symbol it was hoisted to
+    Routine(String),
+    // a parameter or binding, which only exists while the routine declaring it runs, with its
+    // type if it was declared or bound to a typed value
+    Dynamic(Option<TypeIndex>)
+}
+
+impl<'a> Parser<'a> {
+    // runs `parse` in a new scope; the names it declares are dropped afterwards
+    pub(super) fn scoped<T>(&mut self, parse: impl FnOnce(&mut Self) -> ParseResult<'a, T>) -> ParseResult<'a, T> {
+        let len = self.scope.len();
+        let result = parse(self);
+        self.scope.truncate(len);
+        result
+    }
+
+    pub(super) fn declare_locals<'p>(&mut self, patterns: impl IntoIterator<Item = &'p Located<Pattern>>) {
+        for pattern in patterns {
+            let idents = bound_idents(pattern).into_iter().map(|(ident, _)| (ident.clone(), Local::Dynamic(None)));
+            self.scope.extend(idents);
+        }
+    }
+
+    // like `declare_locals`, but a pattern that is a single name has the type `typ`
+    pub(super) fn declare_typed(&mut self, pattern: &Located<Pattern>, typ: Option<TypeIndex>) {
+        match &**pattern {
+            Pattern::Query(ident) => self.scope.push((ident.clone(), Local::Dynamic(typ))),
+            _ => self.declare_locals([pattern])
+        }
+    }
+
+    // the type of `expr`; names have none in the ast, so that of a local or global comes from its declaration
+    pub(super) fn type_of(&self, expr: &Expr) -> Option<TypeIndex> {
+        match expr.kind() {
+            ExprKind::Ident(ident) if expr.typ().is_none() => self.declared_type(ident),
+            _ => *expr.typ()
+        }
+    }
+
+    fn declared_type(&self, ident: &str) -> Option<TypeIndex> {
+        match self.scope.iter().rfind(|(name, _)| name == ident) {
+            Some((_, Local::Dynamic(typ))) => *typ,
+            Some((_, Local::Routine(_))) => None,
+            None => self.global_types.get(ident).copied()
+        }
+    }
+
+    // whether `ident` is a parameter or binding of the innermost routine, rather than a global,
+    // a nested routine or a local of an enclosing routine
+    pub(super) fn is_own_local(&self, ident: &str) -> bool {
+        self.scope.iter().rposition(|(name, _)| name == ident)
+            .is_some_and(|index| index >= self.routine_scope && matches!(self.scope[index].1, Local::Dynamic(_)))
+    }
+
+    // the value of the enumeration constant `ident`, unless a local of that name hides it
+    pub(super) fn enum_constant(&self, ident: &str) -> Option<(TypeIndex, u64)> {
+        if self.scope.iter().any(|(name, _)| name == ident) {
+            return None
+        }
+        self.ast.lock().unwrap().types().enum_constant(ident)
+    }
+
+    // the routine `ident` within `outer` is hoisted to; sibling routines of the same name get a suffix
+    pub(super) fn nested_symbol(&self, outer: &str, ident: &str) -> String {
+        let symbol = format!("{outer}.{ident}");
+        let taken = |symbol: &String| self.nested_routines.iter().any(|func| func.ident() == symbol);
+        if !taken(&symbol) {
+            return symbol
+        }
+        (2..).map(|n| format!("{symbol}.{n}")).find(|symbol| !taken(symbol)).unwrap()
+    }
+
+    // what `ident` refers to at this point of the program: a name, the symbol of a nested routine,
+    // or a local of an enclosing routine, reached through the environment record
+    pub(super) fn resolve(&mut self, ident: String, loc: Location) -> Expr {
+        let Some(index) = self.scope.iter().rposition(|(name, _)| *name == ident) else {
+            return Expr::new(loc, None, ExprKind::Ident(ident))
+        };
+
+        match &self.scope[index].1 {
+            Local::Routine(symbol) => Expr::new(loc, None, ExprKind::Ident(symbol.clone())),
+            Local::Dynamic(typ) if index < self.routine_scope => {
+                let typ = *typ;
+                self.capture(ident, typ, loc)
+            }
+            Local::Dynamic(_) => Expr::new(loc, None, ExprKind::Ident(ident))
+        }
+    }
+
+    // `!(env!i)`, where the innermost routine's environment record points to the captured `ident`
+    fn capture(&mut self, ident: String, typ: Option<TypeIndex>, loc: Location) -> Expr {
+        let routine = self.routine.clone().expect("captures only happen within nested routines");
+        let slot = match self.captures.iter().position(|capture| *capture == ident) {
+            Some(slot) => slot,
+            None => {
+                self.push_warning(ParseError::Capture(ident.clone(), routine.clone()).with_location(loc.clone()));
+                self.captures.push(ident);
+                self.captures.len() - 1
+            }
+        };
+
+        let env = Expr::new(loc.clone(), None, ExprKind::Ident(environment(&routine)));
+        let slot = Expr::new(loc.clone(), Some(self.get_type(TypeKind::UInt64)), ExprKind::IntLit(slot as u64));
+        let addr = Expr::new(loc.clone(), None, ExprKind::Index(Box::new(env), Box::new(slot)));
+        Expr::new(loc, typ, ExprKind::Deref(Box::new(addr)))
+    }
+
+    // the environment record to pass to the nested routine `symbol` as its first argument, if it captures locals
+    pub(super) fn environment_of(&mut self, symbol: &str, loc: &Location) -> Option<Expr> {
+        let env = environment(symbol);
+        self.scope.iter().any(|(name, _)| *name == env).then(|| self.resolve(env, loc.clone()))
+    }
+
+    // `let env = vec n; env!0, ... := @a, ...` for a nested routine capturing the locals `a, ...`
+    // of this one. Captures are by reference, so the record is only valid while this routine runs,
+    // like any `vec` in its frame.
+    pub(super) fn build_environment(&mut self, func: &Function, loc: &Location) -> Stmt {
+        let env = environment(func.ident());
+        let word = self.get_type(TypeKind::UInt64);
+        let size = Expr::new(loc.clone(), Some(word), ExprKind::IntLit(func.captures().len() as u64 - 1));
+        let record = Expr::new(loc.clone(), None, ExprKind::Vec(Box::new(size)));
+        let binding = Stmt::new(loc.clone(), StmtKind::Binding(vec![(Pattern::Query(env.clone()).with_location(loc.clone()), record)]));
+
+        let slots = func.captures().iter().enumerate()
+            .map(|(i, ident)| {
+                let env = Expr::new(loc.clone(), None, ExprKind::Ident(env.clone()));
+                let slot = Expr::new(loc.clone(), Some(word), ExprKind::IntLit(i as u64));
+                // a local this routine captured itself is passed on as is
+                let mut local = self.resolve(ident.clone(), loc.clone());
+                let addr = match std::mem::replace(local.kind_mut(), ExprKind::Error) {
+                    ExprKind::Deref(addr) => *addr,
+                    kind => {
+                        *local.kind_mut() = kind;
+                        let typ = self.type_of(&local).map(|typ| self.pointer_to(typ));
+                        Expr::new(loc.clone(), typ, ExprKind::Ref(Box::new(local)))
+                    }
+                };
+                (Expr::new(loc.clone(), None, ExprKind::Index(Box::new(env), Box::new(slot))), addr)
+            })
+            .collect();
+
+        self.scope.push((env, Local::Dynamic(None)));
+        Stmt::new(loc.clone(), StmtKind::Block(vec![binding, Stmt::new(loc.clone(), StmtKind::Assign(slots))]))
+    }
+}
+
+// the name of the environment record parameter of `routine`, which no identifier can clash with
+pub(super) fn environment(routine: &str) -> String {
+    format!("{routine}.env")
+}
+
+// the routine `value.method(...)` calls for a `value` of the record type `record`
+pub(super) fn method_symbol(record: &str, method: &str) -> String {
+    format!("{record}.{method}")
+}
+
+// passes the environment record to the calls of a capturing routine in its own body; any other
+// reference to it, and any from routines nested in it, which lack the record, is an error
+pub(super) struct PassEnvironment {
+    routine: String,
+    env: Option<Expr>,
+    callee: bool
+}
+
+impl PassEnvironment {
+    pub(super) fn new(routine: String, env: Option<Expr>) -> Self {
+        Self {
+            routine,
+            env,
+            callee: false
+        }
+    }
+}
+
+impl<'a> Visitor<ast::Program, Located<ParseError<'a>>> for PassEnvironment {
+    fn visit(&mut self, _node: &mut ast::Program) -> Result<Action, Located<ParseError<'a>>> {
+        Ok(Action::Continue)
+    }
+}
+
+impl<'a> Visitor<ast::Section, Located<ParseError<'a>>> for PassEnvironment {
+    fn visit(&mut self, _node: &mut ast::Section) -> Result<Action, Located<ParseError<'a>>> {
+        Ok(Action::Continue)
+    }
+}
+
+impl<'a> Visitor<Function, Located<ParseError<'a>>> for PassEnvironment {
+    fn visit(&mut self, _node: &mut Function) -> Result<Action, Located<ParseError<'a>>> {
+        Ok(Action::Continue)
+    }
+}
+
+impl<'a> Visitor<ast::Param, Located<ParseError<'a>>> for PassEnvironment {
+    fn visit(&mut self, _node: &mut ast::Param) -> Result<Action, Located<ParseError<'a>>> {
+        Ok(Action::Continue)
+    }
+}
+
+impl<'a> Visitor<Stmt, Located<ParseError<'a>>> for PassEnvironment {
+    fn visit(&mut self, _node: &mut Stmt) -> Result<Action, Located<ParseError<'a>>> {
+        Ok(Action::Continue)
+    }
+}
+
+impl<'a> Visitor<Expr, Located<ParseError<'a>>> for PassEnvironment {
+    // the callee of a call is traversed right after the call itself
+    fn visit_before(&mut self, node: &mut Expr) -> Result<Action, Located<ParseError<'a>>> {
+        let callee = std::mem::take(&mut self.callee);
+        let loc = node.location().clone();
+        match node.kind_mut() {
+            ExprKind::FuncCall(func, args) if let Some(env) = &self.env
+                && matches!(func.kind(), ExprKind::Ident(ident) if *ident == self.routine) => {
+                args.insert(0, env.clone());
+                self.callee = true;
+            }
+            ExprKind::Ident(ident) if *ident == self.routine && !callee => {
+                return Err(ParseError::CapturingRoutineValue(ident.clone()).with_location(loc))
+            }
+            _ => ()
+        }
+        Ok(Action::Continue)
+    }
+
+    fn visit(&mut self, _node: &mut Expr) -> Result<Action, Located<ParseError<'a>>> {
+        Ok(Action::Continue)
+    }
+}
+
+impl<'a> Visitor<Pattern, Located<ParseError<'a>>> for PassEnvironment {
+    fn visit(&mut self, _node: &mut Pattern) -> Result<Action, Located<ParseError<'a>>> {
+        Ok(Action::Continue)
+    }
+}
@@ -1,12 +1,43 @@
-use std::cell::RefCell;
+use std::{cell::RefCell, collections::HashMap, fmt::Write};
 
 use crate::{
-    ast::{stmt::{Stmt, StmtKind}, expr::{Expr, ExprKind}, types::{TypeIndex, TypeKind}, Param, pattern::Pattern}, 
+    ast::{stmt::{Stmt, StmtKind}, expr::{Expr, ExprKind}, types::{TypeIndex, TypeKind}, Decl, Param, pattern::{Pattern, bound_idents}, visitor::Traversable}, 
     source_file::{WithLocation, Located, Location},
-    token::TokenKind
+    token::{Token, TokenKind, lexer::unescape}
 };
 
-use super::{Parser, ParseResult, ParseError};
+use super::{Parser, ParseResult, ParseError, scope::PassEnvironment};
+
+#[derive(Default)]
+pub(super) struct SwitchOnLabels {
+    default_case: Option<Location>,
+    //         low  high label
+    cases: Vec<(i64, i64, Location)>,
+    // labels of a `switchon` over strings
+    strings: Vec<(String, Location)>
+}
+
+impl SwitchOnLabels {
+    fn add_case<'a>(&mut self, low: i64, high: i64, loc: Location) -> ParseResult<'a, ()> {
+        if let Some((.., prev)) = self.cases.iter().find(|(l, h, _)| low <= *h && *l <= high) {
+            Err(ParseError::OverlappingCase(prev.clone()).with_location(loc))
+        }
+        else {
+            self.cases.push((low, high, loc));
+            Ok(())
+        }
+    }
+
+    fn add_string_case<'a>(&mut self, value: &String, loc: Location) -> ParseResult<'a, ()> {
+        if let Some((_, prev)) = self.strings.iter().find(|(other, _)| other == value) {
+            Err(ParseError::OverlappingCase(prev.clone()).with_location(loc))
+        }
+        else {
+            self.strings.push((value.clone(), loc));
+            Ok(())
+        }
+    }
+}
 
 pub(super) enum StmtContext<'a> {
     ValOf(&'a RefCell<Option<Option<TypeIndex>>>, &'a StmtContext<'a>),
@@ -14,8 +45,10 @@ pub(super) enum StmtContext<'a> {
     NoBlock(&'a StmtContext<'a>),
     Function(&'a Vec<Param>),
     Loop(&'a StmtContext<'a>),
-    SwitchOn(&'a RefCell<Option<Location>>, &'a Option<TypeIndex>, &'a StmtContext<'a>),
+    SwitchOn(&'a RefCell<SwitchOnLabels>, &'a Option<TypeIndex>, &'a StmtContext<'a>),
     Match(&'a StmtContext<'a>),
+    // the handler of a `try`, where `rethrow` is valid
+    Catch(&'a StmtContext<'a>),
     Empty
 }
 
@@ -27,7 +60,8 @@ impl<'a> StmtContext<'a> {
                 | Self::NoBlock(outer)
                 | Self::Loop(outer)
                 | Self::SwitchOn(.., outer)
-                | Self::Match(outer) => Some(outer),
+                | Self::Match(outer)
+                | Self::Catch(outer) => Some(outer),
             Self::Empty
                 | Self::Function(_) => None
         }
@@ -51,7 +85,8 @@ impl<'a> StmtContext<'a> {
         match self {
             Self::Block(_) => true,
             Self::Loop(outer) 
-                | Self::SwitchOn(.. , outer) => outer.require_semicolon(),
+                | Self::SwitchOn(.. , outer)
+                | Self::Catch(outer) => outer.require_semicolon(),
             _ => false
         }
     }
@@ -65,9 +100,9 @@ impl<'a> StmtContext<'a> {
         }
     }
 
-    fn in_switchon(&self) -> Option<(&'a RefCell<Option<Location>>, &'a Option<TypeIndex>)> {
+    fn in_switchon(&self) -> Option<(&'a RefCell<SwitchOnLabels>, &'a Option<TypeIndex>)> {
         match self {
-            Self::SwitchOn(default_case, cond_typ, _) => Some((default_case, cond_typ)),
+            Self::SwitchOn(labels, cond_typ, _) => Some((labels, cond_typ)),
             _ => self.get_outer().map(|ctx| ctx.in_switchon()).flatten()
         }
     }
@@ -78,11 +113,20 @@ impl<'a> StmtContext<'a> {
             _ => self.get_outer().map(|ctx| ctx.in_match()).unwrap_or(false)
         }
     }
+
+    fn in_handler(&self) -> bool {
+        match self {
+            Self::Catch(_) => true,
+            _ => self.get_outer().map(|ctx| ctx.in_handler()).unwrap_or(false)
+        }
+    }
 }
 
 impl<'a> Parser<'a> {
     pub(super) fn parse_stmt(&mut self, context: &StmtContext) -> ParseResult<'a, Stmt> {
-        let stmt = match self.current().kind() {
+        let loop_exits = self.loop_exits.len();
+        self.contextual_keyword(&["try", "throw", "rethrow"]);
+        let mut stmt = match self.current().kind() {
             TokenKind::LBrace => self.parse_block(context),
             TokenKind::ResultIs => self.parse_resultis(context),
             TokenKind::Return => self.parse_return(context),
@@ -98,7 +142,22 @@ impl<'a> Parser<'a> {
             TokenKind::Every => self.parse_match_stmt(context, StmtKind::Every),
             TokenKind::Next => self.parse_next_break(context, false),
             TokenKind::Break => self.parse_next_break(context, true),
+            TokenKind::Loop => self.parse_loop(context),
+            TokenKind::Goto => self.parse_goto(context),
+            TokenKind::Try => self.parse_try(context),
+            TokenKind::Throw => self.parse_throw(context),
+            TokenKind::Rethrow => self.parse_rethrow(context),
+            TokenKind::Asm => self.parse_inline_asm(context),
+            TokenKind::Defer => Err(ParseError::InvalidStmt("defer".into(), "block".into()).with_location(self.current().location().clone())),
+            TokenKind::Ident(_) if context.require_semicolon() && self.at_label() => self.parse_labelled(context),
+            TokenKind::Let if self.at_nested_routine() => self.parse_nested_routine(),
             TokenKind::Let => self.parse_let_binding(context),
+            TokenKind::Assert => {
+                let loc = self.current().location().clone();
+                let (condition, message) = self.parse_assertion(context)?;
+                self.semicolon_if_required(context)?;
+                Ok(Stmt::new(loc, StmtKind::Assert(Box::new(condition), message)))
+            }
             TokenKind::Semicolon => {
                 let loc = self.advance()?.location().clone();
                 Ok(Stmt::new(loc, StmtKind::Nop))
@@ -106,6 +165,12 @@ impl<'a> Parser<'a> {
             _ => self.parse_expr_stmt(context),
         }?;
 
+        while let TokenKind::Repeat | TokenKind::RepeatWhile | TokenKind::RepeatUntil = self.current().kind() {
+            // the body turned out to be a loop
+            self.loop_exits.truncate(loop_exits);
+            stmt = self.parse_repeat(context, stmt)?;
+        }
+
         if let TokenKind::Compound = self.current().kind() {
             self.parse_compound(context, stmt)
         }
@@ -114,6 +179,29 @@ impl<'a> Parser<'a> {
         }
     }
 
+    // `<stmt> repeat`, `<stmt> repeatwhile <expr>` or `<stmt> repeatuntil <expr>`
+    fn parse_repeat(&mut self, context: &StmtContext, body: Stmt) -> ParseResult<'a, Stmt> {
+        let loc = body.location().clone();
+        let (repeat, negate) = match self.advance()?.kind() {
+            TokenKind::Repeat => (true, false),
+            TokenKind::RepeatWhile => (false, false),
+            _ => (false, true)
+        };
+        let body = Box::new(body);
+
+        let kind = if repeat {
+            StmtKind::Repeat(body)
+        }
+        else {
+            let cond = self.parse_expr(context)?;
+            let cond = Box::new(self.coerce(cond, self.get_type(TypeKind::Bool))?);
+            if negate { StmtKind::RepeatUntil(body, cond) } else { StmtKind::RepeatWhile(body, cond) }
+        };
+
+        self.semicolon_if_required(context)?;
+        Ok(Stmt::new(loc, kind))
+    }
+
     fn parse_compound(&mut self, context: &StmtContext, left: Stmt) -> ParseResult<'a, Stmt> {
         let loc = self.current().location().clone();
         let mut stmts = vec![left];
@@ -128,20 +216,126 @@ impl<'a> Parser<'a> {
     }
 
     fn parse_block(&mut self, context: &StmtContext) -> ParseResult<'a, Stmt> {
+        self.scoped(|parser| parser.parse_block_stmts(context))
+    }
+
+    fn parse_block_stmts(&mut self, context: &StmtContext) -> ParseResult<'a, Stmt> {
         let loc = self.current().location().clone();
         self.expect(&[TokenKind::LBrace])?;
 
         let mut stmts = vec![];
+        // with the number of statements before each
+        let mut deferred = vec![];
 
         while self.current().kind() != &TokenKind::RBrace {
-            stmts.push(self.parse_stmt(&StmtContext::Block(context))?)
+            let start = self.current().location().clone();
+            let parsed = match self.current().kind() {
+                TokenKind::Defer => self.parse_defer(context).map(|stmt| deferred.push((stmts.len(), stmt))),
+                _ => self.parse_stmt(&StmtContext::Block(context)).map(|stmt| stmts.push(stmt))
+            };
+            match parsed {
+                Ok(()) => (),
+                Err(err) if self.current().is_eof() => return Err(err),
+                Err(err) => {
+                    let loc = self.recover(err, start, &[TokenKind::Semicolon, TokenKind::RBrace]);
+                    stmts.push(Stmt::new(loc, StmtKind::Error));
+                }
+            }
+        }
+
+        // shadowing is only allowed across blocks
+        let mut bound: HashMap<&String, &Location> = HashMap::new();
+        for stmt in &stmts {
+            let StmtKind::Binding(bindings) = stmt.kind() else {
+                continue
+            };
+            for (ident, loc) in bindings.iter().flat_map(|(pattern, _)| bound_idents(pattern)) {
+                match bound.get(ident) {
+                    Some(prev) => self.errors.push(ParseError::Redefinition((*prev).clone(), ident.clone()).with_location(loc.clone())),
+                    None => { bound.insert(ident, loc); }
+                }
+            }
         }
 
         self.advance()?;
 
+        if !deferred.is_empty() {
+            stmts = run_deferred(stmts, deferred);
+        }
         Ok(Stmt::new(loc, StmtKind::Block(stmts)))
     }
 
+    // `defer stmt` runs `stmt` on every way out of the enclosing block once it was reached
+    fn parse_defer(&mut self, context: &StmtContext) -> ParseResult<'a, Stmt> {
+        let loc = self.expect(&[TokenKind::Defer])?.location().clone();
+        if let StmtContext::SwitchOn(..) = context {
+            return Err(ParseError::DeferInSwitchOn.with_location(loc))
+        }
+
+        let stmt = self.scoped(|parser| parser.parse_stmt(&StmtContext::Block(context)))?;
+        if let Some((exit, loc)) = leaving_exit(&stmt, &labels_in(&stmt), false, false, false) {
+            return Err(ParseError::DeferredExit(exit.into()).with_location(loc))
+        }
+        Ok(stmt)
+    }
+
+    // `try body catch (e) handler`. The body registers the handler on the way in and drops it
+    // on the way out, so `goto` and case labels cannot jump into or out of it.
+    fn parse_try(&mut self, context: &StmtContext) -> ParseResult<'a, Stmt> {
+        let loc = self.expect(&[TokenKind::Try])?.location().clone();
+
+        let (labels, gotos) = (self.labels.len(), self.gotos.len());
+        let body = self.parse_stmt(context)?;
+        self.check_try_jumps(labels, gotos);
+
+        self.contextual_keyword(&["catch"]);
+        self.expect(&[TokenKind::Catch])?;
+        self.expect(&[TokenKind::LParen])?;
+        let caught_loc = self.current().location().clone();
+        let caught = Pattern::Query(self.expect_ident()?).with_location(caught_loc);
+        self.expect(&[TokenKind::RParen])?;
+
+        let handler = self.scoped(|parser| {
+            parser.declare_locals([&caught]);
+            parser.parse_stmt(&StmtContext::Catch(context))
+        })?;
+
+        if let Some(case) = [&body, &handler].into_iter().find_map(case_label_in) {
+            return Err(ParseError::CaseInTry.with_location(case.clone()))
+        }
+        Ok(Stmt::new(loc, StmtKind::Try(Box::new(body), caught, Box::new(handler))))
+    }
+
+    // the labels of the body are only reachable from within it, and its `goto`s only reach those
+    fn check_try_jumps(&mut self, labels: usize, gotos: usize) {
+        let inner = self.labels[labels..].iter()
+            .filter(|label| !self.try_labels.iter().any(|nested| **nested == ***label))
+            .map(|label| (**label).clone())
+            .collect::<Vec<_>>();
+        for goto in self.gotos.split_off(gotos) {
+            if !inner.contains(&*goto) {
+                self.try_exits.push(goto);
+            }
+        }
+        self.try_labels.extend(self.labels[labels..].iter().cloned());
+    }
+
+    fn parse_throw(&mut self, context: &StmtContext) -> ParseResult<'a, Stmt> {
+        let loc = self.expect(&[TokenKind::Throw])?.location().clone();
+        let value = self.parse_expr(context)?;
+        self.semicolon_if_required(context)?;
+        Ok(Stmt::new(loc, StmtKind::Throw(Box::new(value))))
+    }
+
+    fn parse_rethrow(&mut self, context: &StmtContext) -> ParseResult<'a, Stmt> {
+        let loc = self.expect(&[TokenKind::Rethrow])?.location().clone();
+        self.semicolon_if_required(context)?;
+        if !context.in_handler() {
+            return Err(ParseError::InvalidStmt("rethrow".into(), "catch".into()).with_location(loc))
+        }
+        Ok(Stmt::new(loc, StmtKind::Rethrow))
+    }
+
     fn parse_resultis(&mut self, context: &StmtContext) -> ParseResult<'a, Stmt> {
         let loc = self.expect(&[TokenKind::ResultIs])?.location().clone();
         
@@ -154,7 +348,7 @@ impl<'a> Parser<'a> {
 
         let vt = valof_typ.borrow().clone();
         let expr = match vt {
-            Some(vt) if &vt != expr.typ() => Expr::new(loc.clone(), vt, ExprKind::ImplicitCast(Box::new(expr))),
+            Some(Some(vt)) => self.coerce(expr, vt)?,
             None => {
                 *valof_typ.borrow_mut() = Some(expr.typ().clone());
                 expr
@@ -180,13 +374,10 @@ impl<'a> Parser<'a> {
     fn parse_if(&mut self, context: &StmtContext) -> ParseResult<'a, Stmt> {
         let loc = self.expect(&[TokenKind::If])?.location().clone();
 
-        let mut condition = self.parse_expr(context)?;
-        let bool_typ = self.get_type(TypeKind::Bool);
-        if condition.typ() != &Some(bool_typ) {
-            condition = condition.implicit_cast(bool_typ);
-        }
+        let condition = self.parse_expr(context)?;
+        let condition = self.coerce(condition, self.get_type(TypeKind::Bool))?;
 
-        self.advance_if(&[TokenKind::Do])?;
+        self.parse_optional_do()?;
 
         let if_branch = self.parse_stmt(context)?;
         let else_branch = if self.advance_if(&[TokenKind::Else])?.is_some() {
@@ -199,16 +390,22 @@ impl<'a> Parser<'a> {
         Ok(Stmt::new(loc, StmtKind::If(Box::new(condition), Box::new(if_branch), else_branch.map(Box::new))))       
     }
 
+    // `then`, as in other languages, is rejected with a suggestion instead of being parsed as the body
+    fn parse_optional_do(&mut self) -> ParseResult<'a, ()> {
+        if self.current().kind() == &TokenKind::Ident("then") {
+            return self.unexpected(&[TokenKind::Do])
+        }
+        self.advance_if(&[TokenKind::Do])?;
+        Ok(())
+    }
+
     fn parse_unless(&mut self, context: &StmtContext) -> ParseResult<'a, Stmt> {
         let loc = self.expect(&[TokenKind::Unless])?.location().clone();
 
-        let mut condition = self.parse_expr(context)?;
-        let bool_typ = self.get_type(TypeKind::Bool);
-        if condition.typ() != &Some(bool_typ) {
-            condition = condition.implicit_cast(bool_typ);
-        }
+        let condition = self.parse_expr(context)?;
+        let condition = self.coerce(condition, self.get_type(TypeKind::Bool))?;
 
-        self.advance_if(&[TokenKind::Do])?;
+        self.parse_optional_do()?;
 
         let branch = self.parse_stmt(context)?;
         Ok(Stmt::new(loc, StmtKind::Unless(Box::new(condition), Box::new(branch))))
@@ -217,13 +414,10 @@ impl<'a> Parser<'a> {
     fn parse_while(&mut self, context: &StmtContext, negate: bool) -> ParseResult<'a, Stmt> {
         let loc = self.expect(&[TokenKind::While, TokenKind::While])?.location().clone();
 
-        let mut condition = self.parse_expr(context)?;
-        let bool_typ = self.get_type(TypeKind::Bool);
-        if condition.typ() != &Some(bool_typ) {
-            condition = condition.implicit_cast(bool_typ);
-        }
+        let condition = self.parse_expr(context)?;
+        let condition = self.coerce(condition, self.get_type(TypeKind::Bool))?;
 
-        self.advance_if(&[TokenKind::Do])?;
+        self.parse_optional_do()?;
 
         let body = self.parse_stmt(&StmtContext::Loop(context))?;
         let kind = if negate { StmtKind::Until } else { StmtKind::While }
@@ -239,31 +433,41 @@ impl<'a> Parser<'a> {
         let init = self.parse_expr(context)?;
 
         let limit = if self.advance_if(&[TokenKind::To])?.is_some() {
-            let mut expr = self.parse_expr(context)?;
-            if let Some(typ) = init.typ() && init.typ() != expr.typ() {
-                expr = expr.implicit_cast(*typ) 
-            }
-            Some(expr)
+            let expr = self.parse_expr(context)?;
+            Some(match init.typ() {
+                Some(typ) => self.coerce(expr, *typ)?,
+                None => expr
+            })
         }
         else {
             None
         };
 
         let step = if self.advance_if(&[TokenKind::By])?.is_some() {
-            let mut expr = self.parse_expr(context)?;
-            if let Some(typ) = init.typ() && init.typ() != expr.typ() {
-                expr = expr.implicit_cast(*typ) 
-            }
-            Some(expr)
-
+            let expr = self.parse_for_step(context)?;
+            Some(match init.typ() {
+                Some(typ) => self.coerce(expr, *typ)?,
+                None => expr
+            })
         }
         else {
             None
         };
 
-        self.advance_if(&[TokenKind::Do])?;
+        self.parse_optional_do()?;
 
-        let body = self.parse_stmt(&StmtContext::Loop(context))?;
+        if let Some(limit) = &limit {
+            self.check_for_bounds(&init, limit, step.as_ref());
+        }
+
+        let body = self.scoped(|parser| {
+            parser.declare_locals([&iter]);
+            parser.parse_stmt(&StmtContext::Loop(context))
+        })?;
+        // without a limit, the loop counts until something leaves it
+        if limit.is_none() && !leaves_loop(&body, false) {
+            self.push_warning(ParseError::UnboundedForLoop.with_location(loc.clone()));
+        }
 
         Ok(Stmt::new(
             loc,
@@ -276,43 +480,127 @@ impl<'a> Parser<'a> {
         ))
     }
 
+    // `by -n` counts downwards, comparing with the limit using `>=`
+    fn parse_for_step(&mut self, context: &StmtContext) -> ParseResult<'a, Expr> {
+        match self.current().kind() {
+            TokenKind::Minus => self.parse_negation(context),
+            _ => self.parse_expr(context)
+        }
+    }
+
+    // with constant bounds, warns about loops running zero times or forever
+    fn check_for_bounds(&mut self, init: &Expr, limit: &Expr, step: Option<&Expr>) {
+        let (Some(from), Some(to)) = (init.const_int(), limit.const_int()) else {
+            return
+        };
+        let (step_value, step_loc) = match step {
+            Some(step) => match step.const_int() {
+                Some(value) => (value, step.location()),
+                None => return
+            },
+            None => (1, limit.location())
+        };
+
+        let warning = match step_value.signum() {
+            0 if from <= to => ParseError::InfiniteForLoop.with_location(step_loc.clone()),
+            0 | 1 if from > to => ParseError::EmptyForLoop(from, to).with_location(limit.location().clone()),
+            -1 if from < to => ParseError::EmptyForLoop(from, to).with_location(limit.location().clone()),
+            _ => return
+        };
+        self.push_warning(warning);
+    }
+
     fn parse_switchon(&mut self, context: &StmtContext) -> ParseResult<'a, Stmt> {
         let loc = self.expect(&[TokenKind::SwitchOn])?.location().clone();
 
         let condition = self.parse_expr(context)?;
         self.expect(&[TokenKind::Into])?;
 
-        let default_case = RefCell::new(None);
-        let body = self.parse_stmt(&StmtContext::SwitchOn(&default_case, condition.typ(), context))?;
+        let labels = RefCell::new(SwitchOnLabels::default());
+        let cond_typ = self.type_of(&condition);
+        let body = self.parse_stmt(&StmtContext::SwitchOn(&labels, &cond_typ, context))?;
 
         Ok(Stmt::new(loc, StmtKind::SwitchOn(Box::new(condition), Box::new(body))))
     }
 
+    // `case a, b to c:` labels every value listed, ranges included
     fn parse_case(&mut self, context: &StmtContext) -> ParseResult<'a, Stmt> {
         let loc = self.expect(&[TokenKind::Case])?.location().clone();
-        
-        let mut expr = self.parse_expr(context)?;
-        self.expect(&[TokenKind::Colon])?;
 
-        if let Some((_, cond_typ)) = context.in_switchon() {
-            if let Some(cond_typ) = cond_typ && &Some(*cond_typ) != expr.typ() {
-                expr = expr.implicit_cast(*cond_typ);
+        let mut values = vec![];
+        loop {
+            let expr = self.parse_expr(context)?;
+            let upper = if self.advance_if(&[TokenKind::To])?.is_some() {
+                Some(self.parse_expr(context)?)
             }
+            else {
+                None
+            };
+            values.push((expr, upper));
 
-            Ok(Stmt::new(loc, StmtKind::Case(Box::new(expr))))
+            if self.advance_if(&[TokenKind::Comma])?.is_none() {
+                break
+            }
         }
-        else {
-            Err(ParseError::InvalidStmt("case".into(), "switchon".into())
+        self.expect(&[TokenKind::Colon])?;
+
+        let Some((labels, cond_typ)) = context.in_switchon() else {
+            return Err(ParseError::InvalidStmt("case".into(), "switchon".into())
                 .with_location(loc))
+        };
+        let values = values.into_iter()
+            .map(|(expr, upper)| self.check_case_value(expr, upper, labels, cond_typ))
+            .collect::<ParseResult<'a, Vec<_>>>()?;
+        Ok(Stmt::new(loc, StmtKind::Case(values)))
+    }
+
+    fn check_case_value(&mut self, mut expr: Expr, mut upper: Option<Expr>, labels: &RefCell<SwitchOnLabels>, cond_typ: &Option<TypeIndex>) -> ParseResult<'a, (Expr, Option<Expr>)> {
+        let mut loc = expr.location().clone();
+        if let Some(upper) = &upper {
+            loc.extend_to(upper.location());
+        }
+
+        // identifiers are untyped until type checking, so string literal labels decide as well
+        let is_string = *cond_typ == Some(self.get_string_type())
+            || matches!(expr.kind(), ExprKind::StringLit(_))
+            || !labels.borrow().strings.is_empty();
+        if is_string {
+            let ExprKind::StringLit(value) = expr.kind() else {
+                return Err(ParseError::StringCaseLabel.with_location(expr.location().clone()))
+            };
+            if let Some(upper) = upper {
+                return Err(ParseError::StringCaseLabel.with_location(upper.location().clone()))
+            }
+            if !labels.borrow().cases.is_empty() {
+                return Err(ParseError::StringCaseLabel.with_location(expr.location().clone()))
+            }
+            labels.borrow_mut().add_string_case(value, loc)?;
+            return Ok((expr, None))
+        }
+
+        if let Some(cond_typ) = cond_typ {
+            expr = self.coerce(expr, *cond_typ)?;
+            upper = upper.map(|upper| self.coerce(upper, *cond_typ)).transpose()?;
         }
-    } 
+
+        let low = expr.const_int();
+        let high = upper.as_ref().map_or(low, Expr::const_int);
+        if let (Some(low), Some(high)) = (low, high) {
+            if low > high {
+                return Err(ParseError::EmptyCaseRange(low, high).with_location(loc))
+            }
+            labels.borrow_mut().add_case(low, high, loc)?;
+        }
+
+        Ok((expr, upper))
+    }
 
     fn parse_default_case(&mut self, context: &StmtContext) -> ParseResult<'a, Stmt> {
         let loc = self.expect(&[TokenKind::Default])?.location().clone();
         self.expect(&[TokenKind::Colon])?;
 
-        if let Some((default_case, _)) = context.in_switchon() {
-            let mut default_case = default_case.borrow_mut();
+        if let Some((labels, _)) = context.in_switchon() {
+            let default_case = &mut labels.borrow_mut().default_case;
             if let Some(prev) = default_case.as_ref() {
                 Err(ParseError::Redefinition(prev.clone(), "default case".into())
                     .with_location(loc))
@@ -329,6 +617,7 @@ impl<'a> Parser<'a> {
     }
 
     fn parse_match_stmt(&mut self, context: &StmtContext, init: fn(Vec<Expr>, Vec<(Vec<Located<Pattern>>, Box<Stmt>)>) -> StmtKind) -> ParseResult<'a, Stmt> {
+        let keyword = self.current().kind().to_string();
         let loc = self.advance()?.location().clone();
 
         let args = if self.current().kind() != &TokenKind::LParen {
@@ -340,15 +629,22 @@ impl<'a> Parser<'a> {
 
         let mut branches = vec![];
         while self.advance_if(&[TokenKind::Colon])?.is_some() {
-            let patterns = self.parse_pattern_list()?;
+            let patterns = self.parse_match_patterns()?;
             if patterns.len() != args.len() {
                 return Err(ParseError::WrongNumOfPatterns(args.len()).with_location(loc))
             }
 
             self.expect(&[TokenKind::Be])?;
-            let stmt = self.parse_stmt(&StmtContext::Match(context))?;
+            let stmt = self.scoped(|parser| {
+                parser.declare_locals(&patterns);
+                parser.parse_stmt(&StmtContext::Match(context))
+            })?;
             branches.push((patterns, Box::new(stmt)))
         }
+
+        if branches.is_empty() {
+            return Err(ParseError::MissingBranch(keyword).with_location(loc))
+        }
         
         self.semicolon_if_required(context)?;
 
@@ -358,16 +654,64 @@ impl<'a> Parser<'a> {
     fn parse_expr_stmt(&mut self, context: &StmtContext) -> ParseResult<'a, Stmt> {
         let loc = self.current().location().clone();
         let expr = self.parse_expr(context)?;
+        if let TokenKind::Comma | TokenKind::Assign = self.current().kind() {
+            return self.parse_assignment(context, loc, expr)
+        }
+
         if !expr.has_sideeffect() {
             self.push_warning(ParseError::ExprWithoutSideEffect.with_location(loc.clone()))
         }
+        else if let ExprKind::FuncCall(callee, _) = expr.kind()
+            && let ExprKind::Ident(routine) = callee.kind()
+            && !self.lints.is_result_ignored(routine) {
+            self.push_warning(ParseError::UnusedResult(routine.clone()).with_location(loc.clone()))
+        }
 
         self.semicolon_if_required(context)?;
 
         Ok(Stmt::new(loc, StmtKind::Expr(Box::new(expr))))
     }
 
+    // `a, b := x, y`; `first` is the first target, already parsed as an expression statement
+    fn parse_assignment(&mut self, context: &StmtContext, loc: Location, first: Expr) -> ParseResult<'a, Stmt> {
+        let mut targets = vec![first];
+        while self.advance_if(&[TokenKind::Comma])?.is_some() {
+            targets.push(self.parse_expr(context)?);
+        }
+        self.expect(&[TokenKind::Assign])?;
+
+        let mut values = vec![self.parse_expr(context)?];
+        while self.advance_if(&[TokenKind::Comma])?.is_some() {
+            values.push(self.parse_expr(context)?);
+        }
+
+        if values.len() != targets.len() {
+            return Err(ParseError::WrongNumOfValues(targets.len(), values.len()).with_location(loc))
+        }
+        if let Some(target) = targets.iter().find(|target| !target.is_lvalue()) {
+            return Err(ParseError::NotAssignable.with_location(target.location().clone()))
+        }
+
+        let pairs = targets.into_iter()
+            .zip(values)
+            .map(|(target, value)| Ok(match target.typ() {
+                Some(typ) => {
+                    let value = self.coerce(value, *typ)?;
+                    (target, value)
+                }
+                None => (target, value)
+            }))
+            .collect::<ParseResult<'a, Vec<_>>>()?;
+
+        self.semicolon_if_required(context)?;
+        Ok(Stmt::new(loc, StmtKind::Assign(pairs)))
+    }
+
     fn semicolon_if_required(&mut self, context: &StmtContext) -> ParseResult<'a, ()> {
+        // the statement is the body of a trailing-condition loop, which takes the semicolon
+        if let TokenKind::Repeat | TokenKind::RepeatWhile | TokenKind::RepeatUntil = self.current().kind() {
+            return Ok(())
+        }
         if context.require_semicolon() {
             self.expect(&[TokenKind::Semicolon])?;
         }
@@ -377,39 +721,376 @@ impl<'a> Parser<'a> {
     fn parse_next_break(&mut self, context: &StmtContext, is_break: bool) -> ParseResult<'a, Stmt> {
         let loc = self.advance()?.location().clone();
         self.semicolon_if_required(context)?;
-        if !context.in_loop() || !context.in_match() || context.in_switchon().is_none() {
-            Err(
+        if !context.in_loop() && !context.in_match() && context.in_switchon().is_none() {
+            self.loop_exits.push(
                 ParseError::InvalidStmt(if is_break { "break" } else { "next" }.into(), "loop, `match`, `every` or `switchon`".into())
-                    .with_location(loc)
-            )
+                    .with_location(loc.clone())
+            );
         }
-        else {
-            Ok(Stmt::new(loc, if is_break { StmtKind::Break } else { StmtKind::Next }))
+        Ok(Stmt::new(loc, if is_break { StmtKind::Break } else { StmtKind::Next }))
+    }
+
+    // `assert condition, "message"`
+    pub(super) fn parse_assertion(&mut self, context: &StmtContext) -> ParseResult<'a, (Expr, String)> {
+        self.expect(&[TokenKind::Assert])?;
+        let condition = self.parse_expr(context)?;
+        self.expect(&[TokenKind::Comma])?;
+
+        let TokenKind::StringLit(message) = *self.current().kind() else {
+            return self.unexpected(&[TokenKind::Ident("assertion message")])
+        };
+        let message = unescape(message).expect("escapes are checked by the lexer");
+        self.advance()?;
+        Ok((condition, message))
+    }
+
+    // `asm { "line" ... out a, b in c }`; the lines are joined with newlines, and `{name}` in them
+    // refers to the operand bound to the local `name`
+    fn parse_inline_asm(&mut self, context: &StmtContext) -> ParseResult<'a, Stmt> {
+        let loc = self.expect(&[TokenKind::Asm])?.location().clone();
+        self.expect(&[TokenKind::LBrace])?;
+
+        let mut lines = vec![];
+        while let TokenKind::StringLit(line) = *self.current().kind() {
+            lines.push((unescape(line).expect("escapes are checked by the lexer"), self.current().location().clone()));
+            self.advance()?;
+        }
+        if lines.is_empty() {
+            return self.unexpected(&[TokenKind::Ident("assembly string")])
+        }
+
+        let (mut outputs, mut inputs) = (vec![], vec![]);
+        while let TokenKind::Ident(clause @ ("in" | "out")) = *self.current().kind() {
+            let is_out = clause == "out";
+            self.advance()?;
+            let operands = if is_out { &mut outputs } else { &mut inputs };
+            loop {
+                operands.push(self.parse_asm_operand()?);
+                if self.advance_if(&[TokenKind::Comma])?.is_none() {
+                    break
+                }
+            }
+        }
+        self.expect(&[TokenKind::RBrace])?;
+        self.semicolon_if_required(context)?;
+
+        // operands are numbered outputs first, then inputs; a local listed as both is referred to
+        // by its output operand
+        let names = outputs.iter().chain(&inputs)
+            .map(|operand: &Expr| match operand.kind() {
+                ExprKind::Ident(ident) => ident.clone(),
+                _ => unreachable!("operands are locals")
+            })
+            .collect::<Vec<_>>();
+        let template = lines.into_iter()
+            .map(|(line, loc)| number_asm_operands(&line, &names).map_err(|ident| ParseError::UnknownAsmOperand(ident).with_location(loc)))
+            .collect::<ParseResult<'a, Vec<_>>>()?
+            .join("\n");
+        Ok(Stmt::new(loc, StmtKind::InlineAsm(template, outputs, inputs)))
+    }
+
+    fn parse_asm_operand(&mut self) -> ParseResult<'a, Expr> {
+        let loc = self.current().location().clone();
+        let ident = self.expect_ident()?;
+        if !self.is_own_local(&ident) {
+            return Err(ParseError::AsmOperand(ident).with_location(loc))
         }
+        Ok(Expr::new(loc, None, ExprKind::Ident(ident)))
+    }
+
+    fn parse_loop(&mut self, context: &StmtContext) -> ParseResult<'a, Stmt> {
+        let loc = self.expect(&[TokenKind::Loop])?.location().clone();
+        self.semicolon_if_required(context)?;
+        if !context.in_loop() {
+            self.loop_exits.push(
+                ParseError::InvalidStmt("loop".into(), "loop".into())
+                    .with_location(loc.clone())
+            );
+        }
+        Ok(Stmt::new(loc, StmtKind::Loop))
+    }
+
+    fn parse_goto(&mut self, context: &StmtContext) -> ParseResult<'a, Stmt> {
+        let loc = self.expect(&[TokenKind::Goto])?.location().clone();
+        let label_loc = self.current().location().clone();
+        let label = self.expect_ident()?.with_location(label_loc);
+        self.semicolon_if_required(context)?;
+        if self.routine.is_none() {
+            return Err(ParseError::InvalidStmt("goto".into(), "routine".into()).with_location(loc))
+        }
+
+        self.gotos.push(label.clone());
+        Ok(Stmt::new(loc, StmtKind::Goto(label)))
+    }
+
+    // `label:` before a statement. Labels are only recognized in blocks, where statements end
+    // with a semicolon; elsewhere, a `:` after a name may start the next branch of a `match`.
+    fn at_label(&self) -> bool {
+        self.lexer.clone().next().is_some_and(|token| token.kind() == &TokenKind::Colon)
+    }
+
+    fn parse_labelled(&mut self, context: &StmtContext) -> ParseResult<'a, Stmt> {
+        let loc = self.current().location().clone();
+        let label = self.expect_ident()?.with_location(loc.clone());
+        self.expect(&[TokenKind::Colon])?;
+        if self.routine.is_none() {
+            return Err(ParseError::InvalidStmt("label".into(), "routine".into()).with_location(loc))
+        }
+        self.labels.push(label.clone());
+
+        // a label may end a block
+        let body = if self.current().kind() == &TokenKind::RBrace {
+            Stmt::new(self.current().location().clone(), StmtKind::Nop)
+        }
+        else {
+            self.parse_stmt(context)?
+        };
+        Ok(Stmt::new(loc, StmtKind::Labelled(label, Box::new(body))))
     }
 
+    // `let a, b := x, y`; the traditional `let a, b = x, y` is accepted as well.
+    // `let a = x and b = y` binds simultaneously, like a single `let a, b = x, y`.
     fn parse_let_binding(&mut self, context: &StmtContext) -> ParseResult<'a, Stmt> {
         let loc = self.expect(&[TokenKind::Let])?.location().clone();
 
-        let patterns = self.parse_pattern_list()?;
-        self.expect(&[TokenKind::Assign])?;
+        let mut bindings = vec![];
+        loop {
+            let group_loc = self.current().location().clone();
+            let patterns = self.parse_pattern_list()?;
+            self.expect(&[TokenKind::Assign, TokenKind::Eq])?;
 
-        let mut exprs = vec![self.parse_expr(context)?];
-        while self.advance_if(&[TokenKind::Comma])?.is_some() {
-            exprs.push(self.parse_expr(context)?);
+            let mut exprs = vec![self.parse_expr(context)?];
+            while self.advance_if(&[TokenKind::Comma])?.is_some() {
+                exprs.push(self.parse_expr(context)?);
+            }
+
+            if exprs.len() != patterns.len() {
+                return Err(ParseError::WrongNumOfPatterns(patterns.len()).with_location(group_loc))
+            }
+            bindings.extend(patterns.into_iter().zip(exprs));
+
+            if self.advance_if(&[TokenKind::And])?.is_none() {
+                break;
+            }
         }
 
         self.semicolon_if_required(context)?;
+        // the values still refer to names of the enclosing scope
+        bindings.iter().for_each(|(pattern, value)| self.declare_typed(pattern, self.type_of(value)));
+        Ok(Stmt::new(loc, StmtKind::Binding(bindings)))
+    }
 
-        if exprs.len() != patterns.len() {
-            Err(ParseError::WrongNumOfPatterns(patterns.len()).with_location(loc))
+    // `let f(...) be ...` or `let f(...) = ...` rather than a binding; parameters have a type or a
+    // default value, which patterns do not
+    fn at_nested_routine(&self) -> bool {
+        let mut tokens = self.lexer.clone().take_while(|token| !token.is_eof());
+        if !tokens.next().is_some_and(|token| matches!(token.kind(), TokenKind::Ident(_))) {
+            return false
+        }
+
+        match tokens.next().as_ref().map(Token::kind) {
+            Some(TokenKind::Be | TokenKind::Of | TokenKind::Colon | TokenKind::Lt) => true,
+            Some(TokenKind::LParen) => {
+                let mut depth = 0usize;
+                for (i, token) in tokens.enumerate() {
+                    match token.kind() {
+                        TokenKind::RParen if i == 0 => return true,
+                        TokenKind::RParen if depth == 0 => return false,
+                        TokenKind::Of | TokenKind::Eq if depth == 0 => return true,
+                        TokenKind::LParen | TokenKind::LBracket => depth += 1,
+                        TokenKind::RParen | TokenKind::RBracket => depth -= 1,
+                        _ => ()
+                    }
+                }
+                false
+            }
+            _ => false
+        }
+    }
+
+    // the routine is declared in the section, after the one it is nested in
+    fn parse_nested_routine(&mut self) -> ParseResult<'a, Stmt> {
+        let loc = self.expect(&[TokenKind::Let])?.location().clone();
+
+        // `break` and `next` in its body cannot be resolved by a `repeat` after it
+        let loop_exits = std::mem::take(&mut self.loop_exits);
+        let inner_routines = self.nested_routines.len();
+        let func = self.parse_function_decl(loc.clone(), false);
+        let nested_exits = std::mem::replace(&mut self.loop_exits, loop_exits);
+        self.errors.extend(nested_exits);
+        let func = func?;
+
+        // a routine capturing locals gets its environment record built where it is declared
+        let stmt = if func.captures().is_empty() {
+            Stmt::new(loc.clone(), StmtKind::Nop)
         }
         else {
-            Ok(Stmt::new(loc, StmtKind::Binding(
-                        patterns.into_iter()
-                                .zip(exprs.into_iter())
-                                .collect()
-            )))
+            let mut pass = PassEnvironment::new(func.ident().clone(), None);
+            for inner in &mut self.nested_routines[inner_routines..] {
+                inner.traverse(&mut pass)?;
+            }
+            self.build_environment(&func, &loc)
+        };
+
+        self.nested_routines.push(func);
+        self.advance_if(&[TokenKind::Semicolon])?;
+        Ok(stmt)
+    }
+}
+
+// holds the value of a `resultis` while the deferred statements before it run, and what a
+// `throw` out of the block threw while they run on the way out; no identifier can clash with them
+const DEFERRED_RESULT: &str = "defer.result";
+const DEFERRED_THROWN: &str = "defer.thrown";
+
+// runs the statements deferred in a block, the one deferred last first, after its last statement
+// and before each statement after their `defer` that leaves it. Throws, also those of routines
+// called in the block, unwind through a handler that runs them and throws again, so the thrown
+// value is computed before they run.
+fn run_deferred(mut stmts: Vec<Stmt>, deferred: Vec<(usize, Stmt)>) -> Vec<Stmt> {
+    let labels = stmts.iter().flat_map(labels_in).collect::<Vec<_>>();
+    for (i, stmt) in stmts.iter_mut().enumerate() {
+        let pending = deferred.iter().rev()
+            .filter(|(before, _)| *before <= i)
+            .map(|(_, deferred)| deferred.clone())
+            .collect::<Vec<_>>();
+        if !pending.is_empty() {
+            run_before_exits(stmt, &pending, &labels, false, false);
+        }
+    }
+
+    for (before, deferred) in deferred.into_iter().rev() {
+        let loc = deferred.location().clone();
+        let body = Stmt::new(loc.clone(), StmtKind::Block(stmts.split_off(before)));
+        let thrown = Pattern::Query(DEFERRED_THROWN.into()).with_location(loc.clone());
+        let handler = Stmt::new(loc.clone(), StmtKind::Block(vec![deferred.clone(), Stmt::new(loc.clone(), StmtKind::Rethrow)]));
+        stmts.push(Stmt::new(loc, StmtKind::Try(Box::new(body), thrown, Box::new(handler))));
+        stmts.push(deferred);
+    }
+    stmts
+}
+
+// throws are left to the handlers of `run_deferred`
+fn run_before_exits(stmt: &mut Stmt, deferred: &[Stmt], labels: &[String], in_loop: bool, breakable: bool) {
+    if exit_keyword(stmt.kind(), labels, in_loop, breakable, true).is_none() {
+        let (in_loop, breakable) = nested_exits(stmt.kind(), in_loop, breakable);
+        for stmt in stmt.kind_mut().stmts_mut() {
+            run_before_exits(stmt, deferred, labels, in_loop, breakable);
         }
+        return
+    }
+
+    let loc = stmt.location().clone();
+    let mut exit = std::mem::replace(stmt, Stmt::new(loc.clone(), StmtKind::Nop));
+    let mut stmts = vec![];
+    // the result is computed before the deferred statements run
+    if let StmtKind::ResultIs(value) = exit.kind_mut() {
+        let value = std::mem::replace(&mut **value, Expr::new(loc.clone(), None, ExprKind::Ident(DEFERRED_RESULT.into())));
+        stmts.push(Stmt::new(loc.clone(), StmtKind::Binding(vec![(Pattern::Query(DEFERRED_RESULT.into()).with_location(loc.clone()), value)])));
+    }
+    stmts.extend(deferred.iter().cloned());
+    stmts.push(exit);
+    *stmt = Stmt::new(loc, StmtKind::Block(stmts));
+}
+
+// the first statement within `stmt` that leaves it, with the keyword it starts with
+fn leaving_exit(stmt: &Stmt, labels: &[String], in_loop: bool, breakable: bool, caught: bool) -> Option<(&'static str, Location)> {
+    if let Some(exit) = exit_keyword(stmt.kind(), labels, in_loop, breakable, caught) {
+        return Some((exit, stmt.location().clone()))
     }
+    let (in_loop, breakable) = nested_exits(stmt.kind(), in_loop, breakable);
+    let is_try = matches!(stmt.kind(), StmtKind::Try(..));
+    stmt.kind().stmts().into_iter()
+        .enumerate()
+        .find_map(|(i, stmt)| leaving_exit(stmt, labels, in_loop, breakable, caught || (is_try && i == 0)))
+}
+
+// whether a statement leaves the one it is nested in, given whether a loop and a construct
+// `break` leaves are in between and whether a `try` catches what is thrown; `goto` stays
+// within it if it jumps to one of `labels`
+fn exit_keyword(kind: &StmtKind, labels: &[String], in_loop: bool, breakable: bool, caught: bool) -> Option<&'static str> {
+    match kind {
+        StmtKind::Return => Some("return"),
+        StmtKind::ResultIs(_) => Some("resultis"),
+        StmtKind::Throw(_) if !caught => Some("throw"),
+        StmtKind::Rethrow if !caught => Some("rethrow"),
+        StmtKind::Goto(label) if !labels.contains(&**label) => Some("goto"),
+        StmtKind::Break if !breakable => Some("break"),
+        StmtKind::Next if !breakable => Some("next"),
+        StmtKind::Loop if !in_loop => Some("loop"),
+        _ => None
+    }
+}
+
+fn nested_exits(kind: &StmtKind, in_loop: bool, breakable: bool) -> (bool, bool) {
+    match kind {
+        StmtKind::While(..) | StmtKind::Until(..) | StmtKind::Repeat(_) | StmtKind::RepeatWhile(..) | StmtKind::RepeatUntil(..)
+            | StmtKind::For(..) => (true, true),
+        StmtKind::SwitchOn(..) | StmtKind::Match(..) | StmtKind::Every(..) => (in_loop, true),
+        _ => (in_loop, breakable)
+    }
+}
+
+// `line` with each `{name}` replaced by `{n}`, the number of the operand bound to `name`, or the
+// first name not bound to an operand
+fn number_asm_operands(line: &str, names: &[String]) -> Result<String, String> {
+    let mut numbered = String::new();
+    let mut chars = line.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '{' | '}' if chars.peek() == Some(&ch) => {
+                chars.next();
+                numbered.extend([ch, ch]);
+            }
+            '{' => {
+                let name = chars.by_ref().take_while(|ch| *ch != '}').collect::<String>();
+                let index = names.iter().position(|operand| *operand == name).ok_or(name)?;
+                write!(numbered, "{{{index}}}").unwrap();
+            }
+            ch => numbered.push(ch)
+        }
+    }
+    Ok(numbered)
+}
+
+// the first case label within `stmt` belonging to a `switchon` around it
+fn case_label_in(stmt: &Stmt) -> Option<&Location> {
+    match stmt.kind() {
+        StmtKind::Case(_) | StmtKind::DefaultCase => Some(stmt.location()),
+        StmtKind::SwitchOn(..) => None,
+        kind => kind.stmts().into_iter().find_map(case_label_in)
+    }
+}
+
+fn labels_in(stmt: &Stmt) -> Vec<String> {
+    let mut labels = stmt.kind().stmts().into_iter().flat_map(labels_in).collect::<Vec<_>>();
+    if let StmtKind::Labelled(label, _) = stmt.kind() {
+        labels.push((**label).clone());
+    }
+    labels
+}
+
+// whether `stmt` leaves the loop it is the body of; `break` within `nested` constructs
+// leaves those instead
+fn leaves_loop(stmt: &Stmt, nested: bool) -> bool {
+    match stmt.kind() {
+        StmtKind::Break => !nested,
+        // the label may well be outside of the loop
+        StmtKind::Return | StmtKind::ResultIs(_) | StmtKind::Goto(_) => true,
+        // a throw caught within the loop itself is not told apart
+        StmtKind::Throw(_) | StmtKind::Rethrow => true,
+        StmtKind::Expr(expr) => ends_program(expr),
+        StmtKind::While(..) | StmtKind::Until(..) | StmtKind::Repeat(_) | StmtKind::RepeatWhile(..) | StmtKind::RepeatUntil(..)
+            | StmtKind::For(..) | StmtKind::SwitchOn(..) | StmtKind::Match(..) | StmtKind::Every(..) => stmt.kind().stmts().into_iter().any(|stmt| leaves_loop(stmt, true)),
+        kind => kind.stmts().into_iter().any(|stmt| leaves_loop(stmt, nested))
+    }
+}
+
+// `finish` and calls of `stop`, which end the program without returning
+fn ends_program(expr: &Expr) -> bool {
+    let callee = match expr.kind() {
+        ExprKind::FuncCall(callee, _) => callee,
+        _ => expr
+    };
+    matches!(callee.kind(), ExprKind::Ident(routine) if routine == "finish" || routine == "stop")
 }
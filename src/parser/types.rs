@@ -1,12 +1,12 @@
 use crate::{
-    ast::types::{TypeIndex, TypeKind, Type, SumVariant},
-    token::TokenKind, source_file::WithLocation
+    ast::{types::{TypeIndex, TypeKind, Type, SumVariant, Coercion}, expr::Expr},
+    token::TokenKind, source_file::{Location, WithLocation}
 };
 
 use super::{Parser, ParseResult, ParseError, stmt::StmtContext};
 
 impl<'a> Parser<'a> {
-    pub(super) fn parse_type_alias(&mut self) -> ParseResult<'a, ()> {
+    pub(super) fn parse_type_alias(&mut self) -> ParseResult<'a, TypeIndex> {
         self.expect(&[TokenKind::Type])?;
         let loc = self.current().location().clone();
         let alias = self.expect_ident()?; 
@@ -14,22 +14,66 @@ impl<'a> Parser<'a> {
         self.expect(&[TokenKind::Eq])?;
 
         let typ = self.parse_type()?;
+        let id = self.define_alias(alias.clone(), loc.clone(), typ)?;
+
+        // any cycle goes through the alias defined last; it is left undefined to break it
         let mut ast = self.ast.lock().unwrap();
-        if let Some(id) = ast.types().find_alias(&alias) {
-            let existing = ast.types_mut().get_mut(id).unwrap();
-            if let Some(existing_loc) = existing.location() {
-                Err(ParseError::Redefinition(existing_loc.clone(), alias).with_location(loc))
-            }
-            else {
-                existing.set_location(loc);
-                existing.set_kind(TypeKind::Alias(alias, Some(typ)));
-                Ok(())
-            }
+        if ast.types().contains(id, id) {
+            ast.types_mut().get_mut(id).unwrap().set_kind(TypeKind::Alias(alias.clone(), None));
+            return Err(ParseError::RecursiveType(alias).with_location(loc))
         }
-        else {
-            ast.types_mut().define(Type::new(Some(loc), TypeKind::Alias(alias, Some(typ))));
-            Ok(())
+        Ok(id)
+    }
+
+    // gives the name `alias` to `typ`, filling in an alias used before its definition
+    fn define_alias(&self, alias: String, loc: Location, typ: TypeIndex) -> ParseResult<'a, TypeIndex> {
+        let mut ast = self.ast.lock().unwrap();
+        let Some(id) = ast.types().find_alias(&alias) else {
+            return Ok(ast.types_mut().define(Type::new(Some(loc), TypeKind::Alias(alias.clone(), Some(typ)))))
+        };
+
+        let existing = ast.types_mut().get_mut(id).unwrap();
+        if let Some(existing_loc) = existing.location() {
+            return Err(ParseError::Redefinition(existing_loc.clone(), alias).with_location(loc))
+        }
+
+        existing.set_location(loc);
+        existing.set_kind(TypeKind::Alias(alias.clone(), Some(typ)));
+        Ok(id)
+    }
+
+    // `enum Name { A, B, ... }` declares a type and its constants, numbered from zero
+    pub(super) fn parse_enum(&mut self) -> ParseResult<'a, ()> {
+        let loc = self.expect(&[TokenKind::Enum])?.location().clone();
+        let ident_loc = self.current().location().clone();
+        let ident = self.expect_ident()?;
+
+        self.expect(&[TokenKind::LBrace])?;
+        let constants = self.parse_list(TokenKind::RBrace, TokenKind::Comma, Self::parse_enum_constant, &())?;
+
+        for (i, (constant, constant_loc)) in constants.iter().enumerate() {
+            let prev = constants[..i].iter()
+                .find(|(other, _)| other == constant)
+                .map(|(_, loc)| loc.clone())
+                .or_else(|| {
+                    let ast = self.ast.lock().unwrap();
+                    let (typ, _) = ast.types().enum_constant(constant)?;
+                    ast.types().get(typ)?.location().clone()
+                });
+            if let Some(prev) = prev {
+                return Err(ParseError::Redefinition(prev, constant.clone()).with_location(constant_loc.clone()))
+            }
         }
+
+        let constants = constants.into_iter().map(|(constant, _)| constant).collect();
+        let typ = self.ast.lock().unwrap().types_mut().define(Type::new(Some(loc), TypeKind::Enum(ident.clone(), constants)));
+        self.define_alias(ident, ident_loc, typ)?;
+        Ok(())
+    }
+
+    fn parse_enum_constant(&mut self, _: &()) -> ParseResult<'a, (String, Location)> {
+        let loc = self.current().location().clone();
+        Ok((self.expect_ident()?, loc))
     }
 
     pub(super) fn parse_type(&mut self) -> ParseResult<'a, TypeIndex> {
@@ -51,6 +95,8 @@ impl<'a> Parser<'a> {
                 Ok(typ)
             },
             TokenKind::LBracket => self.parse_array_type(),
+            TokenKind::Struct => self.parse_struct_type(),
+            TokenKind::Let => self.parse_routine_type(),
             TokenKind::LogAnd => {
                 self.advance()?;
                 let inner_typ = self.parse_type()?;
@@ -60,10 +106,53 @@ impl<'a> Parser<'a> {
         }
     }
 
+    // result type annotations are followed by `:` or a body, so a bare name never starts a sum type there
+    pub(super) fn parse_result_type(&mut self) -> ParseResult<'a, TypeIndex> {
+        match self.current().kind().clone() {
+            TokenKind::Ident(ident) => {
+                let ident = ident.to_string();
+                self.advance()?;
+                Ok(self.type_ident(ident))
+            }
+            TokenKind::LogAnd => {
+                self.advance()?;
+                let inner_typ = self.parse_result_type()?;
+                Ok(self.pointer_to(inner_typ))
+            },
+            _ => self.parse_type()
+        }
+    }
+
     fn parse_type_param(&mut self, _: &()) -> ParseResult<'a, TypeIndex> {
         self.parse_type()
     }
 
+    // `<T, U>` after the name of a generic routine; each parameter is a type of its own, standing
+    // for the types of the arguments in calls of the routine
+    pub(super) fn parse_type_params(&mut self) -> ParseResult<'a, Vec<(String, TypeIndex)>> {
+        if self.advance_if(&[TokenKind::Lt])?.is_none() {
+            return Ok(vec![])
+        }
+        let params = self.parse_list(TokenKind::Gt, TokenKind::Comma, Self::parse_type_param_decl, &())?;
+
+        let ast = self.ast.lock().unwrap();
+        for (i, (ident, typ)) in params.iter().enumerate() {
+            if let Some((_, prev)) = params[..i].iter().find(|(other, _)| other == ident) {
+                let location = |typ: &TypeIndex| ast.types().get(*typ).and_then(|typ| typ.location().clone()).unwrap_or_default();
+                return Err(ParseError::Redefinition(location(prev), ident.clone()).with_location(location(typ)))
+            }
+        }
+        Ok(params)
+    }
+
+    fn parse_type_param_decl(&mut self, _: &()) -> ParseResult<'a, (String, TypeIndex)> {
+        let loc = self.current().location().clone();
+        let ident = self.expect_ident()?;
+        // not interned, so that the parameters of different routines stay distinct
+        let typ = self.ast.lock().unwrap().types_mut().define(Type::new(Some(loc), TypeKind::Param(ident.clone())));
+        Ok((ident, typ))
+    }
+
     fn parse_sum_variant(&mut self, ident: String) -> ParseResult<'a, SumVariant> {
         Ok(SumVariant::Basic(
             ident,
@@ -84,7 +173,51 @@ impl<'a> Parser<'a> {
         Ok(self.get_type(TypeKind::Sum(variants)))
     }
 
+    // `struct { field :: Type, ... }`
+    fn parse_struct_type(&mut self) -> ParseResult<'a, TypeIndex> {
+        let loc = self.expect(&[TokenKind::Struct])?.location().clone();
+        self.expect(&[TokenKind::LBrace])?;
+        let fields = self.parse_list(TokenKind::RBrace, TokenKind::Comma, Self::parse_struct_field, &())?;
+
+        for (i, (ident, field_loc, _)) in fields.iter().enumerate() {
+            if let Some((_, first_loc, _)) = fields[..i].iter().find(|(other, ..)| other == ident) {
+                return Err(ParseError::Redefinition(first_loc.clone(), ident.clone()).with_location(field_loc.clone()))
+            }
+        }
+
+        let fields = fields.into_iter().map(|(ident, _, typ)| (ident, typ)).collect();
+        // not interned, so that structs with the same fields stay distinct
+        Ok(self.ast.lock().unwrap().types_mut().define(Type::new(Some(loc), TypeKind::Struct(fields))))
+    }
+
+    // `let(T, U) :: R`, spelled like the declaration of a routine; without `:: R` it returns
+    // nothing, like one defined with `be`
+    fn parse_routine_type(&mut self) -> ParseResult<'a, TypeIndex> {
+        self.expect(&[TokenKind::Let])?;
+        self.expect(&[TokenKind::LParen])?;
+        let params = self.parse_list(TokenKind::RParen, TokenKind::Comma, Self::parse_type_param, &())?;
+
+        let result = if self.advance_if(&[TokenKind::Of])?.is_some() {
+            self.parse_result_type()?
+        }
+        else {
+            self.get_type(TypeKind::Unit)
+        };
+        Ok(self.get_type(TypeKind::Routine(params, result)))
+    }
+
+    fn parse_struct_field(&mut self, _: &()) -> ParseResult<'a, (String, Location, TypeIndex)> {
+        let loc = self.current().location().clone();
+        let ident = self.expect_ident()?;
+        self.expect(&[TokenKind::Of])?;
+        Ok((ident, loc, self.parse_type()?))
+    }
+
     fn type_ident(&self, ident: String) -> TypeIndex {
+        if let Some((_, typ)) = self.type_params.iter().rfind(|(param, _)| *param == ident) {
+            return *typ
+        }
+
         let mut ast = self.ast.lock().unwrap(); 
         let types = ast.types_mut();
         if let Some(typ) = types.builtin_by_ident(&ident) {
@@ -102,13 +235,11 @@ impl<'a> Parser<'a> {
         self.advance()?;
         let inner = self.parse_type()?;
         if let TokenKind::Comma = self.expect(&[TokenKind::RBracket, TokenKind::Comma])?.kind() {
-            let mut expr = self.parse_expr(&StmtContext::Empty)?;
+            let expr = self.parse_expr(&StmtContext::Empty)?;
             self.expect(&[TokenKind::RBracket])?;
             
             let index_type = self.get_type(TypeKind::UInt64);
-            if expr.typ() != &Some(index_type) {
-                expr = expr.implicit_cast(index_type)
-            }
+            let expr = self.coerce(expr, index_type)?;
 
             Ok(self.get_type(TypeKind::Array(inner, Box::new(expr))))
         }
@@ -127,10 +258,85 @@ impl<'a> Parser<'a> {
         types.define(Type::new(None, typ)) 
     }
 
+    // the single place where the parser inserts implicit casts
+    pub(super) fn coerce(&self, expr: Expr, typ: TypeIndex) -> ParseResult<'a, Expr> {
+        let loc = expr.location().clone();
+        let ast = self.ast.lock().unwrap();
+        let types = ast.types();
+        expr.coerce(typ, types)
+            .map_err(|(from, to)| if types.is_enum(from) && types.is_enum(to) {
+                ParseError::MixedEnums(types.type_name(from), types.type_name(to)).with_location(loc)
+            }
+            else {
+                ParseError::InvalidCoercion(types.type_name(from), types.type_name(to)).with_location(loc)
+            })
+    }
+
+    // values of different enumerations cannot be compared, even through untyped names
+    pub(super) fn check_mixed_enums(&self, a: Option<TypeIndex>, b: Option<TypeIndex>, loc: &Location) -> ParseResult<'a, ()> {
+        let (Some(a), Some(b)) = (a, b) else {
+            return Ok(())
+        };
+
+        let ast = self.ast.lock().unwrap();
+        let types = ast.types();
+        if types.is_enum(a) && types.is_enum(b) && types.resolve(a) != types.resolve(b) {
+            return Err(ParseError::MixedEnums(types.type_name(a), types.type_name(b)).with_location(loc.clone()))
+        }
+        Ok(())
+    }
+
+    // the type both arms of a conditional are coerced to; the first arm wins
+    // unless only the second one can hold both values
+    pub(super) fn common_type(&self, a: &Expr, b: &Expr) -> Option<TypeIndex> {
+        let (ta, tb) = match (a.typ(), b.typ()) {
+            (Some(ta), Some(tb)) if !a.is_nil() && !b.is_nil() => (*ta, *tb),
+            (_, tb) if a.is_nil() || a.typ().is_none() => return tb.or(*a.typ()),
+            (ta, _) => return *ta
+        };
+
+        let ast = self.ast.lock().unwrap();
+        let types = ast.types();
+        let is_float = |typ: TypeIndex| types.get(types.resolve(typ)).is_some_and(|typ| typ.kind().is_float());
+        let is_integer = |typ: TypeIndex| types.get(types.resolve(typ)).is_some_and(|typ| typ.kind().is_integer());
+
+        if is_integer(ta) && is_float(tb)
+            || types.coercion(tb, ta) == Coercion::Impossible && types.coercion(ta, tb) != Coercion::Impossible {
+            Some(tb)
+        }
+        else {
+            Some(ta)
+        }
+    }
+
     pub(super) fn pointer_to(&self, typ: TypeIndex) -> TypeIndex {
         self.get_type(TypeKind::Pointer(typ))
     }
 
+    // the parameter and result types of a routine pointer of type `typ`
+    pub(super) fn routine_signature(&self, typ: TypeIndex) -> Option<(Vec<TypeIndex>, TypeIndex)> {
+        let ast = self.ast.lock().unwrap();
+        let types = ast.types();
+        match types.get(types.resolve(typ))?.kind() {
+            TypeKind::Routine(params, result) => Some((params.clone(), *result)),
+            _ => None
+        }
+    }
+
+    // the named type whose methods apply to values of type `typ`, which is it or a pointer to it
+    pub(super) fn method_record(&self, typ: TypeIndex) -> Option<String> {
+        let ast = self.ast.lock().unwrap();
+        let types = ast.types();
+        let typ = match types.get(typ)?.kind() {
+            TypeKind::Pointer(inner) => *inner,
+            _ => typ
+        };
+        match types.get(typ)?.kind() {
+            TypeKind::Alias(record, _) => Some(record.clone()),
+            _ => None
+        }
+    }
+
     pub(super) fn get_string_type(&self) -> TypeIndex {
         self.pointer_to(self.get_type(TypeKind::Char))
     }
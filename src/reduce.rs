@@ -0,0 +1,164 @@
+use std::{ops::Range, path::{Path, PathBuf}, process::Command, sync::{Arc, Mutex}};
+
+use crate::{
+    ast::Program,
+    extract::items,
+    lint::LintConfig,
+    parser::Parser,
+    source_file::SourceFile,
+    token::{TokenKind, lexer::Lexer}
+};
+
+pub struct Reduced {
+    pub source: String,
+    // how often the compiler was run
+    pub compilations: usize
+}
+
+// Shrinks the program in `path` while building it still prints `pattern`, by removing one
+// top-level item or statement at a time. Candidates are written next to `path`, so that `get`
+// finds the same files; if the program parses, candidates that do not are skipped without
+// running the compiler. `tags` are those the builds are given, so that sections they skip are
+// not looked into.
+pub fn reduce(path: &str, pattern: &str, tags: &[String], build_args: &[String]) -> Result<Reduced, String> {
+    let source = std::fs::read_to_string(path).map_err(|err| format!("error reading `{path}`: {err}."))?;
+    let mut reducer = Reducer::new(path, pattern, tags, build_args)?;
+    let result = reducer.reduce(path, source);
+    reducer.clean_up();
+    result.map(|source| Reduced { source, compilations: reducer.compilations })
+}
+
+struct Reducer<'a> {
+    pattern: &'a str,
+    tags: &'a [String],
+    build_args: &'a [String],
+    compiler: PathBuf,
+    candidate: PathBuf,
+    output: PathBuf,
+    compilations: usize
+}
+
+impl<'a> Reducer<'a> {
+    fn new(path: &str, pattern: &'a str, tags: &'a [String], build_args: &'a [String]) -> Result<Self, String> {
+        let path = Path::new(path);
+        let stem = path.file_stem().map_or("input".into(), |stem| stem.to_string_lossy());
+        let extension = path.extension().map_or("bpp".into(), |ext| ext.to_string_lossy());
+        Ok(Self {
+            pattern,
+            tags,
+            build_args,
+            compiler: std::env::current_exe().map_err(|err| format!("error locating the compiler: {err}."))?,
+            candidate: path.with_file_name(format!("{stem}.reduce.{extension}")),
+            output: std::env::temp_dir().join(format!("bcplpp-reduce-{}", std::process::id())),
+            compilations: 0
+        })
+    }
+
+    fn reduce(&mut self, path: &str, mut source: String) -> Result<String, String> {
+        if !self.reproduces(&source)? {
+            return Err(format!("building `{path}` does not print `{}`; nothing to reduce.", self.pattern))
+        }
+        let require_parse = parses(path, &source, self.tags);
+
+        // after each pass that removed something, the others are tried again
+        let (mut next, mut progressed) = (0, false);
+        loop {
+            let spans = removable(&SourceFile::new(path.into(), 0, source.clone()), self.tags);
+            let Some(span) = spans.get(next) else {
+                if !progressed {
+                    return Ok(source)
+                }
+                (next, progressed) = (0, false);
+                continue
+            };
+
+            let mut candidate = source.clone();
+            candidate.replace_range(span.clone(), "");
+            if (!require_parse || parses(path, &candidate, self.tags)) && self.reproduces(&candidate)? {
+                source = candidate;
+                progressed = true;
+            }
+            else {
+                next += 1;
+            }
+        }
+    }
+
+    fn reproduces(&mut self, source: &str) -> Result<bool, String> {
+        std::fs::write(&self.candidate, source).map_err(|err| format!("error writing `{}`: {err}.", self.candidate.display()))?;
+        self.compilations += 1;
+
+        let output = Command::new(&self.compiler)
+            .arg("build")
+            .arg(&self.candidate)
+            .arg("-o")
+            .arg(&self.output)
+            .args(self.build_args)
+            .output()
+            .map_err(|err| format!("error running `{}`: {err}.", self.compiler.display()))?;
+        Ok(String::from_utf8_lossy(&output.stdout).contains(self.pattern) || String::from_utf8_lossy(&output.stderr).contains(self.pattern))
+    }
+
+    fn clean_up(&self) {
+        let _ = std::fs::remove_file(&self.candidate);
+        let _ = std::fs::remove_file(&self.output);
+    }
+}
+
+fn parses(path: &str, source: &str, tags: &[String]) -> bool {
+    let file = SourceFile::new(path.into(), 0, source.into());
+    let lints = LintConfig::default();
+    let mut parser = Parser::new(Lexer::from(&file).with_tags(tags), Arc::new(Mutex::new(Program::default())), &lints);
+    parser.parse().is_ok() && parser.errors().is_empty()
+}
+
+// byte ranges that can be removed: the top-level items, then each statement of a block with its
+// `;`, enclosing statements before the ones within them
+fn removable(file: &SourceFile, tags: &[String]) -> Vec<Range<usize>> {
+    let contents = file.contents();
+    let line_starts = std::iter::once(0)
+        .chain(contents.match_indices('\n').map(|(i, _)| i + 1))
+        .collect::<Vec<_>>();
+    // locations count lines from 1 and columns in characters
+    let offset = |line: usize, column: usize| {
+        let start = line_starts.get(line - 1).copied().unwrap_or(contents.len());
+        contents[start..].char_indices().nth(column).map_or(contents.len(), |(i, _)| start + i)
+    };
+
+    let mut spans = items(file, tags).into_iter()
+        .map(|(first, end)| offset(first, 0)..line_starts.get(end - 1).copied().unwrap_or(contents.len()))
+        .collect::<Vec<_>>();
+
+    let tokens = Lexer::from(file).with_tags(tags).take_while(|token| !token.is_eof()).collect::<Vec<_>>();
+    let mut statements = vec![];
+    // where the current statement of each open block starts
+    let mut blocks: Vec<Option<usize>> = vec![];
+    for token in &tokens {
+        let loc = token.location();
+        let start = offset(loc.line(), loc.column());
+        if let Some(current) = blocks.last_mut() && current.is_none() && token.kind() != &TokenKind::RBrace {
+            *current = Some(start);
+        }
+
+        match token.kind() {
+            TokenKind::LBrace => blocks.push(None),
+            TokenKind::RBrace => { blocks.pop(); }
+            TokenKind::Semicolon => if let Some(current) = blocks.last_mut() && let Some(stmt_start) = current.take() {
+                statements.push(stmt_start..start + loc.width());
+            }
+            _ => ()
+        }
+    }
+
+    // a statement on lines of its own takes them along
+    for span in &mut statements {
+        let line_start = contents[..span.start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = contents[span.end..].find('\n').map_or(contents.len(), |i| span.end + i + 1);
+        if contents[line_start..span.start].trim().is_empty() && contents[span.end..line_end].trim().is_empty() {
+            *span = line_start..line_end;
+        }
+    }
+    statements.sort_by_key(|span| (span.start, std::cmp::Reverse(span.end)));
+    spans.extend(statements);
+    spans
+}
@@ -0,0 +1,138 @@
+use std::time::{Duration, Instant};
+
+use crate::memory::{self, PhaseMemory, Snapshot};
+
+// Summary of one compilation for `--build-report`, written as JSON
+#[derive(Default)]
+pub struct BuildReport {
+    inputs: Vec<String>,
+    artifacts: Vec<String>,
+    // in the order the phases ran
+    timings: Vec<(&'static str, Duration)>,
+    // allocations of the same phases, counted from `memory_mark`
+    memory: Vec<(&'static str, PhaseMemory)>,
+    memory_mark: Snapshot,
+    errors: usize,
+    warnings: usize,
+    cache_hits: usize,
+    cache_misses: usize,
+    fatal: bool,
+    interrupted: bool
+}
+
+impl BuildReport {
+    pub fn add_input(&mut self, path: String) {
+        if !self.inputs.contains(&path) {
+            self.inputs.push(path);
+        }
+    }
+
+    pub fn add_artifact(&mut self, path: String) {
+        self.artifacts.push(path);
+    }
+
+    pub fn record_phase(&mut self, phase: &'static str, start: Instant) {
+        self.timings.push((phase, start.elapsed()));
+
+        let (usage, mark) = PhaseMemory::since(&self.memory_mark);
+        self.memory.push((phase, usage));
+        self.memory_mark = mark;
+    }
+
+    // allocations from here on count towards the next phase
+    pub fn mark_memory(&mut self) {
+        memory::reset_peak();
+        self.memory_mark = memory::snapshot();
+    }
+
+    pub fn memory(&self) -> &[(&'static str, PhaseMemory)] {
+        &self.memory
+    }
+
+    pub fn set_diagnostics(&mut self, errors: usize, warnings: usize) {
+        self.errors = errors;
+        self.warnings = warnings;
+    }
+
+    pub fn set_cache_stats(&mut self, hits: usize, misses: usize) {
+        self.cache_hits = hits;
+        self.cache_misses = misses;
+    }
+
+    pub fn set_fatal(&mut self) {
+        self.fatal = true;
+    }
+
+    pub fn set_interrupted(&mut self) {
+        self.interrupted = true;
+    }
+
+    // forgets the artifacts written so far, returning their paths
+    pub fn take_artifacts(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.artifacts)
+    }
+
+    fn status(&self) -> &'static str {
+        if self.fatal {
+            "fatal"
+        }
+        else if self.interrupted {
+            "interrupted"
+        }
+        else if self.errors > 0 {
+            "error"
+        }
+        else if self.warnings > 0 {
+            "warning"
+        }
+        else {
+            "ok"
+        }
+    }
+
+    pub fn to_json(&self, total: Duration) -> String {
+        let mut inputs = self.inputs.clone();
+        inputs.sort();
+
+        let timings = self.timings.iter()
+            .map(|(phase, duration)| (*phase, *duration))
+            .chain(std::iter::once(("total", total)))
+            .map(|(phase, duration)| format!("\"{phase}\": {:.3}", duration.as_secs_f64() * 1000.0))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        format!("{{
+  \"compiler\": \"bcplpp {}\",
+  \"status\": \"{}\",
+  \"inputs\": {},
+  \"artifacts\": {},
+  \"diagnostics\": {{ \"errors\": {}, \"warnings\": {} }},
+  \"timings_ms\": {{ {timings} }},
+  \"cache\": {{ \"hits\": {}, \"misses\": {} }}
+}}
+",
+            env!("CARGO_PKG_VERSION"), self.status(), json_list(&inputs), json_list(&self.artifacts),
+            self.errors, self.warnings, self.cache_hits, self.cache_misses
+        )
+    }
+}
+
+pub(crate) fn json_list(items: &[String]) -> String {
+    format!("[{}]", items.iter().map(|item| json_string(item)).collect::<Vec<_>>().join(", "))
+}
+
+pub(crate) fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c)
+        }
+    }
+    out.push('"');
+    out
+}
@@ -1,54 +1,256 @@
 use std::{
-    io::Read,
+    io::{Read, Write, Seek, SeekFrom, ErrorKind},
     ops::{Deref, DerefMut}, 
-    fmt::{Formatter, Debug}, hash::Hash
+    fmt::{Formatter, Debug}, hash::Hash,
+    fs::{File, OpenOptions},
+    path::PathBuf,
+    sync::{Arc, Mutex, atomic::{AtomicUsize, Ordering}}
 };
 
+use memmap2::Mmap;
+
 pub type SourceFileId = u32;
 
+// mapped sources are validated and indexed in windows of this many bytes
+const READ_CHUNK_SIZE: usize = 1 << 20;
+// line starts beyond this many are moved into a temporary file, so that the index of a
+// generated source of hundreds of megabytes does not stay in memory next to its contents
+const MAX_RESIDENT_LINES: usize = 1 << 20;
+
+// how sources are read; only tests use others than the defaults
+#[derive(Clone, Copy, Debug)]
+pub struct ReadLimits {
+    pub chunk_size: usize,
+    pub max_resident_lines: usize
+}
+
+impl Default for ReadLimits {
+    fn default() -> Self {
+        Self {
+            chunk_size: READ_CHUNK_SIZE,
+            max_resident_lines: MAX_RESIDENT_LINES
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct SourceFile {
     id: SourceFileId,
     path: String,
-    contents: String,
-    lines: Vec<String>
+    text: Arc<SourceText>,
+    // the `get` directive this file was included by
+    included_from: Option<Location>
 }
 
 impl SourceFile {
     pub fn read(path: String, id: SourceFileId) -> std::io::Result<Self> {
-        let mut file = std::fs::File::open(path.clone())?;
-        
-        let mut contents = String::new();
-        contents.reserve(file.metadata().unwrap().len() as usize);
+        let text = SourceText::read(&path, ReadLimits::default())?;
+        Ok(Self::with_text(path, id, Arc::new(text)))
+    }
 
-        file.read_to_string(&mut contents)?;
+    pub fn new(path: String, id: SourceFileId, contents: String) -> Self {
+        Self::with_text(path, id, Arc::new(SourceText::new(contents, ReadLimits::default())))
+    }
 
-        Ok(Self {
+    // a file with text read before, like the daemon's cached sources
+    pub fn with_text(path: String, id: SourceFileId, text: Arc<SourceText>) -> Self {
+        Self {
             id,
             path,
-            lines: contents.split('\n').map(|e| e.to_string()).collect(),
-            contents
-        })
+            text,
+            included_from: None
+        }
     }
 
-    pub fn contents(&self) -> &String {
-        &self.contents
+    pub fn contents(&self) -> &str {
+        self.text.contents()
     }
 
-    pub fn line(&self, line_num: usize) -> Option<&String> {
-        self.lines.get(line_num - 1)
+    pub fn line(&self, line_num: usize) -> Option<&str> {
+        self.text.line(line_num)
     }
 
-    fn id(&self) -> SourceFileId {
+    pub fn id(&self) -> SourceFileId {
         self.id
     }
 
     pub fn path(&self) -> &String {
         &self.path
     }
+
+    pub fn set_included_from(&mut self, loc: Location) {
+        self.included_from = Some(loc);
+    }
+
+    pub fn included_from(&self) -> Option<&Location> {
+        self.included_from.as_ref()
+    }
+}
+
+// the contents of a source file with the offsets of its lines
+#[derive(Debug)]
+pub struct SourceText {
+    contents: Contents,
+    lines: LineIndex
+}
+
+impl SourceText {
+    // Files are mapped rather than read, so the lexer streams through them while the kernel
+    // pages them in and out; sources larger than memory never have to be resident at once.
+    pub fn read(path: &str, limits: ReadLimits) -> std::io::Result<Self> {
+        let file = File::open(path)?;
+        // empty files cannot be mapped
+        if file.metadata()?.len() == 0 {
+            return Ok(Self::new(String::new(), limits))
+        }
+
+        // SAFETY: the mapping is only read, and sources must not change while they are compiled
+        let map = unsafe { Mmap::map(&file)? };
+
+        let mut lines = LineIndex::new(limits.max_resident_lines);
+        let mut offset = 0;
+        while offset < map.len() {
+            // a window holds at least one character of up to four bytes
+            let end = (offset + limits.chunk_size.max(4)).min(map.len());
+            // a character cut off by the end of the window is validated with the next one
+            let valid = match std::str::from_utf8(&map[offset..end]) {
+                Ok(text) => text.len(),
+                Err(err) if err.error_len().is_none() && end < map.len() => err.valid_up_to(),
+                Err(_) => return Err(invalid_utf8(path))
+            };
+            lines.extend(offset, std::str::from_utf8(&map[offset..offset + valid]).unwrap());
+            offset += valid;
+        }
+
+        Ok(Self {
+            contents: Contents::Mapped(map),
+            lines
+        })
+    }
+
+    pub fn new(contents: String, limits: ReadLimits) -> Self {
+        let mut lines = LineIndex::new(limits.max_resident_lines);
+        lines.extend(0, &contents);
+        Self {
+            contents: Contents::Owned(contents),
+            lines
+        }
+    }
+
+    pub fn contents(&self) -> &str {
+        &self.contents
+    }
+
+    // counting from 1, without the line break
+    pub fn line(&self, line_num: usize) -> Option<&str> {
+        let start = self.lines.start(line_num - 1)?;
+        let end = self.lines.start(line_num).map_or(self.contents.len(), |next| next - 1);
+        self.contents.get(start..end)
+    }
 }
 
-#[derive(Clone, PartialEq)]
+// the text of a source file; files read from disk stay mapped instead of being copied
+#[derive(Debug)]
+enum Contents {
+    Mapped(Mmap),
+    Owned(String)
+}
+
+impl Deref for Contents {
+    type Target = str;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            // SAFETY: validated as UTF-8 when the file was read
+            Self::Mapped(map) => unsafe { std::str::from_utf8_unchecked(map) },
+            Self::Owned(contents) => contents
+        }
+    }
+}
+
+fn invalid_utf8(path: &str) -> std::io::Error {
+    std::io::Error::new(ErrorKind::InvalidData, format!("`{path}` is not valid UTF-8"))
+}
+
+// the byte offsets the lines of a source file start at. The first `spilled` of them are kept in
+// a temporary file once there are more than `max_resident`; if that file cannot be
+// created, the index stays in memory.
+#[derive(Debug)]
+struct LineIndex {
+    starts: Vec<usize>,
+    spill: Option<LineSpill>,
+    spilled: usize,
+    max_resident: usize
+}
+
+#[derive(Debug)]
+struct LineSpill {
+    path: PathBuf,
+    // little-endian `u64` offsets
+    file: Mutex<File>
+}
+
+// distinguishes the spill files of one process
+static NEXT_SPILL: AtomicUsize = AtomicUsize::new(0);
+
+impl LineIndex {
+    fn new(max_resident: usize) -> Self {
+        Self {
+            starts: vec![0],
+            spill: None,
+            spilled: 0,
+            max_resident
+        }
+    }
+
+    // indexes the lines starting in `text`, which is found at byte `offset` of the file
+    fn extend(&mut self, offset: usize, text: &str) {
+        for (i, _) in text.match_indices('\n') {
+            self.starts.push(offset + i + 1);
+            if self.starts.len() > self.max_resident && self.spill_starts().is_err() {
+                self.max_resident = usize::MAX;
+            }
+        }
+    }
+
+    fn spill_starts(&mut self) -> std::io::Result<()> {
+        if self.spill.is_none() {
+            let path = std::env::temp_dir().join(format!("bcplpp-lines-{}-{}", std::process::id(), NEXT_SPILL.fetch_add(1, Ordering::Relaxed)));
+            let file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&path)?;
+            self.spill = Some(LineSpill { path, file: Mutex::new(file) });
+        }
+
+        let bytes = self.starts.iter().flat_map(|start| (*start as u64).to_le_bytes()).collect::<Vec<_>>();
+        let mut file = self.spill.as_ref().unwrap().file.lock().unwrap();
+        file.seek(SeekFrom::End(0))?;
+        file.write_all(&bytes)?;
+
+        self.spilled += self.starts.len();
+        self.starts.clear();
+        Ok(())
+    }
+
+    // the offset of the line at `index`, counting from 0
+    fn start(&self, index: usize) -> Option<usize> {
+        if index >= self.spilled {
+            return self.starts.get(index - self.spilled).copied()
+        }
+
+        let mut file = self.spill.as_ref()?.file.lock().unwrap();
+        let mut bytes = [0; 8];
+        file.seek(SeekFrom::Start(index as u64 * 8)).ok()?;
+        file.read_exact(&mut bytes).ok()?;
+        Some(u64::from_le_bytes(bytes) as usize)
+    }
+}
+
+impl Drop for LineSpill {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[derive(Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Location {
     source_file_id: SourceFileId,
     line: u32,
@@ -70,6 +272,13 @@ impl Location {
         self.width = width as u32;
     }
 
+    // widens this location up to the end of `end`; spans over several lines stop at the first one
+    pub fn extend_to(&mut self, end: &Location) {
+        if end.source_file_id == self.source_file_id && end.line == self.line && end.column >= self.column {
+            self.width = end.column + end.width - self.column;
+        }
+    }
+
     pub fn file_id(&self) -> SourceFileId {
         self.source_file_id
     }
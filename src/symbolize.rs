@@ -0,0 +1,123 @@
+use std::collections::HashMap;
+
+use crate::ir::LINE_TABLE_HEADER;
+
+// Resolves addresses from crash logs with the line table of a symbol map (`--emit map`).
+// Addresses are `.section+offset`, `routine+offset` or a plain offset into `.text`; offsets
+// count IR operations like the map does.
+
+struct Line {
+    section: String,
+    address: u64,
+    routine: String,
+    offset: u64,
+    location: String
+}
+
+pub struct LineTable {
+    lines: Vec<Line>,
+    // routine sizes from the symbol table, to reject addresses past the last routine
+    sizes: HashMap<String, u64>
+}
+
+enum Address<'a> {
+    Section(&'a str, u64),
+    Routine(&'a str, u64)
+}
+
+impl LineTable {
+    pub fn read(path: &str) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path).map_err(|err| format!("error reading symbol map `{path}`: {err}."))?;
+
+        let mut table = Self {
+            lines: vec![],
+            sizes: HashMap::new()
+        };
+        let mut in_line_table = false;
+        for line in contents.lines() {
+            if line == LINE_TABLE_HEADER {
+                in_line_table = true;
+                continue
+            }
+
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            match (in_line_table, fields.as_slice()) {
+                (false, [name, _, _, size, "ops"]) => if let Ok(size) = size.parse() {
+                    table.sizes.insert(name.to_string(), size);
+                }
+                (true, [address, routine, location]) => {
+                    let (Some(Address::Section(section, address)), Some(Address::Routine(routine, offset))) = (parse_address(address, false), parse_address(routine, false)) else {
+                        return Err(format!("{path}: malformed line table entry `{}`.", line.trim()))
+                    };
+                    table.lines.push(Line {
+                        section: section.to_string(),
+                        address,
+                        routine: routine.to_string(),
+                        offset,
+                        location: location.to_string()
+                    });
+                }
+                _ => ()
+            }
+        }
+
+        if !in_line_table {
+            return Err(format!("`{path}` has no line table; emit it again with `--emit map`."))
+        }
+        Ok(table)
+    }
+
+    // `routine+offset at file:line` for the address `text`, if it is one and known
+    pub fn resolve(&self, text: &str) -> Option<String> {
+        let address = parse_address(text, false)?;
+        // the last entry at or before the address, within the same routine
+        let line = self.lines.iter()
+            .filter(|line| match address {
+                Address::Section(section, address) => line.section == section && line.address <= address,
+                Address::Routine(routine, offset) => line.routine == routine && line.offset <= offset
+            })
+            .max_by_key(|line| line.address)?;
+
+        let offset = match address {
+            Address::Section(_, address) => line.offset + address - line.address,
+            Address::Routine(_, offset) => offset
+        };
+        if offset >= *self.sizes.get(&line.routine)? {
+            return None
+        }
+        Some(format!("{}+{offset:#x} at {}", line.routine, line.location))
+    }
+
+    // `line` with every hexadecimal address in it followed by its routine and source line
+    pub fn symbolize_line(&self, line: &str) -> String {
+        let mut out = String::new();
+        for (i, word) in line.split(' ').enumerate() {
+            if i > 0 {
+                out.push(' ');
+            }
+            out.push_str(word);
+
+            let trimmed = word.trim_matches(|ch: char| matches!(ch, '(' | ')' | '[' | ']' | '<' | '>' | ',' | ':' | ';'));
+            if parse_address(trimmed, true).is_some() && let Some(resolved) = self.resolve(trimmed) {
+                out.push_str(&format!(" ({resolved})"));
+            }
+        }
+        out
+    }
+}
+
+// with `hex_only`, plain numbers are not taken as addresses, as in free-form log lines
+fn parse_address(text: &str, hex_only: bool) -> Option<Address<'_>> {
+    let parse_offset = |offset: &str| match offset.strip_prefix("0x").or_else(|| offset.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None if hex_only => None,
+        None => offset.parse().ok()
+    };
+
+    match text.rsplit_once('+') {
+        Some((symbol, offset)) if symbol.starts_with('.') => Some(Address::Section(symbol, parse_offset(offset)?)),
+        Some((symbol, offset)) if !symbol.is_empty() => Some(Address::Routine(symbol, parse_offset(offset)?)),
+        Some(_) => None,
+        None => Some(Address::Section(".text", parse_offset(text)?))
+    }
+}
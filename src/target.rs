@@ -0,0 +1,66 @@
+// Code generation parameters of the target machine, passed on to the backends
+#[derive(Clone, Debug, Default)]
+pub struct Target {
+    // `None` selects the backend's generic cpu
+    cpu: Option<String>,
+
+    // explicitly enabled or disabled features, later ones override earlier ones
+    features: Vec<(String, bool)>
+}
+
+impl Target {
+    pub fn set_cpu(&mut self, cpu: String) {
+        self.cpu = Some(cpu);
+    }
+
+    pub fn cpu(&self) -> Option<&String> {
+        self.cpu.as_ref()
+    }
+
+    // substituted for `{target}` in output file names
+    pub fn name(&self) -> &str {
+        self.cpu.as_deref().unwrap_or("generic")
+    }
+
+    // parses a comma-separated list like `+sse2,-fpu`
+    pub fn add_features(&mut self, list: &str) -> Result<(), String> {
+        for feature in list.split(',').map(str::trim).filter(|feature| !feature.is_empty()) {
+            let (enabled, name) = match (feature.strip_prefix('+'), feature.strip_prefix('-')) {
+                (Some(name), _) => (true, name),
+                (_, Some(name)) => (false, name),
+                _ => return Err(format!("target feature `{feature}` has to start with `+` or `-`."))
+            };
+
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.' || c == '_') {
+                return Err(format!("invalid target feature `{feature}`."))
+            }
+
+            self.features.retain(|(other, _)| other != name);
+            self.features.push((name.to_string(), enabled));
+        }
+
+        Ok(())
+    }
+
+    // `None` leaves the feature at the cpu's default
+    pub fn feature(&self, name: &str) -> Option<bool> {
+        self.features.iter().find(|(feature, _)| feature == name).map(|(_, enabled)| *enabled)
+    }
+
+    pub fn features(&self) -> &Vec<(String, bool)> {
+        &self.features
+    }
+
+    // floating point operations have to be emulated in software
+    pub fn soft_float(&self) -> bool {
+        self.feature("soft-float") == Some(true) || self.feature("fpu") == Some(false)
+    }
+
+    // in the `+a,-b` syntax accepted by `add_features`
+    pub fn features_string(&self) -> String {
+        self.features.iter()
+            .map(|(name, enabled)| format!("{}{name}", if *enabled { '+' } else { '-' }))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
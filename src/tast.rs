@@ -0,0 +1,989 @@
+use std::collections::HashMap;
+
+use crate::{
+    match_decl,
+    ast::{
+        Program, Section, Decl, IntoDecl, Function, FunctionBody, Param, Variadic, GlobalDecl, ManifestDecl, Storage,
+        attribute::{Deprecation, CallingConvention, Hook},
+        types::{TypeIndex, TypeKind, Type, SumVariant},
+        expr::{Expr, ExprKind, OverflowMode},
+        stmt::{Stmt, StmtKind},
+        pattern::{Pattern, PatternTerm}
+    },
+    source_file::{SourceFile, SourceFileId, Location, Located, WithLocation}
+};
+
+// The typed ast of a program after type checking, written by `--emit tast` and read back as an
+// input in place of source files. It is a list of s-expressions:
+//
+//   bcplpp-tast 1
+//   (file 0 "main.bpp")
+//   (type 14 @0:3:5+5 (alias Point 15))
+//   (type 15 @0:3:13+6 (struct (x 6) (y 6)))
+//   (type 16 @0:4:22+6 (struct (a 15)) repr-c)
+//   (section @0:1:0+7 Main
+//     (routine @0:5:4+4 norm Int32
+//       (params (param @0:5:9+1 (query @0:5:9+1 p) 16 ?))
+//       (expr (add @0:5:26+1 6 (member ...) (member ...)))))
+//
+// Expressions are `(kind location type operands...)`, statements and patterns
+// `(kind location operands...)`; absent types and values are `?`. Locations `@file:line:column+width`
+// refer to the files listed first, which are read again for diagnostics if they still exist.
+// Everything after `;` on a line is a comment.
+
+pub const TAST_EXT: &str = ".tast";
+const TAST_HEADER: [&str; 2] = ["bcplpp-tast", "1"];
+// lists longer than this are broken into one element per line
+const LINE_WIDTH: usize = 100;
+
+pub fn write_typed_ast(ast: &Program, source_files: &HashMap<SourceFileId, SourceFile>) -> String {
+    let writer = Writer { ast };
+    let mut nodes = vec![];
+
+    let mut files = source_files.iter().collect::<Vec<_>>();
+    files.sort_by_key(|(id, _)| **id);
+    nodes.extend(files.into_iter().map(|(id, file)| list([atom("file"), atom(id), Node::Str(file.path().clone())])));
+
+    for (index, typ) in ast.types().iter().enumerate() {
+        nodes.push(list([atom("type"), atom(index), typ.location().as_ref().map_or(atom("?"), location), writer.type_kind(typ.kind())]
+            .into_iter()
+            .chain(typ.is_c_layout().then(|| atom("repr-c")))
+        ));
+    }
+
+    let mut sections = ast.sections().collect::<Vec<_>>();
+    sections.sort_by(|a, b| a.ident().cmp(b.ident()));
+    nodes.extend(sections.into_iter().map(|section| writer.section(section)));
+
+    let mut out = TAST_HEADER.join(" ");
+    out.push('\n');
+    for node in nodes {
+        node.write(&mut out, 0);
+        out.push('\n');
+    }
+    out
+}
+
+// reads the sections and types of a typed ast into `ast`; returns the files its locations refer
+// to, numbered from `first_id`
+pub fn read_typed_ast(path: &str, contents: &str, ast: &mut Program, first_id: SourceFileId) -> Result<HashMap<SourceFileId, SourceFile>, String> {
+    let nodes = parse_nodes(contents).map_err(|line| format!("{path}:{line}: malformed typed ast"))?;
+    let header = nodes.iter().take(2).map(Node::as_atom).collect::<Vec<_>>();
+    if header != TAST_HEADER.map(Some) {
+        return Err(format!("`{path}` is not a typed ast file."))
+    }
+
+    let mut reader = Reader { path, ast, files: HashMap::new(), types: HashMap::new() };
+    for node in &nodes[2..] {
+        if let Some([head, id, file_path]) = node.as_list() && head.as_atom() == Some("file") {
+            let (id, file_path) = (reader.number(id)?, reader.string(file_path)?);
+            let new_id = first_id + reader.files.len() as SourceFileId;
+            let contents = std::fs::read_to_string(&file_path).unwrap_or_default();
+            reader.files.insert(id, SourceFile::new(file_path, new_id, contents));
+        }
+    }
+
+    let types = nodes[2..].iter()
+        .filter_map(Node::as_list)
+        .filter(|node| node.first().and_then(Node::as_atom) == Some("type"))
+        .collect::<Vec<_>>();
+    reader.types(&types)?;
+
+    for node in &nodes[2..] {
+        match node.as_list() {
+            Some([head, ..]) if matches!(head.as_atom(), Some("file" | "type")) => (),
+            Some([head, rest @ ..]) if head.as_atom() == Some("section") => {
+                let section = reader.section(rest)?;
+                if reader.ast.section(section.ident()).is_some() {
+                    return Err(format!("{path}: section `{}` is defined more than once.", section.ident()))
+                }
+                reader.ast.add_section(section);
+            }
+            _ => return reader.malformed("item")
+        }
+    }
+
+    Ok(reader.files.into_values().map(|file| (file.id(), file)).collect())
+}
+
+#[derive(Debug)]
+enum Node {
+    Atom(String),
+    Str(String),
+    List(Vec<Node>)
+}
+
+impl Node {
+    fn as_atom(&self) -> Option<&str> {
+        match self {
+            Self::Atom(atom) => Some(atom),
+            _ => None
+        }
+    }
+
+    fn as_list(&self) -> Option<&[Node]> {
+        match self {
+            Self::List(nodes) => Some(nodes),
+            _ => None
+        }
+    }
+
+    fn flat(&self) -> String {
+        match self {
+            // names of instances, like `max<Int64, &Point>`, would not read back as one atom
+            Self::Atom(atom) if atom.is_empty() || atom.contains(|c: char| c.is_whitespace() || matches!(c, '(' | ')' | '"' | ';')) => format!("{atom:?}"),
+            Self::Atom(atom) => atom.clone(),
+            Self::Str(s) => format!("{s:?}"),
+            Self::List(nodes) => format!("({})", nodes.iter().map(Node::flat).collect::<Vec<_>>().join(" "))
+        }
+    }
+
+    // atoms leading a broken list stay on its first line
+    fn write(&self, out: &mut String, indent: usize) {
+        let flat = self.flat();
+        let Self::List(nodes) = self else {
+            return out.push_str(&flat)
+        };
+        if indent + flat.len() <= LINE_WIDTH {
+            return out.push_str(&flat)
+        }
+
+        let leading = nodes.iter().take_while(|node| !matches!(node, Self::List(_))).count();
+        out.push('(');
+        out.push_str(&nodes[..leading].iter().map(Node::flat).collect::<Vec<_>>().join(" "));
+        for node in &nodes[leading..] {
+            out.push('\n');
+            out.push_str(&" ".repeat(indent + 2));
+            node.write(out, indent + 2);
+        }
+        out.push(')');
+    }
+}
+
+fn atom(value: impl ToString) -> Node {
+    Node::Atom(value.to_string())
+}
+
+fn list(nodes: impl IntoIterator<Item = Node>) -> Node {
+    Node::List(nodes.into_iter().collect())
+}
+
+fn location(loc: &Location) -> Node {
+    atom(format!("@{}:{}:{}+{}", loc.file_id(), loc.line(), loc.column(), loc.width()))
+}
+
+fn opt_type(typ: Option<TypeIndex>) -> Node {
+    typ.map_or(atom("?"), atom)
+}
+
+// fails with the line of the first malformed token
+fn parse_nodes(src: &str) -> Result<Vec<Node>, usize> {
+    let mut stack: Vec<Vec<Node>> = vec![vec![]];
+    let mut chars = src.chars().peekable();
+    let mut line = 1;
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => line += 1,
+            c if c.is_whitespace() => (),
+            ';' => while chars.next_if(|c| *c != '\n').is_some() {},
+            '(' => stack.push(vec![]),
+            ')' => {
+                let nodes = stack.pop().filter(|_| !stack.is_empty()).ok_or(line)?;
+                stack.last_mut().unwrap().push(Node::List(nodes));
+            }
+            '"' => {
+                let mut s = String::new();
+                loop {
+                    match chars.next().ok_or(line)? {
+                        '"' => break,
+                        '\\' => s.push(unescape(&mut chars).ok_or(line)?),
+                        '\n' => return Err(line),
+                        c => s.push(c)
+                    }
+                }
+                stack.last_mut().unwrap().push(Node::Str(s));
+            }
+            c => {
+                let mut atom = c.to_string();
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace() && !matches!(c, '(' | ')' | '"' | ';')) {
+                    atom.push(c);
+                }
+                stack.last_mut().unwrap().push(Node::Atom(atom));
+            }
+        }
+    }
+
+    match stack.pop() {
+        Some(nodes) if stack.is_empty() => Ok(nodes),
+        _ => Err(line)
+    }
+}
+
+// the escapes written by `{:?}`
+fn unescape(chars: &mut impl Iterator<Item = char>) -> Option<char> {
+    match chars.next()? {
+        'n' => Some('\n'),
+        't' => Some('\t'),
+        'r' => Some('\r'),
+        '0' => Some('\0'),
+        'u' => {
+            if chars.next()? != '{' {
+                return None
+            }
+            let hex = chars.by_ref().take_while(|c| *c != '}').collect::<String>();
+            char::from_u32(u32::from_str_radix(&hex, 16).ok()?)
+        }
+        c @ ('\\' | '"' | '\'') => Some(c),
+        _ => None
+    }
+}
+
+struct Writer<'a> {
+    ast: &'a Program
+}
+
+impl Writer<'_> {
+    fn type_kind(&self, kind: &TypeKind) -> Node {
+        match kind {
+            TypeKind::Pointer(inner) => list([atom("ptr"), atom(inner)]),
+            TypeKind::Array(inner, len) => list([atom("array"), atom(inner), self.expr(len)]),
+            TypeKind::Slice(inner) => list([atom("slice"), atom(inner)]),
+            TypeKind::Alias(ident, inner) => list([atom("alias"), atom(ident), opt_type(*inner)]),
+            TypeKind::Sum(variants) => list(std::iter::once(atom("sum")).chain(variants.iter()
+                .map(|SumVariant::Basic(ident, fields)| list(std::iter::once(atom(ident)).chain(fields.iter().map(atom))))
+            )),
+            TypeKind::Struct(fields) => list(std::iter::once(atom("struct")).chain(fields.iter()
+                .map(|(ident, typ)| list([atom(ident), atom(typ)]))
+            )),
+            TypeKind::Enum(ident, constants) => list([atom("enum"), atom(ident)].into_iter().chain(constants.iter().map(atom))),
+            TypeKind::Param(ident) => list([atom("param"), atom(ident)]),
+            TypeKind::Routine(params, result) => list([atom("routine"), atom(result)].into_iter().chain(params.iter().map(atom))),
+            builtin => atom(format!("{builtin:?}"))
+        }
+    }
+
+    fn section(&self, section: &Section) -> Node {
+        let mut nodes = vec![atom("section"), location(section.location()), atom(section.ident())];
+
+        let mut required = section.required().collect::<Vec<_>>();
+        required.sort_by(|a, b| a.cmp(b));
+        nodes.extend(required.into_iter().map(|require| list([atom("require"), location(require.location()), atom(&**require)])));
+        nodes.extend(section.needs().iter().map(|need| list([atom("needs"), location(need.location()), Node::Str((**need).clone())])));
+
+        let mut declarations = section.declarations().collect::<Vec<_>>();
+        declarations.sort_by_key(|decl| decl.location().clone());
+        nodes.extend(declarations.into_iter().map(|decl| self.decl(&**decl)));
+
+        nodes.extend(section.assertions().iter()
+            .map(|(condition, message)| list([atom("assert"), self.expr(condition), Node::Str(message.clone())]))
+        );
+        list(nodes)
+    }
+
+    fn decl(&self, decl: &dyn Decl) -> Node {
+        let mut nodes = vec![];
+        let mut attributes = vec![];
+        match_decl!{
+            decl;
+            func as Function => {
+                nodes.extend([atom("routine"), location(func.location()), atom(func.ident()), opt_type(*func.return_type())]);
+                nodes.push(list(std::iter::once(atom("params")).chain(func.params().iter().map(|param| list([
+                    atom("param"),
+                    location(param.location()),
+                    self.pattern(param.pattern()),
+                    opt_type(param.typ()),
+                    param.default_value().map_or(atom("?"), |value| self.expr(value))
+                ])))));
+                nodes.push(match func.body() {
+                    FunctionBody::Expr(expr) => list([atom("expr"), self.expr(expr)]),
+                    FunctionBody::Stmt(stmt) => list([atom("stmt"), self.stmt(stmt)]),
+                    FunctionBody::PatternMatchedExpr(branches) => list(std::iter::once(atom("match-expr"))
+                        .chain(branches.iter().map(|(patterns, expr)| self.branch(patterns, self.expr(expr))))
+                    ),
+                    FunctionBody::PatternMatchedStmt(branches) => list(std::iter::once(atom("match-stmt"))
+                        .chain(branches.iter().map(|(patterns, stmt)| self.branch(patterns, self.stmt(stmt))))
+                    )
+                });
+
+                if func.is_tailcall_recursive() {
+                    attributes.push(atom("and"));
+                }
+                if let Some(variadic) = func.variadic() {
+                    attributes.push(list([atom("variadic"), location(variadic.location()), variadic.ident().map_or(atom("?"), atom)]));
+                }
+                if !func.captures().is_empty() {
+                    attributes.push(list(std::iter::once(atom("captures")).chain(func.captures().iter().map(atom))));
+                }
+                if func.is_inline() {
+                    attributes.push(atom("inline"));
+                }
+                if func.calling_convention() == CallingConvention::C {
+                    attributes.push(atom("ccall"));
+                }
+                if let Some(hook) = func.hook() {
+                    attributes.push(list([atom(hook.name()), atom(hook.priority())]));
+                }
+                if let Some(link_section) = func.link_section() {
+                    attributes.push(list([atom("link-section"), Node::Str(link_section.clone())]));
+                }
+            },
+            global as GlobalDecl => {
+                let storage = match global.storage() {
+                    Storage::Global => "global",
+                    Storage::Static => "static"
+                };
+                nodes.extend([
+                    atom(storage),
+                    location(global.location()),
+                    atom(global.ident()),
+                    global.slot().map_or(atom("?"), atom),
+                    opt_type(*global.typ()),
+                    global.value().as_ref().map_or(atom("?"), |value| self.expr(value))
+                ]);
+                if let Some(link_section) = global.link_section() {
+                    attributes.push(list([atom("link-section"), Node::Str(link_section.clone())]));
+                }
+            },
+            manifest as ManifestDecl => nodes.extend([atom("manifest"), location(manifest.location()), atom(manifest.ident()), self.expr(manifest.value())]),
+            _ => unreachable!("unknown declaration {decl:?}")
+        }
+
+        if let Some(deprecation) = decl.deprecation() {
+            attributes.push(list(std::iter::once(atom("deprecated")).chain(deprecation.message().map(|message| Node::Str(message.clone())))));
+        }
+        nodes.extend(attributes);
+        list(nodes)
+    }
+
+    fn branch(&self, patterns: &[Located<Pattern>], body: Node) -> Node {
+        list([atom("branch"), list(patterns.iter().map(|pattern| self.pattern(pattern))), body])
+    }
+
+    fn expr(&self, expr: &Expr) -> Node {
+        let exprs = |exprs: &[&Box<Expr>]| exprs.iter().map(|expr| self.expr(expr)).collect::<Vec<_>>();
+        let (kind, operands) = match expr.kind() {
+            ExprKind::Ident(ident) => ("ident", vec![atom(ident)]),
+            ExprKind::Atom(index) => ("atom", vec![Node::Str(self.ast.atom_name(*index).cloned().unwrap_or_default())]),
+            ExprKind::IntLit(value) => ("int", vec![atom(value)]),
+            ExprKind::FloatLit(value) => ("float", vec![atom(format!("{value:?}"))]),
+            ExprKind::CharLit(c) => ("char", vec![atom(*c as u32)]),
+            ExprKind::StringLit(s) => ("string", vec![Node::Str(s.clone())]),
+            ExprKind::True => ("true", vec![]),
+            ExprKind::False => ("false", vec![]),
+            ExprKind::Nil => ("nil", vec![]),
+            ExprKind::Error => ("error", vec![]),
+            ExprKind::Temporary(index) => ("temporary", vec![atom(index)]),
+            ExprKind::Constant(index) => ("constant", vec![atom(index)]),
+            ExprKind::SizeOf(typ) => ("sizeof", vec![atom(typ)]),
+            ExprKind::OffsetOf(typ, field) => ("offsetof", vec![atom(typ), atom(field)]),
+            ExprKind::Member(inner, field) => ("member", vec![atom(field), self.expr(inner)]),
+            ExprKind::Overflow(mode, inner) => ("overflow", vec![atom(format!("{mode:?}").to_lowercase()), self.expr(inner)]),
+            ExprKind::ValOf(body) => ("valof", vec![self.stmt(body)]),
+            ExprKind::FuncCall(callee, args) => ("call", std::iter::once(self.expr(callee)).chain(args.iter().map(|arg| self.expr(arg))).collect()),
+            ExprKind::VarArgs(args) => ("varargs", args.iter().map(|arg| self.expr(arg)).collect()),
+            ExprKind::NamedArg(name, arg) => ("named-arg", vec![atom(name), self.expr(arg)]),
+            ExprKind::Match(args, branches) | ExprKind::Every(args, branches) => (
+                if matches!(expr.kind(), ExprKind::Match(..)) { "match" } else { "every" },
+                std::iter::once(list(args.iter().map(|arg| self.expr(arg))))
+                    .chain(branches.iter().map(|(patterns, body)| self.branch(patterns, self.expr(body))))
+                    .collect()
+            ),
+            ExprKind::Abs(inner) => ("abs", exprs(&[inner])),
+            ExprKind::Not(inner) => ("not", exprs(&[inner])),
+            ExprKind::Ref(inner) => ("ref", exprs(&[inner])),
+            ExprKind::Deref(inner) => ("deref", exprs(&[inner])),
+            ExprKind::Cast(inner) => ("cast", exprs(&[inner])),
+            ExprKind::ImplicitCast(inner) => ("implicit-cast", exprs(&[inner])),
+            ExprKind::Vec(inner) => ("vec", exprs(&[inner])),
+            ExprKind::Add(lhs, rhs) => ("add", exprs(&[lhs, rhs])),
+            ExprKind::Sub(lhs, rhs) => ("sub", exprs(&[lhs, rhs])),
+            ExprKind::Mul(lhs, rhs) => ("mul", exprs(&[lhs, rhs])),
+            ExprKind::Div(lhs, rhs) => ("div", exprs(&[lhs, rhs])),
+            ExprKind::Mod(lhs, rhs) => ("mod", exprs(&[lhs, rhs])),
+            ExprKind::And(lhs, rhs) => ("and", exprs(&[lhs, rhs])),
+            ExprKind::Or(lhs, rhs) => ("or", exprs(&[lhs, rhs])),
+            ExprKind::XOr(lhs, rhs) => ("xor", exprs(&[lhs, rhs])),
+            ExprKind::LogAnd(lhs, rhs) => ("log-and", exprs(&[lhs, rhs])),
+            ExprKind::LogOr(lhs, rhs) => ("log-or", exprs(&[lhs, rhs])),
+            ExprKind::Eq(lhs, rhs) => ("eq", exprs(&[lhs, rhs])),
+            ExprKind::Ne(lhs, rhs) => ("ne", exprs(&[lhs, rhs])),
+            ExprKind::Gt(lhs, rhs) => ("gt", exprs(&[lhs, rhs])),
+            ExprKind::Ge(lhs, rhs) => ("ge", exprs(&[lhs, rhs])),
+            ExprKind::Lt(lhs, rhs) => ("lt", exprs(&[lhs, rhs])),
+            ExprKind::Le(lhs, rhs) => ("le", exprs(&[lhs, rhs])),
+            ExprKind::LShift(lhs, rhs) => ("lshift", exprs(&[lhs, rhs])),
+            ExprKind::RShift(lhs, rhs) => ("rshift", exprs(&[lhs, rhs])),
+            ExprKind::Index(lhs, rhs) => ("index", exprs(&[lhs, rhs])),
+            ExprKind::FieldSelect(lhs, rhs) => ("field-select", exprs(&[lhs, rhs])),
+            ExprKind::Slice(a, b, c) => ("slice", exprs(&[a, b, c])),
+            ExprKind::Conditional(a, b, c) => ("conditional", exprs(&[a, b, c])),
+            ExprKind::Selector(a, b, c) => ("selector", exprs(&[a, b, c]))
+        };
+        list([atom(kind), location(expr.location()), opt_type(*expr.typ())].into_iter().chain(operands))
+    }
+
+    fn opt_expr(&self, expr: Option<&Expr>) -> Node {
+        expr.map_or(atom("?"), |expr| self.expr(expr))
+    }
+
+    fn stmt(&self, stmt: &Stmt) -> Node {
+        let (kind, operands) = match stmt.kind() {
+            StmtKind::Nop => ("nop", vec![]),
+            StmtKind::Return => ("return", vec![]),
+            StmtKind::DefaultCase => ("default", vec![]),
+            StmtKind::Break => ("break", vec![]),
+            StmtKind::Next => ("next", vec![]),
+            StmtKind::Loop => ("loop", vec![]),
+            StmtKind::Rethrow => ("rethrow", vec![]),
+            StmtKind::Goto(label) => ("goto", vec![location(label.location()), atom(&**label)]),
+            StmtKind::Labelled(label, body) => ("labelled", vec![location(label.location()), atom(&**label), self.stmt(body)]),
+            StmtKind::Error => ("error", vec![]),
+            StmtKind::Expr(expr) => ("expr", vec![self.expr(expr)]),
+            StmtKind::ResultIs(expr) => ("resultis", vec![self.expr(expr)]),
+            StmtKind::Throw(expr) => ("throw", vec![self.expr(expr)]),
+            StmtKind::Block(stmts) => ("block", stmts.iter().map(|stmt| self.stmt(stmt)).collect()),
+            StmtKind::If(condition, then, otherwise) => ("if", vec![
+                self.expr(condition),
+                self.stmt(then),
+                otherwise.as_deref().map_or(atom("?"), |otherwise| self.stmt(otherwise))
+            ]),
+            StmtKind::Unless(condition, body) => ("unless", vec![self.expr(condition), self.stmt(body)]),
+            StmtKind::While(condition, body) => ("while", vec![self.expr(condition), self.stmt(body)]),
+            StmtKind::Until(condition, body) => ("until", vec![self.expr(condition), self.stmt(body)]),
+            StmtKind::SwitchOn(condition, body) => ("switchon", vec![self.expr(condition), self.stmt(body)]),
+            StmtKind::Repeat(body) => ("repeat", vec![self.stmt(body)]),
+            StmtKind::Try(body, caught, handler) => ("try", vec![self.stmt(body), self.pattern(caught), self.stmt(handler)]),
+            StmtKind::RepeatWhile(body, condition) => ("repeat-while", vec![self.stmt(body), self.expr(condition)]),
+            StmtKind::RepeatUntil(body, condition) => ("repeat-until", vec![self.stmt(body), self.expr(condition)]),
+            StmtKind::For(iter, init, bound, step, body) => ("for", vec![
+                self.pattern(iter),
+                self.expr(init),
+                self.opt_expr(bound.as_deref()),
+                self.opt_expr(step.as_deref()),
+                self.stmt(body)
+            ]),
+            StmtKind::Case(values) => ("case", values.iter()
+                .map(|(value, upper)| list([self.expr(value), self.opt_expr(upper.as_ref())]))
+                .collect()
+            ),
+            StmtKind::Match(args, branches) | StmtKind::Every(args, branches) => (
+                if matches!(stmt.kind(), StmtKind::Match(..)) { "match" } else { "every" },
+                std::iter::once(list(args.iter().map(|arg| self.expr(arg))))
+                    .chain(branches.iter().map(|(patterns, body)| self.branch(patterns, self.stmt(body))))
+                    .collect()
+            ),
+            StmtKind::Binding(bindings) => ("let", bindings.iter()
+                .map(|(pattern, value)| list([self.pattern(pattern), self.expr(value)]))
+                .collect()
+            ),
+            StmtKind::Assign(pairs) => ("assign", pairs.iter()
+                .map(|(target, value)| list([self.expr(target), self.expr(value)]))
+                .collect()
+            ),
+            StmtKind::Assert(condition, message) => ("assert", vec![self.expr(condition), Node::Str(message.clone())]),
+            StmtKind::InlineAsm(template, outputs, inputs) => ("asm", vec![
+                Node::Str(template.clone()),
+                list(outputs.iter().map(|operand| self.expr(operand))),
+                list(inputs.iter().map(|operand| self.expr(operand)))
+            ])
+        };
+        list([atom(kind), location(stmt.location())].into_iter().chain(operands))
+    }
+
+    fn pattern(&self, pattern: &Located<Pattern>) -> Node {
+        let (kind, operands) = match &**pattern {
+            Pattern::Any => ("any", vec![]),
+            Pattern::Remaining => ("remaining", vec![]),
+            Pattern::Query(ident) => ("query", vec![atom(ident)]),
+            Pattern::Or(lhs, rhs) => ("or", vec![self.pattern(lhs), self.pattern(rhs)]),
+            Pattern::And(lhs, rhs) => ("and", vec![self.pattern(lhs), self.pattern(rhs)]),
+            Pattern::Variant(ident, args) => ("variant", std::iter::once(atom(ident)).chain(args.iter().map(|arg| self.pattern(arg))).collect()),
+            Pattern::List(args) => ("list", args.iter().map(|arg| self.pattern(arg)).collect()),
+            Pattern::Term(PatternTerm::Range(low, high)) => ("range", vec![self.expr(low), self.expr(high)]),
+            Pattern::Term(PatternTerm::Basic(expr)) => ("basic", vec![self.expr(expr)]),
+            Pattern::Term(PatternTerm::Lt(expr)) => ("lt", vec![self.expr(expr)]),
+            Pattern::Term(PatternTerm::Le(expr)) => ("le", vec![self.expr(expr)]),
+            Pattern::Term(PatternTerm::Gt(expr)) => ("gt", vec![self.expr(expr)]),
+            Pattern::Term(PatternTerm::Ge(expr)) => ("ge", vec![self.expr(expr)]),
+            Pattern::Term(PatternTerm::Ne(expr)) => ("ne", vec![self.expr(expr)]),
+            Pattern::Term(PatternTerm::Eq(expr)) => ("eq", vec![self.expr(expr)])
+        };
+        list([atom(kind), location(pattern.location())].into_iter().chain(operands))
+    }
+}
+
+type BinaryInit = fn(Box<Expr>, Box<Expr>) -> ExprKind;
+
+const BINARY_EXPRS: [(&str, BinaryInit); 20] = [
+    ("add", ExprKind::Add), ("sub", ExprKind::Sub), ("mul", ExprKind::Mul), ("div", ExprKind::Div), ("mod", ExprKind::Mod),
+    ("and", ExprKind::And), ("or", ExprKind::Or), ("xor", ExprKind::XOr), ("log-and", ExprKind::LogAnd), ("log-or", ExprKind::LogOr),
+    ("eq", ExprKind::Eq), ("ne", ExprKind::Ne), ("gt", ExprKind::Gt), ("ge", ExprKind::Ge), ("lt", ExprKind::Lt), ("le", ExprKind::Le),
+    ("lshift", ExprKind::LShift), ("rshift", ExprKind::RShift), ("index", ExprKind::Index), ("field-select", ExprKind::FieldSelect)
+];
+
+const UNARY_EXPRS: [(&str, fn(Box<Expr>) -> ExprKind); 7] = [
+    ("abs", ExprKind::Abs), ("not", ExprKind::Not), ("ref", ExprKind::Ref), ("deref", ExprKind::Deref),
+    ("cast", ExprKind::Cast), ("implicit-cast", ExprKind::ImplicitCast), ("vec", ExprKind::Vec)
+];
+
+const PATTERN_TERMS: [(&str, fn(Expr) -> PatternTerm); 7] = [
+    ("basic", PatternTerm::Basic), ("lt", PatternTerm::Lt), ("le", PatternTerm::Le), ("gt", PatternTerm::Gt),
+    ("ge", PatternTerm::Ge), ("ne", PatternTerm::Ne), ("eq", PatternTerm::Eq)
+];
+
+
+struct Reader<'a> {
+    path: &'a str,
+    ast: &'a mut Program,
+    // by the ids they have in the file
+    files: HashMap<u32, SourceFile>,
+    types: HashMap<u32, TypeIndex>
+}
+
+impl Reader<'_> {
+    fn malformed<T>(&self, what: &str) -> Result<T, String> {
+        Err(format!("{}: malformed {what} in typed ast.", self.path))
+    }
+
+    // names are quoted where they could not be written as an atom
+    fn atom<'n>(&self, node: &'n Node) -> Result<&'n str, String> {
+        match node {
+            Node::Atom(name) | Node::Str(name) => Ok(name),
+            Node::List(_) => self.malformed("name")
+        }
+    }
+
+    fn string(&self, node: &Node) -> Result<String, String> {
+        match node {
+            Node::Str(s) => Ok(s.clone()),
+            _ => self.malformed("string")
+        }
+    }
+
+    fn number<T: std::str::FromStr>(&self, node: &Node) -> Result<T, String> {
+        self.atom(node)?.parse().map_or_else(|_| self.malformed("number"), Ok)
+    }
+
+    // `(head operands...)` with the given head
+    fn tagged<'n>(node: &'n Node, head: &str) -> Option<&'n [Node]> {
+        match node.as_list()? {
+            [first, operands @ ..] if first.as_atom() == Some(head) => Some(operands),
+            _ => None
+        }
+    }
+
+    fn location(&self, node: &Node) -> Result<Location, String> {
+        let parse = || {
+            let (file, rest) = node.as_atom()?.strip_prefix('@')?.split_once(':')?;
+            let (line, rest) = rest.split_once(':')?;
+            let (column, width) = rest.split_once('+')?;
+            let file = self.files.get(&file.parse().ok()?)?;
+            Some(Location::new(file, line.parse().ok()?, column.parse().ok()?, width.parse().ok()?))
+        };
+        parse().map_or_else(|| self.malformed("location"), Ok)
+    }
+
+    fn typ(&self, node: &Node) -> Result<TypeIndex, String> {
+        let index = self.number(node)?;
+        self.types.get(&index).copied().map_or_else(|| self.malformed("type"), Ok)
+    }
+
+    fn opt_type(&self, node: &Node) -> Result<Option<TypeIndex>, String> {
+        if is_absent(node) { Ok(None) } else { self.typ(node).map(Some) }
+    }
+
+    // types are numbered anew; builtins and aliases the program knows already are shared with it
+    fn types(&mut self, nodes: &[&[Node]]) -> Result<(), String> {
+        let mut defined = vec![];
+        for node in nodes {
+            let ([_, index, loc, kind] | [_, index, loc, kind, _]) = node else {
+                return self.malformed("type")
+            };
+            let index = self.number(index)?;
+            let loc = if is_absent(loc) { None } else { Some(self.location(loc)?) };
+
+            let alias = Self::tagged(kind, "alias").and_then(<[Node]>::first).and_then(Node::as_atom);
+            let types = self.ast.types_mut();
+            let typ = match (kind, alias) {
+                (Node::Atom(builtin), _) => match types.builtin_by_ident(builtin) {
+                    Some(typ) => typ,
+                    None => return self.malformed("type")
+                },
+                (_, Some(alias)) if let Some(alias) = types.find_alias(alias) => {
+                    // an alias used but not defined by the program so far is defined here
+                    if let Some(TypeKind::Alias(_, None)) = types.get(alias).map(Type::kind) {
+                        defined.push((index, kind));
+                        if let Some(loc) = loc {
+                            types.get_mut(alias).unwrap().set_location(loc);
+                        }
+                    }
+                    alias
+                }
+                _ => {
+                    defined.push((index, kind));
+                    types.define(Type::new(loc, TypeKind::Unit))
+                }
+            };
+            self.types.insert(index, typ);
+        }
+
+        for (index, kind) in defined {
+            let kind = self.type_kind(kind)?;
+            let typ = self.types[&index];
+            self.ast.types_mut().get_mut(typ).unwrap().set_kind(kind);
+        }
+
+        // `[[repr("C")]]` structs
+        for node in nodes {
+            if let [_, index, _, _, flag] = node {
+                if self.atom(flag)? != "repr-c" {
+                    return self.malformed("type")
+                }
+                let typ = self.types[&self.number::<u32>(index)?];
+                self.ast.types_mut().get_mut(typ).unwrap().set_c_layout();
+            }
+        }
+        Ok(())
+    }
+
+    fn type_kind(&mut self, node: &Node) -> Result<TypeKind, String> {
+        let Some([head, operands @ ..]) = node.as_list() else {
+            return self.malformed("type")
+        };
+
+        Ok(match (self.atom(head)?, operands) {
+            ("ptr", [inner]) => TypeKind::Pointer(self.typ(inner)?),
+            ("slice", [inner]) => TypeKind::Slice(self.typ(inner)?),
+            ("array", [inner, len]) => TypeKind::Array(self.typ(inner)?, Box::new(self.expr(len)?)),
+            ("alias", [ident, inner]) => TypeKind::Alias(self.atom(ident)?.into(), self.opt_type(inner)?),
+            ("sum", variants) => TypeKind::Sum(variants.iter()
+                .map(|variant| match variant.as_list() {
+                    Some([ident, fields @ ..]) => Ok(SumVariant::Basic(
+                        self.atom(ident)?.into(),
+                        fields.iter().map(|field| self.typ(field)).collect::<Result<_, _>>()?
+                    )),
+                    _ => self.malformed("variant")
+                })
+                .collect::<Result<_, _>>()?
+            ),
+            ("struct", fields) => TypeKind::Struct(fields.iter()
+                .map(|field| match field.as_list() {
+                    Some([ident, typ]) => Ok((self.atom(ident)?.into(), self.typ(typ)?)),
+                    _ => self.malformed("field")
+                })
+                .collect::<Result<_, _>>()?
+            ),
+            ("enum", [ident, constants @ ..]) => TypeKind::Enum(
+                self.atom(ident)?.into(),
+                constants.iter().map(|constant| self.atom(constant).map(String::from)).collect::<Result<_, _>>()?
+            ),
+            ("param", [ident]) => TypeKind::Param(self.atom(ident)?.into()),
+            ("routine", [result, params @ ..]) => TypeKind::Routine(
+                params.iter().map(|param| self.typ(param)).collect::<Result<_, _>>()?,
+                self.typ(result)?
+            ),
+            _ => return self.malformed("type")
+        })
+    }
+
+    fn section(&mut self, nodes: &[Node]) -> Result<Section, String> {
+        let [loc, ident, items @ ..] = nodes else {
+            return self.malformed("section")
+        };
+        let mut section = Section::new(self.atom(ident)?.into(), self.location(loc)?);
+
+        for item in items {
+            let Some([head, operands @ ..]) = item.as_list() else {
+                return self.malformed("declaration")
+            };
+            match (self.atom(head)?, operands) {
+                ("require", [loc, ident]) => section.add_require(String::from(self.atom(ident)?).with_location(self.location(loc)?)),
+                ("needs", [loc, name]) => section.add_need(self.string(name)?.with_location(self.location(loc)?)),
+                ("assert", [condition, message]) => section.add_assertion(self.expr(condition)?, self.string(message)?),
+                ("routine", operands) => section.declare(self.routine(operands)?),
+                (storage @ ("global" | "static"), [loc, ident, slot, typ, value, attributes @ ..]) => {
+                    let storage = if storage == "global" { Storage::Global } else { Storage::Static };
+                    let slot = if is_absent(slot) { None } else { Some(self.number(slot)?) };
+                    let value = self.opt_expr(value)?;
+                    let mut global = GlobalDecl::new(self.location(loc)?, storage, self.atom(ident)?.into(), slot, self.opt_type(typ)?, value);
+                    for attribute in attributes {
+                        match Self::tagged(attribute, "link-section") {
+                            Some([link_section]) => global.set_link_section(self.string(link_section)?),
+                            _ => self.deprecation(attribute, &mut global)?
+                        }
+                    }
+                    section.declare(global.into_decl());
+                }
+                ("manifest", [loc, ident, value, attributes @ ..]) => {
+                    let mut manifest = ManifestDecl::new(self.location(loc)?, self.atom(ident)?.into(), self.expr(value)?);
+                    for attribute in attributes {
+                        self.deprecation(attribute, &mut manifest)?;
+                    }
+                    section.declare(manifest.into_decl());
+                }
+                _ => return self.malformed("declaration")
+            }
+        }
+        Ok(section)
+    }
+
+    fn deprecation(&self, node: &Node, decl: &mut dyn Decl) -> Result<(), String> {
+        match Self::tagged(node, "deprecated") {
+            Some(message @ ([] | [_])) => {
+                let message = message.first().map(|message| self.string(message)).transpose()?;
+                decl.set_deprecation(Deprecation::new(message));
+                Ok(())
+            }
+            _ => self.malformed("attribute")
+        }
+    }
+
+    fn routine(&mut self, nodes: &[Node]) -> Result<Box<dyn Decl>, String> {
+        let [loc, ident, return_type, params, body, attributes @ ..] = nodes else {
+            return self.malformed("routine")
+        };
+
+        let Some(params) = Self::tagged(params, "params") else {
+            return self.malformed("parameters")
+        };
+        let params = params.iter()
+            .map(|param| match Self::tagged(param, "param") {
+                Some([loc, pattern, typ, default_value]) => Ok(Param::new(
+                    self.location(loc)?,
+                    self.pattern(pattern)?,
+                    self.opt_type(typ)?,
+                    self.opt_expr(default_value)?
+                )),
+                _ => self.malformed("parameter")
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let body = match body.as_list() {
+            Some([head, body]) if head.as_atom() == Some("expr") => FunctionBody::Expr(self.expr(body)?),
+            Some([head, body]) if head.as_atom() == Some("stmt") => FunctionBody::Stmt(self.stmt(body)?),
+            Some([head, branches @ ..]) if head.as_atom() == Some("match-expr") => FunctionBody::PatternMatchedExpr(branches.iter()
+                .map(|branch| self.branch(branch, Self::expr))
+                .collect::<Result<_, _>>()?
+            ),
+            Some([head, branches @ ..]) if head.as_atom() == Some("match-stmt") => FunctionBody::PatternMatchedStmt(branches.iter()
+                .map(|branch| self.branch(branch, Self::stmt))
+                .collect::<Result<_, _>>()?
+            ),
+            _ => return self.malformed("routine body")
+        };
+
+        let tailcall_recursive = attributes.iter().any(|attribute| attribute.as_atom() == Some("and"));
+        let mut func = Function::new(self.location(loc)?, self.atom(ident)?.into(), params, self.opt_type(return_type)?, tailcall_recursive, body);
+        for attribute in attributes {
+            match (attribute.as_atom(), attribute.as_list()) {
+                (Some("and"), _) => (),
+                (Some("inline"), _) => func.set_inline(),
+                (Some("ccall"), _) => func.set_calling_convention(CallingConvention::C),
+                (_, Some([head, loc, rest])) if head.as_atom() == Some("variadic") => {
+                    let rest = match self.atom(rest)? {
+                        "?" => None,
+                        rest => Some(rest.to_string())
+                    };
+                    func.set_variadic(Variadic::new(self.location(loc)?, rest));
+                }
+                (_, Some([head, captures @ ..])) if head.as_atom() == Some("captures") => func.set_captures(captures.iter()
+                    .map(|capture| self.atom(capture).map(String::from))
+                    .collect::<Result<_, _>>()?
+                ),
+                (_, Some([head, operand])) if let Some(head) = head.as_atom() && head != "deprecated" => match head {
+                    "constructor" => func.set_hook(Hook::Constructor(self.number(operand)?)),
+                    "destructor" => func.set_hook(Hook::Destructor(self.number(operand)?)),
+                    "link-section" => func.set_link_section(self.string(operand)?),
+                    _ => return self.malformed("attribute")
+                },
+                _ => self.deprecation(attribute, &mut func)?
+            }
+        }
+        Ok(func.into_decl())
+    }
+
+    fn branch<T>(&mut self, node: &Node, body: fn(&mut Self, &Node) -> Result<T, String>) -> Result<(Vec<Located<Pattern>>, T), String> {
+        match Self::tagged(node, "branch") {
+            Some([Node::List(patterns), branch_body]) => Ok((
+                patterns.iter().map(|pattern| self.pattern(pattern)).collect::<Result<_, _>>()?,
+                body(self, branch_body)?
+            )),
+            _ => self.malformed("branch")
+        }
+    }
+
+    fn exprs(&mut self, nodes: &[Node]) -> Result<Vec<Expr>, String> {
+        nodes.iter().map(|node| self.expr(node)).collect()
+    }
+
+    fn boxed(&mut self, node: &Node) -> Result<Box<Expr>, String> {
+        self.expr(node).map(Box::new)
+    }
+
+    fn opt_expr(&mut self, node: &Node) -> Result<Option<Expr>, String> {
+        if is_absent(node) { Ok(None) } else { self.expr(node).map(Some) }
+    }
+
+    fn expr(&mut self, node: &Node) -> Result<Expr, String> {
+        let Some([kind, loc, typ, operands @ ..]) = node.as_list() else {
+            return self.malformed("expression")
+        };
+        let (kind, loc, typ) = (self.atom(kind)?, self.location(loc)?, self.opt_type(typ)?);
+
+        if let Some((_, init)) = BINARY_EXPRS.iter().find(|(name, _)| *name == kind) && let [lhs, rhs] = operands {
+            return Ok(Expr::new(loc, typ, init(self.boxed(lhs)?, self.boxed(rhs)?)))
+        }
+        if let Some((_, init)) = UNARY_EXPRS.iter().find(|(name, _)| *name == kind) && let [operand] = operands {
+            return Ok(Expr::new(loc, typ, init(self.boxed(operand)?)))
+        }
+
+        let kind = match (kind, operands) {
+            ("ident", [ident]) => ExprKind::Ident(self.atom(ident)?.into()),
+            ("atom", [name]) => {
+                let name = self.string(name)?;
+                ExprKind::Atom(self.ast.add_atom(name))
+            }
+            ("int", [value]) => ExprKind::IntLit(self.number(value)?),
+            ("float", [value]) => ExprKind::FloatLit(self.number(value)?),
+            ("char", [value]) => ExprKind::CharLit(char::from_u32(self.number(value)?).map_or_else(|| self.malformed("character"), Ok)?),
+            ("string", [value]) => ExprKind::StringLit(self.string(value)?),
+            ("true", []) => ExprKind::True,
+            ("false", []) => ExprKind::False,
+            ("nil", []) => ExprKind::Nil,
+            ("error", []) => ExprKind::Error,
+            ("temporary", [index]) => ExprKind::Temporary(self.number(index)?),
+            ("constant", [index]) => ExprKind::Constant(self.number(index)?),
+            ("sizeof", [typ]) => ExprKind::SizeOf(self.typ(typ)?),
+            ("offsetof", [typ, field]) => ExprKind::OffsetOf(self.typ(typ)?, self.atom(field)?.into()),
+            ("member", [field, inner]) => ExprKind::Member(self.boxed(inner)?, self.atom(field)?.into()),
+            ("overflow", [mode, inner]) => {
+                let mode = OverflowMode::try_from(self.atom(mode)?).map_or_else(|_| self.malformed("overflow mode"), Ok)?;
+                ExprKind::Overflow(mode, self.boxed(inner)?)
+            }
+            ("valof", [body]) => ExprKind::ValOf(Box::new(self.stmt(body)?)),
+            ("call", [callee, args @ ..]) => ExprKind::FuncCall(self.boxed(callee)?, self.exprs(args)?),
+            ("varargs", args) => ExprKind::VarArgs(self.exprs(args)?),
+            ("named-arg", [name, arg]) => ExprKind::NamedArg(self.atom(name)?.into(), self.boxed(arg)?),
+            (kind @ ("match" | "every"), [Node::List(args), branches @ ..]) => {
+                let args = self.exprs(args)?;
+                let branches = branches.iter()
+                    .map(|branch| self.branch(branch, Self::boxed))
+                    .collect::<Result<_, _>>()?;
+                if kind == "match" { ExprKind::Match(args, branches) } else { ExprKind::Every(args, branches) }
+            }
+            ("slice", [a, b, c]) => ExprKind::Slice(self.boxed(a)?, self.boxed(b)?, self.boxed(c)?),
+            ("conditional", [a, b, c]) => ExprKind::Conditional(self.boxed(a)?, self.boxed(b)?, self.boxed(c)?),
+            ("selector", [a, b, c]) => ExprKind::Selector(self.boxed(a)?, self.boxed(b)?, self.boxed(c)?),
+            _ => return self.malformed("expression")
+        };
+        Ok(Expr::new(loc, typ, kind))
+    }
+
+    fn stmt(&mut self, node: &Node) -> Result<Stmt, String> {
+        let Some([kind, loc, operands @ ..]) = node.as_list() else {
+            return self.malformed("statement")
+        };
+        let (kind, loc) = (self.atom(kind)?, self.location(loc)?);
+        let body = |reader: &mut Self, node: &Node| reader.stmt(node).map(Box::new);
+
+        let kind = match (kind, operands) {
+            ("nop", []) => StmtKind::Nop,
+            ("return", []) => StmtKind::Return,
+            ("default", []) => StmtKind::DefaultCase,
+            ("break", []) => StmtKind::Break,
+            ("next", []) => StmtKind::Next,
+            ("loop", []) => StmtKind::Loop,
+            ("rethrow", []) => StmtKind::Rethrow,
+            ("goto", [label_loc, label]) => StmtKind::Goto(self.atom(label)?.to_string().with_location(self.location(label_loc)?)),
+            ("labelled", [label_loc, label, stmt]) => StmtKind::Labelled(self.atom(label)?.to_string().with_location(self.location(label_loc)?), body(self, stmt)?),
+            ("error", []) => StmtKind::Error,
+            ("expr", [expr]) => StmtKind::Expr(self.boxed(expr)?),
+            ("resultis", [expr]) => StmtKind::ResultIs(self.boxed(expr)?),
+            ("throw", [expr]) => StmtKind::Throw(self.boxed(expr)?),
+            ("block", stmts) => StmtKind::Block(stmts.iter().map(|stmt| self.stmt(stmt)).collect::<Result<_, _>>()?),
+            ("if", [condition, then, otherwise]) => StmtKind::If(
+                self.boxed(condition)?,
+                body(self, then)?,
+                if is_absent(otherwise) { None } else { Some(body(self, otherwise)?) }
+            ),
+            ("unless", [condition, stmt]) => StmtKind::Unless(self.boxed(condition)?, body(self, stmt)?),
+            ("while", [condition, stmt]) => StmtKind::While(self.boxed(condition)?, body(self, stmt)?),
+            ("until", [condition, stmt]) => StmtKind::Until(self.boxed(condition)?, body(self, stmt)?),
+            ("switchon", [condition, stmt]) => StmtKind::SwitchOn(self.boxed(condition)?, body(self, stmt)?),
+            ("repeat", [stmt]) => StmtKind::Repeat(body(self, stmt)?),
+            ("try", [stmt, caught, handler]) => StmtKind::Try(body(self, stmt)?, self.pattern(caught)?, body(self, handler)?),
+            ("repeat-while", [stmt, condition]) => StmtKind::RepeatWhile(body(self, stmt)?, self.boxed(condition)?),
+            ("repeat-until", [stmt, condition]) => StmtKind::RepeatUntil(body(self, stmt)?, self.boxed(condition)?),
+            ("for", [iter, init, bound, step, stmt]) => StmtKind::For(
+                self.pattern(iter)?,
+                self.boxed(init)?,
+                self.opt_expr(bound)?.map(Box::new),
+                self.opt_expr(step)?.map(Box::new),
+                body(self, stmt)?
+            ),
+            ("case", values) => StmtKind::Case(values.iter()
+                .map(|value| match value.as_list() {
+                    Some([value, upper]) => Ok((self.expr(value)?, self.opt_expr(upper)?)),
+                    _ => self.malformed("case")
+                })
+                .collect::<Result<_, _>>()?
+            ),
+            (kind @ ("match" | "every"), [Node::List(args), branches @ ..]) => {
+                let args = self.exprs(args)?;
+                let branches = branches.iter()
+                    .map(|branch| self.branch(branch, body))
+                    .collect::<Result<_, _>>()?;
+                if kind == "match" { StmtKind::Match(args, branches) } else { StmtKind::Every(args, branches) }
+            }
+            ("let", bindings) => StmtKind::Binding(bindings.iter()
+                .map(|binding| match binding.as_list() {
+                    Some([pattern, value]) => Ok((self.pattern(pattern)?, self.expr(value)?)),
+                    _ => self.malformed("binding")
+                })
+                .collect::<Result<_, _>>()?
+            ),
+            ("assign", pairs) => StmtKind::Assign(pairs.iter()
+                .map(|pair| match pair.as_list() {
+                    Some([target, value]) => Ok((self.expr(target)?, self.expr(value)?)),
+                    _ => self.malformed("assignment")
+                })
+                .collect::<Result<_, _>>()?
+            ),
+            ("assert", [condition, message]) => StmtKind::Assert(self.boxed(condition)?, self.string(message)?),
+            ("asm", [template, Node::List(outputs), Node::List(inputs)]) => StmtKind::InlineAsm(self.string(template)?, self.exprs(outputs)?, self.exprs(inputs)?),
+            _ => return self.malformed("statement")
+        };
+        Ok(Stmt::new(loc, kind))
+    }
+
+    fn pattern(&mut self, node: &Node) -> Result<Located<Pattern>, String> {
+        let Some([kind, loc, operands @ ..]) = node.as_list() else {
+            return self.malformed("pattern")
+        };
+        let (kind, loc) = (self.atom(kind)?, self.location(loc)?);
+
+        if let Some((_, init)) = PATTERN_TERMS.iter().find(|(name, _)| *name == kind) && let [expr] = operands {
+            return Ok(Pattern::Term(init(self.expr(expr)?)).with_location(loc))
+        }
+
+        let pattern = match (kind, operands) {
+            ("any", []) => Pattern::Any,
+            ("remaining", []) => Pattern::Remaining,
+            ("query", [ident]) => Pattern::Query(self.atom(ident)?.into()),
+            ("range", [low, high]) => Pattern::Term(PatternTerm::Range(self.expr(low)?, self.expr(high)?)),
+            ("or", [lhs, rhs]) => Pattern::Or(Box::new(self.pattern(lhs)?), Box::new(self.pattern(rhs)?)),
+            ("and", [lhs, rhs]) => Pattern::And(Box::new(self.pattern(lhs)?), Box::new(self.pattern(rhs)?)),
+            ("variant", [ident, args @ ..]) => Pattern::Variant(
+                self.atom(ident)?.into(),
+                args.iter().map(|arg| self.pattern(arg)).collect::<Result<_, _>>()?
+            ),
+            ("list", args) => Pattern::List(args.iter().map(|arg| self.pattern(arg)).collect::<Result<_, _>>()?),
+            _ => return self.malformed("pattern")
+        };
+        Ok(pattern.with_location(loc))
+    }
+}
+
+fn is_absent(node: &Node) -> bool {
+    node.as_atom() == Some("?")
+}
@@ -1,4 +1,5 @@
 use std::{
+    collections::HashSet,
     str::Chars,
     iter::Peekable,
     ops::Deref
@@ -6,7 +7,7 @@ use std::{
 
 use crate::{source_file::{SourceFile, Location}, token::{Token, TokenKind}};
 
-const ESCAPE_CHAR: char = '\\';
+const ESCAPE_CHAR: char = '*';
 
 #[repr(u8)]
 #[derive(Clone, Copy, PartialEq)]
@@ -17,13 +18,15 @@ enum Base {
     Hexadecimal = 16
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Lexer<'a> {
     source_file: &'a SourceFile,
     iter: Peekable<Chars<'a>>,
     offset: usize,
     line: usize,
-    column: usize
+    column: usize,
+    // the tags set for conditional compilation, by `-D` or `$$tag` earlier in the file
+    tags: HashSet<String>
 }
 
 impl<'a> Lexer<'a> {
@@ -33,10 +36,21 @@ impl<'a> Lexer<'a> {
             iter: source_file.contents().chars().peekable(),
             offset: 0,
             line: 1,
-            column: 0
+            column: 0,
+            tags: HashSet::new()
         }
     }
 
+    pub fn with_tags(mut self, tags: &[String]) -> Self {
+        self.tags = tags.iter().cloned().collect();
+        self
+    }
+
+    // the character right after the last token, without consuming it
+    pub fn peek_char(&mut self) -> Option<char> {
+        self.iter.peek().copied()
+    }
+
     pub fn current_loc(&self) -> Location {
         Location::new(self.source_file, self.line, self.column, 1)        
     }
@@ -74,6 +88,63 @@ impl<'a> Lexer<'a> {
         }
     }
 
+    // `$$tag`, `$<tag`, `$~tag` or `$>tag` after its `$`, followed by the token this returns.
+    // `$$tag` complements `tag`; the text from `$<tag` up to `$>tag` is only read if it is set,
+    // and that from `$~tag` only if it is not.
+    fn conditional(&mut self, loc: Location) -> Token<'a> {
+        let directive = self.iter.peek().copied().expect("called on a directive");
+        self.next_char();
+        let tag = self.parse_ident();
+        if tag.is_empty() {
+            return Token::error(loc, Some(format!("expect tag after `${directive}`")))
+        }
+
+        match directive {
+            '$' => if !self.tags.remove(tag) {
+                self.tags.insert(tag.to_string());
+            },
+            '<' | '~' if self.tags.contains(tag) != (directive == '<') => if !self.skip_conditional(tag) {
+                return Token::error(loc, Some(format!("`${directive}{tag}` has no matching `$>{tag}`")))
+            },
+            // the end of a section that is read, or the start of one whose condition holds
+            _ => ()
+        }
+        self.next().expect("the lexer never ends")
+    }
+
+    // skips the tokens up to and including `$>tag`, which sections with other tags may be nested in.
+    // strings and comments are skipped whole, so `$>tag` within them does not end the section.
+    fn skip_conditional(&mut self, tag: &str) -> bool {
+        loop {
+            self.skip_whitespace();
+            match self.iter.peek() {
+                None => return false,
+                // directives within the section are not acted upon
+                Some('$') => {
+                    self.next_char();
+                    if self.iter.peek() == Some(&'>') {
+                        self.next_char();
+                        if self.parse_ident() == tag {
+                            return true
+                        }
+                    }
+                }
+                // comments are skipped here, as lexing them moves on to the token after
+                Some('/') => {
+                    self.next_char();
+                    match self.iter.peek() {
+                        Some('/') => self.skip_comment(),
+                        Some('*') => self.skip_multiline_comment(),
+                        _ => ()
+                    }
+                }
+                _ => {
+                    self.next();
+                }
+            }
+        }
+    }
+
     fn skip_whitespace(&mut self) {
         while let Some(ch) = self.iter.peek() && ch.is_whitespace() {
             self.next_char();     
@@ -158,23 +229,84 @@ impl<'a> Lexer<'a> {
         )
     }
 
-    fn parse_string_lit(&mut self, quote: char) -> &'a str {
+    // the contents of a string or character literal, with its escape sequences checked
+    fn parse_string_lit(&mut self, loc: &Location, quote: char) -> Result<&'a str, Token<'a>> {
         let start = self.offset;
 
         while let Some(&ch) = self.iter.peek() && ch != quote {
             self.next_char();
-            if ch == ESCAPE_CHAR {
+            if ch == ESCAPE_CHAR && self.iter.peek().is_some() {
                 self.next_char();
             }
-            else if ch == '\n' {
-                self.line += 1;
-                self.column = 0;
+        }
+
+        if self.iter.peek().is_none() {
+            return Err(Token::error(loc.clone(), Some(format!("unterminated literal, expect closing `{quote}`."))))
+        }
+        self.next_char();
+
+        let raw = &self.source_file.contents()[start..self.offset - 1];
+        if let Err((offset, width, msg)) = unescape(raw) {
+            // literals may span lines, so count from the opening quote
+            let (mut line, mut column) = (loc.line(), loc.column() + 1);
+            for ch in raw.chars().take(offset) {
+                if ch == '\n' {
+                    line += 1;
+                    column = 0;
+                }
+                else {
+                    column += 1;
+                }
             }
+            return Err(Token::error(Location::new(self.source_file, line, column, width), Some(msg)))
         }
+        Ok(raw)
+    }
+}
 
-        self.next_char(); // expect `"`
-        &self.source_file.contents()[start..self.offset - 1]
+// decodes the escape sequences of a literal's contents: `*n`, `*t`, `*s`, `*b`, `*p`, `*c`, `*e`,
+// `**`, `*"`, `*'`, `*xHH` and octal `*ooo`. Errors carry the character offset and width of the bad escape.
+pub fn unescape(raw: &str) -> Result<String, (usize, usize, String)> {
+    let chars = raw.chars().collect::<Vec<_>>();
+    let digits = |start: usize, count: usize, radix: u32| chars.get(start..start + count)
+        .filter(|digits| digits.iter().all(|ch| ch.is_digit(radix)))
+        .and_then(|digits| u8::from_str_radix(&digits.iter().collect::<String>(), radix).ok());
+
+    let mut value = String::with_capacity(raw.len());
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i] != ESCAPE_CHAR {
+            value.push(chars[i]);
+            i += 1;
+            continue
+        }
+
+        let Some(&ch) = chars.get(i + 1) else {
+            return Err((i, 1, format!("unfinished escape sequence `{ESCAPE_CHAR}`.")))
+        };
+        let (decoded, width) = match ch.to_ascii_lowercase() {
+            'n' => ('\n', 2),
+            't' => ('\t', 2),
+            's' => (' ', 2),
+            'b' => ('\x08', 2),
+            'p' => ('\x0c', 2),
+            'c' => ('\r', 2),
+            'e' => ('\x1b', 2),
+            '*' | '"' | '\'' => (ch, 2),
+            'x' => match digits(i + 2, 2, 16) {
+                Some(byte) => (byte as char, 4),
+                None => return Err((i, chars.len().min(i + 4) - i, format!("`{ESCAPE_CHAR}x` expects two hexadecimal digits.")))
+            }
+            '0'..='7' => match digits(i + 1, 3, 8) {
+                Some(byte) => (byte as char, 4),
+                None => return Err((i, chars.len().min(i + 4) - i, "octal escape sequences expect three digits up to `377`.".into()))
+            }
+            _ => return Err((i, 2, format!("unknown escape sequence `{ESCAPE_CHAR}{ch}`.")))
+        };
+        value.push(decoded);
+        i += width;
     }
+    Ok(value)
 }
 
 impl<'a> Iterator for Lexer<'a> {
@@ -201,11 +333,15 @@ impl<'a> Iterator for Lexer<'a> {
             }
             '"' => {
                 self.next_char();
-                Some(Token::string_lit(loc, self.parse_string_lit('"')))
+                Some(self.parse_string_lit(&loc, '"').map_or_else(|err| err, |raw| Token::string_lit(loc, raw)))
             }
             '\'' => {
                 self.next_char();
-                Some(Token::char_lit(loc, self.parse_string_lit('"')))
+                match self.parse_string_lit(&loc, '\'') {
+                    Ok(raw) if unescape(raw).is_ok_and(|value| value.chars().count() == 1) => Some(Token::char_lit(loc, raw)),
+                    Ok(raw) => Some(Token::with_width(loc, raw.len() + 2, TokenKind::Error(Some("character literals contain exactly one character.".into())))),
+                    Err(err) => Some(err)
+                }
             }
             '#' => {
                 self.next_char();
@@ -221,6 +357,20 @@ impl<'a> Iterator for Lexer<'a> {
                 self.next_char();
                 Some(Token::new(loc, TokenKind::try_from(ch).expect("invalid character")))
             }
+            // classic section brackets `$( $)`, optionally tagged like `$(loop ... $)loop`, and
+            // conditional compilation
+            '$' => {
+                self.next_char();
+                let kind = match self.iter.peek() {
+                    Some('(') => TokenKind::LBrace,
+                    Some(')') => TokenKind::RBrace,
+                    Some('$' | '<' | '~' | '>') => return Some(self.conditional(loc)),
+                    _ => return Some(Token::error(loc, Some("expect `(`, `)`, `$`, `<`, `~` or `>` after `$`".into())))
+                };
+                self.next_char();
+                let tag = self.parse_ident();
+                Some(Token::section_bracket(loc, kind, (!tag.is_empty()).then_some(tag)))
+            }
             '-' => {
                 self.next_char();
                 if let Some(&ch) = self.iter.peek() && ch == '>' {
@@ -275,6 +425,10 @@ impl<'a> Iterator for Lexer<'a> {
                 self.next_char();
                 if let Some(&ch) = self.iter.peek() && ch == '.' {
                     self.next_char();
+                    if let Some(&ch) = self.iter.peek() && ch == '.' {
+                        self.next_char();
+                        return Some(Token::with_width(loc, 3, TokenKind::Ellipsis))
+                    }
                     return Some(Token::with_width(loc, 2, TokenKind::Range))
                 }
                 else {
@@ -2,12 +2,14 @@ use std::fmt::Display;
 
 use crate::source_file::Location;
 
-pub(crate) mod lexer;
+pub mod lexer;
 
 #[derive(Debug)]
 pub struct Token<'a> {
     kind: TokenKind<'a>,
-    loc: Location
+    loc: Location,
+    // tag of a section bracket like `$(loop`
+    tag: Option<&'a str>
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -46,6 +48,7 @@ pub enum TokenKind<'a> {
     At, // `@`
     Compound, // `<>`
     Range, // `..`
+    Ellipsis, // `...`
 
     Plus,
     Minus,
@@ -69,6 +72,7 @@ pub enum TokenKind<'a> {
     // Keywords
     True,
     False,
+    Nil,
     Let,
     And,
     ValOf,
@@ -76,10 +80,21 @@ pub enum TokenKind<'a> {
     Return,
     Break,
     Next,
+    Loop,
+    Goto,
+    Defer,
+    Try,
+    Catch,
+    Throw,
+    Rethrow,
+    Asm,
     If,
     Else,
     Unless,
     While,
+    Repeat,
+    RepeatWhile,
+    RepeatUntil,
     For,
     Until,
     SwitchOn,
@@ -92,15 +107,28 @@ pub enum TokenKind<'a> {
     To,
     By,
     Of,
+    FieldOf,
     Be,
     Section,
     Require,
+    Needs,
+    Assert,
     Global,
     Manifest,
     Static,
     Mod,
     Abs,
-    Type
+    SizeOf,
+    OffsetOf,
+    Vec,
+    Slct,
+    Checked,
+    Wrapping,
+    Saturating,
+    Type,
+    Struct,
+    Enum,
+    Get
 }
 
 impl<'a> Display for TokenKind<'a> {
@@ -142,6 +170,7 @@ impl<'a> Display for TokenKind<'a> {
             TK::At => "@",
             TK::Compound => "<>",
             TK::Range => "..",
+            TK::Ellipsis => "...",
 
             TK::Plus => "+",
             TK::Minus => "-",
@@ -163,6 +192,7 @@ impl<'a> Display for TokenKind<'a> {
             TK::RShift => ">>",
 
             TK::True => "true",
+            TK::Nil => "nil",
             TK::False => "false",
             TK::Let => "let",
             TK::And => "and",
@@ -171,6 +201,14 @@ impl<'a> Display for TokenKind<'a> {
             TK::Return => "return",
             TK::Next => "next",
             TK::Break => "break",
+            TK::Loop => "loop",
+            TK::Goto => "goto",
+            TK::Defer => "defer",
+            TK::Try => "try",
+            TK::Catch => "catch",
+            TK::Throw => "throw",
+            TK::Rethrow => "rethrow",
+            TK::Asm => "asm",
             TK::If => "if",
             TK::Else => "else",
             TK::Unless => "unless",
@@ -187,14 +225,30 @@ impl<'a> Display for TokenKind<'a> {
             TK::To => "to",
             TK::By => "by",
             TK::Of => "::",
+            TK::FieldOf => "of",
             TK::Be => "be",
             TK::Section => "section",
             TK::Require => "require",
+            TK::Needs => "needs",
+            TK::Assert => "assert",
             TK::Global => "global",
             TK::Manifest => "manifest",
             TK::Static => "static",
             TK::Mod => "mod",
             TK::Abs => "abs",
+            TK::SizeOf => "sizeof",
+            TK::Vec => "vec",
+            TK::Slct => "slct",
+            TK::OffsetOf => "offsetof",
+            TK::Checked => "checked",
+            TK::Wrapping => "wrapping",
+            TK::Saturating => "saturating",
+            TK::Struct => "struct",
+            TK::Enum => "enum",
+            TK::Get => "get",
+            TK::Repeat => "repeat",
+            TK::RepeatWhile => "repeatwhile",
+            TK::RepeatUntil => "repeatuntil",
             _ => "<unexpected>"
         };
 
@@ -234,6 +288,7 @@ impl<'a> From<&'a str> for TokenKind<'a> {
         use TokenKind as TK;
         match value {
             "true" => TK::True,
+            "nil" => TK::Nil,
             "false" => TK::False,
             "let" => TK::Let,
             "and" => TK::And,
@@ -242,6 +297,10 @@ impl<'a> From<&'a str> for TokenKind<'a> {
             "return" => TK::Return,
             "next" => TK::Next,
             "break" => TK::Break,
+            "loop" => TK::Loop,
+            "goto" => TK::Goto,
+            "defer" => TK::Defer,
+            "asm" => TK::Asm,
             "if" => TK::If,
             "else" => TK::Else,
             "unless" => TK::Unless,
@@ -250,23 +309,35 @@ impl<'a> From<&'a str> for TokenKind<'a> {
             "match" => TK::Match,
             "every" => TK::Every,
             "while" => TK::While,
+            "repeat" => TK::Repeat,
+            "repeatwhile" => TK::RepeatWhile,
+            "repeatuntil" => TK::RepeatUntil,
             "case" => TK::Case,
             "default" => TK::Default,
             "into" => TK::Into,
             "do" => TK::Do,
             "to" => TK::To,
             "by" => TK::By,
-            "of" => TK::Of,
+            "of" => TK::FieldOf,
             "be" => TK::Be,
             "section" => TK::Section,
             "require" => TK::Require,
+            "needs" => TK::Needs,
+            "assert" => TK::Assert,
             "global" => TK::Global,
             "manifest" => TK::Manifest,
             "static" => TK::Static,
             "mod" => TK::Mod,
             "abs" => TK::Abs,
+            "sizeof" => TK::SizeOf,
+            "vec" => TK::Vec,
+            "slct" => TK::Slct,
+            "offsetof" => TK::OffsetOf,
             "for" => TK::For,
             "type" => TK::Type,
+            "struct" => TK::Struct,
+            "enum" => TK::Enum,
+            "get" => TK::Get,
             _ => TK::Ident(value.into())
         } 
     }
@@ -276,7 +347,8 @@ impl<'a> Token<'a> {
     pub fn new(loc: Location, kind: TokenKind<'a>) -> Self {
         Self {
             kind,
-            loc
+            loc,
+            tag: None
         }
     }
 
@@ -284,14 +356,16 @@ impl<'a> Token<'a> {
         loc.set_width(width);
         Self {
             kind,
-            loc
+            loc,
+            tag: None
         }
     }
 
     pub fn eof(loc: Location) -> Self {
         Self {
             kind: TokenKind::Eof,
-            loc
+            loc,
+            tag: None
         }
     }
 
@@ -305,7 +379,8 @@ impl<'a> Token<'a> {
     pub fn error(loc: Location, msg: Option<String>) -> Self {
         Self {
             kind: TokenKind::Error(msg),
-            loc
+            loc,
+            tag: None
         }
     }
 
@@ -313,7 +388,8 @@ impl<'a> Token<'a> {
         loc.set_width(ident.len());
         Self {
             kind: TokenKind::from(ident),
-            loc
+            loc,
+            tag: None
         }
     }
 
@@ -321,7 +397,8 @@ impl<'a> Token<'a> {
         loc.set_width(val.len() + 2);
         Self {
             kind: TokenKind::StringLit(val),
-            loc
+            loc,
+            tag: None
         }
     }
 
@@ -329,10 +406,24 @@ impl<'a> Token<'a> {
         loc.set_width(val.len() + 2);
         Self {
             kind: TokenKind::CharLit(val),
-            loc
+            loc,
+            tag: None
         }
     }
 
+    pub fn section_bracket(mut loc: Location, kind: TokenKind<'a>, tag: Option<&'a str>) -> Self {
+        loc.set_width(2 + tag.map_or(0, str::len));
+        Self {
+            kind,
+            loc,
+            tag
+        }
+    }
+
+    pub fn tag(&self) -> Option<&'a str> {
+        self.tag
+    }
+
     pub fn kind(&self) -> &TokenKind {
         &self.kind
     }
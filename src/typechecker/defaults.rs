@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+
+use crate::{
+    ast::{self, Decl, visitor::{Action, Visitor, Traversable}, expr::{Expr, ExprKind}, stmt::{Stmt, StmtKind}, pattern::Pattern},
+    source_file::{Location, Located, WithLocation}
+};
+
+use super::{Error, TypeCheckError, named::NamedArgs};
+
+// fills in the default values of the trailing parameters a call leaves out, and of those named
+// arguments skip. Defaults are evaluated at each call, so they are copied there before the call
+// sites are type checked.
+pub(super) struct DefaultArgs {
+    named: NamedArgs,
+    // the number of required parameters and the default values of all parameters of each routine
+    routines: HashMap<String, (usize, Vec<Option<Expr>>)>,
+    // routines whose defaults are being filled in, to reject defaults calling their own routine
+    expanding: Vec<String>
+}
+
+impl DefaultArgs {
+    pub(super) fn new(ast: &ast::Program) -> Self {
+        Self {
+            named: NamedArgs::new(ast),
+            routines: ast.sections()
+                .flat_map(|section| section.declarations())
+                .filter_map(|decl| decl.as_any().downcast_ref::<ast::Function>())
+                .filter(|func| func.params().iter().any(|param| param.default_value().is_some()))
+                .map(|func| (
+                    func.ident().clone(),
+                    (func.required_params() as usize, func.params().iter().map(|param| param.default_value().cloned()).collect())
+                ))
+                .collect(),
+            expanding: vec![]
+        }
+    }
+}
+
+impl Visitor<ast::Program, Error> for DefaultArgs {
+    fn visit(&mut self, _node: &mut ast::Program) -> Result<Action, Error> {
+        Ok(Action::Continue)
+    }
+}
+
+impl Visitor<ast::Section, Error> for DefaultArgs {
+    fn visit(&mut self, _node: &mut ast::Section) -> Result<Action, Error> {
+        Ok(Action::Continue)
+    }
+}
+
+impl Visitor<ast::Function, Error> for DefaultArgs {
+    fn visit(&mut self, _node: &mut ast::Function) -> Result<Action, Error> {
+        Ok(Action::Continue)
+    }
+}
+
+impl Visitor<ast::Param, Error> for DefaultArgs {
+    fn visit(&mut self, _node: &mut ast::Param) -> Result<Action, Error> {
+        Ok(Action::Continue)
+    }
+}
+
+impl Visitor<ast::stmt::Stmt, Error> for DefaultArgs {
+    fn visit(&mut self, _node: &mut ast::stmt::Stmt) -> Result<Action, Error> {
+        Ok(Action::Continue)
+    }
+
+    // calls as statements may not return a value, so they cannot be the result of a `valof`
+    fn visit_before(&mut self, node: &mut ast::stmt::Stmt) -> Result<Action, Error> {
+        let StmtKind::Expr(call) = node.kind_mut() else {
+            return Ok(Action::Continue)
+        };
+        let bindings = self.place_args(call)?;
+        if !bindings.is_empty() {
+            let loc = node.location().clone();
+            let call = std::mem::replace(node, Stmt::new(loc.clone(), StmtKind::Nop));
+            *node = Stmt::new(loc.clone(), StmtKind::Block(vec![Stmt::new(loc, StmtKind::Binding(bindings)), call]));
+        }
+        Ok(Action::Continue)
+    }
+}
+
+impl Visitor<Expr, Error> for DefaultArgs {
+    fn visit(&mut self, node: &mut Expr) -> Result<Action, Error> {
+        let bindings = self.place_args(node)?;
+        if !bindings.is_empty() {
+            let loc = node.location().clone();
+            let call = std::mem::replace(node, Expr::new(loc.clone(), None, ExprKind::Error));
+            let typ = *call.typ();
+            let body = vec![
+                Stmt::new(loc.clone(), StmtKind::Binding(bindings)),
+                Stmt::new(loc.clone(), StmtKind::ResultIs(Box::new(call)))
+            ];
+            *node = Expr::new(loc.clone(), typ, ExprKind::ValOf(Box::new(Stmt::new(loc, StmtKind::Block(body)))));
+        }
+        Ok(Action::Continue)
+    }
+}
+
+impl DefaultArgs {
+    // puts the arguments of a call in parameter order and fills in defaults, returning the named
+    // values to bind before the call
+    fn place_args(&mut self, node: &mut Expr) -> Result<Vec<(Located<Pattern>, Expr)>, Error> {
+        let loc = node.location().clone();
+        let ExprKind::FuncCall(callee, args) = node.kind_mut() else {
+            return Ok(vec![])
+        };
+        let (slots, bindings) = match self.named.place(callee, args, &loc)? {
+            Some(placed) => placed,
+            None => (std::mem::take(args).into_iter().map(Some).collect(), vec![])
+        };
+        *args = self.fill_defaults(callee, slots, &loc)?;
+        Ok(bindings)
+    }
+
+    // the arguments of a call to `callee` with the default values the missing ones take filled in
+    fn fill_defaults(&mut self, callee: &Expr, slots: Vec<Option<Expr>>, loc: &Location) -> Result<Vec<Expr>, Error> {
+        // calls leaving out required arguments are left alone, as in BCPL
+        let len = match callee.kind() {
+            ExprKind::Ident(routine) => self.routines.get(routine)
+                .filter(|(required, _)| slots.len() >= *required)
+                .map_or(slots.len(), |(_, defaults)| defaults.len().max(slots.len())),
+            _ => slots.len()
+        };
+        if len == slots.len() && slots.iter().all(Option::is_some) {
+            return Ok(slots.into_iter().flatten().collect())
+        }
+
+        // named arguments only skip parameters with defaults
+        let ExprKind::Ident(routine) = callee.kind() else {
+            unreachable!()
+        };
+        if self.expanding.contains(routine) {
+            return Err(TypeCheckError::RecursiveDefault(routine.clone()).with_location(loc.clone()))
+        }
+        let defaults = self.routines[routine].1.clone();
+        self.expanding.push(routine.clone());
+        let mut values = Vec::with_capacity(len);
+        for (i, slot) in slots.into_iter().chain(std::iter::repeat_with(|| None)).take(len).enumerate() {
+            values.push(match slot {
+                Some(value) => value,
+                None => {
+                    let mut value = defaults[i].clone().expect("parameters arguments leave out have defaults");
+                    value.traverse(self)?;
+                    value
+                }
+            });
+        }
+        self.expanding.pop();
+        Ok(values)
+    }
+}
+
+impl Visitor<ast::pattern::Pattern, Error> for DefaultArgs {
+    fn visit(&mut self, _node: &mut ast::pattern::Pattern) -> Result<Action, Error> {
+        Ok(Action::Continue)
+    }
+}
@@ -0,0 +1,350 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    ast::{self, Decl, IntoDecl, visitor::{Action, Visitor, Traversable}, expr::{Expr, ExprKind}, stmt::StmtKind, pattern::Pattern, types::{TypeIndex, TypeKind, TypeList, Type, Coercion}},
+    source_file::{Location, WithLocation}
+};
+
+use super::{Error, TypeCheckError};
+
+// instances nest at most this deep, which only routines calling themselves with ever larger
+// types like `&T`, `&&T`, ... reach
+const MAX_INSTANCE_DEPTH: usize = 32;
+
+// replaces each generic routine by an instance for every list of type arguments it is called
+// with. Type arguments are inferred from the types of the arguments; an instance is a copy of
+// the routine with its type parameters substituted, declared in the same section under the name
+// `routine<T, ...>`. Generic routines are not exported, as other sections could not instantiate them.
+pub(super) struct Monomorphize {
+    types: TypeList,
+    // generic routines taken out of the program, with the section declaring them
+    generics: HashMap<String, (String, ast::Function)>,
+    // instances created so far by routine and type arguments, with their name and result type
+    instances: HashMap<(String, Vec<TypeIndex>), (String, Option<TypeIndex>)>,
+    names: HashSet<String>,
+    // instances still to be traversed, with their section and how deeply they are nested
+    pending: Vec<(String, ast::Function, usize)>,
+    depth: usize,
+    // declared types of the globals, and of the locals of the routine being traversed
+    globals: HashMap<String, TypeIndex>,
+    locals: HashMap<String, Option<TypeIndex>>,
+    // set from a call of a generic routine up to the visit of its name
+    calling: bool
+}
+
+impl Monomorphize {
+    pub(super) fn new(ast: &mut ast::Program) -> Self {
+        let mut generics = HashMap::new();
+        for section in ast.sections_mut() {
+            let idents = section.declarations()
+                .filter_map(|decl| decl.as_any().downcast_ref::<ast::Function>())
+                .filter(|func| func.is_generic())
+                .map(|func| func.ident().clone())
+                .collect::<Vec<_>>();
+            for ident in idents {
+                let decl = section.remove(&ident).unwrap();
+                let func = decl.as_any().downcast_ref::<ast::Function>().unwrap().clone();
+                generics.insert(ident, (section.ident().clone(), func));
+            }
+        }
+
+        Self {
+            types: TypeList::default(),
+            generics,
+            instances: HashMap::new(),
+            names: HashSet::new(),
+            pending: vec![],
+            depth: 0,
+            globals: ast.sections()
+                .flat_map(|section| section.declarations())
+                .filter_map(|decl| decl.as_any().downcast_ref::<ast::GlobalDecl>())
+                .filter_map(|global| global.typ().map(|typ| (global.ident().clone(), typ)))
+                .collect(),
+            locals: HashMap::new(),
+            calling: false
+        }
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.generics.is_empty()
+    }
+
+    pub(super) fn run(mut self, ast: &mut ast::Program) -> Result<(), Error> {
+        self.types = std::mem::take(ast.types_mut());
+        let result = self.instantiate_all(ast);
+        *ast.types_mut() = self.types;
+        result
+    }
+
+    fn instantiate_all(&mut self, ast: &mut ast::Program) -> Result<(), Error> {
+        ast.traverse(self)?;
+        while let Some((section, mut instance, depth)) = self.pending.pop() {
+            self.depth = depth;
+            instance.traverse(self)?;
+            ast.section_mut(&section).expect("generic routines are declared in a section").declare(instance.into_decl());
+        }
+        Ok(())
+    }
+
+    fn is_generic(&self, ident: &String) -> bool {
+        self.generics.contains_key(ident) && !self.locals.contains_key(ident)
+    }
+
+    // names have no type in the ast; that of a local or global comes from its declaration
+    fn type_of(&self, expr: &Expr) -> Option<TypeIndex> {
+        match expr.kind() {
+            ExprKind::Ident(ident) if expr.typ().is_none() => match self.locals.get(ident) {
+                Some(typ) => *typ,
+                None => self.globals.get(ident).copied()
+            },
+            _ => *expr.typ()
+        }
+    }
+
+    // binds the type parameters in `param` so that it matches `arg`. Fails with the parameter
+    // and both of its types if it was bound to another type before; other mismatches are left
+    // to the checks of the instance.
+    fn unify(&self, param: TypeIndex, arg: TypeIndex, bindings: &mut HashMap<TypeIndex, TypeIndex>) -> Result<(), (TypeIndex, TypeIndex, TypeIndex)> {
+        let types = &self.types;
+        // bound to the type as written, which names the instance, but matched by what it stands for
+        let (Some(param_kind), Some(arg_kind)) = (types.get(param).map(Type::kind), types.get(types.resolve(arg)).map(Type::kind)) else {
+            return Ok(())
+        };
+
+        match (param_kind, arg_kind) {
+            (TypeKind::Param(_), _) => match bindings.get(&param) {
+                Some(bound) if types.coercion(*bound, arg) != Coercion::Identity => Err((param, *bound, arg)),
+                Some(_) => Ok(()),
+                None => {
+                    bindings.insert(param, arg);
+                    Ok(())
+                }
+            },
+            (TypeKind::Pointer(param), TypeKind::Pointer(arg))
+                | (TypeKind::Slice(param), TypeKind::Slice(arg) | TypeKind::Array(arg, _))
+                | (TypeKind::Array(param, _), TypeKind::Array(arg, _)) => self.unify(*param, *arg, bindings),
+            (TypeKind::Routine(params, param_result), TypeKind::Routine(args, arg_result)) if params.len() == args.len() => params.iter()
+                .zip(args)
+                .chain([(param_result, arg_result)])
+                .try_for_each(|(param, arg)| self.unify(*param, *arg, bindings)),
+            _ => Ok(())
+        }
+    }
+
+    // renames the call of a generic routine to the instance for the types of its arguments
+    fn instantiate_call(&mut self, node: &mut Expr) -> Result<(), Error> {
+        let ExprKind::FuncCall(callee, args) = node.kind() else {
+            unreachable!()
+        };
+        let ExprKind::Ident(routine) = callee.kind() else {
+            unreachable!()
+        };
+        let routine = routine.clone();
+        let generic = &self.generics[&routine].1;
+
+        let mut bindings = HashMap::new();
+        for (param, arg) in generic.params().iter().zip(args) {
+            if let Some(param) = param.typ() && let Some(typ) = self.type_of(arg) {
+                self.unify(param, typ, &mut bindings).map_err(|(param, bound, typ)| TypeCheckError::ConflictingTypeArgs(
+                    self.types.type_name(param), routine.clone(), self.types.type_name(bound), self.types.type_name(typ)
+                ).with_location(arg.location().clone()))?;
+            }
+        }
+        let type_args = generic.type_params().iter()
+            .map(|param| bindings.get(param).copied().ok_or_else(||
+                TypeCheckError::UninferredTypeParam(self.types.type_name(*param), routine.clone()).with_location(node.location().clone())
+            ))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // aliases of the same type share an instance
+        let key = (routine, type_args.iter().map(|typ| self.types.resolve(*typ)).collect());
+        let (instance, result) = match self.instances.get(&key).cloned() {
+            Some(instance) => instance,
+            None => self.instantiate(key, &type_args, bindings, node.location())?
+        };
+
+        if let ExprKind::FuncCall(callee, _) = node.kind_mut() {
+            *callee.kind_mut() = ExprKind::Ident(instance);
+        }
+        if let Some(result) = result {
+            node.set_typ(result);
+        }
+        Ok(())
+    }
+
+    fn instantiate(&mut self, key: (String, Vec<TypeIndex>), type_args: &[TypeIndex], mut substitution: HashMap<TypeIndex, TypeIndex>, loc: &Location) -> Result<(String, Option<TypeIndex>), Error> {
+        let routine = &key.0;
+        if self.depth >= MAX_INSTANCE_DEPTH {
+            return Err(TypeCheckError::InstanceDepth(routine.clone()).with_location(loc.clone()))
+        }
+
+        let (section, generic) = &self.generics[routine];
+        let (section, mut instance) = (section.clone(), generic.clone());
+        // distinct types may have the same name, like structs with the same fields
+        let name = format!("{routine}<{}>", type_args.iter().map(|typ| self.types.type_name(*typ)).collect::<Vec<_>>().join(", "));
+        let name = (1..).map(|n| if n == 1 { name.clone() } else { format!("{name}.{n}") })
+            .find(|name| !self.names.contains(name))
+            .unwrap();
+        self.names.insert(name.clone());
+
+        instance.set_ident(name.clone());
+        instance.set_type_params(vec![]);
+        instance.traverse(&mut Substitute { types: &mut self.types, substitution: &mut substitution })?;
+
+        let result = *instance.return_type();
+        self.instances.insert(key, (name.clone(), result));
+        self.pending.push((section, instance, self.depth + 1));
+        Ok((name, result))
+    }
+}
+
+impl Visitor<ast::Program, Error> for Monomorphize {
+    fn visit(&mut self, _node: &mut ast::Program) -> Result<Action, Error> {
+        Ok(Action::Continue)
+    }
+}
+
+impl Visitor<ast::Section, Error> for Monomorphize {
+    fn visit(&mut self, _node: &mut ast::Section) -> Result<Action, Error> {
+        Ok(Action::Continue)
+    }
+}
+
+impl Visitor<ast::Function, Error> for Monomorphize {
+    fn visit_before(&mut self, node: &mut ast::Function) -> Result<Action, Error> {
+        self.locals = node.params().iter()
+            .filter_map(|param| match &**param.pattern() {
+                Pattern::Query(ident) => Some((ident.clone(), param.typ())),
+                _ => None
+            })
+            .chain(node.variadic().and_then(ast::Variadic::ident).map(|rest| (rest.clone(), None)))
+            .collect();
+        Ok(Action::Continue)
+    }
+
+    fn visit(&mut self, _node: &mut ast::Function) -> Result<Action, Error> {
+        self.locals.clear();
+        Ok(Action::Continue)
+    }
+}
+
+impl Visitor<ast::Param, Error> for Monomorphize {
+    fn visit(&mut self, _node: &mut ast::Param) -> Result<Action, Error> {
+        Ok(Action::Continue)
+    }
+}
+
+impl Visitor<ast::stmt::Stmt, Error> for Monomorphize {
+    fn visit(&mut self, node: &mut ast::stmt::Stmt) -> Result<Action, Error> {
+        if let StmtKind::Binding(pairs) = node.kind() {
+            for (pattern, value) in pairs {
+                if let Pattern::Query(ident) = &**pattern {
+                    self.locals.insert(ident.clone(), self.type_of(value));
+                }
+            }
+        }
+        Ok(Action::Continue)
+    }
+}
+
+impl Visitor<Expr, Error> for Monomorphize {
+    fn visit_before(&mut self, node: &mut Expr) -> Result<Action, Error> {
+        if let ExprKind::FuncCall(callee, _) = node.kind()
+            && let ExprKind::Ident(routine) = callee.kind() {
+            self.calling = self.is_generic(routine);
+        }
+        Ok(Action::Continue)
+    }
+
+    fn visit(&mut self, node: &mut Expr) -> Result<Action, Error> {
+        match node.kind() {
+            ExprKind::Ident(ident) if self.is_generic(ident) && !std::mem::take(&mut self.calling) =>
+                return Err(TypeCheckError::GenericRoutineValue(ident.clone()).with_location(node.location().clone())),
+            ExprKind::FuncCall(callee, _) => if let ExprKind::Ident(routine) = callee.kind() && self.is_generic(routine) {
+                self.instantiate_call(node)?;
+            }
+            _ => ()
+        }
+        Ok(Action::Continue)
+    }
+}
+
+impl Visitor<ast::pattern::Pattern, Error> for Monomorphize {
+    fn visit(&mut self, node: &mut ast::pattern::Pattern) -> Result<Action, Error> {
+        // names bound by patterns hide routines of the same name
+        if let Pattern::Query(ident) = node {
+            self.locals.entry(ident.clone()).or_insert(None);
+        }
+        Ok(Action::Continue)
+    }
+}
+
+// replaces the type parameters in every type of a copied generic routine
+struct Substitute<'a> {
+    types: &'a mut TypeList,
+    substitution: &'a mut HashMap<TypeIndex, TypeIndex>
+}
+
+impl Substitute<'_> {
+    fn substitute(&mut self, typ: TypeIndex) -> TypeIndex {
+        self.types.substitute(typ, self.substitution)
+    }
+}
+
+impl Visitor<ast::Program, Error> for Substitute<'_> {
+    fn visit(&mut self, _node: &mut ast::Program) -> Result<Action, Error> {
+        Ok(Action::Continue)
+    }
+}
+
+impl Visitor<ast::Section, Error> for Substitute<'_> {
+    fn visit(&mut self, _node: &mut ast::Section) -> Result<Action, Error> {
+        Ok(Action::Continue)
+    }
+}
+
+impl Visitor<ast::Function, Error> for Substitute<'_> {
+    fn visit(&mut self, node: &mut ast::Function) -> Result<Action, Error> {
+        if let Some(typ) = *node.return_type() {
+            let typ = self.substitute(typ);
+            node.set_return_type(typ);
+        }
+        Ok(Action::Continue)
+    }
+}
+
+impl Visitor<ast::Param, Error> for Substitute<'_> {
+    fn visit(&mut self, node: &mut ast::Param) -> Result<Action, Error> {
+        if let Some(typ) = node.typ() {
+            let typ = self.substitute(typ);
+            node.set_typ(typ);
+        }
+        Ok(Action::Continue)
+    }
+}
+
+impl Visitor<ast::stmt::Stmt, Error> for Substitute<'_> {
+    fn visit(&mut self, _node: &mut ast::stmt::Stmt) -> Result<Action, Error> {
+        Ok(Action::Continue)
+    }
+}
+
+impl Visitor<Expr, Error> for Substitute<'_> {
+    fn visit(&mut self, node: &mut Expr) -> Result<Action, Error> {
+        if let Some(typ) = *node.typ() {
+            let typ = self.substitute(typ);
+            node.set_typ(typ);
+        }
+        match node.kind_mut() {
+            ExprKind::SizeOf(typ) | ExprKind::OffsetOf(typ, _) => *typ = self.types.substitute(*typ, self.substitution),
+            _ => ()
+        }
+        Ok(Action::Continue)
+    }
+}
+
+impl Visitor<ast::pattern::Pattern, Error> for Substitute<'_> {
+    fn visit(&mut self, _node: &mut ast::pattern::Pattern) -> Result<Action, Error> {
+        Ok(Action::Continue)
+    }
+}
@@ -1,39 +1,245 @@
 mod scope;
+mod defaults;
+mod variadic;
+mod named;
+mod generics;
 
-use std::sync::{Arc, Mutex, MutexGuard};
+use std::{collections::HashSet, sync::{Arc, Mutex, MutexGuard}};
 
-use crate::{ast::{self, visitor::{ASTVisitor, Visitor, Traversable}}, source_file::Located};
+use crate::{
+    match_decl, log,
+    ast::{self, visitor::{ASTVisitor, Visitor, Traversable}, types::{TypeList, TypeKind, TypeIndex, Coercion}, expr::{Expr, ExprKind}, stmt::StmtKind, FunctionBody},
+    ir::bound_in_function,
+    source_file::{Location, Located, WithLocation},
+    error::{IntoCompilerError, CompilerError, Severity}
+};
 
-use self::scope::Scope;
+use self::{scope::Scope, defaults::DefaultArgs, variadic::VariadicCalls, generics::Monomorphize};
 
 pub enum TypeCheckError {
+    InvalidCoercion(String, String),
+    UnitResult(String),
+    RecursiveDefault(String),
+    //              routine fixed  given
+    MissingFixedArgs(String, usize, usize),
+    //      field   type
+    NonCField(String, String),
+    //                routine
+    UnknownParamNames(Option<String>),
+    //           routine name
+    UnknownParam(String, String),
+    DuplicateArg(String, String),
+    MissingArg(String, String),
+    //                   param   routine
+    UninferredTypeParam(String, String),
+    //                  param   routine  first   second
+    ConflictingTypeArgs(String, String, String, String),
+    GenericRoutineValue(String),
+    InstanceDepth(String),
+    //                routine type
+    RoutineSignature(String, String)
+}
 
+impl TypeCheckError {
+    fn hint(&self) -> Option<String> {
+        match self {
+            Self::InvalidCoercion(..) => Some("Use an explicit cast with `::`.".into()),
+            Self::UnitResult(_) => Some("Call it as a statement or define it with `=` to return a value.".into()),
+            Self::RecursiveDefault(_) => Some("Pass all arguments in calls inside default values of the routine itself.".into()),
+            Self::MissingFixedArgs(..) => Some("Arguments can only be left out after the ones before `...`.".into()),
+            Self::NonCField(..) => Some("Use a pointer, or a fixed-size array of C compatible types.".into()),
+            Self::UnknownParamNames(_) => Some("Pass the arguments by position; only routines defined in the program take named arguments.".into()),
+            Self::UnknownParam(..) | Self::DuplicateArg(..) => None,
+            Self::MissingArg(..) => Some("Pass it, or give the parameter a default value.".into()),
+            Self::UninferredTypeParam(..) => Some("Declare the types of the arguments, or cast them with `::`.".into()),
+            Self::ConflictingTypeArgs(..) => Some("Cast one of the arguments with `::`.".into()),
+            Self::GenericRoutineValue(_) => Some("Wrap the call in a routine with concrete parameter types.".into()),
+            Self::InstanceDepth(_) => Some("Recursive calls have to pass arguments of the same types.".into()),
+            Self::RoutineSignature(..) => Some("Declare its parameters and result with the types of the routine pointer.".into())
+        }
+    }
+}
+
+impl WithLocation for TypeCheckError {}
+
+impl ToString for TypeCheckError {
+    fn to_string(&self) -> String {
+        match self {
+            Self::InvalidCoercion(from, to) => format!("Cannot implicitly convert `{from}` to `{to}`."),
+            Self::UnitResult(routine) => format!("Routine `{routine}` does not return a value."),
+            Self::RecursiveDefault(routine) => format!("Default values of `{routine}` call `{routine}` with default values again."),
+            Self::MissingFixedArgs(routine, fixed, given) => format!("Variadic routine `{routine}` takes at least {fixed} argument(s), but {given} were given."),
+            Self::NonCField(field, typ) => format!("Field `{field}` of a `repr(\"C\")` struct has type `{typ}`, which has no C equivalent."),
+            Self::UnknownParamNames(Some(routine)) => format!("Parameter names of `{routine}` are unknown here."),
+            Self::UnknownParamNames(None) => format!("Parameter names of routine values are unknown."),
+            Self::UnknownParam(routine, name) => format!("Routine `{routine}` has no parameter `{name}`."),
+            Self::DuplicateArg(routine, name) => format!("Parameter `{name}` of `{routine}` is given more than once."),
+            Self::MissingArg(routine, param) => format!("Call of `{routine}` passes no value for parameter {param}, which has no default value."),
+            Self::UninferredTypeParam(param, routine) => format!("Cannot infer type parameter `{param}` of `{routine}` from the arguments of this call."),
+            Self::ConflictingTypeArgs(param, routine, first, second) => format!("Type parameter `{param}` of `{routine}` is both `{first}` and `{second}` in this call."),
+            Self::GenericRoutineValue(routine) => format!("Generic routine `{routine}` can only be called."),
+            Self::InstanceDepth(routine) => format!("Instances of `{routine}` need ever new instances of it."),
+            Self::RoutineSignature(routine, typ) => format!("Routine `{routine}` cannot be called as `{typ}`.")
+        }
+    }
+}
+
+impl IntoCompilerError for TypeCheckError {}
+impl Into<CompilerError> for TypeCheckError {
+    fn into(self) -> CompilerError {
+        CompilerError::new(Severity::Error, self.to_string(), self.hint(), vec![])
+    }
 }
 
 pub struct TypeChecker<'a> {
-    scope: Scope<'a>
+    scope: Scope<'a>,
+    types: &'a TypeList,
+    // names bound in the routine being checked, which hide routines of the same name
+    locals: HashSet<String>
 }
 
 impl<'a> TypeChecker<'a> {
-    fn new(scope: Scope<'a>) -> Self {
+    fn new(scope: Scope<'a>, types: &'a TypeList) -> Self {
         Self {
-            scope
+            scope,
+            types,
+            locals: HashSet::new()
         }
     }
-}
 
-type Error = Located<TypeCheckError>;
+    // `expr` is used as a value, so it must not be a call of a routine returning `Unit`,
+    // either by name or through a routine pointer
+    fn check_value(&self, expr: &Expr) -> Result<(), Error> {
+        let ExprKind::FuncCall(callee, _) = expr.kind() else {
+            return Ok(())
+        };
 
-unsafe fn get_ref<'a, T>(r: &T) -> &'a T {
-    (r as *const T).as_ref().unwrap()
+        let result = match callee.kind() {
+            ExprKind::Ident(routine) if !self.locals.contains(routine) => self.scope.routine_result(routine).flatten().or(*expr.typ()),
+            _ => *expr.typ()
+        };
+        if result.is_some_and(|result| matches!(self.types.get(self.types.resolve(result)).map(|typ| typ.kind()), Some(TypeKind::Unit))) {
+            let routine = match callee.kind() {
+                ExprKind::Ident(routine) => routine.clone(),
+                _ => callee.typ().map_or_else(|| "<unknown>".into(), |typ| self.types.type_name(typ))
+            };
+            return Err(TypeCheckError::UnitResult(routine).with_location(expr.location().clone()))
+        }
+        Ok(())
+    }
+
+    // the address of `routine` converted to the routine pointer type `typ` has to take
+    // parameters of the same types and return the same. Untyped parameters and results are
+    // words and fit any type, like the routine pointers of classic BCPL.
+    fn check_signature(&self, routine: &String, typ: TypeIndex, loc: &Location) -> Result<(), Error> {
+        let Some(TypeKind::Routine(params, result)) = self.types.get(self.types.resolve(typ)).map(|typ| typ.kind()) else {
+            return Ok(())
+        };
+        let Some((declared, variadic)) = self.scope.routine_params(routine).filter(|_| !self.locals.contains(routine)) else {
+            return Ok(())
+        };
+
+        let fits = |declared: Option<TypeIndex>, typ: TypeIndex| declared.is_none_or(|declared| self.types.coercion(declared, typ) == Coercion::Identity);
+        let same = !variadic && declared.len() == params.len()
+            && declared.iter().zip(params).all(|(declared, param)| fits(*declared, *param))
+            && self.scope.routine_result(routine).is_none_or(|declared| fits(declared, *result));
+        if !same {
+            return Err(TypeCheckError::RoutineSignature(routine.clone(), self.types.type_name(typ)).with_location(loc.clone()))
+        }
+        Ok(())
+    }
 }
 
+type Error = Located<TypeCheckError>;
+
 pub fn typecheck_ast(ast: Arc<Mutex<ast::Program>>) -> Result<(), Error> {
     let mut ast = ast.lock().unwrap();
-    let mut typechecker = TypeChecker::new(Scope::toplevel(unsafe { get_ref(&ast) }));
-    println!("{:#?}", typechecker.scope);
+    // also puts named arguments in place
+    let mut default_args = DefaultArgs::new(&ast);
+    ast.traverse(&mut default_args)?;
+    // once all arguments are in place, before anything relying on the instances being declared
+    let generics = Monomorphize::new(&mut ast);
+    if !generics.is_empty() {
+        generics.run(&mut ast)?;
+    }
+    // after the defaults, which may themselves be passed as further arguments
+    let mut variadic_calls = VariadicCalls::new(&ast);
+    if !variadic_calls.is_empty() {
+        ast.traverse(&mut variadic_calls)?;
+    }
+
+    let toplevel = Scope::toplevel(&ast);
+    log!(Trace, "toplevel scope: {toplevel:#?}");
+    let (types, sections) = ast.types_and_sections_mut();
+    check_c_layouts(types)?;
+
+    // global declaration pass; once it is done, routine bodies only depend on signatures
+    let mut typechecker = TypeChecker::new(Scope::new(Some(&toplevel)), types);
+    let mut routines = vec![];
+    for section in sections {
+        for decl in section.declarations_mut() {
+            if decl.as_any().is::<ast::Function>() {
+                routines.push(decl.as_mut_any().downcast_mut::<ast::Function>().unwrap());
+                continue;
+            }
 
-    ast.traverse(&mut typechecker).map(|_| ())
+            match_decl!{
+                mut decl;
+                manifest as ast::ManifestDecl => {
+                    typechecker.check_value(manifest.value())?;
+                    manifest.value_mut().traverse(&mut typechecker)?;
+                },
+                global as ast::GlobalDecl => if let Some(value) = global.value_mut() {
+                    typechecker.check_value(value)?;
+                    value.traverse(&mut typechecker)?;
+                },
+                _ => ()
+            }
+        }
+    }
+
+    typecheck_routines(routines, &toplevel, types)
+}
+
+// fields of `[[repr("C")]]` structs are only known once all aliases are defined. Structs read
+// from metadata files have no location and were checked when their section was compiled.
+fn check_c_layouts(types: &TypeList) -> Result<(), Error> {
+    for typ in types.iter().filter(|typ| typ.is_c_layout()) {
+        if let TypeKind::Struct(fields) = typ.kind()
+            && let Some(loc) = typ.location()
+            && let Some((field, non_c)) = fields.iter().find_map(|(field, typ)| types.non_c_type(*typ).map(|non_c| (field, non_c))) {
+            return Err(TypeCheckError::NonCField(field.clone(), types.type_name(non_c)).with_location(loc.clone()))
+        }
+    }
+    Ok(())
+}
+
+// checks routine bodies on all available cores, reporting the first error in source order
+fn typecheck_routines(mut routines: Vec<&mut ast::Function>, toplevel: &Scope, types: &TypeList) -> Result<(), Error> {
+    if routines.is_empty() {
+        return Ok(())
+    }
+
+    let workers = std::thread::available_parallelism().map_or(1, |n| n.get()).min(routines.len());
+    let chunk_size = routines.len().div_ceil(workers);
+
+    let errors = std::thread::scope(|s| {
+        let handles = routines.chunks_mut(chunk_size)
+            .map(|chunk| s.spawn(move || {
+                let mut typechecker = TypeChecker::new(Scope::new(Some(toplevel)), types);
+                chunk.iter_mut().filter_map(|func| func.traverse(&mut typechecker).err()).collect::<Vec<_>>()
+            }))
+            .collect::<Vec<_>>();
+
+        handles.into_iter()
+            .flat_map(|handle| handle.join().expect("typechecker thread panicked"))
+            .collect::<Vec<_>>()
+    });
+
+    match errors.into_iter().min_by(|a, b| a.location().cmp(b.location())) {
+        Some(err) => Err(err),
+        None => Ok(())
+    }
 }
 
 impl<'a> Visitor<ast::Program, Error> for TypeChecker<'a> {
@@ -50,24 +256,59 @@ impl<'a> Visitor<ast::Section, Error> for TypeChecker<'a> {
 
 impl<'a> Visitor<ast::Function, Error> for TypeChecker<'a> {
     fn visit(&mut self, node: &mut ast::Function) -> Result<ast::visitor::Action, Error> {
+        self.locals = bound_in_function(node);
+        match node.body() {
+            FunctionBody::Expr(expr) => self.check_value(expr)?,
+            FunctionBody::PatternMatchedExpr(branches) => for (_, expr) in branches {
+                self.check_value(expr)?
+            }
+            FunctionBody::Stmt(_) | FunctionBody::PatternMatchedStmt(_) => ()
+        }
         Ok(ast::visitor::Action::Continue)
     }
 }
 
 impl<'a> Visitor<ast::Param, Error> for TypeChecker<'a> {
     fn visit(&mut self, node: &mut ast::Param) -> Result<ast::visitor::Action, Error> {
+        if let Some(default_value) = node.default_value() {
+            self.check_value(default_value)?;
+        }
         Ok(ast::visitor::Action::Continue) 
     }
 }
 
 impl<'a> Visitor<ast::stmt::Stmt, Error> for TypeChecker<'a> {
     fn visit(&mut self, node: &mut ast::stmt::Stmt) -> Result<ast::visitor::Action, Error> {
+        // expression statements discard their value
+        if !matches!(node.kind(), StmtKind::Expr(_)) {
+            for expr in node.kind().exprs() {
+                self.check_value(expr)?;
+            }
+        }
         Ok(ast::visitor::Action::Continue) 
     } 
 }
 
 impl<'a> Visitor<ast::expr::Expr, Error> for TypeChecker<'a> {
     fn visit(&mut self, node: &mut ast::expr::Expr) -> Result<ast::visitor::Action, Error> {
+        // casts inserted by the parser have to stay valid once the operand's type is known
+        if let ExprKind::ImplicitCast(inner) = node.kind() 
+            && let (Some(from), Some(to)) = (*inner.typ(), *node.typ())
+            && self.types.coercion(from, to) == Coercion::Impossible {
+            return Err(TypeCheckError::InvalidCoercion(self.types.type_name(from), self.types.type_name(to)).with_location(node.location().clone()))
+        }
+
+        // routine names converted to routine pointers, implicitly or with `::`
+        if let ExprKind::ImplicitCast(inner) | ExprKind::Cast(inner) = node.kind()
+            && let ExprKind::Ident(routine) = inner.kind()
+            && let Some(typ) = *node.typ() {
+            self.check_signature(routine, typ, node.location())?;
+        }
+
+        for operand in node.kind().children() {
+            self.check_value(operand)?;
+        }
+
         Ok(ast::visitor::Action::Continue)
     }
 }
@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+
+use crate::{
+    ast::{self, Decl, expr::{Expr, ExprKind}, pattern::Pattern},
+    source_file::{Location, Located, WithLocation}
+};
+
+use super::{Error, TypeCheckError};
+
+// the locals named values are bound to, numbered in the order they are written; no identifier
+// can clash with them
+const NAMED_VALUE: &str = "named.";
+
+// maps the values of `name := value` arguments to the positions of their parameters, so that
+// later passes only see positional calls. `DefaultArgs` fills in the parameters no argument
+// sets. Arguments are evaluated from left to right, so values with effects written in another
+// order than their parameters are bound to locals in the order they are written first.
+pub(super) struct NamedArgs {
+    // the name, if the parameter is a plain identifier, and whether it has a default value
+    routines: HashMap<String, Vec<(Option<String>, bool)>>
+}
+
+impl NamedArgs {
+    pub(super) fn new(ast: &ast::Program) -> Self {
+        Self {
+            routines: ast.sections()
+                .flat_map(|section| section.declarations())
+                .filter_map(|decl| decl.as_any().downcast_ref::<ast::Function>())
+                .map(|func| (
+                    func.ident().clone(),
+                    func.params().iter()
+                        .map(|param| match &**param.pattern() {
+                            Pattern::Query(name) => (Some(name.clone()), param.default_value().is_some()),
+                            _ => (None, param.default_value().is_some())
+                        })
+                        .collect()
+                ))
+                .collect()
+        }
+    }
+
+    // the arguments of a call by the position of their parameters, with `None` where `DefaultArgs`
+    // has to fill in the default value, and the bindings to evaluate before the call; `None` for
+    // calls without named arguments
+    pub(super) fn place(&self, callee: &Expr, args: &mut Vec<Expr>, loc: &Location) -> Result<Option<(Vec<Option<Expr>>, Vec<(Located<Pattern>, Expr)>)>, Error> {
+        // the parser keeps named arguments after the positional ones
+        let Some(first_named) = args.iter().position(|arg| matches!(arg.kind(), ExprKind::NamedArg(..))) else {
+            return Ok(None)
+        };
+        // imported routines and routine values carry no parameter names
+        let routine = match callee.kind() {
+            ExprKind::Ident(routine) => Some(routine.clone()),
+            _ => None
+        };
+        let Some(params) = routine.as_ref().and_then(|routine| self.routines.get(routine)) else {
+            return Err(TypeCheckError::UnknownParamNames(routine).with_location(loc.clone()))
+        };
+        let routine = routine.unwrap();
+
+        let mut slots = args.drain(..first_named).map(Some).collect::<Vec<_>>();
+        let mut named = vec![];
+        for mut arg in args.drain(..) {
+            let ExprKind::NamedArg(name, value) = std::mem::replace(arg.kind_mut(), ExprKind::Error) else {
+                unreachable!()
+            };
+            let Some(index) = params.iter().position(|(param, _)| param.as_ref() == Some(&name)) else {
+                return Err(TypeCheckError::UnknownParam(routine, name).with_location(arg.location().clone()))
+            };
+            if slots.len() <= index {
+                slots.resize_with(index + 1, || None);
+            }
+            if slots[index].is_some() {
+                return Err(TypeCheckError::DuplicateArg(routine, name).with_location(arg.location().clone()))
+            }
+            slots[index] = Some(*value);
+            named.push(index);
+        }
+
+        let mut bindings = vec![];
+        if named.is_sorted() || !named.iter().any(|index| slots[*index].as_ref().is_some_and(Expr::has_sideeffect)) {
+            return Ok(Some((slots, bindings)))
+        }
+        for (i, index) in named.into_iter().enumerate() {
+            let value = slots[index].take().unwrap();
+            let local = format!("{NAMED_VALUE}{i}");
+            slots[index] = Some(Expr::new(value.location().clone(), *value.typ(), ExprKind::Ident(local.clone())));
+            bindings.push((Pattern::Query(local).with_location(value.location().clone()), value));
+        }
+
+        if let Some(i) = slots.iter().zip(params).position(|(slot, (_, has_default))| slot.is_none() && !has_default) {
+            let param = params[i].0.as_ref().map_or_else(|| format!("#{}", i + 1), |param| format!("`{param}`"));
+            return Err(TypeCheckError::MissingArg(routine, param).with_location(loc.clone()))
+        }
+        Ok(Some((slots, bindings)))
+    }
+}
+
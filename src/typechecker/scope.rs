@@ -1,11 +1,17 @@
 use std::{collections::HashMap, rc::Rc};
 
-use crate::ast::{Decl, types::{TypeIndex, TypeKind}, self};
+use crate::ast::{Decl, Function, types::{TypeIndex, TypeKind}, self};
 
+// The names are owned, so that the toplevel scope does not borrow the program
+// while its routines are checked.
 #[derive(Debug)]
 pub struct Scope<'a> {
     bindings: HashMap<&'a String, &'a dyn Decl>,
-    types: HashMap<&'a String, TypeIndex>,
+    types: HashMap<String, TypeIndex>,
+    // result types of all routines of the program and its imports
+    routines: HashMap<String, Option<TypeIndex>>,
+    // their parameter types, and whether they take further arguments
+    params: HashMap<String, (Vec<Option<TypeIndex>>, bool)>,
 
     outer: Option<&'a Scope<'a>>
 }
@@ -15,18 +21,46 @@ impl<'a> Scope<'a> {
         Self {
             bindings: HashMap::new(),
             types: HashMap::new(),
+            routines: HashMap::new(),
+            params: HashMap::new(),
             outer
         }
     }
 
-    pub fn toplevel(ast: &'a ast::Program) -> Self {
+    pub fn toplevel(ast: &ast::Program) -> Self {
         Self {
             types: ast.types().iter().enumerate().filter_map(|(idx, typ)| match typ.kind() {
-                TypeKind::Alias(id, _) => Some((id, idx as u32)),
+                TypeKind::Alias(id, _) => Some((id.clone(), idx as u32)),
                 _ => None 
             }).collect(),
             bindings: HashMap::new(),
+            routines: ast.sections()
+                .flat_map(|section| section.declarations())
+                .filter_map(|decl| decl.as_any().downcast_ref::<Function>())
+                .map(|func| (func.ident().clone(), *func.return_type()))
+                .chain(ast.interfaces().flat_map(|interface| interface.routines().iter()
+                    .map(|routine| (routine.ident.clone(), routine.return_type))
+                ))
+                .collect(),
+            params: ast.sections()
+                .flat_map(|section| section.declarations())
+                .filter_map(|decl| decl.as_any().downcast_ref::<Function>())
+                .map(|func| (func.ident().clone(), (func.params().iter().map(|param| param.typ()).collect(), func.variadic().is_some())))
+                .chain(ast.interfaces().flat_map(|interface| interface.routines().iter()
+                    .map(|routine| (routine.ident.clone(), (routine.params.clone(), routine.variadic)))
+                ))
+                .collect(),
             outer: None
         }
     }
+
+    pub fn routine_result(&self, ident: &String) -> Option<Option<TypeIndex>> {
+        self.routines.get(ident).copied()
+            .or_else(|| self.outer.and_then(|outer| outer.routine_result(ident)))
+    }
+
+    pub fn routine_params(&self, ident: &String) -> Option<&(Vec<Option<TypeIndex>>, bool)> {
+        self.params.get(ident)
+            .or_else(|| self.outer.and_then(|outer| outer.routine_params(ident)))
+    }
 }
@@ -0,0 +1,105 @@
+use std::collections::HashMap;
+
+use crate::{
+    ast::{self, Decl, visitor::{Action, Visitor}, expr::{Expr, ExprKind}},
+    source_file::WithLocation
+};
+
+use super::{Error, TypeCheckError};
+
+// gathers the arguments a call passes after the fixed parameters of a variadic routine into
+// one `VarArgs` argument, which codegen passes as a vector of their count followed by them.
+// Calls through routine values cannot be resolved and pass all arguments as they are.
+pub(super) struct VariadicCalls {
+    // the number of fixed parameters of each variadic routine, including imported ones
+    routines: HashMap<String, usize>
+}
+
+impl VariadicCalls {
+    pub(super) fn new(ast: &ast::Program) -> Self {
+        let local = ast.sections()
+            .flat_map(|section| section.declarations())
+            .filter_map(|decl| decl.as_any().downcast_ref::<ast::Function>())
+            .filter(|func| func.variadic().is_some())
+            .map(|func| (func.ident().clone(), func.params().len()));
+        let imported = ast.interfaces()
+            .flat_map(|interface| interface.routines())
+            .filter(|routine| routine.variadic)
+            .map(|routine| (routine.ident.clone(), routine.params.len()));
+
+        Self {
+            routines: imported.chain(local).collect()
+        }
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.routines.is_empty()
+    }
+}
+
+impl Visitor<ast::Program, Error> for VariadicCalls {
+    fn visit(&mut self, _node: &mut ast::Program) -> Result<Action, Error> {
+        Ok(Action::Continue)
+    }
+}
+
+impl Visitor<ast::Section, Error> for VariadicCalls {
+    fn visit(&mut self, _node: &mut ast::Section) -> Result<Action, Error> {
+        Ok(Action::Continue)
+    }
+}
+
+impl Visitor<ast::Function, Error> for VariadicCalls {
+    fn visit(&mut self, _node: &mut ast::Function) -> Result<Action, Error> {
+        Ok(Action::Continue)
+    }
+}
+
+impl Visitor<ast::Param, Error> for VariadicCalls {
+    fn visit(&mut self, _node: &mut ast::Param) -> Result<Action, Error> {
+        Ok(Action::Continue)
+    }
+}
+
+impl Visitor<ast::stmt::Stmt, Error> for VariadicCalls {
+    fn visit(&mut self, _node: &mut ast::stmt::Stmt) -> Result<Action, Error> {
+        Ok(Action::Continue)
+    }
+}
+
+impl Visitor<Expr, Error> for VariadicCalls {
+    fn visit(&mut self, node: &mut Expr) -> Result<Action, Error> {
+        let loc = node.location().clone();
+        let ExprKind::FuncCall(callee, args) = node.kind_mut() else {
+            return Ok(Action::Continue)
+        };
+        let ExprKind::Ident(routine) = callee.kind() else {
+            return Ok(Action::Continue)
+        };
+        let Some(&fixed) = self.routines.get(routine) else {
+            return Ok(Action::Continue)
+        };
+        // already gathered, as in typed ASTs read back in
+        if args.last().is_some_and(|arg| matches!(arg.kind(), ExprKind::VarArgs(_))) {
+            return Ok(Action::Continue)
+        }
+        // the vector would end up in the slot of a missing fixed argument
+        if args.len() < fixed {
+            return Err(TypeCheckError::MissingFixedArgs(routine.clone(), fixed, args.len()).with_location(loc))
+        }
+
+        let rest = args.split_off(fixed);
+        let rest_loc = rest.iter()
+            .map(|arg| arg.location().clone())
+            .reduce(|mut a, b| { a.extend_to(&b); a })
+            .unwrap_or_else(|| loc.clone());
+        args.push(Expr::new(rest_loc, None, ExprKind::VarArgs(rest)));
+        Ok(Action::Continue)
+    }
+}
+
+impl Visitor<ast::pattern::Pattern, Error> for VariadicCalls {
+    fn visit(&mut self, _node: &mut ast::pattern::Pattern) -> Result<Action, Error> {
+        Ok(Action::Continue)
+    }
+}
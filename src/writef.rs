@@ -0,0 +1,221 @@
+use crate::{
+    match_decl,
+    ast::{Program, Function, FunctionBody, GlobalDecl, ManifestDecl, expr::{Expr, ExprKind}, stmt::Stmt, types::{TypeIndex, TypeKind, TypeList}},
+    source_file::{Located, WithLocation},
+    error::{IntoCompilerError, CompilerError, Severity},
+    lint::Lint
+};
+
+// Directives of the runtime library's `writef`; widths are a single character, `0`-`9`
+// or `A`-`Z` for 10 to 35:
+//
+//   %s      string            %tW     string padded to W characters
+//   %c      character         %%      a literal `%`
+//   %n, %d  decimal number    %iW     decimal number padded to W digits
+//   %uW     unsigned number   %xW     hexadecimal, W digits
+//   %oW     octal, W digits   %bW     binary, W digits
+//   %f      floating point number
+const ROUTINE: &str = "writef";
+
+#[derive(Clone, Copy, PartialEq)]
+enum Arg {
+    String,
+    Char,
+    Number,
+    Float
+}
+
+impl Arg {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::String => "a string",
+            Self::Char => "a character",
+            Self::Number => "a number",
+            Self::Float => "a floating point number"
+        }
+    }
+
+    // unknown types are not reported, they are checked at runtime
+    fn accepts(&self, typ: Option<&TypeKind>, types: &TypeList) -> bool {
+        let Some(typ) = typ else {
+            return true
+        };
+        let is_char = |inner: &TypeIndex| matches!(types.get(types.resolve(*inner)).map(|typ| typ.kind()), Some(TypeKind::Char));
+
+        match (self, typ) {
+            (_, TypeKind::Alias(_, None)) => true,
+            (Self::String, TypeKind::Pointer(inner) | TypeKind::Array(inner, _) | TypeKind::Slice(inner)) => is_char(inner),
+            (Self::Char, typ) => matches!(typ, TypeKind::Char) || typ.is_integer(),
+            (Self::Number, typ) => typ.is_word(),
+            (Self::Float, typ) => typ.is_float(),
+            _ => false
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum FormatError {
+    InvalidDirective(String),
+    MissingWidth(char),
+    //       expected found
+    ArgCount(usize, usize),
+    //      directive expected      found
+    ArgType(String, &'static str, String)
+}
+
+impl FormatError {
+    fn severity(&self) -> Severity {
+        Severity::Warning
+    }
+
+    pub fn lint(&self) -> Option<Lint> {
+        Some(Lint::FormatString)
+    }
+
+    fn hint(&self) -> Option<String> {
+        match self {
+            Self::InvalidDirective(_) => Some("Write `%%` for a literal `%`.".into()),
+            Self::MissingWidth(_) => Some("Widths are a single digit, or a letter from `A` to `Z` for 10 to 35.".into()),
+            _ => None
+        }
+    }
+}
+
+impl WithLocation for FormatError {}
+
+impl ToString for FormatError {
+    fn to_string(&self) -> String {
+        match self {
+            Self::InvalidDirective(directive) => format!("Unknown `{ROUTINE}` directive `{directive}`."),
+            Self::MissingWidth(directive) => format!("Directive `%{directive}` expects a field width."),
+            Self::ArgCount(expected, found) => format!("Format string expects {expected} argument(s), but {found} were given."),
+            Self::ArgType(directive, expected, found) => format!("Directive `{directive}` expects {expected}, found `{found}`.")
+        }
+    }
+}
+
+impl IntoCompilerError for FormatError {}
+impl Into<CompilerError> for FormatError {
+    fn into(self) -> CompilerError {
+        CompilerError::new(self.severity(), self.to_string(), self.hint(), vec![])
+    }
+}
+
+// the arguments the directives of `format` consume, with their spelling
+fn parse_format(format: &str) -> Result<Vec<(String, Arg)>, FormatError> {
+    let mut args = vec![];
+    let mut chars = format.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '%' {
+            continue
+        }
+
+        let Some(directive) = chars.next() else {
+            return Err(FormatError::InvalidDirective("%".into()))
+        };
+        let arg = match directive.to_ascii_lowercase() {
+            '%' => continue,
+            's' => Arg::String,
+            'c' => Arg::Char,
+            'n' | 'd' => Arg::Number,
+            'f' => Arg::Float,
+            't' | 'i' | 'u' | 'x' | 'o' | 'b' => {
+                let width = chars.next().filter(|width| width.is_ascii_digit() || width.is_ascii_uppercase());
+                let Some(width) = width else {
+                    return Err(FormatError::MissingWidth(directive))
+                };
+                let arg = if directive.eq_ignore_ascii_case(&'t') { Arg::String } else { Arg::Number };
+                args.push((format!("%{directive}{width}"), arg));
+                continue
+            }
+            _ => return Err(FormatError::InvalidDirective(format!("%{directive}")))
+        };
+        args.push((format!("%{directive}"), arg));
+    }
+    Ok(args)
+}
+
+// Checks calls of `writef` with a literal format string against the arguments given,
+// unless the program defines a `writef` of its own.
+pub fn check_formats(ast: &Program) -> Vec<Located<FormatError>> {
+    let declarations = || ast.sections().flat_map(|section| section.declarations());
+    if declarations().any(|decl| decl.ident() == ROUTINE) {
+        return vec![]
+    }
+
+    let mut calls = vec![];
+    for decl in declarations() {
+        match_decl!{
+            decl;
+            func as Function => match func.body() {
+                FunctionBody::Expr(expr) => calls_in_expr(expr, &mut calls),
+                FunctionBody::Stmt(stmt) => calls_in_stmt(stmt, &mut calls),
+                FunctionBody::PatternMatchedExpr(branches) => branches.iter().for_each(|(_, expr)| calls_in_expr(expr, &mut calls)),
+                FunctionBody::PatternMatchedStmt(branches) => branches.iter().for_each(|(_, stmt)| calls_in_stmt(stmt, &mut calls))
+            },
+            global as GlobalDecl => if let Some(value) = global.value() {
+                calls_in_expr(value, &mut calls)
+            },
+            manifest as ManifestDecl => calls_in_expr(manifest.value(), &mut calls),
+            _ => ()
+        }
+    }
+
+    let mut warnings = vec![];
+    for (call, args) in calls {
+        let Some((format, args)) = args.split_first() else {
+            continue
+        };
+        let ExprKind::StringLit(literal) = strip_casts(format).kind() else {
+            continue
+        };
+
+        let directives = match parse_format(literal) {
+            Ok(directives) => directives,
+            Err(err) => {
+                warnings.push(err.with_location(format.location().clone()));
+                continue
+            }
+        };
+        if directives.len() != args.len() {
+            warnings.push(FormatError::ArgCount(directives.len(), args.len()).with_location(call.location().clone()));
+            continue
+        }
+
+        for ((directive, expected), arg) in directives.into_iter().zip(args) {
+            let arg = strip_casts(arg);
+            let typ = arg.typ().map(|typ| ast.types().resolve(typ));
+            if !expected.accepts(typ.and_then(|typ| ast.types().get(typ)).map(|typ| typ.kind()), ast.types()) {
+                let found = ast.types().type_name(typ.unwrap());
+                warnings.push(FormatError::ArgType(directive, expected.name(), found).with_location(arg.location().clone()));
+            }
+        }
+    }
+
+    warnings.sort_by(|a, b| a.location().cmp(b.location()));
+    warnings
+}
+
+// the type an argument had before it was converted to the parameter type
+fn strip_casts(expr: &Expr) -> &Expr {
+    match expr.kind() {
+        ExprKind::ImplicitCast(inner) => strip_casts(inner),
+        _ => expr
+    }
+}
+
+fn calls_in_expr<'a>(expr: &'a Expr, calls: &mut Vec<(&'a Expr, &'a [Expr])>) {
+    if let ExprKind::FuncCall(callee, args) = expr.kind() && let ExprKind::Ident(ident) = callee.kind() && ident == ROUTINE {
+        calls.push((expr, args));
+    }
+    if let ExprKind::ValOf(body) = expr.kind() {
+        calls_in_stmt(body, calls);
+    }
+
+    expr.kind().children().into_iter().for_each(|child| calls_in_expr(child, calls));
+}
+
+fn calls_in_stmt<'a>(stmt: &'a Stmt, calls: &mut Vec<(&'a Expr, &'a [Expr])>) {
+    stmt.kind().exprs().into_iter().for_each(|expr| calls_in_expr(expr, calls));
+    stmt.kind().stmts().into_iter().for_each(|stmt| calls_in_stmt(stmt, calls));
+}
@@ -0,0 +1,102 @@
+// checks programs through the stable library interface, the way external tools use it
+
+use bcplpp::api::{self, NodeKind, Severity, Source};
+
+#[test]
+fn outline_and_diagnostics() {
+    let checked = api::check(vec![Source::new("outline.bpp", "section Outline
+
+manifest Limit = 10
+
+static count = 0
+
+let twice(n :: Int32) = n * 2
+
+let start be twice(Limit)
+")]);
+    assert!(!checked.has_errors(), "{:?}", checked.diagnostics);
+
+    let [section] = checked.sections.as_slice() else {
+        panic!("expected one section, found {:?}", checked.sections)
+    };
+    assert_eq!((section.kind, section.name.as_str()), (NodeKind::Section, "Outline"));
+
+    let outline = section.children.iter().map(|decl| (decl.kind, decl.name.as_str(), decl.span.line)).collect::<Vec<_>>();
+    assert_eq!(outline, [
+        (NodeKind::Manifest, "Limit", 3),
+        (NodeKind::Static, "count", 5),
+        (NodeKind::Routine, "twice", 7),
+        (NodeKind::Routine, "start", 9)
+    ]);
+
+    let checked = api::check(vec![Source::new("broken.bpp", "section Broken\n\nlet start be resultis 1\n")]);
+    let [diag] = checked.diagnostics.as_slice() else {
+        panic!("expected one diagnostic, found {:?}", checked.diagnostics)
+    };
+    assert_eq!(diag.severity, Severity::Error);
+    assert_eq!((diag.span.file.as_str(), diag.span.line), ("broken.bpp", 3));
+    assert!(diag.to_json().starts_with("{ \"severity\": \"error\""));
+}
+
+#[test]
+fn definitions_across_files() {
+    let checked = api::check(vec![
+        Source::new("first.bpp", "section First\n\nlet helper(n :: Int32) = n + 1\n\nlet start be helper(1)\n"),
+        Source::new("second.bpp", "section Second\n\nstatic count = 0\n\nlet helper(n :: Int32) = n * 2\n")
+    ]);
+    let [diag] = checked.diagnostics.as_slice() else {
+        panic!("expected one diagnostic, found {:?}", checked.diagnostics)
+    };
+    assert_eq!(diag.message, "Redefinition of `helper`.");
+    assert_eq!((diag.span.file.as_str(), diag.span.line), ("second.bpp", 5));
+
+    let [first] = diag.related.as_slice() else {
+        panic!("expected the first definition, found {:?}", diag.related)
+    };
+    assert_eq!((first.span.file.as_str(), first.span.line), ("first.bpp", 3));
+}
+
+#[test]
+fn mangled_symbols() {
+    for (ident, symbol) in [
+        ("start", "_BM4Main5start"),
+        ("outer.inner.2", "_BM4Main5outer5innerD2_"),
+        ("max<Int64, &Point>", "_BM4Main3maxI5Int648$26PointE")
+    ] {
+        assert_eq!(api::mangle(ident, "Main"), symbol);
+        assert_eq!(api::demangle(symbol), Some(format!("{ident} [Main]")));
+    }
+    assert_eq!(api::demangle("_BM4Main5start+0x10"), None);
+    assert_eq!(api::demangle("printf"), None);
+}
+
+#[test]
+fn invalid_case_labels() {
+    for (labels, message, line, previous) in [
+        ("case 1, 2 to 5: n := 1;\n    case 4: n := 2;", "Case label overlaps with a previous label.", 6, Some(5)),
+        ("case 1 to 3: n := 1;\n    case 3 to 9: n := 2;", "Case label overlaps with a previous label.", 6, Some(5)),
+        ("case 10 to 1: n := 1;", "Case range `10 to 1` is empty.", 5, None)
+    ] {
+        let source = format!("section Cases\n\nlet pick(n :: Int32) be\n    switchon n into {{\n    {labels}\n    }}\n");
+        let checked = api::check(vec![Source::new("cases.bpp", &source)]);
+        let [diag] = checked.diagnostics.as_slice() else {
+            panic!("expected one diagnostic for {labels:?}, found {:?}", checked.diagnostics)
+        };
+        assert_eq!((diag.message.as_str(), diag.span.line), (message, line), "{labels:?}");
+        assert_eq!(diag.related.first().map(|prev| prev.span.line), previous, "{labels:?}");
+    }
+
+    let checked = api::check(vec![Source::new("strings.bpp", "section Strings
+
+let pick(s :: String) be
+    switchon s into {
+    case \"red\", \"green\": writes(\"warm\");
+    case \"blue\", \"red\": writes(\"cold\");
+    }
+")]);
+    let [diag] = checked.diagnostics.as_slice() else {
+        panic!("expected one diagnostic, found {:?}", checked.diagnostics)
+    };
+    assert_eq!((diag.message.as_str(), diag.span.line), ("Case label overlaps with a previous label.", 6));
+    assert_eq!(diag.related.first().map(|prev| prev.span.line), Some(5));
+}
@@ -0,0 +1,290 @@
+// builds programs with the `bcplpp` driver and inspects the artifacts it writes, like the
+// listing of the lowered IR
+
+use std::{path::{Path, PathBuf}, process::{Command, Stdio}, fs, io::Write};
+
+// a directory of its own for each test, as they run in parallel
+fn scratch_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("bcplpp-{}-{test}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// the diagnostics of a successful run
+fn bcplpp(args: &[&str]) -> String {
+    let run = Command::new(env!("CARGO_BIN_EXE_bcplpp"))
+        .args(args)
+        .output()
+        .expect("error running bcplpp");
+    // diagnostics go to stdout, fatal errors to stderr
+    let diagnostics = String::from_utf8(run.stdout).unwrap() + &String::from_utf8(run.stderr).unwrap();
+    assert!(run.status.success(), "`bcplpp {}` failed:\n{diagnostics}", args.join(" "));
+    diagnostics
+}
+
+// the diagnostics of building `source`, and the IR listing written, one line per instruction
+fn build(test: &str, source: &str) -> (String, Vec<String>) {
+    build_files(test, &[source], &[])
+}
+
+// like `build`, for a program of several source files and with extra driver options
+fn build_files(test: &str, sources: &[&str], options: &[&str]) -> (String, Vec<String>) {
+    let dir = scratch_dir(test);
+    let mut inputs = vec![];
+    for (i, source) in sources.iter().enumerate() {
+        let input = dir.join(format!("{test}{i}.bpp"));
+        fs::write(&input, source).unwrap();
+        inputs.push(input.to_str().unwrap().to_string());
+    }
+    let output = dir.join(test);
+    let mut args = vec!["build", "--emit=asm"];
+    args.extend(options);
+    args.extend(inputs.iter().map(String::as_str));
+    args.extend(["-o", output.to_str().unwrap()]);
+    let diagnostics = bcplpp(&args);
+
+    let listing = fs::read_to_string(output.with_extension("ir")).unwrap();
+    fs::remove_dir_all(&dir).ok();
+    (diagnostics, listing.lines().map(|line| line.trim().to_string()).collect())
+}
+
+fn ir_listing(test: &str, source: &str) -> Vec<String> {
+    build(test, source).1
+}
+
+// the instructions of `routine` in `listing`, up to the next routine
+fn routine<'a>(listing: &'a [String], name: &str) -> &'a [String] {
+    let start = listing.iter().position(|line| *line == format!("{name}:")).unwrap_or_else(|| panic!("no routine `{name}` in {listing:#?}"));
+    let len = listing[start..].iter().position(String::is_empty).unwrap_or(listing.len() - start);
+    &listing[start..start + len]
+}
+
+fn index_of(lines: &[String], text: &str) -> usize {
+    lines.iter().position(|line| line == text).unwrap_or_else(|| panic!("no `{text}` in {lines:#?}"))
+}
+
+#[test]
+fn thrown_values_are_computed_before_deferred_statements() {
+    let listing = ir_listing("defer_throw", "section Defer
+
+let f() be {
+    let x := 1;
+    defer x := 2;
+    throw x;
+}
+
+let start be try { f(); } catch (e) writef(\"%n*n\", e)
+");
+    let f = routine(&listing, "f");
+    assert!(index_of(f, "eval throwvalue(x)") < index_of(f, "store x := 2"), "{f:#?}");
+}
+
+#[test]
+fn throws_from_callees_run_deferred_statements() {
+    let listing = ir_listing("defer_callee", "section Defer
+
+let g() be throw 7
+
+let f() be {
+    defer writef(\"cleanup*n\");
+    g();
+}
+
+let start be try { f(); } catch (e) writef(\"%n*n\", e)
+");
+    let f = routine(&listing, "f");
+    // the handler the body is run under cleans up and throws on
+    let handler = f.iter().position(|line| line.starts_with("mov") && line.ends_with("caughtvalue()")).unwrap_or_else(|| panic!("no handler in {f:#?}"));
+    let cleanup = f[handler..].iter().position(|line| line.starts_with("eval writef(")).map(|i| handler + i);
+    let rethrow = f[handler..].iter().position(|line| line.starts_with("eval throwvalue(")).map(|i| handler + i);
+    assert!(cleanup.is_some() && cleanup < rethrow, "{f:#?}");
+}
+
+#[test]
+fn taking_the_address_of_a_global_may_initialize_it() {
+    let (diagnostics, _) = build("address_of_global", "section Globals
+
+global a :: Int64
+global d :: Int64
+global unset :: Int64
+
+let set(p :: &Int64) be !p := 1
+
+let start be {
+    set(@a);
+    let q = @d;
+    !q := 3;
+    writef(\"%n %n %n*n\", a, d, unset);
+}
+");
+    let warned = diagnostics.lines().filter(|line| line.contains("is read, but never initialized")).collect::<Vec<_>>();
+    assert!(matches!(warned.as_slice(), [warning] if warning.contains("`unset`")), "{diagnostics}");
+}
+
+#[test]
+fn data_size_is_reported_per_section() {
+    let (diagnostics, _) = build_files("data_size", &["section Colors
+
+global { red: 3 }
+
+let name() = \"red\"
+let other() = \"blue\"
+", "section Main
+
+global { count: 2; total: 4 }
+
+let start be {
+    writef(\"red\");
+    writef(\"%s*n\", \"done\");
+}
+"], &["--report-data-size"]);
+    // the constant both sections use counts for the one lowered first
+    assert!(diagnostics.contains("  Colors: 16 bytes of read-only data in 2 constants (1 duplicates merged), 8 bytes of globals\n"), "{diagnostics}");
+    assert!(diagnostics.contains("  Main: 16 bytes of read-only data in 2 constants (0 duplicates merged), 16 bytes of globals\n"), "{diagnostics}");
+}
+
+#[test]
+fn flat_images_need_a_native_backend() {
+    let dir = scratch_dir("output_format");
+    let input = dir.join("output_format.bpp");
+    fs::write(&input, "section Image\n\nlet start be return\n").unwrap();
+    let built = Command::new(env!("CARGO_BIN_EXE_bcplpp"))
+        .args(["build", "--output-format", "ihex", input.to_str().unwrap(), "-o", dir.join("image").to_str().unwrap()])
+        .output()
+        .expect("error running bcplpp");
+    fs::remove_dir_all(&dir).ok();
+
+    let diagnostics = String::from_utf8(built.stdout).unwrap() + &String::from_utf8(built.stderr).unwrap();
+    assert!(!built.status.success(), "{diagnostics}");
+    assert!(diagnostics.contains("`--output-format ihex` requires a native backend and linker"), "{diagnostics}");
+    assert!(!diagnostics.contains("Compiling:"), "{diagnostics}");
+}
+
+#[test]
+fn daemon_reports_missing_files() {
+    let dir = scratch_dir("daemon_missing");
+    let mut daemon = Command::new(env!("CARGO_BIN_EXE_bcplpp"))
+        .arg("daemon")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("error running bcplpp");
+    writeln!(daemon.stdin.take().unwrap(), "{}\tcheck\tmissing.bpp", dir.display()).unwrap();
+    let served = daemon.wait_with_output().unwrap();
+    fs::remove_dir_all(&dir).ok();
+
+    // the request fails, not the daemon
+    let output = String::from_utf8(served.stdout).unwrap() + &String::from_utf8(served.stderr).unwrap();
+    assert!(served.status.success(), "{output}");
+    assert!(output.contains("fatal error: error reading `missing.bpp`"), "{output}");
+    assert!(output.contains("#bcplpp-done 1"), "{output}");
+}
+
+// builds `program` and the typed ast emitted for it, which have to give the same IR
+fn assert_typed_ast_round_trip(dir: &Path, program: &Path) {
+    let name = program.file_stem().unwrap().to_str().unwrap();
+    let from_source = dir.join(format!("{name}-source"));
+    bcplpp(&["build", "--object", "--emit", "asm", "--emit", "tast", program.to_str().unwrap(), "-o", from_source.to_str().unwrap()]);
+    let from_tast = dir.join(format!("{name}-tast"));
+    bcplpp(&["build", "--object", "--emit=asm", from_source.with_extension("tast").to_str().unwrap(), "-o", from_tast.to_str().unwrap()]);
+
+    // the first line stamps the listing with the fingerprint of the inputs
+    let listing = |output: &PathBuf| fs::read_to_string(output.with_extension("ir")).unwrap().lines().skip(1).collect::<Vec<_>>().join("\n");
+    assert_eq!(listing(&from_source), listing(&from_tast), "{name}");
+}
+
+#[test]
+fn typed_asts_build_like_their_sources() {
+    let dir = scratch_dir("tast_round_trip");
+    let mut programs = fs::read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/corpus")).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "bpp"))
+        .collect::<Vec<_>>();
+    programs.sort();
+    assert!(!programs.is_empty());
+
+    for program in programs {
+        assert_typed_ast_round_trip(&dir, &program);
+    }
+    fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn named_arguments_skip_parameters_with_defaults() {
+    let listing = ir_listing("named_defaults", "section Named
+
+let area(width :: Int32, height :: Int32 = 2, depth :: Int32 = 3) = width * height * depth
+
+let twice(n :: Int32 = area(1, depth := 5)) = n * 2
+
+let start be {
+    area(height := 4, width := 1);
+    area(1, depth := 7);
+    twice();
+}
+");
+    let start = routine(&listing, "start");
+    index_of(start, "eval area(1, 4, 3)");
+    index_of(start, "eval area(1, 2, 7)");
+    index_of(start, "eval twice(cast area(1, 2, 5))");
+}
+
+#[test]
+fn named_arguments_are_evaluated_in_source_order() {
+    let listing = ir_listing("named_order", "section Named
+
+static counter = 0
+
+let bump() = valof {
+    counter := counter + 1;
+    resultis counter;
+}
+
+let sub(a :: Int32, b :: Int32) = a - b
+
+let show(a :: Int32, b :: Int32) be writef(\"%n %n*n\", a, b)
+
+let start be {
+    writef(\"%n*n\", sub(b := bump(), a := bump()));
+    show(b := bump(), a := 1);
+    show(a := bump(), b := bump());
+}
+");
+    let start = routine(&listing, "start");
+    let first = index_of(start, "bind named.0 := bump()");
+    assert_eq!(start[first + 1], "bind named.1 := bump()", "{start:#?}");
+    index_of(start, "mov %0, sub(named.1, named.0)");
+
+    let second = first + 1 + index_of(&start[first + 1..], "bind named.0 := bump()");
+    assert_eq!(start[second + 1..second + 3], ["bind named.1 := 1", "eval show(named.1, named.0)"], "{start:#?}");
+    // values in parameter order are left to the lowering, which keeps them in order as well
+    assert_eq!(start[second + 3..second + 5], ["mov %1, bump()", "eval show(%1, bump())"], "{start:#?}");
+}
+
+#[test]
+fn typed_asts_keep_instance_names() {
+    let dir = scratch_dir("tast_instances");
+    let program = dir.join("instances.bpp");
+    fs::write(&program, "section Instances
+
+type Point = struct { x :: Int32, y :: Int32 }
+
+let first<T>(items :: &T) = !items
+let second<A, B>(_ :: A, b :: B) = b
+
+let start be {
+    let p = (vec 2) :: &Point;
+    p.x := 1;
+    let q = first(p) :: Point;
+    let r = second(1, p) :: &Point;
+    writef(\"%n %n*n\", q.x, r.x);
+}
+").unwrap();
+    assert_typed_ast_round_trip(&dir, &program);
+
+    // instances are named by the types as written
+    let tast = fs::read_to_string(dir.join("instances-source.tast")).unwrap();
+    assert!(tast.contains(" first<Point> ") && tast.contains(" \"second<Int32, &Point>\" "), "{tast}");
+    fs::remove_dir_all(&dir).ok();
+}
@@ -0,0 +1,51 @@
+// parses every program in `tests/corpus/` and compares the dumped ast with the
+// golden `.ast` file next to it. run with `BLESS=1` to regenerate the golden files.
+
+use std::{path::{Path, PathBuf}, process::Command, fs};
+
+fn corpus_programs() -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus");
+    let mut programs: Vec<PathBuf> = fs::read_dir(&dir)
+        .expect("error reading tests/corpus")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "bpp"))
+        .collect();
+    programs.sort();
+    programs
+}
+
+fn dump_ast(program: &Path) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_bcplpp"))
+        .arg("--dump-ast")
+        .arg(program)
+        .output()
+        .expect("error running bcplpp");
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(output.status.success(), "{} failed to parse:\n{stdout}", program.display());
+    stdout
+}
+
+#[test]
+fn golden_asts() {
+    let bless = std::env::var_os("BLESS").is_some();
+    let mut failures = vec![];
+
+    for program in corpus_programs() {
+        let golden = program.with_extension("ast");
+        let actual = dump_ast(&program);
+
+        if bless {
+            fs::write(&golden, &actual).unwrap();
+            continue;
+        }
+
+        match fs::read_to_string(&golden) {
+            Ok(expected) if expected == actual => (),
+            Ok(_) => failures.push(format!("{}: ast differs from {}", program.display(), golden.display())),
+            Err(_) => failures.push(format!("{}: missing golden file {}", program.display(), golden.display()))
+        }
+    }
+
+    assert!(failures.is_empty(), "{}\nrerun with `BLESS=1` if the changes are intended.", failures.join("\n"));
+}
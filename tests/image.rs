@@ -0,0 +1,91 @@
+// converts hand-built ELF files into flat images, as there is no backend to link real ones yet
+
+use bcplpp::image::Image;
+
+const PT_LOAD: u32 = 1;
+const PT_NOTE: u32 = 4;
+
+// a little-endian executable with one program header per segment, followed by their contents
+fn elf(is_64: bool, segments: &[(u32, u64, &[u8])]) -> Vec<u8> {
+    let (header_size, phentsize) = if is_64 { (0x40, 0x38) } else { (0x34, 0x20) };
+    let mut bytes = vec![0; header_size + phentsize * segments.len()];
+    bytes[..7].copy_from_slice(&[0x7f, b'E', b'L', b'F', if is_64 { 2 } else { 1 }, 1, 1]);
+
+    fn write(bytes: &mut [u8], offset: usize, size: usize, value: u64) {
+        bytes[offset..offset + size].copy_from_slice(&value.to_le_bytes()[..size]);
+    }
+    if is_64 {
+        write(&mut bytes, 0x20, 8, header_size as u64);
+        write(&mut bytes, 0x36, 2, phentsize as u64);
+        write(&mut bytes, 0x38, 2, segments.len() as u64);
+    }
+    else {
+        write(&mut bytes, 0x1c, 4, header_size as u64);
+        write(&mut bytes, 0x2a, 2, phentsize as u64);
+        write(&mut bytes, 0x2c, 2, segments.len() as u64);
+    }
+
+    for (i, (kind, paddr, contents)) in segments.iter().enumerate() {
+        let header = header_size + i * phentsize;
+        let offset = bytes.len() as u64;
+        write(&mut bytes, header, 4, *kind as u64);
+        if is_64 {
+            write(&mut bytes, header + 0x08, 8, offset);
+            write(&mut bytes, header + 0x18, 8, *paddr);
+            write(&mut bytes, header + 0x20, 8, contents.len() as u64);
+        }
+        else {
+            write(&mut bytes, header + 0x04, 4, offset);
+            write(&mut bytes, header + 0x0c, 4, *paddr);
+            write(&mut bytes, header + 0x10, 4, contents.len() as u64);
+        }
+        bytes.extend_from_slice(contents);
+    }
+    bytes
+}
+
+#[test]
+fn binary_images_fill_gaps_between_segments() {
+    for is_64 in [false, true] {
+        let image = Image::from_elf(&elf(is_64, &[
+            (PT_LOAD, 0x1004, &[5, 6]),
+            (PT_NOTE, 0x0, &[9, 9, 9]),
+            (PT_LOAD, 0x1000, &[1, 2]),
+            (PT_LOAD, 0x2000, &[])
+        ])).unwrap();
+        assert_eq!(image.to_binary(), [1, 2, 0, 0, 5, 6]);
+    }
+}
+
+#[test]
+fn intel_hex_records_have_checksums_and_extended_addresses() {
+    let image = Image::from_elf(&elf(false, &[(PT_LOAD, 0x0800_0000, &[1, 2, 3])])).unwrap();
+    assert_eq!(image.to_intel_hex().unwrap(), ":020000040800F2\n:03000000010203F7\n:00000001FF\n");
+}
+
+#[test]
+fn intel_hex_records_do_not_cross_64k_boundaries() {
+    let contents = (0..16).collect::<Vec<u8>>();
+    let image = Image::from_elf(&elf(false, &[(PT_LOAD, 0xfff8, &contents)])).unwrap();
+    let hex = image.to_intel_hex().unwrap();
+    let records = hex.lines().map(|record| &record[..9]).collect::<Vec<_>>();
+    assert_eq!(records, [":02000004", ":08FFF800", ":02000004", ":08000000", ":00000001"], "{hex}");
+}
+
+#[test]
+fn invalid_images_are_rejected() {
+    let error = |bytes: &[u8]| Image::from_elf(bytes).err().unwrap_or_else(|| panic!("{bytes:?} was accepted"));
+    assert_eq!(error(b"#!/bin/sh\n"), "not an ELF file");
+    assert_eq!(error(&elf(false, &[(PT_NOTE, 0x1000, &[1])])), "ELF file has no loadable segments");
+
+    let mut big_endian = elf(false, &[(PT_LOAD, 0x1000, &[1])]);
+    big_endian[5] = 2;
+    assert_eq!(error(&big_endian), "big-endian ELF files are not supported");
+
+    let mut truncated = elf(false, &[(PT_LOAD, 0x1000, &[1, 2, 3, 4])]);
+    truncated.truncate(truncated.len() - 2);
+    assert_eq!(error(&truncated), "truncated ELF segment");
+
+    let image = Image::from_elf(&elf(true, &[(PT_LOAD, 0x1_0000_0000, &[1])])).unwrap();
+    assert_eq!(image.to_intel_hex().err().unwrap(), "address 0x100000000 does not fit into an Intel HEX file");
+}
@@ -0,0 +1,44 @@
+// conditional compilation: which parts of a source the lexer reads for the tags set
+
+use bcplpp::{source_file::SourceFile, token::{lexer::Lexer, TokenKind}};
+
+// the tokens read from `source`, up to the end of the file
+fn tokens(source: &str, tags: &[&str]) -> Vec<String> {
+    let file = SourceFile::new("test.bpp".into(), 0, source.into());
+    let tags: Vec<String> = tags.iter().map(|tag| tag.to_string()).collect();
+    Lexer::from(&file)
+        .with_tags(&tags)
+        .take_while(|token| !matches!(token.kind(), TokenKind::Eof))
+        .map(|token| token.kind().to_string())
+        .collect()
+}
+
+#[test]
+fn sections_follow_their_tags() {
+    let source = "a $<on b $>on c $~on d $>on e";
+    assert_eq!(tokens(source, &["on"]), ["a", "b", "c", "e"]);
+    assert_eq!(tokens(source, &[]), ["a", "c", "d", "e"]);
+
+    // sections with other tags nested in a skipped one are skipped with it
+    assert_eq!(tokens("a $<on b $<off c $>off d $>on e", &["off"]), ["a", "e"]);
+}
+
+#[test]
+fn tags_toggle_within_the_file() {
+    let source = "$<on a $>on $$on $<on b $>on $$on $<on c $>on";
+    assert_eq!(tokens(source, &[]), ["b"]);
+    assert_eq!(tokens(source, &["on"]), ["a", "c"]);
+}
+
+#[test]
+fn strings_and_comments_do_not_end_sections() {
+    let source = "a $<on \"$>on\" '$' // $>on\n /* $>on */ b $>on c";
+    assert_eq!(tokens(source, &[]), ["a", "c"]);
+}
+
+#[test]
+fn unterminated_sections() {
+    assert_eq!(tokens("a $<on b", &[]), ["a", "`$<on` has no matching `$>on`"]);
+    assert_eq!(tokens("a $~on b", &["on"]), ["a", "`$~on` has no matching `$>on`"]);
+    assert_eq!(tokens("a $< b", &[]), ["a", "expect tag after `$<`", "b"]);
+}
@@ -0,0 +1,43 @@
+// reads sources in small windows and with small line indexes, which otherwise only files of
+// megabytes would need
+
+use std::{fs, io::ErrorKind};
+
+use bcplpp::source_file::{SourceText, ReadLimits};
+
+fn read(test: &str, contents: &[u8], limits: ReadLimits) -> std::io::Result<SourceText> {
+    let path = std::env::temp_dir().join(format!("bcplpp-{}-{test}.bpp", std::process::id()));
+    fs::write(&path, contents).unwrap();
+    let text = SourceText::read(path.to_str().unwrap(), limits);
+    fs::remove_file(&path).ok();
+    text
+}
+
+#[test]
+fn characters_split_across_windows() {
+    // `é` starts in the last byte of the first window, `€` spans the third and fourth
+    let contents = "abcé\nxyz€\n";
+    for chunk_size in 4..=contents.len() {
+        let text = read(&format!("split-{chunk_size}"), contents.as_bytes(), ReadLimits { chunk_size, ..Default::default() }).unwrap();
+        assert_eq!(text.contents(), contents, "{chunk_size}");
+        assert_eq!((text.line(1), text.line(2), text.line(3)), (Some("abcé"), Some("xyz€"), Some("")), "{chunk_size}");
+    }
+
+    for (test, contents) in [("invalid", &b"abc\xff\ndef"[..]), ("truncated", &b"abcd\xc3"[..])] {
+        let err = read(test, contents, ReadLimits { chunk_size: 4, ..Default::default() }).err().unwrap_or_else(|| panic!("{test} was read"));
+        assert_eq!(err.kind(), ErrorKind::InvalidData, "{test}");
+    }
+}
+
+#[test]
+fn line_indexes_spill_to_disk() {
+    let contents = (1..=100).map(|i| format!("line {i}\n")).collect::<String>();
+    let limits = ReadLimits { max_resident_lines: 8, chunk_size: 16 };
+    for text in [read("spill", contents.as_bytes(), limits).unwrap(), SourceText::new(contents.clone(), limits)] {
+        for i in 1..=100 {
+            assert_eq!(text.line(i), Some(format!("line {i}").as_str()));
+        }
+        assert_eq!(text.line(101), Some(""));
+        assert_eq!(text.line(102), None);
+    }
+}